@@ -0,0 +1,57 @@
+//! Times the tree-walking interpreter, the stack machine and the register
+//! machine on the scripts next to this file. Run with `cargo bench`.
+
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use rs_crafting_interpreters::bytecode::{self, vm::Vm};
+use rs_crafting_interpreters::lox::{Lox, LoxError};
+use rs_crafting_interpreters::register::{self, vm::RegisterVm};
+
+const SCRIPTS: [&str; 3] = ["fib", "binary_trees", "strings"];
+/// Each engine runs a script this many times, the fastest run counts
+const RUNS: usize = 3;
+
+/// The fastest of `RUNS` runs
+fn fastest(run: impl Fn() -> Result<(), LoxError>) -> Duration {
+    (0..RUNS)
+        .map(|_| {
+            let start = Instant::now();
+            run().unwrap_or_else(|error| panic!("{}", error));
+            start.elapsed()
+        })
+        .min()
+        .unwrap_or_default()
+}
+
+fn main() {
+    let mut rows = Vec::new();
+
+    for name in SCRIPTS {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("benches")
+            .join(name)
+            .with_extension("lox");
+        let source = std::fs::read_to_string(&path).unwrap();
+
+        let tree = fastest(|| Lox::new().eval(&source).map(drop));
+        let stack = fastest(|| bytecode::interpret(&source, &mut Vm::new()));
+        let register = fastest(|| register::interpret(&source, &mut RegisterVm::new()));
+        rows.push((name, tree, stack, register));
+    }
+
+    println!(
+        "\n{:<14} {:>12} {:>12} {:>12} {:>16}",
+        "script", "tree", "vm", "register", "register vs vm"
+    );
+    for (name, tree, stack, register) in rows {
+        println!(
+            "{:<14} {:>12.2?} {:>12.2?} {:>12.2?} {:>15.2}x",
+            name,
+            tree,
+            stack,
+            register,
+            stack.as_secs_f64() / register.as_secs_f64()
+        );
+    }
+}
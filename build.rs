@@ -0,0 +1,46 @@
+//! With the `ffi` feature, generates `lox.h`, the header of the C API in
+//! `src/ffi.rs`, into `OUT_DIR`. `tests/ffi.rs` checks that the committed
+//! `include/lox.h` matches it.
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "ffi")]
+    generate_header();
+}
+
+#[cfg(feature = "ffi")]
+fn generate_header() {
+    use cbindgen::{Builder, Config, EnumConfig, ExportConfig, Language, RenameRule, Style};
+
+    println!("cargo:rerun-if-changed=src/ffi.rs");
+
+    let config = Config {
+        language: Language::C,
+        style: Style::Both,
+        cpp_compat: true,
+        include_guard: Some("LOX_H".to_string()),
+        autogen_warning: Some(
+            "/* Generated from src/ffi.rs by build.rs, do not edit by hand. */".to_string(),
+        ),
+        enumeration: EnumConfig {
+            prefix_with_name: true,
+            rename_variants: RenameRule::ScreamingSnakeCase,
+            ..EnumConfig::default()
+        },
+        documentation: true,
+        // `LoxValue::kind` is a plain integer, C still needs the names of its values.
+        export: ExportConfig {
+            include: vec!["LoxType".to_string()],
+            ..ExportConfig::default()
+        },
+        ..Config::default()
+    };
+
+    let out_dir = std::env::var("OUT_DIR").expect("OUT_DIR set by cargo");
+    Builder::new()
+        .with_src("src/ffi.rs")
+        .with_config(config)
+        .generate()
+        .expect("C header for src/ffi.rs")
+        .write_to_file(std::path::Path::new(&out_dir).join("lox.h"));
+}
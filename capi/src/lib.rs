@@ -0,0 +1,4 @@
+//! The C API of the interpreter as a shared and a static library, see
+//! `include/lox.h` for its header
+
+pub use rs_crafting_interpreters::ffi::*;
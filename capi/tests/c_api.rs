@@ -0,0 +1,42 @@
+use std::path::Path;
+use std::process::Command;
+
+/// Compile `tests/c/api.c` with the system C compiler against the generated
+/// header and `liblox`, then run it
+#[test]
+fn c_program_uses_the_api() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).parent().unwrap();
+    // Integration tests run from `target/<profile>/deps`, the library sits one level up.
+    let library = std::env::current_exe()
+        .unwrap()
+        .parent()
+        .and_then(Path::parent)
+        .unwrap()
+        .to_path_buf();
+    let binary = Path::new(env!("CARGO_TARGET_TMPDIR")).join("c_api");
+
+    let cc = Command::new("cc")
+        .args(["-std=c99", "-Wall", "-Wextra", "-Werror", "-o"])
+        .arg(&binary)
+        .arg(root.join("tests/c/api.c"))
+        .arg("-I")
+        .arg(root.join("include"))
+        .arg("-L")
+        .arg(&library)
+        .arg(format!("-Wl,-rpath,{}", library.display()))
+        .arg("-llox")
+        .output()
+        .unwrap();
+    assert!(
+        cc.status.success(),
+        "cc failed: {}",
+        String::from_utf8_lossy(&cc.stderr)
+    );
+
+    let output = Command::new(&binary).output().unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "printed by lox\n<native fn add>\nok\n"
+    );
+    assert!(output.status.success());
+}
@@ -0,0 +1,158 @@
+#ifndef LOX_H
+#define LOX_H
+
+/* Generated from src/ffi.rs by build.rs, do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Result of an API call
+ */
+typedef enum LoxStatus {
+  LOX_STATUS_OK,
+  /**
+   * Scanner, parser or resolver errors, the script did not run
+   */
+  LOX_STATUS_COMPILE_ERROR,
+  /**
+   * The script failed while running, or a global is undefined
+   */
+  LOX_STATUS_RUNTIME_ERROR,
+  /**
+   * A null pointer, invalid UTF-8, an unknown `LoxType` or a value that
+   * cannot be passed in
+   */
+  LOX_STATUS_INVALID_ARGUMENT,
+  /**
+   * A bug in the interpreter stopped the call, the message says where
+   */
+  LOX_STATUS_INTERNAL_ERROR,
+} LoxStatus;
+
+/**
+ * Kinds of `LoxValue`, stored in its `kind` field
+ */
+typedef enum LoxType {
+  LOX_TYPE_NULL,
+  LOX_TYPE_BOOL,
+  LOX_TYPE_NUMBER,
+  LOX_TYPE_STRING,
+  /**
+   * A function, class or instance, only readable as the text it prints as
+   */
+  LOX_TYPE_OBJECT,
+} LoxType;
+
+/**
+ * An interpreter owned by C code, created by `lox_new` and released by `lox_free`
+ */
+typedef struct Lox Lox;
+
+/**
+ * A value crossing the API. `kind` is a `LoxType`, kept as a plain integer
+ * so values outside the enum coming from C can be rejected. `string` is set
+ * for strings and objects and points into memory owned by the interpreter
+ * or the caller.
+ */
+typedef struct LoxValue {
+  uint32_t kind;
+  bool boolean;
+  double number;
+  const char *string;
+} LoxValue;
+
+/**
+ * A native function, never null. On failure it returns false and may
+ * store a string with the error message in `result`.
+ */
+typedef bool (*LoxNativeFn)(void *user_data,
+                            int argument_count,
+                            const struct LoxValue *arguments,
+                            struct LoxValue *result);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Create an interpreter with `clock` defined, null if that failed
+ */
+struct Lox *lox_new(void);
+
+/**
+ * Release an interpreter, strings it returned become invalid
+ *
+ * # Safety
+ *
+ * `lox` must be null or come from `lox_new` and not be used afterwards.
+ */
+void lox_free(struct Lox *lox);
+
+/**
+ * Run a script. When `result` is not null it receives the value of the
+ * last statement if that is an expression, `null` otherwise. Strings in
+ * it stay valid until the next call on the same interpreter.
+ *
+ * # Safety
+ *
+ * `lox` must come from `lox_new`, `source` must be a NUL-terminated string
+ * and `result` null or writable.
+ */
+enum LoxStatus lox_eval(struct Lox *lox, const char *source, struct LoxValue *result);
+
+/**
+ * Define or redefine a global variable, strings are copied
+ *
+ * # Safety
+ *
+ * `lox` must come from `lox_new`, `name` and a string value must be
+ * NUL-terminated strings.
+ */
+enum LoxStatus lox_set_global(struct Lox *lox, const char *name, struct LoxValue value);
+
+/**
+ * Read a global variable into `result`, its string is valid until the
+ * next call on the same interpreter
+ *
+ * # Safety
+ *
+ * `lox` must come from `lox_new`, `name` must be a NUL-terminated string
+ * and `result` writable.
+ */
+enum LoxStatus lox_get_global(struct Lox *lox, const char *name, struct LoxValue *result);
+
+/**
+ * Define a global function implemented in C, taking `arity` arguments or
+ * any number when it is negative. Strings in the arguments are only valid
+ * during the call, `user_data` is passed back on every call.
+ *
+ * # Safety
+ *
+ * `lox` must come from `lox_new` and `name` must be a NUL-terminated
+ * string. `function` must write valid values to `result`, strings in it
+ * are copied right after it returns.
+ */
+enum LoxStatus lox_register_native(struct Lox *lox,
+                                   const char *name,
+                                   int arity,
+                                   LoxNativeFn function,
+                                   void *user_data);
+
+/**
+ * The message of the last call's error, or null when it succeeded. Valid
+ * until the next call on the same interpreter.
+ *
+ * # Safety
+ *
+ * `lox` must be null or come from `lox_new`.
+ */
+const char *lox_error(const struct Lox *lox);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* LOX_H */
@@ -0,0 +1,281 @@
+use std::rc::Rc;
+
+use crate::expr::{self, ExprVisitor};
+use crate::stmt::{self, Stmt, StmtVisitor};
+use crate::token::{Literal, Token, TokenTrait};
+
+/// Render statements as a Graphviz digraph rooted at a `Program` node
+pub fn print_dot(statements: &[Stmt]) -> String {
+    let mut printer = DotPrinter::default();
+    let root = printer.node("Program", &[]);
+    for (i, statement) in statements.iter().enumerate() {
+        let child = statement.accept(&mut printer);
+        printer.edge(root, child, &format!("statements[{i}]"));
+    }
+
+    let mut output = String::from("digraph ast {\n");
+    output.push_str("  node [shape=box, fontname=\"monospace\"];\n");
+    for line in printer.lines {
+        output.push_str("  ");
+        output.push_str(&line);
+        output.push('\n');
+    }
+    output.push('}');
+
+    output
+}
+
+/// Escape a label for a double quoted DOT string, lines are joined with `\n`
+fn escape(label: &str) -> String {
+    label
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// Visits the tree, emitting a node per AST node and returning its id
+#[derive(Default)]
+struct DotPrinter {
+    next_id: usize,
+    lines: Vec<String>,
+}
+
+impl DotPrinter {
+    /// Emit a node whose label is the kind followed by extra detail lines
+    fn node(&mut self, kind: &str, details: &[&str]) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let mut label = escape(kind);
+        for detail in details {
+            label.push_str("\\n");
+            label.push_str(&escape(detail));
+        }
+        self.lines.push(format!("n{id} [label=\"{label}\"];"));
+
+        id
+    }
+
+    /// Emit a leaf for an operator token, labelled with its lexeme
+    fn token(&mut self, token: &Token) -> usize {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.lines.push(format!(
+            "n{id} [label=\"{}\", shape=ellipse];",
+            escape(token.lexeme())
+        ));
+
+        id
+    }
+
+    fn edge(&mut self, from: usize, to: usize, field: &str) {
+        self.lines
+            .push(format!("n{from} -> n{to} [label=\"{}\"];", escape(field)));
+    }
+
+    fn statements(&mut self, parent: usize, field: &str, statements: &[Stmt]) {
+        for (i, statement) in statements.iter().enumerate() {
+            let child = statement.accept(self);
+            self.edge(parent, child, &format!("{field}[{i}]"));
+        }
+    }
+
+    fn function(&mut self, function: &stmt::Function) -> usize {
+        let params: Vec<&str> = function.params.iter().map(|p| p.lexeme()).collect();
+        let signature = format!("{}({})", function.name.lexeme(), params.join(", "));
+        let id = self.node("Function", &[&signature]);
+        self.statements(id, "body", &function.body);
+
+        id
+    }
+}
+
+impl ExprVisitor<usize> for DotPrinter {
+    fn visit_assign_expr(&mut self, expr: &expr::Assign) -> usize {
+        let id = self.node("Assign", &[expr.name.lexeme()]);
+        let value = expr.value.accept(self);
+        self.edge(id, value, "value");
+
+        id
+    }
+
+    fn visit_binary_expr(&mut self, expr: &expr::Binary) -> usize {
+        let id = self.node("Binary", &[expr.operator.lexeme()]);
+        let left = expr.left.accept(self);
+        self.edge(id, left, "left");
+        let operator = self.token(&expr.operator);
+        self.edge(id, operator, "operator");
+        let right = expr.right.accept(self);
+        self.edge(id, right, "right");
+
+        id
+    }
+
+    fn visit_call_expr(&mut self, expr: &expr::Call) -> usize {
+        let id = self.node("Call", &[]);
+        let callee = expr.callee.accept(self);
+        self.edge(id, callee, "callee");
+        for (i, argument) in expr.arguments.iter().enumerate() {
+            let child = argument.accept(self);
+            self.edge(id, child, &format!("arguments[{i}]"));
+        }
+
+        id
+    }
+
+    fn visit_get_expr(&mut self, expr: &expr::Get) -> usize {
+        let id = self.node("Get", &[expr.name.lexeme()]);
+        let object = expr.object.accept(self);
+        self.edge(id, object, "object");
+
+        id
+    }
+
+    fn visit_grouping_expr(&mut self, expr: &expr::Grouping) -> usize {
+        let id = self.node("Grouping", &[]);
+        let expression = expr.expression.accept(self);
+        self.edge(id, expression, "expression");
+
+        id
+    }
+
+    fn visit_literal_expr(&mut self, expr: &expr::Literal) -> usize {
+        let value = match &expr.value {
+            Literal::String(value) => format!("{:?}", value),
+            value => value.to_string(),
+        };
+
+        self.node("Literal", &[&value])
+    }
+
+    fn visit_logical_expr(&mut self, expr: &expr::Logical) -> usize {
+        let id = self.node("Logical", &[expr.operator.lexeme()]);
+        let left = expr.left.accept(self);
+        self.edge(id, left, "left");
+        let operator = self.token(&expr.operator);
+        self.edge(id, operator, "operator");
+        let right = expr.right.accept(self);
+        self.edge(id, right, "right");
+
+        id
+    }
+
+    fn visit_set_expr(&mut self, expr: &expr::Set) -> usize {
+        let id = self.node("Set", &[expr.name.lexeme()]);
+        let object = expr.object.accept(self);
+        self.edge(id, object, "object");
+        let value = expr.value.accept(self);
+        self.edge(id, value, "value");
+
+        id
+    }
+
+    fn visit_super_expr(&mut self, expr: &expr::Super) -> usize {
+        self.node("Super", &[expr.method.lexeme()])
+    }
+
+    fn visit_this_expr(&mut self, _expr: &expr::This) -> usize {
+        self.node("This", &[])
+    }
+
+    fn visit_unary_expr(&mut self, expr: &expr::Unary) -> usize {
+        let id = self.node("Unary", &[expr.operator.lexeme()]);
+        let operator = self.token(&expr.operator);
+        self.edge(id, operator, "operator");
+        let right = expr.right.accept(self);
+        self.edge(id, right, "right");
+
+        id
+    }
+
+    fn visit_variable_expr(&mut self, expr: &expr::Variable) -> usize {
+        self.node("Variable", &[expr.name.lexeme()])
+    }
+}
+
+impl StmtVisitor<usize> for DotPrinter {
+    fn visit_block_stmt(&mut self, stmt: &stmt::Block) -> usize {
+        let id = self.node("Block", &[]);
+        self.statements(id, "statements", &stmt.statements);
+
+        id
+    }
+
+    fn visit_class_stmt(&mut self, stmt: &stmt::Class) -> usize {
+        let id = self.node("Class", &[stmt.name.lexeme()]);
+        if let Some(superclass) = &stmt.superclass {
+            let child = self.visit_variable_expr(superclass);
+            self.edge(id, child, "superclass");
+        }
+        for (i, method) in stmt.methods.iter().enumerate() {
+            let child = self.function(method);
+            self.edge(id, child, &format!("methods[{i}]"));
+        }
+
+        id
+    }
+
+    fn visit_expression_stmt(&mut self, stmt: &stmt::Expression) -> usize {
+        let id = self.node("Expression", &[]);
+        let expression = stmt.expression.accept(self);
+        self.edge(id, expression, "expression");
+
+        id
+    }
+
+    fn visit_function_stmt(&mut self, stmt: &Rc<stmt::Function>) -> usize {
+        self.function(stmt)
+    }
+
+    fn visit_if_stmt(&mut self, stmt: &stmt::If) -> usize {
+        let id = self.node("If", &[]);
+        let condition = stmt.condition.accept(self);
+        self.edge(id, condition, "condition");
+        let then_branch = stmt.then_branch.accept(self);
+        self.edge(id, then_branch, "then_branch");
+        if let Some(else_branch) = &stmt.else_branch {
+            let else_branch = else_branch.accept(self);
+            self.edge(id, else_branch, "else_branch");
+        }
+
+        id
+    }
+
+    fn visit_print_stmt(&mut self, stmt: &stmt::Print) -> usize {
+        let id = self.node("Print", &[]);
+        let expression = stmt.expression.accept(self);
+        self.edge(id, expression, "expression");
+
+        id
+    }
+
+    fn visit_return_stmt(&mut self, stmt: &stmt::Return) -> usize {
+        let id = self.node("Return", &[]);
+        if let Some(value) = &stmt.value {
+            let value = value.accept(self);
+            self.edge(id, value, "value");
+        }
+
+        id
+    }
+
+    fn visit_var_stmt(&mut self, stmt: &stmt::Var) -> usize {
+        let id = self.node("Var", &[stmt.name.lexeme()]);
+        if let Some(initializer) = &stmt.initializer {
+            let initializer = initializer.accept(self);
+            self.edge(id, initializer, "initializer");
+        }
+
+        id
+    }
+
+    fn visit_while_stmt(&mut self, stmt: &stmt::While) -> usize {
+        let id = self.node("While", &[]);
+        let condition = stmt.condition.accept(self);
+        self.edge(id, condition, "condition");
+        let body = stmt.body.accept(self);
+        self.edge(id, body, "body");
+
+        id
+    }
+}
@@ -0,0 +1,577 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use crate::expr::{self, Expr, ExprVisitor};
+use crate::json::{Json, JsonError};
+use crate::stmt::{self, Stmt, StmtVisitor};
+use crate::token::{Literal, Span, Token, TokenTrait};
+
+/// Errors raised while reading an AST back from JSON
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum AstJsonError {
+    #[error("invalid json: {0}")]
+    InvalidJson(#[from] JsonError),
+    #[error("missing field: {0}")]
+    MissingField(String),
+    #[error("field {0} must be {1}")]
+    InvalidField(String, String),
+    #[error("unknown node kind: {0}")]
+    UnknownKind(String),
+    #[error("unknown token type: {0}")]
+    UnknownTokenType(String),
+}
+
+/// Encode a program as a JSON array of statement nodes
+pub fn statements_to_json(statements: &[Stmt]) -> Json {
+    Json::Array(
+        statements
+            .iter()
+            .map(|s| s.accept(&mut AstJsonEncoder))
+            .collect(),
+    )
+}
+
+pub fn expr_to_json(expr: &Expr) -> Json {
+    expr.accept(&mut AstJsonEncoder)
+}
+
+/// Decode a JSON array of statement nodes produced by `statements_to_json`
+pub fn statements_from_json(json: &Json) -> Result<Vec<Stmt>, AstJsonError> {
+    as_array(json, "statements")?
+        .iter()
+        .map(stmt_from_json)
+        .collect()
+}
+
+/// Decode JSON text produced by `statements_to_json`
+pub fn statements_from_str(source: &str) -> Result<Vec<Stmt>, AstJsonError> {
+    statements_from_json(&Json::parse(source)?)
+}
+
+fn node(kind: &str, fields: Vec<(&str, Json)>) -> Json {
+    let mut entries = vec![("kind".to_string(), Json::String(kind.to_string()))];
+    entries.extend(fields.into_iter().map(|(k, v)| (k.to_string(), v)));
+
+    Json::Object(entries)
+}
+
+fn span_to_json(span: Span) -> Json {
+    Json::Object(vec![
+        ("line".to_string(), Json::Number(span.line as f64)),
+        ("column".to_string(), Json::Number(span.column as f64)),
+        ("start".to_string(), Json::Number(span.start as f64)),
+        ("end".to_string(), Json::Number(span.end as f64)),
+    ])
+}
+
+fn literal_to_json(literal: &Literal) -> Json {
+    match literal {
+        Literal::String(value) => node("String", vec![("value", Json::String(value.clone()))]),
+        Literal::Number(value) => node("Number", vec![("value", number_to_json(*value))]),
+        Literal::Boolean(value) => node("Boolean", vec![("value", Json::Bool(*value))]),
+        Literal::Null => node("Null", vec![]),
+    }
+}
+
+/// JSON has no infinities or NaN, the optimizer folds `1/0` and `0/0` into them,
+/// so they are written as the strings `"Infinity"`, `"-Infinity"` and `"NaN"`
+fn number_to_json(value: f64) -> Json {
+    match value {
+        value if value.is_nan() => Json::String("NaN".to_string()),
+        f64::INFINITY => Json::String("Infinity".to_string()),
+        f64::NEG_INFINITY => Json::String("-Infinity".to_string()),
+        value => Json::Number(value),
+    }
+}
+
+fn token_to_json(token: &Token) -> Json {
+    Json::Object(vec![
+        (
+            "type".to_string(),
+            Json::String(format!("{:?}", token.token_type())),
+        ),
+        (
+            "lexeme".to_string(),
+            Json::String(token.lexeme().to_string()),
+        ),
+        ("literal".to_string(), literal_to_json(token.literal())),
+        ("span".to_string(), span_to_json(token.span())),
+    ])
+}
+
+fn option_to_json<T>(value: Option<&T>, encode: impl FnOnce(&T) -> Json) -> Json {
+    value.map(encode).unwrap_or(Json::Null)
+}
+
+/// Encodes nodes as objects tagged with a `kind` field
+struct AstJsonEncoder;
+
+impl AstJsonEncoder {
+    fn statements(&mut self, statements: &[Stmt]) -> Json {
+        Json::Array(statements.iter().map(|s| s.accept(self)).collect())
+    }
+
+    fn function(&mut self, function: &stmt::Function) -> Json {
+        node(
+            "Function",
+            vec![
+                ("name", token_to_json(&function.name)),
+                (
+                    "params",
+                    Json::Array(function.params.iter().map(token_to_json).collect()),
+                ),
+                ("body", self.statements(&function.body)),
+            ],
+        )
+    }
+}
+
+impl ExprVisitor<Json> for AstJsonEncoder {
+    fn visit_assign_expr(&mut self, expr: &expr::Assign) -> Json {
+        node(
+            "Assign",
+            vec![
+                ("name", token_to_json(&expr.name)),
+                ("value", expr.value.accept(self)),
+            ],
+        )
+    }
+
+    fn visit_binary_expr(&mut self, expr: &expr::Binary) -> Json {
+        node(
+            "Binary",
+            vec![
+                ("left", expr.left.accept(self)),
+                ("operator", token_to_json(&expr.operator)),
+                ("right", expr.right.accept(self)),
+            ],
+        )
+    }
+
+    fn visit_call_expr(&mut self, expr: &expr::Call) -> Json {
+        node(
+            "Call",
+            vec![
+                ("callee", expr.callee.accept(self)),
+                ("paren", token_to_json(&expr.paren)),
+                (
+                    "arguments",
+                    Json::Array(expr.arguments.iter().map(|a| a.accept(self)).collect()),
+                ),
+            ],
+        )
+    }
+
+    fn visit_get_expr(&mut self, expr: &expr::Get) -> Json {
+        node(
+            "Get",
+            vec![
+                ("object", expr.object.accept(self)),
+                ("name", token_to_json(&expr.name)),
+            ],
+        )
+    }
+
+    fn visit_grouping_expr(&mut self, expr: &expr::Grouping) -> Json {
+        node(
+            "Grouping",
+            vec![
+                ("expression", expr.expression.accept(self)),
+                ("span", span_to_json(expr.span)),
+            ],
+        )
+    }
+
+    fn visit_literal_expr(&mut self, expr: &expr::Literal) -> Json {
+        node(
+            "Literal",
+            vec![
+                ("value", literal_to_json(&expr.value)),
+                ("span", span_to_json(expr.span)),
+            ],
+        )
+    }
+
+    fn visit_logical_expr(&mut self, expr: &expr::Logical) -> Json {
+        node(
+            "Logical",
+            vec![
+                ("left", expr.left.accept(self)),
+                ("operator", token_to_json(&expr.operator)),
+                ("right", expr.right.accept(self)),
+            ],
+        )
+    }
+
+    fn visit_set_expr(&mut self, expr: &expr::Set) -> Json {
+        node(
+            "Set",
+            vec![
+                ("object", expr.object.accept(self)),
+                ("name", token_to_json(&expr.name)),
+                ("value", expr.value.accept(self)),
+            ],
+        )
+    }
+
+    fn visit_super_expr(&mut self, expr: &expr::Super) -> Json {
+        node(
+            "Super",
+            vec![
+                ("keyword", token_to_json(&expr.keyword)),
+                ("method", token_to_json(&expr.method)),
+            ],
+        )
+    }
+
+    fn visit_this_expr(&mut self, expr: &expr::This) -> Json {
+        node("This", vec![("keyword", token_to_json(&expr.keyword))])
+    }
+
+    fn visit_unary_expr(&mut self, expr: &expr::Unary) -> Json {
+        node(
+            "Unary",
+            vec![
+                ("operator", token_to_json(&expr.operator)),
+                ("right", expr.right.accept(self)),
+            ],
+        )
+    }
+
+    fn visit_variable_expr(&mut self, expr: &expr::Variable) -> Json {
+        node("Variable", vec![("name", token_to_json(&expr.name))])
+    }
+}
+
+impl StmtVisitor<Json> for AstJsonEncoder {
+    fn visit_block_stmt(&mut self, stmt: &stmt::Block) -> Json {
+        node(
+            "Block",
+            vec![
+                ("statements", self.statements(&stmt.statements)),
+                ("span", span_to_json(stmt.span)),
+            ],
+        )
+    }
+
+    fn visit_class_stmt(&mut self, stmt: &stmt::Class) -> Json {
+        let superclass = option_to_json(stmt.superclass.as_ref(), |s| self.visit_variable_expr(s));
+        let methods = stmt.methods.iter().map(|m| self.function(m)).collect();
+
+        node(
+            "Class",
+            vec![
+                ("name", token_to_json(&stmt.name)),
+                ("superclass", superclass),
+                ("methods", Json::Array(methods)),
+            ],
+        )
+    }
+
+    fn visit_expression_stmt(&mut self, stmt: &stmt::Expression) -> Json {
+        node(
+            "Expression",
+            vec![("expression", stmt.expression.accept(self))],
+        )
+    }
+
+    fn visit_function_stmt(&mut self, stmt: &Rc<stmt::Function>) -> Json {
+        self.function(stmt)
+    }
+
+    fn visit_if_stmt(&mut self, stmt: &stmt::If) -> Json {
+        let else_branch = option_to_json(stmt.else_branch.as_ref(), |s| s.accept(self));
+
+        node(
+            "If",
+            vec![
+                ("condition", stmt.condition.accept(self)),
+                ("then_branch", stmt.then_branch.accept(self)),
+                ("else_branch", else_branch),
+                ("span", span_to_json(stmt.span)),
+            ],
+        )
+    }
+
+    fn visit_print_stmt(&mut self, stmt: &stmt::Print) -> Json {
+        node(
+            "Print",
+            vec![
+                ("expression", stmt.expression.accept(self)),
+                ("span", span_to_json(stmt.span)),
+            ],
+        )
+    }
+
+    fn visit_return_stmt(&mut self, stmt: &stmt::Return) -> Json {
+        let value = option_to_json(stmt.value.as_ref(), |v| v.accept(self));
+
+        node(
+            "Return",
+            vec![("keyword", token_to_json(&stmt.keyword)), ("value", value)],
+        )
+    }
+
+    fn visit_var_stmt(&mut self, stmt: &stmt::Var) -> Json {
+        let initializer = option_to_json(stmt.initializer.as_ref(), |i| i.accept(self));
+
+        node(
+            "Var",
+            vec![
+                ("name", token_to_json(&stmt.name)),
+                ("initializer", initializer),
+            ],
+        )
+    }
+
+    fn visit_while_stmt(&mut self, stmt: &stmt::While) -> Json {
+        node(
+            "While",
+            vec![
+                ("condition", stmt.condition.accept(self)),
+                ("body", stmt.body.accept(self)),
+                ("span", span_to_json(stmt.span)),
+            ],
+        )
+    }
+}
+
+fn field<'a>(json: &'a Json, name: &str) -> Result<&'a Json, AstJsonError> {
+    json.get(name)
+        .ok_or_else(|| AstJsonError::MissingField(name.to_string()))
+}
+
+fn invalid(name: &str, expected: &str) -> AstJsonError {
+    AstJsonError::InvalidField(name.to_string(), expected.to_string())
+}
+
+fn as_array<'a>(json: &'a Json, name: &str) -> Result<&'a Vec<Json>, AstJsonError> {
+    match json {
+        Json::Array(values) => Ok(values),
+        _ => Err(invalid(name, "an array")),
+    }
+}
+
+fn as_str<'a>(json: &'a Json, name: &str) -> Result<&'a str, AstJsonError> {
+    match json {
+        Json::String(value) => Ok(value),
+        _ => Err(invalid(name, "a string")),
+    }
+}
+
+fn as_number(json: &Json, name: &str) -> Result<f64, AstJsonError> {
+    match json {
+        Json::Number(value) => Ok(*value),
+        _ => Err(invalid(name, "a number")),
+    }
+}
+
+fn number_from_json(json: &Json) -> Result<f64, AstJsonError> {
+    match json {
+        Json::String(value) if value == "NaN" => Ok(f64::NAN),
+        Json::String(value) if value == "Infinity" => Ok(f64::INFINITY),
+        Json::String(value) if value == "-Infinity" => Ok(f64::NEG_INFINITY),
+        json => as_number(json, "value"),
+    }
+}
+
+fn as_bool(json: &Json, name: &str) -> Result<bool, AstJsonError> {
+    match json {
+        Json::Bool(value) => Ok(*value),
+        _ => Err(invalid(name, "a boolean")),
+    }
+}
+
+fn kind(json: &Json) -> Result<&str, AstJsonError> {
+    as_str(field(json, "kind")?, "kind")
+}
+
+/// Decode an optional field, JSON null and a missing key both mean `None`
+fn optional<T>(
+    json: &Json,
+    name: &str,
+    decode: impl FnOnce(&Json) -> Result<T, AstJsonError>,
+) -> Result<Option<T>, AstJsonError> {
+    match json.get(name) {
+        None | Some(Json::Null) => Ok(None),
+        Some(value) => decode(value).map(Some),
+    }
+}
+
+fn boxed_expr(json: &Json, name: &str) -> Result<Box<Expr>, AstJsonError> {
+    expr_from_json(field(json, name)?).map(Box::new)
+}
+
+fn span_from_json(json: &Json) -> Result<Span, AstJsonError> {
+    let number = |name: &str| as_number(field(json, name)?, name);
+
+    Ok(Span {
+        line: number("line")? as u32,
+        column: number("column")? as u32,
+        start: number("start")? as usize,
+        end: number("end")? as usize,
+    })
+}
+
+fn literal_from_json(json: &Json) -> Result<Literal, AstJsonError> {
+    match kind(json)? {
+        "String" => Ok(Literal::String(
+            as_str(field(json, "value")?, "value")?.to_string(),
+        )),
+        "Number" => Ok(Literal::Number(number_from_json(field(json, "value")?)?)),
+        "Boolean" => Ok(Literal::Boolean(as_bool(field(json, "value")?, "value")?)),
+        "Null" => Ok(Literal::Null),
+        kind => Err(AstJsonError::UnknownKind(kind.to_string())),
+    }
+}
+
+fn token_from_json(json: &Json) -> Result<Token, AstJsonError> {
+    let token_type = as_str(field(json, "type")?, "type")?
+        .parse()
+        .map_err(AstJsonError::UnknownTokenType)?;
+
+    Ok(Token::new(
+        token_type,
+        as_str(field(json, "lexeme")?, "lexeme")?.to_string(),
+        literal_from_json(field(json, "literal")?)?,
+        span_from_json(field(json, "span")?)?,
+    ))
+}
+
+fn tokens_from_json(json: &Json, name: &str) -> Result<Vec<Token>, AstJsonError> {
+    as_array(field(json, name)?, name)?
+        .iter()
+        .map(token_from_json)
+        .collect()
+}
+
+fn variable_from_json(json: &Json) -> Result<expr::Variable, AstJsonError> {
+    Ok(expr::Variable {
+        name: token_from_json(field(json, "name")?)?,
+        depth: Cell::default(),
+    })
+}
+
+pub fn expr_from_json(json: &Json) -> Result<Expr, AstJsonError> {
+    let expr = match kind(json)? {
+        "Assign" => Expr::Assign(expr::Assign {
+            name: token_from_json(field(json, "name")?)?,
+            value: boxed_expr(json, "value")?,
+            depth: Cell::default(),
+        }),
+        "Binary" => Expr::Binary(expr::Binary {
+            left: boxed_expr(json, "left")?,
+            operator: token_from_json(field(json, "operator")?)?,
+            right: boxed_expr(json, "right")?,
+        }),
+        "Call" => Expr::Call(expr::Call {
+            callee: boxed_expr(json, "callee")?,
+            paren: token_from_json(field(json, "paren")?)?,
+            arguments: as_array(field(json, "arguments")?, "arguments")?
+                .iter()
+                .map(expr_from_json)
+                .collect::<Result<_, _>>()?,
+        }),
+        "Get" => Expr::Get(expr::Get {
+            object: boxed_expr(json, "object")?,
+            name: token_from_json(field(json, "name")?)?,
+        }),
+        "Grouping" => Expr::Grouping(expr::Grouping {
+            expression: boxed_expr(json, "expression")?,
+            span: span_from_json(field(json, "span")?)?,
+        }),
+        "Literal" => Expr::Literal(expr::Literal {
+            value: literal_from_json(field(json, "value")?)?,
+            span: span_from_json(field(json, "span")?)?,
+        }),
+        "Logical" => Expr::Logical(expr::Logical {
+            left: boxed_expr(json, "left")?,
+            operator: token_from_json(field(json, "operator")?)?,
+            right: boxed_expr(json, "right")?,
+        }),
+        "Set" => Expr::Set(expr::Set {
+            object: boxed_expr(json, "object")?,
+            name: token_from_json(field(json, "name")?)?,
+            value: boxed_expr(json, "value")?,
+        }),
+        "Super" => Expr::Super(expr::Super {
+            keyword: token_from_json(field(json, "keyword")?)?,
+            method: token_from_json(field(json, "method")?)?,
+            depth: Cell::default(),
+        }),
+        "This" => Expr::This(expr::This {
+            keyword: token_from_json(field(json, "keyword")?)?,
+            depth: Cell::default(),
+        }),
+        "Unary" => Expr::Unary(expr::Unary {
+            operator: token_from_json(field(json, "operator")?)?,
+            right: boxed_expr(json, "right")?,
+        }),
+        "Variable" => Expr::Variable(variable_from_json(json)?),
+        kind => return Err(AstJsonError::UnknownKind(kind.to_string())),
+    };
+
+    Ok(expr)
+}
+
+fn function_from_json(json: &Json) -> Result<Rc<stmt::Function>, AstJsonError> {
+    match kind(json)? {
+        "Function" => Ok(Rc::new(stmt::Function {
+            name: token_from_json(field(json, "name")?)?,
+            params: tokens_from_json(json, "params")?,
+            body: statements_from_json(field(json, "body")?)?,
+        })),
+        kind => Err(AstJsonError::UnknownKind(kind.to_string())),
+    }
+}
+
+fn boxed_stmt(json: &Json) -> Result<Box<Stmt>, AstJsonError> {
+    stmt_from_json(json).map(Box::new)
+}
+
+pub fn stmt_from_json(json: &Json) -> Result<Stmt, AstJsonError> {
+    let stmt = match kind(json)? {
+        "Block" => Stmt::Block(stmt::Block {
+            statements: statements_from_json(field(json, "statements")?)?,
+            span: span_from_json(field(json, "span")?)?,
+        }),
+        "Class" => Stmt::Class(stmt::Class {
+            name: token_from_json(field(json, "name")?)?,
+            superclass: optional(json, "superclass", variable_from_json)?,
+            methods: as_array(field(json, "methods")?, "methods")?
+                .iter()
+                .map(function_from_json)
+                .collect::<Result<_, _>>()?,
+        }),
+        "Expression" => Stmt::Expression(stmt::Expression {
+            expression: expr_from_json(field(json, "expression")?)?,
+        }),
+        "Function" => Stmt::Function(function_from_json(json)?),
+        "If" => Stmt::If(stmt::If {
+            condition: expr_from_json(field(json, "condition")?)?,
+            then_branch: boxed_stmt(field(json, "then_branch")?)?,
+            else_branch: optional(json, "else_branch", boxed_stmt)?,
+            span: span_from_json(field(json, "span")?)?,
+        }),
+        "Print" => Stmt::Print(stmt::Print {
+            expression: expr_from_json(field(json, "expression")?)?,
+            span: span_from_json(field(json, "span")?)?,
+        }),
+        "Return" => Stmt::Return(stmt::Return {
+            keyword: token_from_json(field(json, "keyword")?)?,
+            value: optional(json, "value", expr_from_json)?,
+        }),
+        "Var" => Stmt::Var(stmt::Var {
+            name: token_from_json(field(json, "name")?)?,
+            initializer: optional(json, "initializer", expr_from_json)?,
+        }),
+        "While" => Stmt::While(stmt::While {
+            condition: expr_from_json(field(json, "condition")?)?,
+            body: boxed_stmt(field(json, "body")?)?,
+            span: span_from_json(field(json, "span")?)?,
+        }),
+        kind => return Err(AstJsonError::UnknownKind(kind.to_string())),
+    };
+
+    Ok(stmt)
+}
@@ -0,0 +1,582 @@
+use std::rc::Rc;
+use std::str::FromStr;
+
+use crate::ast_dot::print_dot;
+use crate::ast_json::statements_to_json;
+use crate::expr::{self, Expr, ExprVisitor};
+use crate::stmt::{self, Stmt, StmtVisitor};
+use crate::token::{Literal, Span, TokenTrait};
+
+/// Output formats of `rlox ast`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AstFormat {
+    /// Parenthesised prefix form, e.g. `(* (- 123) (group 45.67))`
+    Lisp,
+    /// Reverse Polish notation, e.g. `123 neg 45.67 *`
+    Rpn,
+    /// Indented tree with the span of every node
+    Tree,
+    /// Lossless JSON encoding, see `ast_json`
+    Json,
+    /// Graphviz digraph, see `ast_dot`
+    Dot,
+}
+
+impl FromStr for AstFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "lisp" => Ok(AstFormat::Lisp),
+            "rpn" => Ok(AstFormat::Rpn),
+            "tree" => Ok(AstFormat::Tree),
+            "json" => Ok(AstFormat::Json),
+            "dot" => Ok(AstFormat::Dot),
+            _ => Err(s.to_string()),
+        }
+    }
+}
+
+/// Render statements in the given format, one top level statement per line
+pub fn print_ast(statements: &[Stmt], format: AstFormat) -> String {
+    let lines: Vec<String> = match format {
+        AstFormat::Lisp => statements
+            .iter()
+            .map(|s| s.accept(&mut AstPrinter))
+            .collect(),
+        AstFormat::Rpn => statements
+            .iter()
+            .map(|s| s.accept(&mut RpnPrinter))
+            .collect(),
+        AstFormat::Tree => statements
+            .iter()
+            .map(|s| s.accept(&mut TreePrinter))
+            .collect(),
+        AstFormat::Json => return statements_to_json(statements).to_string(),
+        AstFormat::Dot => return print_dot(statements),
+    };
+
+    lines.join("\n")
+}
+
+/// Quote string literals so they can't be confused with identifiers
+fn literal_to_string(literal: &Literal) -> String {
+    match literal {
+        Literal::String(value) => format!("{:?}", value),
+        _ => literal.to_string(),
+    }
+}
+
+/// Prints the parenthesised prefix form used in Crafting Interpreters
+pub struct AstPrinter;
+
+impl AstPrinter {
+    pub fn print(&mut self, expr: &Expr) -> String {
+        expr.accept(self)
+    }
+
+    fn parenthesize(&self, name: &str, parts: &[String]) -> String {
+        let mut output = format!("({name}");
+        for part in parts {
+            output.push(' ');
+            output.push_str(part);
+        }
+        output.push(')');
+
+        output
+    }
+
+    fn function(&mut self, function: &stmt::Function) -> String {
+        let params: Vec<&str> = function.params.iter().map(|p| p.lexeme()).collect();
+        let mut parts = vec![
+            function.name.lexeme().to_string(),
+            format!("({})", params.join(" ")),
+        ];
+        parts.extend(function.body.iter().map(|s| s.accept(self)));
+
+        self.parenthesize("fun", &parts)
+    }
+}
+
+impl ExprVisitor<String> for AstPrinter {
+    fn visit_assign_expr(&mut self, expr: &expr::Assign) -> String {
+        let parts = [expr.name.lexeme().to_string(), expr.value.accept(self)];
+        self.parenthesize("=", &parts)
+    }
+
+    fn visit_binary_expr(&mut self, expr: &expr::Binary) -> String {
+        let parts = [expr.left.accept(self), expr.right.accept(self)];
+        self.parenthesize(expr.operator.lexeme(), &parts)
+    }
+
+    fn visit_call_expr(&mut self, expr: &expr::Call) -> String {
+        let mut parts = vec![expr.callee.accept(self)];
+        parts.extend(expr.arguments.iter().map(|a| a.accept(self)));
+        self.parenthesize("call", &parts)
+    }
+
+    fn visit_get_expr(&mut self, expr: &expr::Get) -> String {
+        let parts = [expr.object.accept(self), expr.name.lexeme().to_string()];
+        self.parenthesize(".", &parts)
+    }
+
+    fn visit_grouping_expr(&mut self, expr: &expr::Grouping) -> String {
+        let parts = [expr.expression.accept(self)];
+        self.parenthesize("group", &parts)
+    }
+
+    fn visit_literal_expr(&mut self, expr: &expr::Literal) -> String {
+        literal_to_string(&expr.value)
+    }
+
+    fn visit_logical_expr(&mut self, expr: &expr::Logical) -> String {
+        let parts = [expr.left.accept(self), expr.right.accept(self)];
+        self.parenthesize(expr.operator.lexeme(), &parts)
+    }
+
+    fn visit_set_expr(&mut self, expr: &expr::Set) -> String {
+        let target = [expr.object.accept(self), expr.name.lexeme().to_string()];
+        let target = self.parenthesize(".", &target);
+        let parts = [target, expr.value.accept(self)];
+        self.parenthesize("=", &parts)
+    }
+
+    fn visit_super_expr(&mut self, expr: &expr::Super) -> String {
+        self.parenthesize("super", &[expr.method.lexeme().to_string()])
+    }
+
+    fn visit_this_expr(&mut self, _expr: &expr::This) -> String {
+        "this".to_string()
+    }
+
+    fn visit_unary_expr(&mut self, expr: &expr::Unary) -> String {
+        let parts = [expr.right.accept(self)];
+        self.parenthesize(expr.operator.lexeme(), &parts)
+    }
+
+    fn visit_variable_expr(&mut self, expr: &expr::Variable) -> String {
+        expr.name.lexeme().to_string()
+    }
+}
+
+impl StmtVisitor<String> for AstPrinter {
+    fn visit_block_stmt(&mut self, stmt: &stmt::Block) -> String {
+        let parts: Vec<String> = stmt.statements.iter().map(|s| s.accept(self)).collect();
+        self.parenthesize("block", &parts)
+    }
+
+    fn visit_class_stmt(&mut self, stmt: &stmt::Class) -> String {
+        let mut parts = vec![stmt.name.lexeme().to_string()];
+        if let Some(superclass) = &stmt.superclass {
+            parts.push("<".to_string());
+            parts.push(superclass.name.lexeme().to_string());
+        }
+        parts.extend(stmt.methods.iter().map(|m| self.function(m)));
+
+        self.parenthesize("class", &parts)
+    }
+
+    fn visit_expression_stmt(&mut self, stmt: &stmt::Expression) -> String {
+        let parts = [stmt.expression.accept(self)];
+        self.parenthesize(";", &parts)
+    }
+
+    fn visit_function_stmt(&mut self, stmt: &Rc<stmt::Function>) -> String {
+        self.function(stmt)
+    }
+
+    fn visit_if_stmt(&mut self, stmt: &stmt::If) -> String {
+        let mut parts = vec![stmt.condition.accept(self), stmt.then_branch.accept(self)];
+        if let Some(else_branch) = &stmt.else_branch {
+            parts.push(else_branch.accept(self));
+        }
+
+        self.parenthesize("if", &parts)
+    }
+
+    fn visit_print_stmt(&mut self, stmt: &stmt::Print) -> String {
+        let parts = [stmt.expression.accept(self)];
+        self.parenthesize("print", &parts)
+    }
+
+    fn visit_return_stmt(&mut self, stmt: &stmt::Return) -> String {
+        let parts: Vec<String> = stmt.value.iter().map(|v| v.accept(self)).collect();
+        self.parenthesize("return", &parts)
+    }
+
+    fn visit_var_stmt(&mut self, stmt: &stmt::Var) -> String {
+        let mut parts = vec![stmt.name.lexeme().to_string()];
+        if let Some(initializer) = &stmt.initializer {
+            parts.push("=".to_string());
+            parts.push(initializer.accept(self));
+        }
+
+        self.parenthesize("var", &parts)
+    }
+
+    fn visit_while_stmt(&mut self, stmt: &stmt::While) -> String {
+        let parts = [stmt.condition.accept(self), stmt.body.accept(self)];
+        self.parenthesize("while", &parts)
+    }
+}
+
+/// Prints operands before their operator, statements end with their keyword
+pub struct RpnPrinter;
+
+impl RpnPrinter {
+    pub fn print(&mut self, expr: &Expr) -> String {
+        expr.accept(self)
+    }
+
+    fn postfix(&self, parts: &[String], operator: &str) -> String {
+        let mut output: Vec<&str> = parts.iter().map(String::as_str).collect();
+        output.push(operator);
+
+        output.join(" ")
+    }
+
+    fn body(&mut self, statements: &[Stmt]) -> String {
+        let statements: Vec<String> = statements.iter().map(|s| s.accept(self)).collect();
+        format!("{{ {} }}", statements.join("; "))
+    }
+
+    fn function(&mut self, function: &stmt::Function) -> String {
+        let params: Vec<&str> = function.params.iter().map(|p| p.lexeme()).collect();
+        let parts = [
+            format!("({})", params.join(" ")),
+            self.body(&function.body),
+            function.name.lexeme().to_string(),
+        ];
+
+        self.postfix(&parts, "fun")
+    }
+}
+
+impl ExprVisitor<String> for RpnPrinter {
+    fn visit_assign_expr(&mut self, expr: &expr::Assign) -> String {
+        let parts = [expr.value.accept(self), expr.name.lexeme().to_string()];
+        self.postfix(&parts, "=")
+    }
+
+    fn visit_binary_expr(&mut self, expr: &expr::Binary) -> String {
+        let parts = [expr.left.accept(self), expr.right.accept(self)];
+        self.postfix(&parts, expr.operator.lexeme())
+    }
+
+    fn visit_call_expr(&mut self, expr: &expr::Call) -> String {
+        let mut parts = vec![expr.callee.accept(self)];
+        parts.extend(expr.arguments.iter().map(|a| a.accept(self)));
+        self.postfix(&parts, &format!("call/{}", expr.arguments.len()))
+    }
+
+    fn visit_get_expr(&mut self, expr: &expr::Get) -> String {
+        let parts = [expr.object.accept(self), expr.name.lexeme().to_string()];
+        self.postfix(&parts, ".")
+    }
+
+    fn visit_grouping_expr(&mut self, expr: &expr::Grouping) -> String {
+        // Evaluation order already encodes the grouping.
+        expr.expression.accept(self)
+    }
+
+    fn visit_literal_expr(&mut self, expr: &expr::Literal) -> String {
+        literal_to_string(&expr.value)
+    }
+
+    fn visit_logical_expr(&mut self, expr: &expr::Logical) -> String {
+        let parts = [expr.left.accept(self), expr.right.accept(self)];
+        self.postfix(&parts, expr.operator.lexeme())
+    }
+
+    fn visit_set_expr(&mut self, expr: &expr::Set) -> String {
+        let parts = [
+            expr.object.accept(self),
+            expr.value.accept(self),
+            expr.name.lexeme().to_string(),
+        ];
+        self.postfix(&parts, ".=")
+    }
+
+    fn visit_super_expr(&mut self, expr: &expr::Super) -> String {
+        self.postfix(&[expr.method.lexeme().to_string()], "super")
+    }
+
+    fn visit_this_expr(&mut self, _expr: &expr::This) -> String {
+        "this".to_string()
+    }
+
+    fn visit_unary_expr(&mut self, expr: &expr::Unary) -> String {
+        // Unary minus gets its own name so it can't be read as a subtraction.
+        let operator = match expr.operator.lexeme() {
+            "-" => "neg",
+            lexeme => lexeme,
+        };
+        let parts = [expr.right.accept(self)];
+        self.postfix(&parts, operator)
+    }
+
+    fn visit_variable_expr(&mut self, expr: &expr::Variable) -> String {
+        expr.name.lexeme().to_string()
+    }
+}
+
+impl StmtVisitor<String> for RpnPrinter {
+    fn visit_block_stmt(&mut self, stmt: &stmt::Block) -> String {
+        self.body(&stmt.statements)
+    }
+
+    fn visit_class_stmt(&mut self, stmt: &stmt::Class) -> String {
+        let mut parts: Vec<String> = stmt.methods.iter().map(|m| self.function(m)).collect();
+        parts.push(stmt.name.lexeme().to_string());
+        if let Some(superclass) = &stmt.superclass {
+            parts.push(superclass.name.lexeme().to_string());
+            parts.push("<".to_string());
+        }
+
+        self.postfix(&parts, "class")
+    }
+
+    fn visit_expression_stmt(&mut self, stmt: &stmt::Expression) -> String {
+        stmt.expression.accept(self)
+    }
+
+    fn visit_function_stmt(&mut self, stmt: &Rc<stmt::Function>) -> String {
+        self.function(stmt)
+    }
+
+    fn visit_if_stmt(&mut self, stmt: &stmt::If) -> String {
+        let mut parts = vec![stmt.condition.accept(self), stmt.then_branch.accept(self)];
+        match &stmt.else_branch {
+            Some(else_branch) => {
+                parts.push(else_branch.accept(self));
+                self.postfix(&parts, "if-else")
+            }
+            None => self.postfix(&parts, "if"),
+        }
+    }
+
+    fn visit_print_stmt(&mut self, stmt: &stmt::Print) -> String {
+        let parts = [stmt.expression.accept(self)];
+        self.postfix(&parts, "print")
+    }
+
+    fn visit_return_stmt(&mut self, stmt: &stmt::Return) -> String {
+        let parts: Vec<String> = stmt.value.iter().map(|v| v.accept(self)).collect();
+        self.postfix(&parts, "return")
+    }
+
+    fn visit_var_stmt(&mut self, stmt: &stmt::Var) -> String {
+        let mut parts: Vec<String> = stmt.initializer.iter().map(|i| i.accept(self)).collect();
+        parts.push(stmt.name.lexeme().to_string());
+        self.postfix(&parts, "var")
+    }
+
+    fn visit_while_stmt(&mut self, stmt: &stmt::While) -> String {
+        let parts = [stmt.condition.accept(self), stmt.body.accept(self)];
+        self.postfix(&parts, "while")
+    }
+}
+
+/// Prints one node per line, children indented below their parent
+pub struct TreePrinter;
+
+impl TreePrinter {
+    pub fn print(&mut self, expr: &Expr) -> String {
+        expr.accept(self)
+    }
+
+    fn leaf(&self, label: &str, span: Span) -> String {
+        format!("{label} [{}:{}]", span.line, span.column)
+    }
+
+    /// Render a node label followed by its children, each prefixed with its field name
+    fn node<F: AsRef<str>>(&self, label: &str, span: Span, children: &[(F, String)]) -> String {
+        let mut output = self.leaf(label, span);
+
+        for (field, child) in children {
+            let field = field.as_ref();
+            let mut lines = child.lines();
+            if let Some(first) = lines.next() {
+                output.push_str(&format!("\n  {field}: {first}"));
+            }
+            for line in lines {
+                output.push_str(&format!("\n  {line}"));
+            }
+        }
+
+        output
+    }
+
+    fn statements(&mut self, field: &str, statements: &[Stmt]) -> Vec<(String, String)> {
+        statements
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (format!("{field}[{i}]"), s.accept(self)))
+            .collect()
+    }
+
+    fn function(&mut self, function: &stmt::Function) -> String {
+        let params: Vec<&str> = function.params.iter().map(|p| p.lexeme()).collect();
+        let label = format!("Function {}({})", function.name.lexeme(), params.join(", "));
+        let body = self.statements("body", &function.body);
+
+        self.node(&label, function.name.span(), &body)
+    }
+}
+
+impl ExprVisitor<String> for TreePrinter {
+    fn visit_assign_expr(&mut self, expr: &expr::Assign) -> String {
+        let label = format!("Assign {}", expr.name.lexeme());
+        let children = [("value", expr.value.accept(self))];
+        self.node(&label, expr.name.span(), &children)
+    }
+
+    fn visit_binary_expr(&mut self, expr: &expr::Binary) -> String {
+        let label = format!("Binary '{}'", expr.operator.lexeme());
+        let children = [
+            ("left", expr.left.accept(self)),
+            ("right", expr.right.accept(self)),
+        ];
+        self.node(&label, expr.operator.span(), &children)
+    }
+
+    fn visit_call_expr(&mut self, expr: &expr::Call) -> String {
+        let mut children = vec![("callee".to_string(), expr.callee.accept(self))];
+        for (i, argument) in expr.arguments.iter().enumerate() {
+            children.push((format!("arguments[{i}]"), argument.accept(self)));
+        }
+
+        self.node("Call", expr.paren.span(), &children)
+    }
+
+    fn visit_get_expr(&mut self, expr: &expr::Get) -> String {
+        let label = format!("Get {}", expr.name.lexeme());
+        let children = [("object", expr.object.accept(self))];
+        self.node(&label, expr.name.span(), &children)
+    }
+
+    fn visit_grouping_expr(&mut self, expr: &expr::Grouping) -> String {
+        let children = [("expression", expr.expression.accept(self))];
+        self.node("Grouping", expr.span, &children)
+    }
+
+    fn visit_literal_expr(&mut self, expr: &expr::Literal) -> String {
+        let label = format!("Literal {}", literal_to_string(&expr.value));
+        self.leaf(&label, expr.span)
+    }
+
+    fn visit_logical_expr(&mut self, expr: &expr::Logical) -> String {
+        let label = format!("Logical '{}'", expr.operator.lexeme());
+        let children = [
+            ("left", expr.left.accept(self)),
+            ("right", expr.right.accept(self)),
+        ];
+        self.node(&label, expr.operator.span(), &children)
+    }
+
+    fn visit_set_expr(&mut self, expr: &expr::Set) -> String {
+        let label = format!("Set {}", expr.name.lexeme());
+        let children = [
+            ("object", expr.object.accept(self)),
+            ("value", expr.value.accept(self)),
+        ];
+        self.node(&label, expr.name.span(), &children)
+    }
+
+    fn visit_super_expr(&mut self, expr: &expr::Super) -> String {
+        let label = format!("Super {}", expr.method.lexeme());
+        self.leaf(&label, expr.keyword.span())
+    }
+
+    fn visit_this_expr(&mut self, expr: &expr::This) -> String {
+        self.leaf("This", expr.keyword.span())
+    }
+
+    fn visit_unary_expr(&mut self, expr: &expr::Unary) -> String {
+        let label = format!("Unary '{}'", expr.operator.lexeme());
+        let children = [("right", expr.right.accept(self))];
+        self.node(&label, expr.operator.span(), &children)
+    }
+
+    fn visit_variable_expr(&mut self, expr: &expr::Variable) -> String {
+        let label = format!("Variable {}", expr.name.lexeme());
+        self.leaf(&label, expr.name.span())
+    }
+}
+
+impl StmtVisitor<String> for TreePrinter {
+    fn visit_block_stmt(&mut self, stmt: &stmt::Block) -> String {
+        let statements = self.statements("statements", &stmt.statements);
+
+        self.node("Block", stmt.span, &statements)
+    }
+
+    fn visit_class_stmt(&mut self, stmt: &stmt::Class) -> String {
+        let label = format!("Class {}", stmt.name.lexeme());
+        let mut children = Vec::new();
+        if let Some(superclass) = &stmt.superclass {
+            children.push((
+                "superclass".to_string(),
+                self.visit_variable_expr(superclass),
+            ));
+        }
+        for (i, method) in stmt.methods.iter().enumerate() {
+            children.push((format!("methods[{i}]"), self.function(method)));
+        }
+
+        self.node(&label, stmt.name.span(), &children)
+    }
+
+    fn visit_expression_stmt(&mut self, stmt: &stmt::Expression) -> String {
+        let children = [("expression", stmt.expression.accept(self))];
+        self.node("Expression", stmt.expression.span(), &children)
+    }
+
+    fn visit_function_stmt(&mut self, stmt: &Rc<stmt::Function>) -> String {
+        self.function(stmt)
+    }
+
+    fn visit_if_stmt(&mut self, stmt: &stmt::If) -> String {
+        let mut children = vec![
+            ("condition", stmt.condition.accept(self)),
+            ("then", stmt.then_branch.accept(self)),
+        ];
+        if let Some(else_branch) = &stmt.else_branch {
+            children.push(("else", else_branch.accept(self)));
+        }
+
+        self.node("If", stmt.span, &children)
+    }
+
+    fn visit_print_stmt(&mut self, stmt: &stmt::Print) -> String {
+        let children = [("expression", stmt.expression.accept(self))];
+        self.node("Print", stmt.span, &children)
+    }
+
+    fn visit_return_stmt(&mut self, stmt: &stmt::Return) -> String {
+        let children: Vec<(&str, String)> = stmt
+            .value
+            .iter()
+            .map(|v| ("value", v.accept(self)))
+            .collect();
+        self.node("Return", stmt.keyword.span(), &children)
+    }
+
+    fn visit_var_stmt(&mut self, stmt: &stmt::Var) -> String {
+        let label = format!("Var {}", stmt.name.lexeme());
+        let children: Vec<(&str, String)> = stmt
+            .initializer
+            .iter()
+            .map(|i| ("initializer", i.accept(self)))
+            .collect();
+        self.node(&label, stmt.name.span(), &children)
+    }
+
+    fn visit_while_stmt(&mut self, stmt: &stmt::While) -> String {
+        let children = [
+            ("condition", stmt.condition.accept(self)),
+            ("body", stmt.body.accept(self)),
+        ];
+        self.node("While", stmt.span, &children)
+    }
+}
@@ -0,0 +1,57 @@
+//! Bytecode backend: a single-pass compiler from tokens into chunks of
+//! instructions, the tools to inspect them and a stack machine running them
+
+pub mod chunk;
+pub mod compiler;
+pub mod disassembler;
+pub mod gc;
+pub mod object;
+pub mod runtime;
+pub mod serializer;
+pub mod shape;
+pub mod value;
+pub mod verifier;
+pub mod vm;
+
+use crate::bytecode::compiler::{Compiler, CompilerTrait};
+use crate::bytecode::object::{Heap, ObjRef};
+use crate::bytecode::vm::Vm;
+use crate::lox::LoxError;
+use crate::optimizer::OptLevel;
+use crate::scanner::{Scanner, ScannerTrait};
+
+/// Scan and compile a program into its top-level script function
+pub fn compile(source: &str, heap: &mut Heap) -> Result<ObjRef, LoxError> {
+    compile_with(source, heap, OptLevel::None)
+}
+
+/// Compile a program with the optimisations of `opt_level`
+pub fn compile_with(
+    source: &str,
+    heap: &mut Heap,
+    opt_level: OptLevel,
+) -> Result<ObjRef, LoxError> {
+    let tokens = Scanner::new(source.to_string())
+        .scan_tokens()
+        .map_err(LoxError::ScannerErrors)?;
+
+    let mut compiler = Compiler::new(tokens, heap);
+    compiler.set_opt_level(opt_level);
+    compiler.compile().map_err(LoxError::CompileErrors)
+}
+
+/// Read a precompiled script and verify it before it can run
+pub fn load(bytes: &[u8], heap: &mut Heap) -> Result<ObjRef, LoxError> {
+    let script = serializer::deserialize(bytes, heap)?;
+    verifier::verify(script, heap)?;
+
+    Ok(script)
+}
+
+/// Compile and run a program on the virtual machine
+pub fn interpret(source: &str, vm: &mut Vm) -> Result<(), LoxError> {
+    let script = compile(source, vm.heap_mut())?;
+    vm.run(script)?;
+
+    Ok(())
+}
@@ -0,0 +1,250 @@
+use std::fmt::Display;
+
+use crate::bytecode::value::Value;
+
+/// Instructions of the stack machine, operands follow the opcode as bytes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum OpCode {
+    /// Push the constant at the index in the next byte
+    Constant,
+    Null,
+    True,
+    False,
+    Pop,
+    /// Local slot in the next byte
+    GetLocal,
+    SetLocal,
+    /// Constant index of the name in the next byte
+    GetGlobal,
+    DefineGlobal,
+    SetGlobal,
+    /// Index into the closure's upvalues in the next byte
+    GetUpvalue,
+    SetUpvalue,
+    /// Constant index of the property name in the next byte
+    GetProperty,
+    SetProperty,
+    /// Constant index of the method name in the next byte
+    GetSuper,
+    Equal,
+    Greater,
+    Less,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Not,
+    Negate,
+    Print,
+    /// Forward jump by the 16-bit offset in the next two bytes
+    Jump,
+    JumpIfFalse,
+    /// Backward jump by the 16-bit offset in the next two bytes
+    Loop,
+    /// Argument count in the next byte
+    Call,
+    /// Constant index of the method name, then the argument count
+    Invoke,
+    SuperInvoke,
+    /// Constant index of the function, then an `is_local, index` byte pair per upvalue
+    Closure,
+    /// Move the local on top of the stack into its upvalue and pop it
+    CloseUpvalue,
+    Return,
+    /// Constant index of the class name in the next byte
+    Class,
+    Inherit,
+    /// Constant index of the method name in the next byte
+    Method,
+}
+
+impl OpCode {
+    const ALL: [OpCode; 37] = [
+        OpCode::Constant,
+        OpCode::Null,
+        OpCode::True,
+        OpCode::False,
+        OpCode::Pop,
+        OpCode::GetLocal,
+        OpCode::SetLocal,
+        OpCode::GetGlobal,
+        OpCode::DefineGlobal,
+        OpCode::SetGlobal,
+        OpCode::GetUpvalue,
+        OpCode::SetUpvalue,
+        OpCode::GetProperty,
+        OpCode::SetProperty,
+        OpCode::GetSuper,
+        OpCode::Equal,
+        OpCode::Greater,
+        OpCode::Less,
+        OpCode::Add,
+        OpCode::Subtract,
+        OpCode::Multiply,
+        OpCode::Divide,
+        OpCode::Not,
+        OpCode::Negate,
+        OpCode::Print,
+        OpCode::Jump,
+        OpCode::JumpIfFalse,
+        OpCode::Loop,
+        OpCode::Call,
+        OpCode::Invoke,
+        OpCode::SuperInvoke,
+        OpCode::Closure,
+        OpCode::CloseUpvalue,
+        OpCode::Return,
+        OpCode::Class,
+        OpCode::Inherit,
+        OpCode::Method,
+    ];
+}
+
+/// Decode an opcode, returning the byte back when it is not one
+impl TryFrom<u8> for OpCode {
+    type Error = u8;
+
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        OpCode::ALL.get(byte as usize).copied().ok_or(byte)
+    }
+}
+
+/// The name used by the disassembler, e.g. `OP_CONSTANT`
+impl Display for OpCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            OpCode::Constant => "OP_CONSTANT",
+            OpCode::Null => "OP_NULL",
+            OpCode::True => "OP_TRUE",
+            OpCode::False => "OP_FALSE",
+            OpCode::Pop => "OP_POP",
+            OpCode::GetLocal => "OP_GET_LOCAL",
+            OpCode::SetLocal => "OP_SET_LOCAL",
+            OpCode::GetGlobal => "OP_GET_GLOBAL",
+            OpCode::DefineGlobal => "OP_DEFINE_GLOBAL",
+            OpCode::SetGlobal => "OP_SET_GLOBAL",
+            OpCode::GetUpvalue => "OP_GET_UPVALUE",
+            OpCode::SetUpvalue => "OP_SET_UPVALUE",
+            OpCode::GetProperty => "OP_GET_PROPERTY",
+            OpCode::SetProperty => "OP_SET_PROPERTY",
+            OpCode::GetSuper => "OP_GET_SUPER",
+            OpCode::Equal => "OP_EQUAL",
+            OpCode::Greater => "OP_GREATER",
+            OpCode::Less => "OP_LESS",
+            OpCode::Add => "OP_ADD",
+            OpCode::Subtract => "OP_SUBTRACT",
+            OpCode::Multiply => "OP_MULTIPLY",
+            OpCode::Divide => "OP_DIVIDE",
+            OpCode::Not => "OP_NOT",
+            OpCode::Negate => "OP_NEGATE",
+            OpCode::Print => "OP_PRINT",
+            OpCode::Jump => "OP_JUMP",
+            OpCode::JumpIfFalse => "OP_JUMP_IF_FALSE",
+            OpCode::Loop => "OP_LOOP",
+            OpCode::Call => "OP_CALL",
+            OpCode::Invoke => "OP_INVOKE",
+            OpCode::SuperInvoke => "OP_SUPER_INVOKE",
+            OpCode::Closure => "OP_CLOSURE",
+            OpCode::CloseUpvalue => "OP_CLOSE_UPVALUE",
+            OpCode::Return => "OP_RETURN",
+            OpCode::Class => "OP_CLASS",
+            OpCode::Inherit => "OP_INHERIT",
+            OpCode::Method => "OP_METHOD",
+        };
+
+        f.pad(name)
+    }
+}
+
+/// A run of consecutive bytes compiled from the same source line
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineRun {
+    pub line: u32,
+    pub count: u32,
+}
+
+/// Compiled code of one function with the constants it refers to
+#[derive(Debug, Clone, Default)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    pub constants: Vec<Value>,
+    /// Source line of every byte of `code`, run-length encoded
+    lines: Vec<LineRun>,
+}
+
+impl Chunk {
+    pub fn new() -> Chunk {
+        Chunk::default()
+    }
+
+    /// A chunk read back from a file, the line table is taken as is
+    pub fn from_parts(code: Vec<u8>, constants: Vec<Value>, lines: Vec<LineRun>) -> Chunk {
+        Chunk {
+            code,
+            constants,
+            lines,
+        }
+    }
+
+    pub fn write(&mut self, byte: u8, line: u32) {
+        self.code.push(byte);
+
+        match self.lines.last_mut() {
+            Some(run) if run.line == line => run.count += 1,
+            _ => self.lines.push(LineRun { line, count: 1 }),
+        }
+    }
+
+    /// Drop the code from `length` on, with its lines
+    pub fn truncate(&mut self, length: usize) {
+        let mut removed = self.code.len().saturating_sub(length);
+        self.code.truncate(length);
+
+        while let Some(run) = self.lines.last_mut() {
+            if removed < run.count as usize {
+                run.count -= removed as u32;
+                break;
+            }
+            removed -= run.count as usize;
+            self.lines.pop();
+        }
+    }
+
+    pub fn write_op(&mut self, op: OpCode, line: u32) {
+        self.write(op as u8, line);
+    }
+
+    /// Add a constant, reusing an identical one already in the pool, and
+    /// return its index. Numbers compare by their bits, so `-0` and `0`
+    /// stay apart.
+    pub fn add_constant(&mut self, value: Value) -> usize {
+        let identical = |constant: &Value| match (constant.as_number(), value.as_number()) {
+            (Some(a), Some(b)) => a.to_bits() == b.to_bits(),
+            _ => *constant == value,
+        };
+        if let Some(index) = self.constants.iter().position(identical) {
+            return index;
+        }
+
+        self.constants.push(value);
+        self.constants.len() - 1
+    }
+
+    /// Source line of the byte at `offset`
+    pub fn line(&self, offset: usize) -> u32 {
+        let mut end = 0;
+        for run in &self.lines {
+            end += run.count as usize;
+            if offset < end {
+                return run.line;
+            }
+        }
+
+        0
+    }
+
+    pub fn lines(&self) -> &[LineRun] {
+        &self.lines
+    }
+}
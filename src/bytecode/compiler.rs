@@ -0,0 +1,1556 @@
+use crate::bytecode::chunk::{Chunk, OpCode};
+use crate::bytecode::object::{Heap, ObjFunction, ObjRef, Object};
+use crate::bytecode::shape::InlineCache;
+use crate::bytecode::value::Value;
+use crate::optimizer::OptLevel;
+use crate::parser::{MAX_ARITY, MAX_NESTING, MAX_OPERATORS};
+use crate::token::{Literal, Span, Token, TokenTrait, TokenType};
+
+/// Locals are addressed by a one byte slot
+const MAX_LOCALS: usize = 256;
+/// Constants are addressed by a one byte index
+const MAX_CONSTANTS: usize = 256;
+/// Upvalues are addressed by a one byte index
+const MAX_UPVALUES: usize = 256;
+/// Inline caches are addressed by a two byte index
+const MAX_CACHES: usize = u16::MAX as usize + 1;
+
+/// Single-pass compiler turning tokens straight into bytecode
+#[derive(Debug)]
+pub struct Compiler<'h> {
+    tokens: Vec<Token>,
+    current: usize,
+    heap: &'h mut Heap,
+    /// Functions being compiled, the innermost last
+    functions: Vec<FunctionState>,
+    /// Classes being compiled, the innermost last
+    classes: Vec<ClassState>,
+    errors: Vec<CompileError>,
+    opt_level: OptLevel,
+    /// Declarations and expressions being compiled inside each other
+    depth: usize,
+    /// Binary and logical operators in the expressions of the statement
+    /// being compiled, which can't contain statements of their own
+    operators: usize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FunctionKind {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+#[derive(Debug)]
+struct FunctionState {
+    function: ObjFunction,
+    kind: FunctionKind,
+    locals: Vec<Local>,
+    /// Variables captured from enclosing functions, in the closure's order
+    upvalues: Vec<Upvalue>,
+    scope_depth: usize,
+    /// Offset of every instruction emitted, for the peephole optimiser
+    starts: Vec<usize>,
+    /// Offset of every `CONSTANT` that added its value to the pool, which
+    /// nothing else uses yet, so the value goes if the instruction does
+    fresh_constants: Vec<usize>,
+    /// The last offset a jump lands on, instructions before it can't be
+    /// merged with the ones after
+    label: usize,
+}
+
+#[derive(Debug)]
+struct Local {
+    name: String,
+    /// `None` while the variable's initializer is being compiled
+    depth: Option<usize>,
+    /// Whether a closure captures the variable, so leaving its scope must
+    /// close the upvalue instead of just popping it
+    is_captured: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Upvalue {
+    /// Local slot in the enclosing function when `is_local`, otherwise the
+    /// index of one of its upvalues
+    index: u8,
+    is_local: bool,
+}
+
+#[derive(Debug)]
+struct ClassState {
+    has_superclass: bool,
+}
+
+/// Binding power of operators, lowest first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Precedence {
+    None,
+    Assignment,
+    Or,
+    And,
+    Equality,
+    Comparison,
+    Term,
+    Factor,
+    Unary,
+    Call,
+    Primary,
+}
+
+impl Precedence {
+    fn next(self) -> Precedence {
+        match self {
+            Precedence::None => Precedence::Assignment,
+            Precedence::Assignment => Precedence::Or,
+            Precedence::Or => Precedence::And,
+            Precedence::And => Precedence::Equality,
+            Precedence::Equality => Precedence::Comparison,
+            Precedence::Comparison => Precedence::Term,
+            Precedence::Term => Precedence::Factor,
+            Precedence::Factor => Precedence::Unary,
+            Precedence::Unary => Precedence::Call,
+            Precedence::Call | Precedence::Primary => Precedence::Primary,
+        }
+    }
+
+    /// Precedence of a token used as an infix operator
+    fn of(token_type: TokenType) -> Precedence {
+        match token_type {
+            TokenType::LeftParen | TokenType::Dot => Precedence::Call,
+            TokenType::Minus | TokenType::Plus => Precedence::Term,
+            TokenType::Slash | TokenType::Star => Precedence::Factor,
+            TokenType::BangEqual | TokenType::EqualEqual => Precedence::Equality,
+            TokenType::Greater
+            | TokenType::GreaterEqual
+            | TokenType::Less
+            | TokenType::LessEqual => Precedence::Comparison,
+            TokenType::And => Precedence::And,
+            TokenType::Or => Precedence::Or,
+            _ => Precedence::None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum CompileError {
+    #[error("expected {0}, found {1} at {2}")]
+    UnexpectedToken(String, String, Span),
+    #[error("invalid assignment target at {0}")]
+    InvalidAssignmentTarget(Span),
+    #[error("can't have more than 255 arguments at {0}")]
+    TooManyArguments(Span),
+    #[error("can't have more than 255 parameters at {0}")]
+    TooManyParameters(Span),
+    #[error("too many constants in one chunk at {0}")]
+    TooManyConstants(Span),
+    #[error("too many local variables in function at {0}")]
+    TooManyLocals(Span),
+    #[error("too many closure variables in function at {0}")]
+    TooManyUpvalues(Span),
+    #[error("too many property accesses in function at {0}")]
+    TooManyCaches(Span),
+    #[error("too much code to jump over at {0}")]
+    JumpTooLarge(Span),
+    #[error("loop body too large at {0}")]
+    LoopTooLarge(Span),
+    #[error("can't read local variable '{0}' in its own initializer at {1}")]
+    ReadInOwnInitializer(String, Span),
+    #[error("variable '{0}' is already declared in this scope at {1}")]
+    AlreadyDeclared(String, Span),
+    #[error("can't return from top-level code at {0}")]
+    TopLevelReturn(Span),
+    #[error("can't return a value from an initializer at {0}")]
+    ReturnFromInitializer(Span),
+    #[error("can't use 'this' outside of a class at {0}")]
+    ThisOutsideClass(Span),
+    #[error("can't use 'super' outside of a class at {0}")]
+    SuperOutsideClass(Span),
+    #[error("can't use 'super' in a class with no superclass at {0}")]
+    SuperWithoutSuperclass(Span),
+    #[error("a class can't inherit from itself at {0}")]
+    InheritFromSelf(Span),
+    #[error("expression needs too many registers at {0}")]
+    TooManyRegisters(Span),
+    #[error("{0} nested too deeply at {1}")]
+    NestedTooDeeply(&'static str, Span),
+    #[error("too many operators in one expression at {0}")]
+    TooManyOperators(Span),
+}
+
+impl CompileError {
+    pub fn span(&self) -> Span {
+        match self {
+            CompileError::UnexpectedToken(_, _, span)
+            | CompileError::InvalidAssignmentTarget(span)
+            | CompileError::TooManyArguments(span)
+            | CompileError::TooManyParameters(span)
+            | CompileError::TooManyConstants(span)
+            | CompileError::TooManyLocals(span)
+            | CompileError::TooManyUpvalues(span)
+            | CompileError::TooManyCaches(span)
+            | CompileError::JumpTooLarge(span)
+            | CompileError::LoopTooLarge(span)
+            | CompileError::ReadInOwnInitializer(_, span)
+            | CompileError::AlreadyDeclared(_, span)
+            | CompileError::TopLevelReturn(span)
+            | CompileError::ReturnFromInitializer(span)
+            | CompileError::ThisOutsideClass(span)
+            | CompileError::SuperOutsideClass(span)
+            | CompileError::SuperWithoutSuperclass(span)
+            | CompileError::InheritFromSelf(span)
+            | CompileError::TooManyRegisters(span)
+            | CompileError::NestedTooDeeply(_, span)
+            | CompileError::TooManyOperators(span) => *span,
+        }
+    }
+}
+
+pub trait CompilerTrait<'h> {
+    fn new(tokens: Vec<Token>, heap: &'h mut Heap) -> Self;
+    /// Compile every declaration into the top-level script function,
+    /// recovering from errors so all of them are reported
+    fn compile(self) -> Result<ObjRef, Vec<CompileError>>;
+    /// Fold constants and drop dead code while emitting, see `OptLevel`
+    fn set_opt_level(&mut self, opt_level: OptLevel);
+    /// Compile a declaration, on error record it and skip to the next statement
+    fn declaration(&mut self);
+    fn class_declaration(&mut self) -> Result<(), CompileError>;
+    fn method(&mut self) -> Result<(), CompileError>;
+    fn fun_declaration(&mut self) -> Result<(), CompileError>;
+    /// Compile a function body into its own function object and load it
+    fn function(&mut self, kind: FunctionKind) -> Result<(), CompileError>;
+    fn var_declaration(&mut self) -> Result<(), CompileError>;
+    fn statement(&mut self) -> Result<(), CompileError>;
+    fn for_statement(&mut self) -> Result<(), CompileError>;
+    fn if_statement(&mut self) -> Result<(), CompileError>;
+    fn print_statement(&mut self) -> Result<(), CompileError>;
+    fn return_statement(&mut self) -> Result<(), CompileError>;
+    fn while_statement(&mut self) -> Result<(), CompileError>;
+    /// Compile the declarations of a block, the opening brace is already consumed
+    fn block(&mut self) -> Result<(), CompileError>;
+    fn expression_statement(&mut self) -> Result<(), CompileError>;
+    fn expression(&mut self) -> Result<(), CompileError>;
+    /// Compile an expression whose operators bind at least as tightly as `precedence`
+    fn parse_precedence(&mut self, precedence: Precedence) -> Result<(), CompileError>;
+    /// Compile the expression starting with the previous token
+    fn prefix(&mut self, token_type: TokenType, can_assign: bool) -> Result<(), CompileError>;
+    fn infix(&mut self, token_type: TokenType, can_assign: bool) -> Result<(), CompileError>;
+    fn binary(&mut self) -> Result<(), CompileError>;
+    fn unary(&mut self) -> Result<(), CompileError>;
+    fn and(&mut self) -> Result<(), CompileError>;
+    fn or(&mut self) -> Result<(), CompileError>;
+    fn call(&mut self) -> Result<(), CompileError>;
+    fn dot(&mut self, can_assign: bool) -> Result<(), CompileError>;
+    fn argument_list(&mut self) -> Result<u8, CompileError>;
+    fn literal(&mut self) -> Result<(), CompileError>;
+    fn this(&mut self) -> Result<(), CompileError>;
+    fn super_(&mut self) -> Result<(), CompileError>;
+    /// Load or assign a local or global variable
+    fn named_variable(&mut self, name: &Token, can_assign: bool) -> Result<(), CompileError>;
+    fn resolve_local(&mut self, function: usize, name: &Token) -> Option<u8>;
+    fn resolve_upvalue(&mut self, function: usize, name: &Token) -> Option<u8>;
+    fn add_upvalue(&mut self, function: usize, index: u8, is_local: bool, name: &Token) -> u8;
+    /// Declare a variable and return its name constant, zero for locals
+    fn parse_variable(&mut self, expected: &str) -> Result<u8, CompileError>;
+    fn declare_variable(&mut self, name: &Token);
+    fn define_variable(&mut self, global: u8);
+    fn add_local(&mut self, name: &str);
+    /// Make the innermost local usable
+    fn mark_initialized(&mut self);
+    fn begin_scope(&mut self);
+    fn end_scope(&mut self);
+    fn identifier_constant(&mut self, name: &Token) -> u8;
+    fn make_constant(&mut self, value: Value) -> u8;
+    fn emit_op(&mut self, op: OpCode);
+    fn emit_op_byte(&mut self, op: OpCode, byte: u8);
+    fn emit_constant(&mut self, value: Value);
+    /// Emit a forward jump with a placeholder offset and return its position
+    fn emit_jump(&mut self, op: OpCode) -> usize;
+    fn patch_jump(&mut self, offset: usize);
+    fn emit_loop(&mut self, loop_start: usize);
+    fn emit_return(&mut self);
+    /// Consume the current token if it has the given type
+    fn token_match(&mut self, token_type: TokenType) -> bool;
+    /// Consume a token of the given type or fail with "expected {expected}"
+    fn consume(&mut self, token_type: TokenType, expected: &str) -> Result<Token, CompileError>;
+    fn check(&self, token_type: TokenType) -> bool;
+    fn advance(&mut self) -> Token;
+    fn is_at_end(&self) -> bool;
+    fn peek(&self) -> &Token;
+    fn previous(&self) -> &Token;
+    /// Build an "expected X, found Y" error at the given token
+    fn error_at(&self, token: &Token, expected: &str) -> CompileError;
+    /// Run `compile` one level deeper, failing past `MAX_NESTING`; `what`
+    /// names the construct in the error
+    fn nested(
+        &mut self,
+        what: &'static str,
+        compile: impl FnOnce(&mut Self) -> Result<(), CompileError>,
+    ) -> Result<(), CompileError>;
+    /// Count the binary or logical operator just consumed, failing past
+    /// `MAX_OPERATORS` like the parser
+    fn operator(&mut self) -> Result<(), CompileError>;
+    /// Discard tokens until the start of the next statement
+    fn synchronize(&mut self);
+}
+
+impl<'h> CompilerTrait<'h> for Compiler<'h> {
+    fn new(tokens: Vec<Token>, heap: &'h mut Heap) -> Compiler<'h> {
+        let mut compiler = Compiler {
+            tokens,
+            current: 0,
+            heap,
+            functions: Vec::new(),
+            classes: Vec::new(),
+            errors: Vec::new(),
+            opt_level: OptLevel::None,
+            depth: 0,
+            operators: 0,
+        };
+        compiler
+            .functions
+            .push(FunctionState::new(FunctionKind::Script, None));
+        compiler
+    }
+
+    fn compile(mut self) -> Result<ObjRef, Vec<CompileError>> {
+        while !self.is_at_end() {
+            self.declaration();
+        }
+        self.emit_return();
+
+        if !self.errors.is_empty() {
+            return Err(self.errors);
+        }
+
+        let script = self.functions.pop().expect("script function");
+        Ok(self.heap.alloc(Object::Function(script.function)))
+    }
+
+    fn set_opt_level(&mut self, opt_level: OptLevel) {
+        self.opt_level = opt_level;
+    }
+
+    fn declaration(&mut self) {
+        self.operators = 0;
+        let result = self.nested("statement", |compiler| {
+            if compiler.token_match(TokenType::Class) {
+                compiler.class_declaration()
+            } else if compiler.token_match(TokenType::Fun) {
+                compiler.fun_declaration()
+            } else if compiler.token_match(TokenType::Var) {
+                compiler.var_declaration()
+            } else {
+                compiler.statement()
+            }
+        });
+
+        if let Err(e) = result {
+            self.errors.push(e);
+            self.synchronize();
+        }
+    }
+
+    fn class_declaration(&mut self) -> Result<(), CompileError> {
+        let name = self.consume(TokenType::Identifier, "class name")?;
+        let name_constant = self.identifier_constant(&name);
+        self.declare_variable(&name);
+
+        self.emit_op_byte(OpCode::Class, name_constant);
+        self.define_variable(name_constant);
+
+        self.classes.push(ClassState {
+            has_superclass: false,
+        });
+        let result = self.class_body(&name);
+        let class = self.classes.pop().expect("class being compiled");
+
+        if class.has_superclass {
+            self.end_scope();
+        }
+        result
+    }
+
+    fn method(&mut self) -> Result<(), CompileError> {
+        let name = self.consume(TokenType::Identifier, "method name")?;
+        let constant = self.identifier_constant(&name);
+
+        let kind = if name.lexeme() == "init" {
+            FunctionKind::Initializer
+        } else {
+            FunctionKind::Method
+        };
+        self.function(kind)?;
+        self.emit_op_byte(OpCode::Method, constant);
+
+        Ok(())
+    }
+
+    fn fun_declaration(&mut self) -> Result<(), CompileError> {
+        let global = self.parse_variable("function name")?;
+        // Usable in its own body, so the function can call itself recursively.
+        self.mark_initialized();
+        self.function(FunctionKind::Function)?;
+        self.define_variable(global);
+
+        Ok(())
+    }
+
+    fn function(&mut self, kind: FunctionKind) -> Result<(), CompileError> {
+        let name = self.previous().clone();
+        let name_string = self.heap.intern(name.lexeme());
+        self.functions
+            .push(FunctionState::new(kind, Some(name_string)));
+        self.begin_scope();
+
+        let result = self.function_body(kind);
+
+        // The function's scope is discarded with it, no need to end it.
+        let mut state = self.functions.pop().expect("function being compiled");
+        result?;
+
+        state.function.upvalue_count = state.upvalues.len();
+        let function = self.heap.alloc(Object::Function(state.function));
+        let constant = self.make_constant(Value::object(function));
+        self.emit_op_byte(OpCode::Closure, constant);
+
+        for upvalue in state.upvalues {
+            self.chunk_write(upvalue.is_local as u8);
+            self.chunk_write(upvalue.index);
+        }
+
+        Ok(())
+    }
+
+    fn var_declaration(&mut self) -> Result<(), CompileError> {
+        let global = self.parse_variable("variable name")?;
+
+        if self.token_match(TokenType::Equal) {
+            self.expression()?;
+        } else {
+            self.emit_op(OpCode::Null);
+        }
+        self.consume(TokenType::Semicolon, "';' after variable declaration")?;
+
+        self.define_variable(global);
+
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), CompileError> {
+        self.operators = 0;
+        if self.token_match(TokenType::For) {
+            return self.for_statement();
+        }
+        if self.token_match(TokenType::If) {
+            return self.if_statement();
+        }
+        if self.token_match(TokenType::Print) {
+            return self.print_statement();
+        }
+        if self.token_match(TokenType::Return) {
+            return self.return_statement();
+        }
+        if self.token_match(TokenType::While) {
+            return self.while_statement();
+        }
+        if self.token_match(TokenType::LeftBrace) {
+            self.begin_scope();
+            let result = self.block();
+            self.end_scope();
+            return result;
+        }
+
+        self.expression_statement()
+    }
+
+    fn for_statement(&mut self) -> Result<(), CompileError> {
+        self.begin_scope();
+        let result = self.for_clauses();
+        self.end_scope();
+
+        result
+    }
+
+    fn if_statement(&mut self) -> Result<(), CompileError> {
+        self.consume(TokenType::LeftParen, "'(' after 'if'")?;
+        let condition_start = self.chunk().code.len();
+        self.expression()?;
+        self.consume(TokenType::RightParen, "')' after if condition")?;
+
+        // Both branches are compiled for their errors, the one that can't run is dropped.
+        if let Some(truthy) = self.constant_condition(condition_start) {
+            let then_start = self.chunk().code.len();
+            self.statement()?;
+            if !truthy {
+                self.truncate(then_start);
+            }
+
+            if self.token_match(TokenType::Else) {
+                let else_start = self.chunk().code.len();
+                self.statement()?;
+                if truthy {
+                    self.truncate(else_start);
+                }
+            }
+            return Ok(());
+        }
+
+        let then_jump = self.emit_condition_jump();
+        self.emit_op(OpCode::Pop);
+        self.statement()?;
+
+        let else_jump = self.emit_jump(OpCode::Jump);
+        self.patch_jump(then_jump);
+        self.emit_op(OpCode::Pop);
+
+        if self.token_match(TokenType::Else) {
+            self.statement()?;
+        }
+        self.patch_jump(else_jump);
+
+        Ok(())
+    }
+
+    fn print_statement(&mut self) -> Result<(), CompileError> {
+        self.expression()?;
+        self.consume(TokenType::Semicolon, "';' after value")?;
+        self.emit_op(OpCode::Print);
+
+        Ok(())
+    }
+
+    fn return_statement(&mut self) -> Result<(), CompileError> {
+        let keyword = self.previous().clone();
+        let kind = self.state().kind;
+        if kind == FunctionKind::Script {
+            self.errors
+                .push(CompileError::TopLevelReturn(keyword.span()));
+        }
+
+        if self.token_match(TokenType::Semicolon) {
+            self.emit_return();
+            return Ok(());
+        }
+
+        if kind == FunctionKind::Initializer {
+            self.errors
+                .push(CompileError::ReturnFromInitializer(keyword.span()));
+        }
+        self.expression()?;
+        self.consume(TokenType::Semicolon, "';' after return value")?;
+        self.emit_op(OpCode::Return);
+
+        Ok(())
+    }
+
+    fn while_statement(&mut self) -> Result<(), CompileError> {
+        let loop_start = self.chunk().code.len();
+        self.consume(TokenType::LeftParen, "'(' after 'while'")?;
+        self.expression()?;
+        self.consume(TokenType::RightParen, "')' after condition")?;
+
+        if let Some(truthy) = self.constant_condition(loop_start) {
+            self.statement()?;
+            match truthy {
+                true => self.emit_loop(loop_start),
+                false => self.truncate(loop_start),
+            }
+            return Ok(());
+        }
+
+        let exit_jump = self.emit_condition_jump();
+        self.emit_op(OpCode::Pop);
+        self.statement()?;
+        self.emit_loop(loop_start);
+
+        self.patch_jump(exit_jump);
+        self.emit_op(OpCode::Pop);
+
+        Ok(())
+    }
+
+    fn block(&mut self) -> Result<(), CompileError> {
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            self.declaration();
+        }
+
+        self.consume(TokenType::RightBrace, "'}' after block")?;
+
+        Ok(())
+    }
+
+    fn expression_statement(&mut self) -> Result<(), CompileError> {
+        self.expression()?;
+        self.consume(TokenType::Semicolon, "';' after expression")?;
+        self.emit_op(OpCode::Pop);
+
+        Ok(())
+    }
+
+    fn expression(&mut self) -> Result<(), CompileError> {
+        self.nested("expression", |compiler| {
+            compiler.parse_precedence(Precedence::Assignment)
+        })
+    }
+
+    fn parse_precedence(&mut self, precedence: Precedence) -> Result<(), CompileError> {
+        if !starts_expression(self.peek().token_type()) {
+            return Err(self.error_at(self.peek(), "expression"));
+        }
+
+        let token = self.advance();
+        let can_assign = precedence <= Precedence::Assignment;
+        self.prefix(token.token_type(), can_assign)?;
+
+        while precedence <= Precedence::of(self.peek().token_type()) {
+            let operator = self.advance();
+            self.infix(operator.token_type(), can_assign)?;
+        }
+
+        if can_assign && self.token_match(TokenType::Equal) {
+            // Report without unwinding, the compiler is not confused.
+            let equals = self.previous().span();
+            self.errors
+                .push(CompileError::InvalidAssignmentTarget(equals));
+            self.expression()?;
+        }
+
+        Ok(())
+    }
+
+    fn prefix(&mut self, token_type: TokenType, can_assign: bool) -> Result<(), CompileError> {
+        match token_type {
+            TokenType::LeftParen => {
+                self.expression()?;
+                self.consume(TokenType::RightParen, "')' after expression")?;
+            }
+            TokenType::Minus | TokenType::Bang => self.unary()?,
+            TokenType::Number | TokenType::String => {
+                let value = match self.previous().literal().clone() {
+                    Literal::Number(value) => Value::number(value),
+                    Literal::String(value) => Value::object(self.heap.intern(&value)),
+                    _ => Value::NULL,
+                };
+                self.emit_constant(value);
+            }
+            TokenType::False | TokenType::True | TokenType::Null => self.literal()?,
+            TokenType::Identifier => {
+                let name = self.previous().clone();
+                self.named_variable(&name, can_assign)?;
+            }
+            TokenType::This => self.this()?,
+            TokenType::Super => self.super_()?,
+            _ => unreachable!("not the start of an expression: {:?}", token_type),
+        }
+
+        Ok(())
+    }
+
+    fn infix(&mut self, token_type: TokenType, can_assign: bool) -> Result<(), CompileError> {
+        match token_type {
+            TokenType::LeftParen => self.call(),
+            TokenType::Dot => self.dot(can_assign),
+            TokenType::And => {
+                self.operator()?;
+                self.and()
+            }
+            TokenType::Or => {
+                self.operator()?;
+                self.or()
+            }
+            _ => {
+                self.operator()?;
+                self.binary()
+            }
+        }
+    }
+
+    fn binary(&mut self) -> Result<(), CompileError> {
+        let operator = self.previous().token_type();
+        self.parse_precedence(Precedence::of(operator).next())?;
+
+        match operator {
+            TokenType::BangEqual => {
+                self.emit_op(OpCode::Equal);
+                self.emit_op(OpCode::Not);
+            }
+            TokenType::EqualEqual => self.emit_op(OpCode::Equal),
+            TokenType::Greater => self.emit_op(OpCode::Greater),
+            TokenType::GreaterEqual => {
+                self.emit_op(OpCode::Less);
+                self.emit_op(OpCode::Not);
+            }
+            TokenType::Less => self.emit_op(OpCode::Less),
+            TokenType::LessEqual => {
+                self.emit_op(OpCode::Greater);
+                self.emit_op(OpCode::Not);
+            }
+            TokenType::Plus => self.emit_op(OpCode::Add),
+            TokenType::Minus => self.emit_op(OpCode::Subtract),
+            TokenType::Star => self.emit_op(OpCode::Multiply),
+            TokenType::Slash => self.emit_op(OpCode::Divide),
+            _ => unreachable!("not a binary operator: {:?}", operator),
+        }
+
+        Ok(())
+    }
+
+    fn unary(&mut self) -> Result<(), CompileError> {
+        let operator = self.previous().token_type();
+        self.nested("expression", |compiler| {
+            compiler.parse_precedence(Precedence::Unary)
+        })?;
+
+        match operator {
+            TokenType::Minus => self.emit_op(OpCode::Negate),
+            _ => self.emit_op(OpCode::Not),
+        }
+
+        Ok(())
+    }
+
+    fn and(&mut self) -> Result<(), CompileError> {
+        let end_jump = self.emit_jump(OpCode::JumpIfFalse);
+
+        self.emit_op(OpCode::Pop);
+        self.parse_precedence(Precedence::And)?;
+
+        self.patch_jump(end_jump);
+
+        Ok(())
+    }
+
+    fn or(&mut self) -> Result<(), CompileError> {
+        let else_jump = self.emit_jump(OpCode::JumpIfFalse);
+        let end_jump = self.emit_jump(OpCode::Jump);
+
+        self.patch_jump(else_jump);
+        self.emit_op(OpCode::Pop);
+
+        self.parse_precedence(Precedence::Or)?;
+        self.patch_jump(end_jump);
+
+        Ok(())
+    }
+
+    fn call(&mut self) -> Result<(), CompileError> {
+        let argument_count = self.argument_list()?;
+        self.emit_op_byte(OpCode::Call, argument_count);
+
+        Ok(())
+    }
+
+    fn dot(&mut self, can_assign: bool) -> Result<(), CompileError> {
+        let name = self.consume(TokenType::Identifier, "property name after '.'")?;
+        let constant = self.identifier_constant(&name);
+
+        if can_assign && self.token_match(TokenType::Equal) {
+            self.expression()?;
+            self.emit_op_byte(OpCode::SetProperty, constant);
+            self.emit_cache();
+        } else if self.token_match(TokenType::LeftParen) {
+            let argument_count = self.argument_list()?;
+            self.emit_op_byte(OpCode::Invoke, constant);
+            self.chunk_write(argument_count);
+            self.emit_cache();
+        } else {
+            self.emit_op_byte(OpCode::GetProperty, constant);
+            self.emit_cache();
+        }
+
+        Ok(())
+    }
+
+    fn argument_list(&mut self) -> Result<u8, CompileError> {
+        let mut count = 0;
+
+        if !self.check(TokenType::RightParen) {
+            loop {
+                if count >= MAX_ARITY {
+                    self.errors
+                        .push(CompileError::TooManyArguments(self.peek().span()));
+                }
+                self.expression()?;
+                count += 1;
+
+                if !self.token_match(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+
+        self.consume(TokenType::RightParen, "')' after arguments")?;
+
+        Ok(count.min(MAX_ARITY) as u8)
+    }
+
+    fn literal(&mut self) -> Result<(), CompileError> {
+        match self.previous().token_type() {
+            TokenType::False => self.emit_op(OpCode::False),
+            TokenType::True => self.emit_op(OpCode::True),
+            _ => self.emit_op(OpCode::Null),
+        }
+
+        Ok(())
+    }
+
+    fn this(&mut self) -> Result<(), CompileError> {
+        let keyword = self.previous().clone();
+        if self.classes.is_empty() {
+            self.errors
+                .push(CompileError::ThisOutsideClass(keyword.span()));
+            return Ok(());
+        }
+
+        self.named_variable(&keyword, false)
+    }
+
+    fn super_(&mut self) -> Result<(), CompileError> {
+        let keyword = self.previous().clone();
+        match self.classes.last() {
+            None => self
+                .errors
+                .push(CompileError::SuperOutsideClass(keyword.span())),
+            Some(class) if !class.has_superclass => self
+                .errors
+                .push(CompileError::SuperWithoutSuperclass(keyword.span())),
+            Some(_) => {}
+        }
+
+        self.consume(TokenType::Dot, "'.' after 'super'")?;
+        let name = self.consume(TokenType::Identifier, "superclass method name")?;
+        let constant = self.identifier_constant(&name);
+
+        self.named_variable(&synthetic(&keyword, TokenType::This, "this"), false)?;
+        if self.token_match(TokenType::LeftParen) {
+            let argument_count = self.argument_list()?;
+            self.named_variable(&keyword, false)?;
+            self.emit_op_byte(OpCode::SuperInvoke, constant);
+            self.chunk_write(argument_count);
+        } else {
+            self.named_variable(&keyword, false)?;
+            self.emit_op_byte(OpCode::GetSuper, constant);
+        }
+
+        Ok(())
+    }
+
+    fn named_variable(&mut self, name: &Token, can_assign: bool) -> Result<(), CompileError> {
+        let innermost = self.functions.len() - 1;
+        let (get_op, set_op, operand) = if let Some(slot) = self.resolve_local(innermost, name) {
+            (OpCode::GetLocal, OpCode::SetLocal, slot)
+        } else if let Some(index) = self.resolve_upvalue(innermost, name) {
+            (OpCode::GetUpvalue, OpCode::SetUpvalue, index)
+        } else {
+            let constant = self.identifier_constant(name);
+            (OpCode::GetGlobal, OpCode::SetGlobal, constant)
+        };
+
+        if can_assign && self.token_match(TokenType::Equal) {
+            self.expression()?;
+            self.emit_op_byte(set_op, operand);
+        } else {
+            self.emit_op_byte(get_op, operand);
+        }
+
+        Ok(())
+    }
+
+    fn resolve_local(&mut self, function: usize, name: &Token) -> Option<u8> {
+        let state = &self.functions[function];
+        let (slot, local) = state
+            .locals
+            .iter()
+            .enumerate()
+            .rev()
+            .find(|(_, local)| local.name == name.lexeme())?;
+
+        if local.depth.is_none() {
+            self.errors.push(CompileError::ReadInOwnInitializer(
+                name.lexeme().to_string(),
+                name.span(),
+            ));
+        }
+
+        Some(slot as u8)
+    }
+
+    fn resolve_upvalue(&mut self, function: usize, name: &Token) -> Option<u8> {
+        // The script has no enclosing function, its variables are globals.
+        let enclosing = function.checked_sub(1)?;
+
+        if let Some(slot) = self.resolve_local(enclosing, name) {
+            self.functions[enclosing].locals[slot as usize].is_captured = true;
+            return Some(self.add_upvalue(function, slot, true, name));
+        }
+
+        let index = self.resolve_upvalue(enclosing, name)?;
+        Some(self.add_upvalue(function, index, false, name))
+    }
+
+    fn add_upvalue(&mut self, function: usize, index: u8, is_local: bool, name: &Token) -> u8 {
+        let upvalue = Upvalue { index, is_local };
+        let upvalues = &mut self.functions[function].upvalues;
+        if let Some(index) = upvalues.iter().position(|u| *u == upvalue) {
+            return index as u8;
+        }
+
+        if upvalues.len() >= MAX_UPVALUES {
+            self.errors.push(CompileError::TooManyUpvalues(name.span()));
+            return 0;
+        }
+
+        upvalues.push(upvalue);
+        (upvalues.len() - 1) as u8
+    }
+
+    fn parse_variable(&mut self, expected: &str) -> Result<u8, CompileError> {
+        let name = self.consume(TokenType::Identifier, expected)?;
+
+        self.declare_variable(&name);
+        if self.state().scope_depth > 0 {
+            return Ok(0);
+        }
+
+        Ok(self.identifier_constant(&name))
+    }
+
+    fn declare_variable(&mut self, name: &Token) {
+        let state = self.state();
+        if state.scope_depth == 0 {
+            return;
+        }
+
+        let depth = state.scope_depth;
+        let already_declared = state
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth.is_none_or(|d| d >= depth))
+            .any(|local| local.name == name.lexeme());
+        if already_declared {
+            self.errors.push(CompileError::AlreadyDeclared(
+                name.lexeme().to_string(),
+                name.span(),
+            ));
+        }
+
+        if self.state().locals.len() >= MAX_LOCALS {
+            self.errors.push(CompileError::TooManyLocals(name.span()));
+            return;
+        }
+        self.add_local(name.lexeme());
+    }
+
+    fn define_variable(&mut self, global: u8) {
+        if self.state().scope_depth > 0 {
+            self.mark_initialized();
+            return;
+        }
+
+        self.emit_op_byte(OpCode::DefineGlobal, global);
+    }
+
+    fn add_local(&mut self, name: &str) {
+        self.state().locals.push(Local {
+            name: name.to_string(),
+            depth: None,
+            is_captured: false,
+        });
+    }
+
+    fn mark_initialized(&mut self) {
+        let state = self.state();
+        let depth = state.scope_depth;
+        if depth == 0 {
+            return;
+        }
+
+        if let Some(local) = state.locals.last_mut() {
+            local.depth = Some(depth);
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.state().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        let state = self.state();
+        state.scope_depth -= 1;
+        let depth = state.scope_depth;
+
+        while let Some(local) = self
+            .state()
+            .locals
+            .pop_if(|local| local.depth.is_none_or(|d| d > depth))
+        {
+            if local.is_captured {
+                self.emit_op(OpCode::CloseUpvalue);
+            } else {
+                self.emit_op(OpCode::Pop);
+            }
+        }
+    }
+
+    fn identifier_constant(&mut self, name: &Token) -> u8 {
+        let string = self.heap.intern(name.lexeme());
+        self.make_constant(Value::object(string))
+    }
+
+    fn make_constant(&mut self, value: Value) -> u8 {
+        let index = self.chunk().add_constant(value);
+        if index >= MAX_CONSTANTS {
+            let span = self.previous().span();
+            self.errors.push(CompileError::TooManyConstants(span));
+            return 0;
+        }
+
+        index as u8
+    }
+
+    fn emit_op(&mut self, op: OpCode) {
+        if self.opt_level >= OptLevel::Fold && self.fold(op) {
+            return;
+        }
+
+        let start = self.chunk().code.len();
+        self.state().starts.push(start);
+        self.chunk_write(op as u8);
+    }
+
+    fn emit_op_byte(&mut self, op: OpCode, byte: u8) {
+        self.emit_op(op);
+        self.chunk_write(byte);
+    }
+
+    fn emit_constant(&mut self, value: Value) {
+        let pool_size = self.chunk().constants.len();
+        let constant = self.make_constant(value);
+        let start = self.chunk().code.len();
+        self.emit_op_byte(OpCode::Constant, constant);
+
+        if self.chunk().constants.len() > pool_size {
+            self.state().fresh_constants.push(start);
+        }
+    }
+
+    fn emit_jump(&mut self, op: OpCode) -> usize {
+        self.emit_op(op);
+        self.chunk_write(0xff);
+        self.chunk_write(0xff);
+
+        self.chunk().code.len() - 2
+    }
+
+    fn patch_jump(&mut self, offset: usize) {
+        // Jump over the operand itself too.
+        let jump = self.chunk().code.len() - offset - 2;
+        if jump > u16::MAX as usize {
+            let span = self.previous().span();
+            self.errors.push(CompileError::JumpTooLarge(span));
+        }
+
+        let [high, low] = (jump as u16).to_be_bytes();
+        let code = &mut self.chunk().code;
+        code[offset] = high;
+        code[offset + 1] = low;
+
+        let target = self.chunk().code.len();
+        self.state().label = target;
+    }
+
+    fn emit_loop(&mut self, loop_start: usize) {
+        self.emit_op(OpCode::Loop);
+        let state = self.state();
+        state.label = state.label.max(loop_start);
+
+        let offset = self.chunk().code.len() - loop_start + 2;
+        if offset > u16::MAX as usize {
+            let span = self.previous().span();
+            self.errors.push(CompileError::LoopTooLarge(span));
+        }
+
+        let [high, low] = (offset as u16).to_be_bytes();
+        self.chunk_write(high);
+        self.chunk_write(low);
+    }
+
+    fn emit_return(&mut self) {
+        if self.state().kind == FunctionKind::Initializer {
+            self.emit_op_byte(OpCode::GetLocal, 0);
+        } else {
+            self.emit_op(OpCode::Null);
+        }
+
+        self.emit_op(OpCode::Return);
+    }
+
+    fn token_match(&mut self, token_type: TokenType) -> bool {
+        if !self.check(token_type) {
+            return false;
+        }
+
+        self.advance();
+        true
+    }
+
+    fn consume(&mut self, token_type: TokenType, expected: &str) -> Result<Token, CompileError> {
+        if self.check(token_type) {
+            return Ok(self.advance());
+        }
+
+        Err(self.error_at(self.peek(), expected))
+    }
+
+    fn check(&self, token_type: TokenType) -> bool {
+        if self.is_at_end() {
+            return false;
+        }
+
+        self.peek().token_type() == token_type
+    }
+
+    fn advance(&mut self) -> Token {
+        if !self.is_at_end() {
+            self.current += 1;
+        }
+
+        self.previous().clone()
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.peek().token_type() == TokenType::Eof
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.current]
+    }
+
+    fn previous(&self) -> &Token {
+        &self.tokens[self.current.saturating_sub(1)]
+    }
+
+    fn error_at(&self, token: &Token, expected: &str) -> CompileError {
+        let found = match token.token_type() {
+            TokenType::Eof => "end of file".to_string(),
+            _ => format!("'{}'", token.lexeme()),
+        };
+
+        CompileError::UnexpectedToken(expected.to_string(), found, token.span())
+    }
+
+    fn nested(
+        &mut self,
+        what: &'static str,
+        compile: impl FnOnce(&mut Self) -> Result<(), CompileError>,
+    ) -> Result<(), CompileError> {
+        if self.depth >= MAX_NESTING {
+            return Err(CompileError::NestedTooDeeply(what, self.peek().span()));
+        }
+
+        self.depth += 1;
+        let result = compile(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn operator(&mut self) -> Result<(), CompileError> {
+        self.operators += 1;
+        if self.operators > MAX_OPERATORS {
+            return Err(CompileError::TooManyOperators(self.previous().span()));
+        }
+
+        Ok(())
+    }
+
+    fn synchronize(&mut self) {
+        self.advance();
+
+        while !self.is_at_end() {
+            if self.previous().token_type() == TokenType::Semicolon {
+                return;
+            }
+
+            match self.peek().token_type() {
+                TokenType::Class
+                | TokenType::Fun
+                | TokenType::Var
+                | TokenType::For
+                | TokenType::If
+                | TokenType::While
+                | TokenType::Print
+                | TokenType::Return => return,
+                _ => {}
+            }
+
+            self.advance();
+        }
+    }
+}
+
+impl Compiler<'_> {
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.state().function.chunk
+    }
+
+    /// The innermost function being compiled
+    fn state(&mut self) -> &mut FunctionState {
+        self.functions.last_mut().expect("function being compiled")
+    }
+
+    /// Write a raw byte at the line of the previous token
+    fn chunk_write(&mut self, byte: u8) {
+        let line = self.previous().line();
+        self.chunk().write(byte, line);
+    }
+
+    /// Allocate an inline cache for the instruction just emitted and write its index
+    fn emit_cache(&mut self) {
+        let caches = &mut self.state().function.caches;
+        let index = caches.len();
+        if index < MAX_CACHES {
+            caches.push(InlineCache::default());
+        } else {
+            let span = self.previous().span();
+            self.errors.push(CompileError::TooManyCaches(span));
+        }
+
+        let [high, low] = (index as u16).to_be_bytes();
+        self.chunk_write(high);
+        self.chunk_write(low);
+    }
+
+    /// Drop the code emitted from `length` on, with the constants only it used
+    fn truncate(&mut self, length: usize) {
+        while let Some(&start) = self.state().fresh_constants.last() {
+            if start < length {
+                break;
+            }
+            self.state().fresh_constants.pop();
+            let chunk = self.chunk();
+            if chunk.code[start + 1] as usize == chunk.constants.len() - 1 {
+                chunk.constants.pop();
+            }
+        }
+        self.chunk().truncate(length);
+
+        let state = self.state();
+        while state.starts.last().is_some_and(|start| *start >= length) {
+            state.starts.pop();
+        }
+        state.label = state.label.min(length);
+    }
+
+    /// The last `count` instructions when nothing jumps between them, so
+    /// they can be replaced as a whole
+    fn last_instructions(&mut self, count: usize) -> Option<Vec<usize>> {
+        let state = self.state();
+        let first = state.starts.len().checked_sub(count)?;
+        let starts = state.starts[first..].to_vec();
+
+        (state.label <= starts[0]).then_some(starts)
+    }
+
+    /// The value pushed by the instruction at `offset` if it loads a constant
+    fn constant_at(&mut self, offset: usize) -> Option<Value> {
+        let chunk = self.chunk();
+        match OpCode::try_from(chunk.code[offset]) {
+            Ok(OpCode::Constant) => Some(chunk.constants[chunk.code[offset + 1] as usize]),
+            Ok(OpCode::Null) => Some(Value::NULL),
+            Ok(OpCode::True) => Some(Value::boolean(true)),
+            Ok(OpCode::False) => Some(Value::boolean(false)),
+            _ => None,
+        }
+    }
+
+    /// Replace an operator applied to constants by its result, returning
+    /// whether it did. Operations that would fail at runtime are kept.
+    fn fold(&mut self, op: OpCode) -> bool {
+        let operands = match op {
+            OpCode::Not | OpCode::Negate => 1,
+            OpCode::Equal
+            | OpCode::Greater
+            | OpCode::Less
+            | OpCode::Add
+            | OpCode::Subtract
+            | OpCode::Multiply
+            | OpCode::Divide => 2,
+            _ => return false,
+        };
+        let Some(starts) = self.last_instructions(operands) else {
+            return false;
+        };
+        let Some(values) = starts
+            .iter()
+            .map(|start| self.constant_at(*start))
+            .collect::<Option<Vec<Value>>>()
+        else {
+            return false;
+        };
+
+        let result = match (op, values.as_slice()) {
+            (OpCode::Not, [value]) => Value::boolean(value.is_falsey()),
+            (OpCode::Negate, [value]) => match value.as_number() {
+                Some(number) => Value::number(-number),
+                None => return false,
+            },
+            (OpCode::Equal, [a, b]) => Value::boolean(a == b),
+            (OpCode::Add, [a, b]) if a.as_object().is_some() => {
+                let strings = (a.as_object(), b.as_object());
+                let (Some(a), Some(b)) = strings else {
+                    return false;
+                };
+                let (Object::String(a), Object::String(b)) = (self.heap.get(a), self.heap.get(b))
+                else {
+                    return false;
+                };
+                let concatenated = format!("{a}{b}");
+                Value::object(self.heap.intern(&concatenated))
+            }
+            (op, [a, b]) => {
+                let (Some(a), Some(b)) = (a.as_number(), b.as_number()) else {
+                    return false;
+                };
+                match op {
+                    OpCode::Greater => Value::boolean(a > b),
+                    OpCode::Less => Value::boolean(a < b),
+                    OpCode::Add => Value::number(a + b),
+                    OpCode::Subtract => Value::number(a - b),
+                    OpCode::Multiply => Value::number(a * b),
+                    _ => Value::number(a / b),
+                }
+            }
+            _ => return false,
+        };
+
+        self.truncate(starts[0]);
+        self.emit_value(result);
+        true
+    }
+
+    /// Push a constant, with the dedicated instructions where there is one
+    fn emit_value(&mut self, value: Value) {
+        match value.as_boolean() {
+            Some(true) => self.emit_op(OpCode::True),
+            Some(false) => self.emit_op(OpCode::False),
+            None if value.is_null() => self.emit_op(OpCode::Null),
+            None => self.emit_constant(value),
+        }
+    }
+
+    /// The truthiness of a condition compiled from `start` when it is a
+    /// constant, which is removed
+    fn constant_condition(&mut self, start: usize) -> Option<bool> {
+        if self.opt_level < OptLevel::Fold {
+            return None;
+        }
+
+        let starts = self.last_instructions(1)?;
+        if starts[0] != start {
+            return None;
+        }
+        let value = self.constant_at(start)?;
+
+        self.truncate(start);
+        Some(!value.is_falsey())
+    }
+
+    /// Jump when the condition on the stack is falsey. It is only tested, so
+    /// `!!x` can test `x` directly.
+    fn emit_condition_jump(&mut self) -> usize {
+        while self.opt_level >= OptLevel::Fold {
+            let Some(starts) = self.last_instructions(2) else {
+                break;
+            };
+            let not = OpCode::Not as u8;
+            let code = &self.chunk().code;
+            if code[starts[0]] != not || code[starts[1]] != not {
+                break;
+            }
+            self.truncate(starts[0]);
+        }
+
+        self.emit_jump(OpCode::JumpIfFalse)
+    }
+
+    fn class_body(&mut self, name: &Token) -> Result<(), CompileError> {
+        if self.token_match(TokenType::Less) {
+            let superclass = self.consume(TokenType::Identifier, "superclass name")?;
+            self.named_variable(&superclass, false)?;
+
+            if superclass.lexeme() == name.lexeme() {
+                self.errors
+                    .push(CompileError::InheritFromSelf(superclass.span()));
+            }
+
+            // Methods find the superclass through a local named `super`.
+            self.begin_scope();
+            self.add_local("super");
+            self.define_variable(0);
+
+            self.named_variable(name, false)?;
+            self.emit_op(OpCode::Inherit);
+            if let Some(class) = self.classes.last_mut() {
+                class.has_superclass = true;
+            }
+        }
+
+        self.named_variable(name, false)?;
+        self.consume(TokenType::LeftBrace, "'{' before class body")?;
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            self.method()?;
+        }
+        self.consume(TokenType::RightBrace, "'}' after class body")?;
+        self.emit_op(OpCode::Pop);
+
+        Ok(())
+    }
+
+    fn function_body(&mut self, kind: FunctionKind) -> Result<(), CompileError> {
+        let kind_name = if kind == FunctionKind::Function {
+            "function"
+        } else {
+            "method"
+        };
+        self.consume(TokenType::LeftParen, &format!("'(' after {kind_name} name"))?;
+
+        if !self.check(TokenType::RightParen) {
+            loop {
+                if self.state().function.arity >= MAX_ARITY {
+                    self.errors
+                        .push(CompileError::TooManyParameters(self.peek().span()));
+                }
+                self.state().function.arity += 1;
+
+                let constant = self.parse_variable("parameter name")?;
+                self.define_variable(constant);
+
+                if !self.token_match(TokenType::Comma) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightParen, "')' after parameters")?;
+
+        self.consume(
+            TokenType::LeftBrace,
+            &format!("'{{' before {kind_name} body"),
+        )?;
+        self.block()?;
+        self.emit_return();
+
+        Ok(())
+    }
+
+    /// The clauses and body of a `for` loop, inside the loop's own scope
+    fn for_clauses(&mut self) -> Result<(), CompileError> {
+        self.consume(TokenType::LeftParen, "'(' after 'for'")?;
+        if self.token_match(TokenType::Semicolon) {
+            // No initializer.
+        } else if self.token_match(TokenType::Var) {
+            self.var_declaration()?;
+        } else {
+            self.expression_statement()?;
+        }
+
+        let condition_start = self.chunk().code.len();
+        let mut loop_start = condition_start;
+        let mut exit_jump = None;
+        let mut never_runs = false;
+        if !self.token_match(TokenType::Semicolon) {
+            self.expression()?;
+            self.consume(TokenType::Semicolon, "';' after loop condition")?;
+
+            match self.constant_condition(condition_start) {
+                Some(truthy) => never_runs = !truthy,
+                None => {
+                    exit_jump = Some(self.emit_condition_jump());
+                    self.emit_op(OpCode::Pop);
+                }
+            }
+        }
+
+        if !self.token_match(TokenType::RightParen) {
+            // The increment runs after the body, so jump over it to the body first.
+            let body_jump = self.emit_jump(OpCode::Jump);
+            let increment_start = self.chunk().code.len();
+            self.expression()?;
+            self.emit_op(OpCode::Pop);
+            self.consume(TokenType::RightParen, "')' after for clauses")?;
+
+            self.emit_loop(loop_start);
+            loop_start = increment_start;
+            self.patch_jump(body_jump);
+        }
+
+        self.statement()?;
+        if never_runs {
+            // Only the initializer runs, the rest was compiled for its errors.
+            self.truncate(condition_start);
+            return Ok(());
+        }
+        self.emit_loop(loop_start);
+
+        if let Some(exit_jump) = exit_jump {
+            self.patch_jump(exit_jump);
+            self.emit_op(OpCode::Pop);
+        }
+
+        Ok(())
+    }
+}
+
+impl FunctionState {
+    fn new(kind: FunctionKind, name: Option<ObjRef>) -> FunctionState {
+        // Slot zero holds the called function, or `this` inside methods.
+        let slot_zero = match kind {
+            FunctionKind::Method | FunctionKind::Initializer => "this",
+            FunctionKind::Script | FunctionKind::Function => "",
+        };
+
+        FunctionState {
+            function: ObjFunction {
+                name,
+                ..ObjFunction::default()
+            },
+            kind,
+            locals: vec![Local {
+                name: slot_zero.to_string(),
+                depth: Some(0),
+                is_captured: false,
+            }],
+            upvalues: Vec::new(),
+            scope_depth: 0,
+            starts: Vec::new(),
+            fresh_constants: Vec::new(),
+            label: 0,
+        }
+    }
+}
+
+/// Whether an expression can start with this token
+fn starts_expression(token_type: TokenType) -> bool {
+    matches!(
+        token_type,
+        TokenType::LeftParen
+            | TokenType::Minus
+            | TokenType::Bang
+            | TokenType::Number
+            | TokenType::String
+            | TokenType::False
+            | TokenType::True
+            | TokenType::Null
+            | TokenType::Identifier
+            | TokenType::This
+            | TokenType::Super
+    )
+}
+
+/// A token that does not appear in the source, located at `at`
+fn synthetic(at: &Token, token_type: TokenType, lexeme: &str) -> Token {
+    Token::new(token_type, lexeme.to_string(), Literal::Null, at.span())
+}
@@ -0,0 +1,222 @@
+use std::fmt::Write;
+
+use crate::bytecode::chunk::{Chunk, OpCode};
+use crate::bytecode::object::{Heap, ObjRef, Object};
+
+/// Disassemble a compiled script followed by every function nested in it
+pub fn disassemble(function: ObjRef, heap: &Heap) -> String {
+    let mut out = String::new();
+    disassemble_function(function, heap, &mut out);
+    out
+}
+
+fn disassemble_function(reference: ObjRef, heap: &Heap, out: &mut String) {
+    let function = heap.function(reference);
+    let name = match function.name {
+        Some(name) => heap.string(name),
+        None => "<script>",
+    };
+    out.push_str(&disassemble_chunk(&function.chunk, name, heap));
+
+    for constant in &function.chunk.constants {
+        if let Some(nested) = constant.as_object() {
+            if let Object::Function(_) = heap.get(nested) {
+                out.push('\n');
+                disassemble_function(nested, heap, out);
+            }
+        }
+    }
+}
+
+/// Disassemble one chunk under a `== name ==` header
+pub fn disassemble_chunk(chunk: &Chunk, name: &str, heap: &Heap) -> String {
+    let mut out = format!("== {} ==\n", name);
+
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        offset = disassemble_instruction(chunk, offset, heap, &mut out);
+    }
+
+    out
+}
+
+/// Write the instruction at `offset` as one line and return the offset of the next one
+pub fn disassemble_instruction(
+    chunk: &Chunk,
+    offset: usize,
+    heap: &Heap,
+    out: &mut String,
+) -> usize {
+    let _ = write!(out, "{:04} ", offset);
+    if offset > 0 && chunk.line(offset) == chunk.line(offset - 1) {
+        out.push_str("   | ");
+    } else {
+        let _ = write!(out, "{:4} ", chunk.line(offset));
+    }
+
+    let op = match OpCode::try_from(chunk.code[offset]) {
+        Ok(op) => op,
+        Err(byte) => {
+            let _ = writeln!(out, "Unknown opcode {}", byte);
+            return offset + 1;
+        }
+    };
+
+    match op {
+        OpCode::Constant
+        | OpCode::GetGlobal
+        | OpCode::DefineGlobal
+        | OpCode::SetGlobal
+        | OpCode::GetSuper
+        | OpCode::Class
+        | OpCode::Method => constant_instruction(op, chunk, offset, heap, out),
+        OpCode::GetLocal
+        | OpCode::SetLocal
+        | OpCode::GetUpvalue
+        | OpCode::SetUpvalue
+        | OpCode::Call => byte_instruction(op, chunk, offset, out),
+        OpCode::Jump | OpCode::JumpIfFalse => jump_instruction(op, 1, chunk, offset, out),
+        OpCode::Loop => jump_instruction(op, -1, chunk, offset, out),
+        OpCode::GetProperty | OpCode::SetProperty => {
+            let next = constant_instruction(op, chunk, offset, heap, out);
+            cache_operand(chunk, next, out)
+        }
+        OpCode::Invoke => {
+            let next = invoke_instruction(op, chunk, offset, heap, out);
+            cache_operand(chunk, next, out)
+        }
+        OpCode::SuperInvoke => invoke_instruction(op, chunk, offset, heap, out),
+        OpCode::Closure => closure_instruction(chunk, offset, heap, out),
+        OpCode::Null
+        | OpCode::True
+        | OpCode::False
+        | OpCode::Pop
+        | OpCode::Equal
+        | OpCode::Greater
+        | OpCode::Less
+        | OpCode::Add
+        | OpCode::Subtract
+        | OpCode::Multiply
+        | OpCode::Divide
+        | OpCode::Not
+        | OpCode::Negate
+        | OpCode::Print
+        | OpCode::CloseUpvalue
+        | OpCode::Return
+        | OpCode::Inherit => {
+            let _ = writeln!(out, "{}", op);
+            offset + 1
+        }
+    }
+}
+
+/// Read an operand byte, `None` when the chunk ends early
+fn operand(chunk: &Chunk, offset: usize) -> Option<u8> {
+    chunk.code.get(offset).copied()
+}
+
+fn constant_instruction(
+    op: OpCode,
+    chunk: &Chunk,
+    offset: usize,
+    heap: &Heap,
+    out: &mut String,
+) -> usize {
+    let constant = operand(chunk, offset + 1).unwrap_or_default();
+    let _ = write!(out, "{:<16} {:4} '", op, constant);
+    match chunk.constants.get(constant as usize) {
+        Some(value) => {
+            let _ = write!(out, "{}", value.display(heap));
+        }
+        None => out.push_str("<invalid constant>"),
+    }
+    out.push_str("'\n");
+
+    offset + 2
+}
+
+/// Append the inline cache index following an instruction to its line
+fn cache_operand(chunk: &Chunk, offset: usize, out: &mut String) -> usize {
+    let high = operand(chunk, offset).unwrap_or_default();
+    let low = operand(chunk, offset + 1).unwrap_or_default();
+    out.pop();
+    let _ = writeln!(out, " (cache {})", u16::from_be_bytes([high, low]));
+
+    offset + 2
+}
+
+fn byte_instruction(op: OpCode, chunk: &Chunk, offset: usize, out: &mut String) -> usize {
+    let slot = operand(chunk, offset + 1).unwrap_or_default();
+    let _ = writeln!(out, "{:<16} {:4}", op, slot);
+
+    offset + 2
+}
+
+fn jump_instruction(
+    op: OpCode,
+    sign: isize,
+    chunk: &Chunk,
+    offset: usize,
+    out: &mut String,
+) -> usize {
+    let high = operand(chunk, offset + 1).unwrap_or_default();
+    let low = operand(chunk, offset + 2).unwrap_or_default();
+    let jump = u16::from_be_bytes([high, low]) as isize;
+    let target = offset as isize + 3 + sign * jump;
+    let _ = writeln!(out, "{:<16} {:4} -> {}", op, offset, target);
+
+    offset + 3
+}
+
+fn invoke_instruction(
+    op: OpCode,
+    chunk: &Chunk,
+    offset: usize,
+    heap: &Heap,
+    out: &mut String,
+) -> usize {
+    let constant = operand(chunk, offset + 1).unwrap_or_default();
+    let argument_count = operand(chunk, offset + 2).unwrap_or_default();
+    let _ = write!(out, "{:<16} ({} args) {:4} '", op, argument_count, constant);
+    match chunk.constants.get(constant as usize) {
+        Some(value) => {
+            let _ = write!(out, "{}", value.display(heap));
+        }
+        None => out.push_str("<invalid constant>"),
+    }
+    out.push_str("'\n");
+
+    offset + 3
+}
+
+/// The function constant followed by one line per captured variable
+fn closure_instruction(chunk: &Chunk, offset: usize, heap: &Heap, out: &mut String) -> usize {
+    let constant = operand(chunk, offset + 1).unwrap_or_default();
+    let _ = write!(out, "{:<16} {:4} ", OpCode::Closure, constant);
+
+    let Some(value) = chunk.constants.get(constant as usize) else {
+        out.push_str("<invalid constant>\n");
+        return offset + 2;
+    };
+    let _ = writeln!(out, "{}", value.display(heap));
+
+    let upvalue_count = match value.as_object().map(|reference| heap.get(reference)) {
+        Some(Object::Function(function)) => function.upvalue_count,
+        _ => 0,
+    };
+
+    let mut offset = offset + 2;
+    for _ in 0..upvalue_count {
+        let is_local = operand(chunk, offset).unwrap_or_default();
+        let index = operand(chunk, offset + 1).unwrap_or_default();
+        let kind = if is_local == 1 { "local" } else { "upvalue" };
+        let _ = writeln!(
+            out,
+            "{:04}      |                     {} {}",
+            offset, kind, index
+        );
+        offset += 2;
+    }
+
+    offset
+}
@@ -0,0 +1,314 @@
+//! Garbage collection of the heap. The owner of the roots starts a
+//! collection, marks the roots, then `finish_collection` traces everything
+//! reachable from them and frees the rest.
+//!
+//! Two collectors are available. `MarkSweep` traces the whole heap every
+//! time. `Generational` allocates into a nursery and mostly runs minor
+//! collections, which trace only young objects and the old objects written
+//! to since the last collection, then promote the survivors. A write barrier
+//! records those old objects, so pauses grow with the live young objects
+//! instead of the whole heap.
+
+use std::fmt::Display;
+use std::time::Duration;
+
+use crate::bytecode::object::{Heap, ObjRef, ObjUpvalue, Object};
+use crate::bytecode::value::Value;
+
+/// How much the heap may grow after a collection before the next one
+const HEAP_GROW_FACTOR: usize = 2;
+/// Bytes allocated into the nursery before a minor collection
+const NURSERY_BYTES: usize = 256 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum GcMode {
+    #[default]
+    MarkSweep,
+    Generational,
+}
+
+impl std::str::FromStr for GcMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "mark-sweep" => Ok(GcMode::MarkSweep),
+            "generational" => Ok(GcMode::Generational),
+            _ => Err(s.to_string()),
+        }
+    }
+}
+
+/// What a collection traces and frees
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Collection {
+    /// Only the nursery
+    Minor,
+    /// The whole heap
+    Major,
+}
+
+/// Pause times and work done by the collector over the life of a VM
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GcStats {
+    pub minor_collections: usize,
+    pub major_collections: usize,
+    pub bytes_freed: usize,
+    pub total_pause: Duration,
+    pub max_pause: Duration,
+}
+
+impl GcStats {
+    pub fn collections(&self) -> usize {
+        self.minor_collections + self.major_collections
+    }
+
+    pub fn mean_pause(&self) -> Duration {
+        match self.collections() {
+            0 => Duration::ZERO,
+            count => self.total_pause / count as u32,
+        }
+    }
+
+    pub fn record(&mut self, collection: Collection, pause: Duration, bytes_freed: usize) {
+        match collection {
+            Collection::Minor => self.minor_collections += 1,
+            Collection::Major => self.major_collections += 1,
+        }
+
+        self.bytes_freed += bytes_freed;
+        self.total_pause += pause;
+        self.max_pause = self.max_pause.max(pause);
+    }
+}
+
+/// One line summary, e.g. for `rlox run --gc-stats`
+impl Display for GcStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "gc: {} collections ({} minor, {} major), {} bytes freed, pause total {:?}, mean {:?}, max {:?}",
+            self.collections(),
+            self.minor_collections,
+            self.major_collections,
+            self.bytes_freed,
+            self.total_pause,
+            self.mean_pause(),
+            self.max_pause
+        )
+    }
+}
+
+impl Heap {
+    /// Whether the next allocation should be preceded by a collection
+    pub fn should_collect(&self) -> bool {
+        self.stress
+            || self.bytes_allocated > self.next_gc
+            || (self.mode == GcMode::Generational && self.young_bytes > NURSERY_BYTES)
+    }
+
+    /// Collect before every allocation, to find objects that are not rooted
+    pub fn set_stress(&mut self, stress: bool) {
+        self.stress = stress;
+    }
+
+    pub fn mode(&self) -> GcMode {
+        self.mode
+    }
+
+    /// Switch collectors, the objects alive so far start out old
+    pub fn set_mode(&mut self, mode: GcMode) {
+        for entry in self.objects.iter_mut().flatten() {
+            entry.old = mode == GcMode::Generational;
+            entry.remembered = false;
+        }
+
+        self.mode = mode;
+        self.nursery.clear();
+        self.remembered.clear();
+        self.young_bytes = 0;
+    }
+
+    pub fn bytes_allocated(&self) -> usize {
+        self.bytes_allocated
+    }
+
+    /// Number of live objects
+    pub fn object_count(&self) -> usize {
+        self.objects.len() - self.free.len()
+    }
+
+    /// Record that `container` was written to, so a minor collection traces
+    /// it in case it now refers to a young object
+    pub fn write_barrier(&mut self, container: ObjRef) {
+        if self.mode != GcMode::Generational {
+            return;
+        }
+
+        if let Some(entry) = &mut self.objects[container.0 as usize] {
+            if entry.old && !entry.remembered {
+                entry.remembered = true;
+                self.remembered.push(container);
+            }
+        }
+    }
+
+    /// Choose the kind of collection, the roots are marked next
+    pub fn begin_collection(&mut self) -> Collection {
+        self.collection = match self.mode {
+            GcMode::Generational if self.bytes_allocated <= self.next_gc => Collection::Minor,
+            _ => Collection::Major,
+        };
+
+        if self.collection == Collection::Minor {
+            // Not marked, they are old, but their references may be young.
+            self.gray.extend(self.remembered.iter().copied());
+        }
+        self.collection
+    }
+
+    pub fn mark_value(&mut self, value: Value) {
+        if let Some(reference) = value.as_object() {
+            self.mark_object(reference);
+        }
+    }
+
+    /// Mark an object reachable and queue its references for tracing
+    pub fn mark_object(&mut self, reference: ObjRef) {
+        let Some(entry) = &mut self.objects[reference.0 as usize] else {
+            panic!("marking freed object {:?}", reference);
+        };
+        if entry.marked || (self.collection == Collection::Minor && entry.old) {
+            return;
+        }
+
+        entry.marked = true;
+        self.gray.push(reference);
+    }
+
+    /// Free every object not reachable from the marked roots, returning the
+    /// number of bytes freed
+    pub fn finish_collection(&mut self) -> usize {
+        self.trace_references();
+
+        let freed = match self.collection {
+            Collection::Minor => self.sweep_nursery(),
+            Collection::Major => self.sweep(),
+        };
+
+        for reference in self.remembered.drain(..) {
+            if let Some(entry) = &mut self.objects[reference.0 as usize] {
+                entry.remembered = false;
+            }
+        }
+        self.young_bytes = 0;
+
+        if self.collection == Collection::Major {
+            self.next_gc = (self.bytes_allocated * HEAP_GROW_FACTOR).max(self.next_gc);
+        }
+        freed
+    }
+
+    fn trace_references(&mut self) {
+        while let Some(reference) = self.gray.pop() {
+            self.blacken(reference);
+        }
+    }
+
+    /// Mark everything an object refers to
+    fn blacken(&mut self, reference: ObjRef) {
+        let mut references = Vec::new();
+        let mut values = Vec::new();
+
+        match self.get(reference) {
+            Object::String(_) => {}
+            Object::Function(function) => {
+                references.extend(function.name);
+                values.extend(&function.chunk.constants);
+            }
+            Object::Closure(closure) => {
+                references.push(closure.function);
+                references.extend(&closure.upvalues);
+            }
+            Object::Upvalue(ObjUpvalue::Closed(value)) => values.push(*value),
+            Object::Upvalue(ObjUpvalue::Open(_)) => {}
+            Object::Native(native) => references.push(native.name),
+            Object::Class(class) => {
+                references.push(class.name);
+                for (name, method) in &class.methods {
+                    references.extend([*name, *method]);
+                }
+            }
+            Object::Instance(instance) => {
+                references.push(instance.class);
+                values.extend(&instance.fields);
+            }
+            Object::BoundMethod(bound) => {
+                references.push(bound.method);
+                values.push(bound.receiver);
+            }
+        }
+
+        for reference in references {
+            self.mark_object(reference);
+        }
+        for value in values {
+            self.mark_value(value);
+        }
+    }
+
+    /// Free the unmarked objects of the whole heap, survivors become old
+    fn sweep(&mut self) -> usize {
+        let generational = self.mode == GcMode::Generational;
+        let mut freed = 0;
+
+        for index in 0..self.objects.len() {
+            match &mut self.objects[index] {
+                Some(entry) if entry.marked => {
+                    entry.marked = false;
+                    entry.old = generational;
+                }
+                Some(_) => freed += self.free(index),
+                None => {}
+            }
+        }
+
+        self.nursery.clear();
+        freed
+    }
+
+    /// Free the unmarked young objects and promote the rest
+    fn sweep_nursery(&mut self) -> usize {
+        let mut freed = 0;
+
+        for reference in std::mem::take(&mut self.nursery) {
+            let index = reference.0 as usize;
+            match &mut self.objects[index] {
+                Some(entry) if entry.marked => {
+                    entry.marked = false;
+                    entry.old = true;
+                }
+                Some(_) => freed += self.free(index),
+                None => {}
+            }
+        }
+
+        freed
+    }
+
+    /// Release a slot, returning the bytes freed. The string table holds
+    /// strings weakly, so a freed string leaves it too.
+    fn free(&mut self, index: usize) -> usize {
+        let Some(entry) = self.objects[index].take() else {
+            return 0;
+        };
+
+        if let Object::String(string) = &entry.object {
+            self.strings.remove(string);
+        }
+
+        self.free.push(index as u32);
+        self.bytes_allocated -= entry.size;
+        entry.size
+    }
+}
@@ -0,0 +1,265 @@
+use std::collections::HashMap;
+
+use crate::bytecode::chunk::Chunk;
+use crate::bytecode::gc::{Collection, GcMode};
+use crate::bytecode::shape::{InlineCache, ShapeId};
+use crate::bytecode::value::Value;
+
+/// Handle to an object stored in a `Heap`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ObjRef(pub(super) u32);
+
+#[derive(Debug)]
+pub enum Object {
+    String(Box<str>),
+    Function(ObjFunction),
+    Closure(ObjClosure),
+    Upvalue(ObjUpvalue),
+    Native(ObjNative),
+    Class(ObjClass),
+    Instance(ObjInstance),
+    BoundMethod(ObjBoundMethod),
+}
+
+impl Object {
+    /// Approximate number of bytes the object occupies, driving when to collect
+    fn size(&self) -> usize {
+        let owned = match self {
+            Object::String(string) => string.len(),
+            Object::Function(function) => {
+                function.chunk.code.len()
+                    + function.chunk.constants.len() * std::mem::size_of::<Value>()
+                    + function.caches.len() * std::mem::size_of::<InlineCache>()
+            }
+            Object::Closure(closure) => closure.upvalues.len() * std::mem::size_of::<ObjRef>(),
+            Object::Class(class) => class.methods.len() * 2 * std::mem::size_of::<ObjRef>(),
+            Object::Instance(instance) => instance.fields.len() * std::mem::size_of::<Value>(),
+            Object::Upvalue(_) | Object::Native(_) | Object::BoundMethod(_) => 0,
+        };
+
+        std::mem::size_of::<Entry>() + owned
+    }
+}
+
+/// A compiled function, the top-level script is a function without a name
+#[derive(Debug, Default)]
+pub struct ObjFunction {
+    pub name: Option<ObjRef>,
+    pub arity: usize,
+    /// Number of variables captured from enclosing functions
+    pub upvalue_count: usize,
+    pub chunk: Chunk,
+    /// One inline cache per property access and method call site
+    pub caches: Vec<InlineCache>,
+    /// Size of a frame's register window, only used by the register machine
+    pub registers: usize,
+}
+
+/// A function together with the variables it captured, what Lox code calls
+#[derive(Debug)]
+pub struct ObjClosure {
+    pub function: ObjRef,
+    pub upvalues: Vec<ObjRef>,
+}
+
+/// A captured variable, on the stack while its scope is alive and moved
+/// into the upvalue once the scope ends
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ObjUpvalue {
+    /// Index of the variable's stack slot
+    Open(usize),
+    Closed(Value),
+}
+
+/// Signature of functions implemented in Rust, errors become runtime errors
+pub type NativeFn = fn(&mut Heap, &[Value]) -> Result<Value, String>;
+
+#[derive(Debug)]
+pub struct ObjNative {
+    pub name: ObjRef,
+    pub arity: usize,
+    pub function: NativeFn,
+}
+
+#[derive(Debug)]
+pub struct ObjClass {
+    pub name: ObjRef,
+    /// Methods by name, including those copied from the superclass
+    pub methods: HashMap<ObjRef, ObjRef>,
+}
+
+#[derive(Debug)]
+pub struct ObjInstance {
+    pub class: ObjRef,
+    /// Where each field is in `fields`
+    pub shape: ShapeId,
+    pub fields: Vec<Value>,
+}
+
+/// A method read from an instance, remembering the instance as `this`
+#[derive(Debug)]
+pub struct ObjBoundMethod {
+    pub receiver: Value,
+    pub method: ObjRef,
+}
+
+/// Bytes allocated before the first collection
+const INITIAL_NEXT_GC: usize = 1024 * 1024;
+
+/// Storage for every object created by the compiler and at runtime, freed by
+/// the garbage collector once unreachable
+#[derive(Debug)]
+pub struct Heap {
+    /// Slots of freed objects are `None` until reused
+    pub(super) objects: Vec<Option<Entry>>,
+    /// Indices of the free slots in `objects`
+    pub(super) free: Vec<u32>,
+    /// Interned strings, so equal strings share one object. The table does
+    /// not keep strings alive, unreachable ones are removed on collection.
+    pub(super) strings: HashMap<Box<str>, ObjRef>,
+    /// Objects marked reachable but whose references are not traced yet
+    pub(super) gray: Vec<ObjRef>,
+    pub(super) bytes_allocated: usize,
+    /// Collect once `bytes_allocated` grows past this
+    pub(super) next_gc: usize,
+    /// Collect before every allocation
+    pub(super) stress: bool,
+    pub(super) mode: GcMode,
+    /// The collection in progress, or the last one
+    pub(super) collection: Collection,
+    /// Objects allocated since the last generational collection
+    pub(super) nursery: Vec<ObjRef>,
+    pub(super) young_bytes: usize,
+    /// Old objects written to since the last collection, which may refer to
+    /// young objects
+    pub(super) remembered: Vec<ObjRef>,
+}
+
+#[derive(Debug)]
+pub(super) struct Entry {
+    pub(super) object: Object,
+    /// Size accounted for the object when it was allocated
+    pub(super) size: usize,
+    pub(super) marked: bool,
+    /// Survived a generational collection, so minor collections skip it
+    pub(super) old: bool,
+    /// Already in the heap's remembered set
+    pub(super) remembered: bool,
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Heap {
+            objects: Vec::new(),
+            free: Vec::new(),
+            strings: HashMap::new(),
+            gray: Vec::new(),
+            bytes_allocated: 0,
+            next_gc: INITIAL_NEXT_GC,
+            stress: false,
+            mode: GcMode::MarkSweep,
+            collection: Collection::Major,
+            nursery: Vec::new(),
+            young_bytes: 0,
+            remembered: Vec::new(),
+        }
+    }
+}
+
+impl Heap {
+    pub fn new() -> Heap {
+        Heap::default()
+    }
+
+    pub fn alloc(&mut self, object: Object) -> ObjRef {
+        let size = object.size();
+        self.bytes_allocated += size;
+
+        let entry = Some(Entry {
+            object,
+            size,
+            marked: false,
+            old: false,
+            remembered: false,
+        });
+        let reference = match self.free.pop() {
+            Some(index) => {
+                self.objects[index as usize] = entry;
+                ObjRef(index)
+            }
+            None => {
+                self.objects.push(entry);
+                ObjRef(self.objects.len() as u32 - 1)
+            }
+        };
+
+        if self.mode == GcMode::Generational {
+            self.nursery.push(reference);
+            self.young_bytes += size;
+        }
+        reference
+    }
+
+    /// Return the string object with these contents, creating it if needed
+    pub fn intern(&mut self, chars: &str) -> ObjRef {
+        if let Some(reference) = self.strings.get(chars) {
+            return *reference;
+        }
+
+        let reference = self.alloc(Object::String(chars.into()));
+        self.strings.insert(chars.into(), reference);
+        reference
+    }
+
+    pub fn get(&self, reference: ObjRef) -> &Object {
+        match &self.objects[reference.0 as usize] {
+            Some(entry) => &entry.object,
+            None => panic!("use of freed object {:?}", reference),
+        }
+    }
+
+    pub fn get_mut(&mut self, reference: ObjRef) -> &mut Object {
+        match &mut self.objects[reference.0 as usize] {
+            Some(entry) => &mut entry.object,
+            None => panic!("use of freed object {:?}", reference),
+        }
+    }
+
+    /// Contents of a string object, panics on any other object
+    pub fn string(&self, reference: ObjRef) -> &str {
+        match self.get(reference) {
+            Object::String(string) => string,
+            object => panic!("expected a string, found {:?}", object),
+        }
+    }
+
+    /// Name of a function, class or native for messages, `script` for the top level
+    pub fn name_of(&self, reference: ObjRef) -> &str {
+        let name = match self.get(reference) {
+            Object::Function(function) => function.name,
+            Object::Closure(closure) => return self.name_of(closure.function),
+            Object::Native(native) => Some(native.name),
+            Object::Class(class) => Some(class.name),
+            Object::BoundMethod(bound) => return self.name_of(bound.method),
+            _ => None,
+        };
+
+        name.map_or("script", |name| self.string(name))
+    }
+
+    /// A function object, panics on any other object
+    pub fn function(&self, reference: ObjRef) -> &ObjFunction {
+        match self.get(reference) {
+            Object::Function(function) => function,
+            object => panic!("expected a function, found {:?}", object),
+        }
+    }
+
+    /// A closure object, panics on any other object
+    pub fn closure(&self, reference: ObjRef) -> &ObjClosure {
+        match self.get(reference) {
+            Object::Closure(closure) => closure,
+            object => panic!("expected a closure, found {:?}", object),
+        }
+    }
+}
@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+use std::time::Instant;
+
+use crate::bytecode::chunk::Chunk;
+use crate::bytecode::gc::GcStats;
+use crate::bytecode::object::{Heap, NativeFn, ObjClass, ObjNative, ObjRef, ObjUpvalue, Object};
+use crate::bytecode::shape::Shapes;
+use crate::bytecode::value::Value;
+use crate::bytecode::vm::{TraceFrame, VmError, VmRuntimeError};
+
+/// A function call in progress, as far as the shared runtime needs to know
+pub(crate) trait Frame {
+    fn closure(&self) -> ObjRef;
+    /// Offset of the next instruction in the closure's chunk
+    fn ip(&self) -> usize;
+}
+
+/// State of a running program shared by the stack and register machines,
+/// with the helpers that only depend on it: allocation and garbage
+/// collection, upvalues, globals and error traces
+#[derive(Debug)]
+pub(crate) struct Runtime<F> {
+    pub(crate) heap: Heap,
+    /// The value stack, or the register windows of every frame
+    pub(crate) slots: Vec<Value>,
+    pub(crate) frames: Vec<F>,
+    pub(crate) globals: HashMap<ObjRef, Value>,
+    /// Upvalues still pointing into `slots`, ordered by slot
+    pub(crate) open_upvalues: Vec<ObjRef>,
+    /// Interned `"init"`, looked up on every class call
+    pub(crate) init_string: ObjRef,
+    pub(crate) gc_stats: GcStats,
+    pub(crate) shapes: Shapes,
+}
+
+impl<F: Frame> Runtime<F> {
+    pub(crate) fn new() -> Runtime<F> {
+        let mut heap = Heap::new();
+        let init_string = heap.intern("init");
+
+        Runtime {
+            heap,
+            slots: Vec::new(),
+            frames: Vec::new(),
+            globals: HashMap::new(),
+            open_upvalues: Vec::new(),
+            init_string,
+            gc_stats: GcStats::default(),
+            shapes: Shapes::new(),
+        }
+    }
+
+    /// Drop the calls of a script that failed, globals are kept
+    pub(crate) fn reset(&mut self) {
+        self.slots.clear();
+        self.frames.clear();
+        self.open_upvalues.clear();
+    }
+
+    pub(crate) fn define_native(&mut self, name: &str, arity: usize, function: NativeFn) {
+        let name = self.heap.intern(name);
+        let native = self.heap.alloc(Object::Native(ObjNative {
+            name,
+            arity,
+            function,
+        }));
+        self.globals.insert(name, Value::object(native));
+    }
+
+    pub(crate) fn global(&mut self, name: &str) -> Option<Value> {
+        let name = self.heap.intern(name);
+        self.globals.get(&name).copied()
+    }
+
+    /// Allocate an object, collecting garbage first when the heap has grown
+    pub(crate) fn alloc(&mut self, object: Object) -> ObjRef {
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+
+        self.heap.alloc(object)
+    }
+
+    pub(crate) fn intern(&mut self, chars: &str) -> ObjRef {
+        if self.heap.should_collect() {
+            self.collect_garbage();
+        }
+
+        self.heap.intern(chars)
+    }
+
+    /// Mark the roots, everything the running program can still reach from
+    /// outside the heap, and free the rest
+    fn collect_garbage(&mut self) {
+        let start = Instant::now();
+        let collection = self.heap.begin_collection();
+
+        for value in &self.slots {
+            self.heap.mark_value(*value);
+        }
+        for frame in &self.frames {
+            self.heap.mark_object(frame.closure());
+        }
+        for upvalue in &self.open_upvalues {
+            self.heap.mark_object(*upvalue);
+        }
+        for (name, value) in &self.globals {
+            self.heap.mark_object(*name);
+            self.heap.mark_value(*value);
+        }
+        self.heap.mark_object(self.init_string);
+        // Shapes outlive the instances using them, and so do their field names.
+        for name in self.shapes.names() {
+            self.heap.mark_object(name);
+        }
+
+        let freed = self.heap.finish_collection();
+        self.gc_stats.record(collection, start.elapsed(), freed);
+    }
+
+    /// The upvalue for a slot, shared by every closure capturing it
+    pub(crate) fn capture_upvalue(&mut self, slot: usize) -> ObjRef {
+        let position = self
+            .open_upvalues
+            .partition_point(|upvalue| self.open_slot(*upvalue) < slot);
+
+        if let Some(upvalue) = self.open_upvalues.get(position) {
+            if self.open_slot(*upvalue) == slot {
+                return *upvalue;
+            }
+        }
+
+        let upvalue = self.alloc(Object::Upvalue(ObjUpvalue::Open(slot)));
+        self.open_upvalues.insert(position, upvalue);
+        upvalue
+    }
+
+    /// Move every variable at or above `last` out of the slots into its upvalue
+    pub(crate) fn close_upvalues(&mut self, last: usize) {
+        while let Some(&upvalue) = self.open_upvalues.last() {
+            let slot = self.open_slot(upvalue);
+            if slot < last {
+                break;
+            }
+
+            let value = self.slots[slot];
+            *self.object_mut(upvalue) = Object::Upvalue(ObjUpvalue::Closed(value));
+            self.open_upvalues.pop();
+        }
+    }
+
+    pub(crate) fn upvalue(&self, reference: ObjRef) -> ObjUpvalue {
+        match self.heap.get(reference) {
+            Object::Upvalue(upvalue) => *upvalue,
+            object => panic!("expected an upvalue, found {:?}", object),
+        }
+    }
+
+    /// Slot of an upvalue in `open_upvalues`
+    fn open_slot(&self, reference: ObjRef) -> usize {
+        match self.upvalue(reference) {
+            ObjUpvalue::Open(slot) => slot,
+            ObjUpvalue::Closed(_) => panic!("closed upvalue in the open list"),
+        }
+    }
+
+    /// Attach the current line and a stack trace to an error
+    pub(crate) fn runtime_error(&self, error: VmError) -> VmRuntimeError {
+        let trace: Vec<TraceFrame> = self
+            .frames
+            .iter()
+            .rev()
+            .map(|frame| TraceFrame {
+                function: self.heap.name_of(frame.closure()).to_string(),
+                line: self
+                    .chunk(frame.closure())
+                    .line(frame.ip().saturating_sub(1)),
+            })
+            .collect();
+
+        VmRuntimeError {
+            error,
+            line: trace.first().map_or(0, |frame| frame.line),
+            trace,
+        }
+    }
+
+    pub(crate) fn undefined_variable(&self, name: ObjRef) -> VmError {
+        VmError::UndefinedVariable(self.heap.string(name).to_string())
+    }
+
+    pub(crate) fn undefined_property(&self, name: ObjRef) -> VmError {
+        VmError::UndefinedProperty(self.heap.string(name).to_string())
+    }
+
+    pub(crate) fn find_method(
+        &self,
+        class: ObjRef,
+        name: ObjRef,
+    ) -> Result<Option<ObjRef>, VmError> {
+        match self.heap.get(class) {
+            Object::Class(class) => Ok(class.methods.get(&name).copied()),
+            _ => Err(VmError::SuperclassMustBeClass),
+        }
+    }
+
+    /// The object a value refers to when it is an instance
+    pub(crate) fn as_instance(&self, value: Value) -> Option<ObjRef> {
+        let reference = value.as_object()?;
+        matches!(self.heap.get(reference), Object::Instance(_)).then_some(reference)
+    }
+
+    pub(crate) fn class_mut(&mut self, reference: ObjRef) -> &mut ObjClass {
+        match self.object_mut(reference) {
+            Object::Class(class) => class,
+            object => panic!("expected a class, found {:?}", object),
+        }
+    }
+
+    /// An object about to be modified, passed through the collector's write barrier
+    pub(crate) fn object_mut(&mut self, reference: ObjRef) -> &mut Object {
+        self.heap.write_barrier(reference);
+        self.heap.get_mut(reference)
+    }
+
+    pub(crate) fn frame(&self) -> &F {
+        self.frames.last().expect("call frame")
+    }
+
+    pub(crate) fn frame_mut(&mut self) -> &mut F {
+        self.frames.last_mut().expect("call frame")
+    }
+
+    pub(crate) fn chunk(&self, closure: ObjRef) -> &Chunk {
+        &self
+            .heap
+            .function(self.heap.closure(closure).function)
+            .chunk
+    }
+}
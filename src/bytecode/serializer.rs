@@ -0,0 +1,237 @@
+//! Binary format of precompiled scripts (`.loxc`). A file is the magic bytes
+//! and the format version followed by the script function. Integers are
+//! little endian, and a function is written as
+//!
+//! - its name, a `0` byte for the script or `1` and a string
+//! - arity and upvalue count as `u16`
+//! - the number of inline caches as a `u32`, they start out empty
+//! - the code as a `u32` length and the bytes
+//! - the line table as a `u32` count of `u32` line and `u32` count pairs
+//! - the constants as a `u16` count, each a tag byte and its payload
+//!
+//! Strings are a `u32` length and UTF-8 bytes, nested functions are written
+//! in place as constants.
+
+use crate::bytecode::chunk::{Chunk, LineRun};
+use crate::bytecode::object::{Heap, ObjFunction, ObjRef, Object};
+use crate::bytecode::shape::InlineCache;
+use crate::bytecode::value::Value;
+
+pub const MAGIC: &[u8; 4] = b"LOXC";
+/// Bumped whenever the format or the instruction set changes
+pub const FORMAT_VERSION: u16 = 2;
+
+/// Deepest nesting of functions read back, the reader recurses on each level
+const MAX_NESTING: usize = 256;
+
+const TAG_NULL: u8 = 0;
+const TAG_FALSE: u8 = 1;
+const TAG_TRUE: u8 = 2;
+const TAG_NUMBER: u8 = 3;
+const TAG_STRING: u8 = 4;
+const TAG_FUNCTION: u8 = 5;
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum LoadError {
+    #[error("not a compiled lox file")]
+    NotCompiled,
+    #[error("unsupported bytecode version {0}, expected {FORMAT_VERSION}")]
+    UnsupportedVersion(u16),
+    #[error("unexpected end of file at byte {0}")]
+    Truncated(usize),
+    #[error("invalid utf-8 in string at byte {0}")]
+    InvalidString(usize),
+    #[error("unknown constant tag {0} at byte {1}")]
+    UnknownConstant(u8, usize),
+    #[error("functions nested too deeply at byte {0}")]
+    TooDeep(usize),
+    #[error("{0} unexpected bytes after the script")]
+    TrailingBytes(usize),
+}
+
+/// Whether the bytes start like a compiled file
+pub fn is_compiled(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Write a compiled script and every function nested in it
+pub fn serialize(script: ObjRef, heap: &Heap) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend(MAGIC);
+    out.extend(FORMAT_VERSION.to_le_bytes());
+    write_function(heap.function(script), heap, &mut out);
+    out
+}
+
+fn write_function(function: &ObjFunction, heap: &Heap, out: &mut Vec<u8>) {
+    match function.name {
+        Some(name) => {
+            out.push(1);
+            write_string(heap.string(name), out);
+        }
+        None => out.push(0),
+    }
+    out.extend((function.arity as u16).to_le_bytes());
+    out.extend((function.upvalue_count as u16).to_le_bytes());
+    out.extend((function.caches.len() as u32).to_le_bytes());
+
+    let chunk = &function.chunk;
+    out.extend((chunk.code.len() as u32).to_le_bytes());
+    out.extend(&chunk.code);
+
+    out.extend((chunk.lines().len() as u32).to_le_bytes());
+    for run in chunk.lines() {
+        out.extend(run.line.to_le_bytes());
+        out.extend(run.count.to_le_bytes());
+    }
+
+    out.extend((chunk.constants.len() as u16).to_le_bytes());
+    for constant in &chunk.constants {
+        write_constant(*constant, heap, out);
+    }
+}
+
+fn write_constant(value: Value, heap: &Heap, out: &mut Vec<u8>) {
+    if value.is_null() {
+        out.push(TAG_NULL);
+    } else if let Some(boolean) = value.as_boolean() {
+        out.push(if boolean { TAG_TRUE } else { TAG_FALSE });
+    } else if let Some(number) = value.as_number() {
+        out.push(TAG_NUMBER);
+        out.extend(number.to_le_bytes());
+    } else if let Some(reference) = value.as_object() {
+        match heap.get(reference) {
+            Object::String(string) => {
+                out.push(TAG_STRING);
+                write_string(string, out);
+            }
+            Object::Function(function) => {
+                out.push(TAG_FUNCTION);
+                write_function(function, heap, out);
+            }
+            object => panic!("compiled constants are never {:?}", object),
+        }
+    }
+}
+
+fn write_string(string: &str, out: &mut Vec<u8>) {
+    out.extend((string.len() as u32).to_le_bytes());
+    out.extend(string.as_bytes());
+}
+
+/// Read a compiled script into the heap. The result is not verified yet.
+pub fn deserialize(bytes: &[u8], heap: &mut Heap) -> Result<ObjRef, LoadError> {
+    let mut reader = Reader { bytes, position: 0 };
+
+    if reader.take(MAGIC.len()).ok() != Some(MAGIC.as_slice()) {
+        return Err(LoadError::NotCompiled);
+    }
+    let version = reader.u16()?;
+    if version != FORMAT_VERSION {
+        return Err(LoadError::UnsupportedVersion(version));
+    }
+
+    let script = reader.function(heap, 0)?;
+    match bytes.len() - reader.position {
+        0 => Ok(script),
+        trailing => Err(LoadError::TrailingBytes(trailing)),
+    }
+}
+
+struct Reader<'b> {
+    bytes: &'b [u8],
+    position: usize,
+}
+
+impl<'b> Reader<'b> {
+    fn take(&mut self, count: usize) -> Result<&'b [u8], LoadError> {
+        let end = self
+            .position
+            .checked_add(count)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(LoadError::Truncated(self.bytes.len()))?;
+
+        let taken = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(taken)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], LoadError> {
+        Ok(self.take(N)?.try_into().expect("slice of N bytes"))
+    }
+
+    fn u8(&mut self) -> Result<u8, LoadError> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, LoadError> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, LoadError> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn string(&mut self, heap: &mut Heap) -> Result<ObjRef, LoadError> {
+        let length = self.u32()? as usize;
+        let start = self.position;
+        let string =
+            std::str::from_utf8(self.take(length)?).map_err(|_| LoadError::InvalidString(start))?;
+
+        Ok(heap.intern(string))
+    }
+
+    fn function(&mut self, heap: &mut Heap, depth: usize) -> Result<ObjRef, LoadError> {
+        if depth > MAX_NESTING {
+            return Err(LoadError::TooDeep(self.position));
+        }
+
+        let name = match self.u8()? {
+            0 => None,
+            _ => Some(self.string(heap)?),
+        };
+        let arity = self.u16()? as usize;
+        let upvalue_count = self.u16()? as usize;
+        let cache_count = self.u32()?;
+
+        let code_length = self.u32()? as usize;
+        let code = self.take(code_length)?.to_vec();
+
+        let line_count = self.u32()? as usize;
+        let mut lines = Vec::new();
+        for _ in 0..line_count {
+            let line = self.u32()?;
+            let count = self.u32()?;
+            lines.push(LineRun { line, count });
+        }
+
+        let constant_count = self.u16()? as usize;
+        let mut constants = Vec::new();
+        for _ in 0..constant_count {
+            constants.push(self.constant(heap, depth)?);
+        }
+
+        Ok(heap.alloc(Object::Function(ObjFunction {
+            name,
+            arity,
+            upvalue_count,
+            chunk: Chunk::from_parts(code, constants, lines),
+            // Cache indices are two bytes, more caches could never be used.
+            caches: vec![InlineCache::Empty; cache_count.min(u16::MAX as u32 + 1) as usize],
+            ..ObjFunction::default()
+        })))
+    }
+
+    fn constant(&mut self, heap: &mut Heap, depth: usize) -> Result<Value, LoadError> {
+        let position = self.position;
+        match self.u8()? {
+            TAG_NULL => Ok(Value::NULL),
+            TAG_FALSE => Ok(Value::boolean(false)),
+            TAG_TRUE => Ok(Value::boolean(true)),
+            TAG_NUMBER => Ok(Value::number(f64::from_le_bytes(self.array()?))),
+            TAG_STRING => Ok(Value::object(self.string(heap)?)),
+            TAG_FUNCTION => Ok(Value::object(self.function(heap, depth + 1)?)),
+            tag => Err(LoadError::UnknownConstant(tag, position)),
+        }
+    }
+}
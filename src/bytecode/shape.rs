@@ -0,0 +1,157 @@
+//! Hidden classes and inline caches. Instances keep their fields in a vector
+//! and point to a shape mapping each field name to its slot. Instances given
+//! the same fields in the same order share a shape, so a call site that
+//! remembers the shape it saw last finds the slot again without hashing the
+//! name.
+
+use std::collections::HashMap;
+use std::fmt::Display;
+
+use crate::bytecode::object::ObjRef;
+
+/// Index of a shape in `Shapes`, shapes are never freed so ids are never reused
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ShapeId(u32);
+
+/// The field layout of instances
+#[derive(Debug, Default)]
+struct Shape {
+    /// Slot of every field in the instance's fields
+    slots: HashMap<ObjRef, usize>,
+    /// The shapes reached by adding one more field
+    transitions: HashMap<ObjRef, ShapeId>,
+}
+
+/// Every shape created so far, as a tree rooted at the shape without fields
+#[derive(Debug)]
+pub struct Shapes {
+    shapes: Vec<Shape>,
+}
+
+impl Default for Shapes {
+    fn default() -> Self {
+        Shapes::new()
+    }
+}
+
+impl Shapes {
+    /// Shape of new instances
+    pub const ROOT: ShapeId = ShapeId(0);
+
+    pub fn new() -> Shapes {
+        Shapes {
+            shapes: vec![Shape::default()],
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.shapes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shapes.is_empty()
+    }
+
+    /// Slot of a field in instances of a shape
+    pub fn slot(&self, shape: ShapeId, name: ObjRef) -> Option<usize> {
+        self.shapes[shape.0 as usize].slots.get(&name).copied()
+    }
+
+    /// The shape of an instance once a new field is added, its slot is the
+    /// current number of fields
+    pub fn add_field(&mut self, shape: ShapeId, name: ObjRef) -> ShapeId {
+        if let Some(next) = self.shapes[shape.0 as usize].transitions.get(&name) {
+            return *next;
+        }
+
+        let mut slots = self.shapes[shape.0 as usize].slots.clone();
+        slots.insert(name, slots.len());
+        let next = ShapeId(self.shapes.len() as u32);
+        self.shapes.push(Shape {
+            slots,
+            transitions: HashMap::new(),
+        });
+        self.shapes[shape.0 as usize].transitions.insert(name, next);
+        next
+    }
+
+    /// Every field name, the collector keeps them alive as long as the shapes
+    pub fn names(&self) -> impl Iterator<Item = ObjRef> + '_ {
+        self.shapes
+            .iter()
+            .flat_map(|shape| shape.transitions.keys().copied())
+    }
+}
+
+/// What a property access or method call found the last time it ran
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum InlineCache {
+    #[default]
+    Empty,
+    /// A field of instances with the shape
+    Field { shape: ShapeId, slot: usize },
+    /// A method of the class, valid until a class or method is defined again
+    Method {
+        shape: ShapeId,
+        class: ObjRef,
+        epoch: u64,
+        method: ObjRef,
+    },
+    /// A field added to instances with the shape `from`, giving them `to`
+    Transition { from: ShapeId, to: ShapeId },
+}
+
+/// Hits and misses of the caches of one kind of instruction
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheCounter {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+impl CacheCounter {
+    pub fn record(&mut self, hit: bool) {
+        match hit {
+            true => self.hits += 1,
+            false => self.misses += 1,
+        }
+    }
+
+    /// Share of lookups answered by the cache, from 0 to 1
+    pub fn hit_rate(&self) -> f64 {
+        match self.hits + self.misses {
+            0 => 0.0,
+            total => self.hits as f64 / total as f64,
+        }
+    }
+}
+
+impl Display for CacheCounter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} hits, {} misses, {:.1}% hit rate",
+            self.hits,
+            self.misses,
+            self.hit_rate() * 100.0
+        )
+    }
+}
+
+/// Inline cache counters over the life of a VM
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub get_property: CacheCounter,
+    pub set_property: CacheCounter,
+    pub invoke: CacheCounter,
+    pub shapes: usize,
+}
+
+/// Report printed by `rlox run --profile`
+impl Display for CacheStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "inline caches ({} shapes):", self.shapes)?;
+        writeln!(f, "  get property: {}", self.get_property)?;
+        writeln!(f, "  set property: {}", self.set_property)?;
+        write!(f, "  invoke:       {}", self.invoke)
+    }
+}
@@ -0,0 +1,205 @@
+use std::fmt::{Debug, Display};
+
+use crate::bytecode::object::{Heap, ObjRef, Object};
+
+/// Values of the bytecode backend, objects live in a `Heap`. Strings are
+/// interned, so comparing references compares contents.
+///
+/// With the `nan-boxing` feature a value is a single `u64`: numbers are
+/// stored as their own bits, and everything else hides in the unused bits of
+/// a quiet NaN. Otherwise it is a plain enum, easier to inspect in a debugger.
+#[derive(Clone, Copy)]
+pub struct Value(Repr);
+
+#[cfg(not(feature = "nan-boxing"))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Repr {
+    Null,
+    Boolean(bool),
+    Number(f64),
+    Object(ObjRef),
+}
+
+#[cfg(not(feature = "nan-boxing"))]
+impl Value {
+    pub const NULL: Value = Value(Repr::Null);
+
+    pub fn boolean(value: bool) -> Value {
+        Value(Repr::Boolean(value))
+    }
+
+    pub fn number(value: f64) -> Value {
+        Value(Repr::Number(value))
+    }
+
+    pub fn object(reference: ObjRef) -> Value {
+        Value(Repr::Object(reference))
+    }
+
+    pub fn is_null(self) -> bool {
+        matches!(self.0, Repr::Null)
+    }
+
+    pub fn as_boolean(self) -> Option<bool> {
+        match self.0 {
+            Repr::Boolean(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_number(self) -> Option<f64> {
+        match self.0 {
+            Repr::Number(value) => Some(value),
+            _ => None,
+        }
+    }
+
+    pub fn as_object(self) -> Option<ObjRef> {
+        match self.0 {
+            Repr::Object(reference) => Some(reference),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(not(feature = "nan-boxing"))]
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+#[cfg(feature = "nan-boxing")]
+type Repr = u64;
+
+/// Set for objects only
+#[cfg(feature = "nan-boxing")]
+const SIGN_BIT: u64 = 0x8000_0000_0000_0000;
+/// Exponent bits, the quiet NaN bit and one more, never set together by a number
+#[cfg(feature = "nan-boxing")]
+const QNAN: u64 = 0x7ffc_0000_0000_0000;
+#[cfg(feature = "nan-boxing")]
+const TAG_NULL: u64 = 1;
+#[cfg(feature = "nan-boxing")]
+const TAG_FALSE: u64 = 2;
+#[cfg(feature = "nan-boxing")]
+const TAG_TRUE: u64 = 3;
+
+#[cfg(feature = "nan-boxing")]
+impl Value {
+    pub const NULL: Value = Value(QNAN | TAG_NULL);
+
+    pub fn boolean(value: bool) -> Value {
+        Value(QNAN | if value { TAG_TRUE } else { TAG_FALSE })
+    }
+
+    pub fn number(value: f64) -> Value {
+        // Every NaN shares one encoding, so none looks like a tagged value.
+        if value.is_nan() {
+            return Value(f64::NAN.to_bits());
+        }
+
+        Value(value.to_bits())
+    }
+
+    pub fn object(reference: ObjRef) -> Value {
+        Value(SIGN_BIT | QNAN | reference.0 as u64)
+    }
+
+    pub fn is_null(self) -> bool {
+        self.0 == QNAN | TAG_NULL
+    }
+
+    pub fn as_boolean(self) -> Option<bool> {
+        match self.0 {
+            bits if bits == QNAN | TAG_TRUE => Some(true),
+            bits if bits == QNAN | TAG_FALSE => Some(false),
+            _ => None,
+        }
+    }
+
+    pub fn as_number(self) -> Option<f64> {
+        (self.0 & QNAN != QNAN).then_some(f64::from_bits(self.0))
+    }
+
+    pub fn as_object(self) -> Option<ObjRef> {
+        (self.0 & (SIGN_BIT | QNAN) == SIGN_BIT | QNAN).then_some(ObjRef(self.0 as u32))
+    }
+}
+
+/// Numbers compare as numbers, so NaN is not equal to itself
+#[cfg(feature = "nan-boxing")]
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self.as_number(), other.as_number()) {
+            (Some(a), Some(b)) => a == b,
+            _ => self.0 == other.0,
+        }
+    }
+}
+
+impl Value {
+    /// Only `null` and `false` are falsey
+    pub fn is_falsey(&self) -> bool {
+        self.is_null() || self.as_boolean() == Some(false)
+    }
+
+    /// Format the value the way `print` shows it
+    pub fn display(self, heap: &Heap) -> ValueDisplay<'_> {
+        ValueDisplay { value: self, heap }
+    }
+}
+
+/// Shows the variant the value holds, whatever the representation
+impl Debug for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(value) = self.as_boolean() {
+            f.debug_tuple("Boolean").field(&value).finish()
+        } else if let Some(value) = self.as_number() {
+            f.debug_tuple("Number").field(&value).finish()
+        } else if let Some(reference) = self.as_object() {
+            f.debug_tuple("Object").field(&reference).finish()
+        } else {
+            write!(f, "Null")
+        }
+    }
+}
+
+pub struct ValueDisplay<'h> {
+    value: Value,
+    heap: &'h Heap,
+}
+
+impl Display for ValueDisplay<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(value) = self.value.as_boolean() {
+            return write!(f, "{}", value);
+        }
+        if let Some(value) = self.value.as_number() {
+            return write!(f, "{}", value);
+        }
+        let Some(reference) = self.value.as_object() else {
+            return write!(f, "null");
+        };
+
+        match self.heap.get(reference) {
+            Object::String(string) => write!(f, "{}", string),
+            Object::Function(function) => match function.name {
+                Some(name) => write!(f, "<fn {}>", self.heap.string(name)),
+                None => write!(f, "<script>"),
+            },
+            Object::Closure(closure) => {
+                write!(f, "{}", Value::object(closure.function).display(self.heap))
+            }
+            Object::Upvalue(_) => write!(f, "upvalue"),
+            Object::Native(native) => {
+                write!(f, "<native fn {}>", self.heap.string(native.name))
+            }
+            Object::Class(class) => write!(f, "{}", self.heap.string(class.name)),
+            Object::Instance(instance) => {
+                write!(f, "{} instance", self.heap.name_of(instance.class))
+            }
+            Object::BoundMethod(bound) => write!(f, "<fn {}>", self.heap.name_of(bound.method)),
+        }
+    }
+}
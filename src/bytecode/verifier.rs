@@ -0,0 +1,442 @@
+//! Checks bytecode that did not come from the compiler before the VM runs it.
+//! Every path through a function is followed with the height of the stack,
+//! so an instruction can never read a missing operand, local or constant,
+//! and every jump lands on an instruction. Captured locals are followed too,
+//! they must be closed before they leave the stack.
+
+use crate::bytecode::chunk::{Chunk, OpCode};
+use crate::bytecode::object::{Heap, ObjFunction, ObjRef, Object};
+
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("{kind} at offset {offset} in {function}")]
+pub struct VerifyError {
+    pub kind: VerifyErrorKind,
+    pub offset: usize,
+    pub function: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum VerifyErrorKind {
+    #[error("unknown opcode {0}")]
+    UnknownOpcode(u8),
+    #[error("instruction is cut off by the end of the code")]
+    TruncatedInstruction,
+    #[error("execution runs past the end of the code")]
+    MissingReturn,
+    #[error("jump to {0} does not land on an instruction")]
+    BadJumpTarget(isize),
+    #[error("stack underflow, needs {0} values but has {1}")]
+    StackUnderflow(usize, usize),
+    #[error("stack height is {0} on one path and {1} on another")]
+    InconsistentStack(usize, usize),
+    #[error("captured local {0} is popped without being closed")]
+    UnclosedLocal(usize),
+    #[error("constant {0} is out of range")]
+    ConstantOutOfRange(usize),
+    #[error("constant {0} is not a {1}")]
+    ConstantType(usize, &'static str),
+    #[error("local slot {0} is out of range")]
+    LocalOutOfRange(usize),
+    #[error("upvalue {0} is out of range")]
+    UpvalueOutOfRange(usize),
+    #[error("inline cache {0} is out of range")]
+    CacheOutOfRange(usize),
+    #[error("line table does not cover the code")]
+    BadLineTable,
+}
+
+/// Verify a function and every function nested in its constants
+pub fn verify(function: ObjRef, heap: &Heap) -> Result<(), VerifyError> {
+    let object = heap.function(function);
+    Verifier {
+        function: object,
+        chunk: &object.chunk,
+        heap,
+        name: heap.name_of(function),
+    }
+    .verify()?;
+
+    for constant in &object.chunk.constants {
+        if let Some(nested) = constant.as_object() {
+            if let Object::Function(_) = heap.get(nested) {
+                verify(nested, heap)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+struct Verifier<'h> {
+    function: &'h ObjFunction,
+    chunk: &'h Chunk,
+    heap: &'h Heap,
+    name: &'h str,
+}
+
+/// What an instruction does to the stack and where execution goes next
+struct Step {
+    /// Values that must be on the stack
+    needs: usize,
+    pops: usize,
+    pushes: usize,
+    next: usize,
+    jump: Option<isize>,
+    /// Falls through to `next`, false for returns and unconditional jumps
+    continues: bool,
+    /// Closes the upvalues of the values it pops
+    closes: bool,
+    /// Local slots captured by a new closure
+    captures: Vec<usize>,
+}
+
+/// The stack on entry to an instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct State {
+    height: usize,
+    /// One bit per local slot with an open upvalue, slots are a byte
+    captured: [u64; 4],
+}
+
+impl State {
+    fn is_captured(&self, slot: usize) -> bool {
+        slot < 256 && self.captured[slot / 64] & (1 << (slot % 64)) != 0
+    }
+
+    fn merge(&mut self, other: &State) {
+        for (captured, other) in self.captured.iter_mut().zip(other.captured) {
+            *captured |= other;
+        }
+    }
+
+    fn set_captured(&mut self, slot: usize, captured: bool) {
+        if slot < 256 {
+            let bit = 1 << (slot % 64);
+            match captured {
+                true => self.captured[slot / 64] |= bit,
+                false => self.captured[slot / 64] &= !bit,
+            }
+        }
+    }
+}
+
+impl Verifier<'_> {
+    fn verify(&self) -> Result<(), VerifyError> {
+        let covered: u64 = self.chunk.lines().iter().map(|run| run.count as u64).sum();
+        if covered != self.chunk.code.len() as u64 {
+            return Err(self.error(VerifyErrorKind::BadLineTable, 0));
+        }
+
+        // Jumps must land on one of these.
+        let mut starts = vec![false; self.chunk.code.len() + 1];
+        let mut offset = 0;
+        while offset < self.chunk.code.len() {
+            starts[offset] = true;
+            offset += self.length(offset);
+        }
+
+        // The stack on entry to each instruction, `None` until reached.
+        let mut states: Vec<Option<State>> = vec![None; self.chunk.code.len() + 1];
+        let entry = State {
+            height: self.function.arity + 1,
+            captured: [0; 4],
+        };
+        let mut pending = vec![(0, entry)];
+
+        while let Some((offset, mut state)) = pending.pop() {
+            if let Some(known) = states[offset] {
+                if known.height != state.height {
+                    let kind = VerifyErrorKind::InconsistentStack(known.height, state.height);
+                    return Err(self.error(kind, offset));
+                }
+                // A local captured on any path must be closed, so revisit
+                // until no path adds a capture.
+                state.merge(&known);
+                if known == state {
+                    continue;
+                }
+            }
+            states[offset] = Some(state);
+
+            if offset == self.chunk.code.len() {
+                return Err(self.error(VerifyErrorKind::MissingReturn, offset));
+            }
+
+            let step = self
+                .step(offset, state.height)
+                .map_err(|kind| self.error(kind, offset))?;
+            let state = self
+                .apply(&step, state)
+                .map_err(|kind| self.error(kind, offset))?;
+
+            if let Some(target) = step.jump {
+                if target < 0 || target as usize >= starts.len() || !starts[target as usize] {
+                    return Err(self.error(VerifyErrorKind::BadJumpTarget(target), offset));
+                }
+                pending.push((target as usize, state));
+            }
+            if step.continues {
+                pending.push((step.next, state));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The stack after an instruction, only `OP_CLOSE_UPVALUE` may pop a
+    /// captured local
+    fn apply(&self, step: &Step, mut state: State) -> Result<State, VerifyErrorKind> {
+        if step.needs > state.height {
+            return Err(VerifyErrorKind::StackUnderflow(step.needs, state.height));
+        }
+
+        let lowest = state.height - step.pops;
+        for slot in lowest..state.height {
+            if step.closes {
+                state.set_captured(slot, false);
+            } else if state.is_captured(slot) {
+                return Err(VerifyErrorKind::UnclosedLocal(slot));
+            }
+        }
+
+        state.height = lowest + step.pushes;
+        for slot in &step.captures {
+            state.set_captured(*slot, true);
+        }
+        Ok(state)
+    }
+
+    /// Size of the instruction at `offset` in bytes, whether it is valid or not
+    fn length(&self, offset: usize) -> usize {
+        let code = &self.chunk.code;
+        match OpCode::try_from(code[offset]) {
+            Ok(
+                OpCode::Constant
+                | OpCode::GetLocal
+                | OpCode::SetLocal
+                | OpCode::GetGlobal
+                | OpCode::DefineGlobal
+                | OpCode::SetGlobal
+                | OpCode::GetUpvalue
+                | OpCode::SetUpvalue
+                | OpCode::GetSuper
+                | OpCode::Call
+                | OpCode::Class
+                | OpCode::Method,
+            ) => 2,
+            Ok(OpCode::Jump | OpCode::JumpIfFalse | OpCode::Loop | OpCode::SuperInvoke) => 3,
+            Ok(OpCode::GetProperty | OpCode::SetProperty) => 4,
+            Ok(OpCode::Invoke) => 5,
+            Ok(OpCode::Closure) => {
+                let function = code
+                    .get(offset + 1)
+                    .and_then(|&index| self.chunk.constants.get(index as usize))
+                    .and_then(|constant| constant.as_object())
+                    .map(|reference| self.heap.get(reference));
+                match function {
+                    Some(Object::Function(function)) => 2 + function.upvalue_count * 2,
+                    _ => 2,
+                }
+            }
+            _ => 1,
+        }
+    }
+
+    /// Decode the instruction at `offset`, checking its operands
+    fn step(&self, offset: usize, height: usize) -> Result<Step, VerifyErrorKind> {
+        let code = &self.chunk.code;
+        let op = OpCode::try_from(code[offset]).map_err(VerifyErrorKind::UnknownOpcode)?;
+        let operand = |index: usize| {
+            code.get(offset + index)
+                .copied()
+                .ok_or(VerifyErrorKind::TruncatedInstruction)
+        };
+        let step = |needs, pops, pushes, length| Step {
+            needs,
+            pops,
+            pushes,
+            next: offset + length,
+            jump: None,
+            continues: true,
+            closes: false,
+            captures: Vec::new(),
+        };
+
+        let step = match op {
+            OpCode::Constant => {
+                self.constant(operand(1)?, None)?;
+                step(0, 0, 1, 2)
+            }
+            OpCode::Null | OpCode::True | OpCode::False => step(0, 0, 1, 1),
+            OpCode::Pop | OpCode::Print => step(1, 1, 0, 1),
+            OpCode::CloseUpvalue => Step {
+                closes: true,
+                ..step(1, 1, 0, 1)
+            },
+            OpCode::GetLocal | OpCode::SetLocal => {
+                let slot = operand(1)? as usize;
+                if slot >= height {
+                    return Err(VerifyErrorKind::LocalOutOfRange(slot));
+                }
+                match op {
+                    OpCode::GetLocal => step(0, 0, 1, 2),
+                    _ => step(1, 0, 0, 2),
+                }
+            }
+            OpCode::GetGlobal | OpCode::DefineGlobal | OpCode::SetGlobal => {
+                self.constant(operand(1)?, Some("string"))?;
+                match op {
+                    OpCode::GetGlobal => step(0, 0, 1, 2),
+                    OpCode::DefineGlobal => step(1, 1, 0, 2),
+                    _ => step(1, 0, 0, 2),
+                }
+            }
+            OpCode::GetUpvalue | OpCode::SetUpvalue => {
+                let index = operand(1)? as usize;
+                if index >= self.function.upvalue_count {
+                    return Err(VerifyErrorKind::UpvalueOutOfRange(index));
+                }
+                match op {
+                    OpCode::GetUpvalue => step(0, 0, 1, 2),
+                    _ => step(1, 0, 0, 2),
+                }
+            }
+            OpCode::GetProperty | OpCode::SetProperty => {
+                self.constant(operand(1)?, Some("string"))?;
+                self.cache(operand(2)?, operand(3)?)?;
+                match op {
+                    OpCode::GetProperty => step(1, 1, 1, 4),
+                    _ => step(2, 2, 1, 4),
+                }
+            }
+            OpCode::GetSuper => {
+                self.constant(operand(1)?, Some("string"))?;
+                step(2, 2, 1, 2)
+            }
+            OpCode::Equal
+            | OpCode::Greater
+            | OpCode::Less
+            | OpCode::Add
+            | OpCode::Subtract
+            | OpCode::Multiply
+            | OpCode::Divide => step(2, 2, 1, 1),
+            OpCode::Not | OpCode::Negate => step(1, 1, 1, 1),
+            OpCode::Jump | OpCode::JumpIfFalse | OpCode::Loop => {
+                let jump = u16::from_be_bytes([operand(1)?, operand(2)?]) as isize;
+                let next = offset + 3;
+                let target = match op {
+                    OpCode::Loop => next as isize - jump,
+                    _ => next as isize + jump,
+                };
+
+                Step {
+                    jump: Some(target),
+                    continues: op == OpCode::JumpIfFalse,
+                    ..step(usize::from(op == OpCode::JumpIfFalse), 0, 0, 3)
+                }
+            }
+            OpCode::Call => {
+                let arguments = operand(1)? as usize;
+                step(arguments + 1, arguments + 1, 1, 2)
+            }
+            OpCode::Invoke => {
+                self.constant(operand(1)?, Some("string"))?;
+                let arguments = operand(2)? as usize;
+                self.cache(operand(3)?, operand(4)?)?;
+                step(arguments + 1, arguments + 1, 1, 5)
+            }
+            OpCode::SuperInvoke => {
+                self.constant(operand(1)?, Some("string"))?;
+                // The superclass is on top of the receiver and arguments.
+                let operands = operand(2)? as usize + 2;
+                step(operands, operands, 1, 3)
+            }
+            OpCode::Closure => {
+                let constant = operand(1)?;
+                self.constant(constant, Some("function"))?;
+                let function = self.chunk.constants[constant as usize]
+                    .as_object()
+                    .map(|reference| self.heap.function(reference))
+                    .expect("checked function constant");
+
+                let mut captures = Vec::new();
+                for upvalue in 0..function.upvalue_count {
+                    let is_local = operand(2 + upvalue * 2)? == 1;
+                    let index = operand(3 + upvalue * 2)? as usize;
+                    // A local function captures the slot the closure is pushed into.
+                    if is_local && index > height {
+                        return Err(VerifyErrorKind::LocalOutOfRange(index));
+                    }
+                    if !is_local && index >= self.function.upvalue_count {
+                        return Err(VerifyErrorKind::UpvalueOutOfRange(index));
+                    }
+                    if is_local {
+                        captures.push(index);
+                    }
+                }
+                Step {
+                    captures,
+                    ..step(0, 0, 1, 2 + function.upvalue_count * 2)
+                }
+            }
+            OpCode::Return => Step {
+                continues: false,
+                ..step(1, 1, 0, 1)
+            },
+            OpCode::Class => {
+                self.constant(operand(1)?, Some("string"))?;
+                step(0, 0, 1, 2)
+            }
+            OpCode::Inherit => step(2, 1, 0, 1),
+            OpCode::Method => {
+                self.constant(operand(1)?, Some("string"))?;
+                step(2, 1, 0, 2)
+            }
+        };
+
+        Ok(step)
+    }
+
+    /// Check that a constant exists and, when `expected` is given, its type
+    fn constant(&self, index: u8, expected: Option<&'static str>) -> Result<(), VerifyErrorKind> {
+        let index = index as usize;
+        let value = self
+            .chunk
+            .constants
+            .get(index)
+            .ok_or(VerifyErrorKind::ConstantOutOfRange(index))?;
+
+        let object = value.as_object().map(|reference| self.heap.get(reference));
+        let matches = match expected {
+            None => true,
+            Some("string") => matches!(object, Some(Object::String(_))),
+            Some(_) => matches!(object, Some(Object::Function(_))),
+        };
+        if !matches {
+            return Err(VerifyErrorKind::ConstantType(
+                index,
+                expected.unwrap_or_default(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Check the two byte index of an inline cache
+    fn cache(&self, high: u8, low: u8) -> Result<(), VerifyErrorKind> {
+        let index = u16::from_be_bytes([high, low]) as usize;
+        if index >= self.function.caches.len() {
+            return Err(VerifyErrorKind::CacheOutOfRange(index));
+        }
+
+        Ok(())
+    }
+
+    fn error(&self, kind: VerifyErrorKind, offset: usize) -> VerifyError {
+        VerifyError {
+            kind,
+            offset,
+            function: self.name.to_string(),
+        }
+    }
+}
@@ -0,0 +1,831 @@
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::bytecode::chunk::OpCode;
+use crate::bytecode::disassembler::disassemble_instruction;
+use crate::bytecode::gc::{GcMode, GcStats};
+use crate::bytecode::object::{
+    Heap, NativeFn, ObjBoundMethod, ObjClass, ObjClosure, ObjInstance, ObjRef, ObjUpvalue, Object,
+};
+use crate::bytecode::runtime::{Frame, Runtime};
+use crate::bytecode::shape::{CacheStats, InlineCache, Shapes};
+use crate::bytecode::value::Value;
+
+/// Maximum nesting of calls before reporting a stack overflow, the same as
+/// the tree-walking interpreter
+pub const FRAMES_MAX: usize = 255;
+
+/// Stack-based virtual machine executing compiled chunks
+#[derive(Debug)]
+pub struct Vm {
+    /// The heap, the value stack, the call frames and the globals
+    runtime: Runtime<CallFrame>,
+    /// Print the stack and every instruction before executing it
+    trace: bool,
+    /// Bumped whenever a class or method is defined, invalidating the
+    /// cached method lookups
+    class_epoch: u64,
+    cache_stats: CacheStats,
+}
+
+/// A field value or a method found on an instance
+#[derive(Debug, Clone, Copy)]
+enum Property {
+    Field(Value),
+    Method(ObjRef),
+}
+
+/// A function call in progress
+#[derive(Debug, Clone, Copy)]
+struct CallFrame {
+    closure: ObjRef,
+    /// Offset of the next instruction in the closure's chunk
+    ip: usize,
+    /// Stack index of the frame's slot zero, the called function or `this`
+    slots: usize,
+}
+
+impl Frame for CallFrame {
+    fn closure(&self) -> ObjRef {
+        self.closure
+    }
+
+    fn ip(&self) -> usize {
+        self.ip
+    }
+}
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum VmError {
+    #[error("operand of '{0}' must be a number")]
+    OperandMustBeNumber(&'static str),
+    #[error("operands of '{0}' must be numbers")]
+    OperandsMustBeNumbers(&'static str),
+    #[error("operands of '{0}' must be two numbers or two strings")]
+    OperandsMustBeNumbersOrStrings(&'static str),
+    #[error("undefined variable '{0}'")]
+    UndefinedVariable(String),
+    #[error("can only call functions and classes")]
+    NotCallable,
+    #[error("expected {0} arguments but got {1}")]
+    ArityMismatch(usize, usize),
+    #[error("{0}: {1}")]
+    NativeError(String, String),
+    #[error("stack overflow")]
+    StackOverflow,
+    #[error("undefined property '{0}'")]
+    UndefinedProperty(String),
+    #[error("only instances have properties")]
+    OnlyInstancesHaveProperties,
+    #[error("only instances have fields")]
+    OnlyInstancesHaveFields,
+    #[error("superclass must be a class")]
+    SuperclassMustBeClass,
+    #[error("invalid bytecode: {0}")]
+    InvalidBytecode(String),
+}
+
+/// A runtime error with the line it happened at and the calls leading to it
+#[derive(Debug, Clone, thiserror::Error)]
+#[error("{error} at line: {line}\n{}", trace.iter().map(ToString::to_string).collect::<Vec<_>>().join("\n"))]
+pub struct VmRuntimeError {
+    pub error: VmError,
+    pub line: u32,
+    /// Innermost call first
+    pub trace: Vec<TraceFrame>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceFrame {
+    pub function: String,
+    pub line: u32,
+}
+
+impl Display for TraceFrame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.function == "script" {
+            write!(f, "[line {}] in script", self.line)
+        } else {
+            write!(f, "[line {}] in {}()", self.line, self.function)
+        }
+    }
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Vm::new()
+    }
+}
+
+impl Vm {
+    pub fn new() -> Vm {
+        let mut vm = Vm {
+            runtime: Runtime::new(),
+            trace: false,
+            class_epoch: 0,
+            cache_stats: CacheStats::default(),
+        };
+        vm.define_native("clock", 0, clock);
+        vm
+    }
+
+    /// Objects of the running program, compiled or loaded scripts go there
+    pub fn heap(&self) -> &Heap {
+        &self.runtime.heap
+    }
+
+    pub fn heap_mut(&mut self) -> &mut Heap {
+        &mut self.runtime.heap
+    }
+
+    pub fn set_trace(&mut self, trace: bool) {
+        self.trace = trace;
+    }
+
+    /// Collect garbage before every allocation
+    pub fn set_gc_stress(&mut self, stress: bool) {
+        self.runtime.heap.set_stress(stress);
+    }
+
+    pub fn set_gc_mode(&mut self, mode: GcMode) {
+        self.runtime.heap.set_mode(mode);
+    }
+
+    /// Collections and their pause times so far
+    pub fn gc_stats(&self) -> &GcStats {
+        &self.runtime.gc_stats
+    }
+
+    /// Hit rates of the inline caches so far
+    pub fn cache_stats(&self) -> CacheStats {
+        CacheStats {
+            shapes: self.runtime.shapes.len(),
+            ..self.cache_stats.clone()
+        }
+    }
+
+    /// Define a global function implemented in Rust
+    pub fn define_native(&mut self, name: &str, arity: usize, function: NativeFn) {
+        self.runtime.define_native(name, arity, function);
+    }
+
+    pub fn global(&mut self, name: &str) -> Option<Value> {
+        self.runtime.global(name)
+    }
+
+    /// Run a compiled script, globals are kept for the next one
+    pub fn run(&mut self, script: ObjRef) -> Result<(), VmRuntimeError> {
+        // Keep the function reachable while its closure is allocated.
+        self.runtime.slots.push(Value::object(script));
+        let script = self.runtime.alloc(Object::Closure(ObjClosure {
+            function: script,
+            upvalues: Vec::new(),
+        }));
+        *self.runtime.slots.last_mut().expect("script function") = Value::object(script);
+        let result = self
+            .call(script, 0)
+            .and_then(|()| self.execute())
+            .map_err(|error| self.runtime.runtime_error(error));
+
+        if result.is_err() {
+            self.runtime.reset();
+        }
+        result
+    }
+
+    fn execute(&mut self) -> Result<(), VmError> {
+        loop {
+            if self.trace {
+                self.trace_instruction();
+            }
+
+            let byte = self.read_byte();
+            let op = OpCode::try_from(byte)
+                .map_err(|byte| VmError::InvalidBytecode(format!("unknown opcode {}", byte)))?;
+
+            match op {
+                OpCode::Constant => {
+                    let constant = self.read_constant();
+                    self.push(constant);
+                }
+                OpCode::Null => self.push(Value::NULL),
+                OpCode::True => self.push(Value::boolean(true)),
+                OpCode::False => self.push(Value::boolean(false)),
+                OpCode::Pop => {
+                    self.pop();
+                }
+                OpCode::GetLocal => {
+                    let slot = self.read_byte() as usize;
+                    let value = self.runtime.slots[self.runtime.frame().slots + slot];
+                    self.push(value);
+                }
+                OpCode::SetLocal => {
+                    let slot = self.read_byte() as usize;
+                    let index = self.runtime.frame().slots + slot;
+                    self.runtime.slots[index] = self.peek(0);
+                }
+                OpCode::GetGlobal => {
+                    let name = self.read_string();
+                    match self.runtime.globals.get(&name) {
+                        Some(value) => self.push(*value),
+                        None => return Err(self.runtime.undefined_variable(name)),
+                    }
+                }
+                OpCode::DefineGlobal => {
+                    let name = self.read_string();
+                    let value = self.pop();
+                    self.runtime.globals.insert(name, value);
+                }
+                OpCode::SetGlobal => {
+                    let name = self.read_string();
+                    if !self.runtime.globals.contains_key(&name) {
+                        return Err(self.runtime.undefined_variable(name));
+                    }
+                    self.runtime.globals.insert(name, self.peek(0));
+                }
+                OpCode::GetUpvalue => {
+                    let index = self.read_byte() as usize;
+                    let upvalue = self
+                        .runtime
+                        .heap
+                        .closure(self.runtime.frame().closure)
+                        .upvalues[index];
+                    let value = match self.runtime.upvalue(upvalue) {
+                        ObjUpvalue::Open(slot) => self.runtime.slots[slot],
+                        ObjUpvalue::Closed(value) => value,
+                    };
+                    self.push(value);
+                }
+                OpCode::SetUpvalue => {
+                    let index = self.read_byte() as usize;
+                    let upvalue = self
+                        .runtime
+                        .heap
+                        .closure(self.runtime.frame().closure)
+                        .upvalues[index];
+                    let value = self.peek(0);
+                    match self.runtime.upvalue(upvalue) {
+                        ObjUpvalue::Open(slot) => self.runtime.slots[slot] = value,
+                        ObjUpvalue::Closed(_) => {
+                            *self.runtime.object_mut(upvalue) =
+                                Object::Upvalue(ObjUpvalue::Closed(value))
+                        }
+                    }
+                }
+                OpCode::GetProperty => {
+                    let name = self.read_string();
+                    let site = self.read_short() as usize;
+                    self.get_property(name, site)?;
+                }
+                OpCode::SetProperty => {
+                    let name = self.read_string();
+                    let site = self.read_short() as usize;
+                    self.set_property(name, site)?;
+                }
+                OpCode::GetSuper => {
+                    let name = self.read_string();
+                    let Some(superclass) = self.pop().as_object() else {
+                        return Err(VmError::SuperclassMustBeClass);
+                    };
+                    self.bind_method(superclass, name)?;
+                }
+                OpCode::Equal => {
+                    let b = self.pop();
+                    let a = self.pop();
+                    self.push(Value::boolean(a == b));
+                }
+                OpCode::Greater => self.comparison(">", |a, b| a > b)?,
+                OpCode::Less => self.comparison("<", |a, b| a < b)?,
+                OpCode::Add => self.add()?,
+                OpCode::Subtract => self.arithmetic("-", |a, b| a - b)?,
+                OpCode::Multiply => self.arithmetic("*", |a, b| a * b)?,
+                OpCode::Divide => self.arithmetic("/", |a, b| a / b)?,
+                OpCode::Not => {
+                    let value = self.pop();
+                    self.push(Value::boolean(value.is_falsey()));
+                }
+                OpCode::Negate => {
+                    let Some(value) = self.peek(0).as_number() else {
+                        return Err(VmError::OperandMustBeNumber("-"));
+                    };
+                    self.pop();
+                    self.push(Value::number(-value));
+                }
+                OpCode::Print => {
+                    let value = self.pop();
+                    println!("{}", value.display(&self.runtime.heap));
+                }
+                OpCode::Jump => {
+                    let offset = self.read_short() as usize;
+                    self.runtime.frame_mut().ip += offset;
+                }
+                OpCode::JumpIfFalse => {
+                    let offset = self.read_short() as usize;
+                    if self.peek(0).is_falsey() {
+                        self.runtime.frame_mut().ip += offset;
+                    }
+                }
+                OpCode::Loop => {
+                    let offset = self.read_short() as usize;
+                    self.runtime.frame_mut().ip -= offset;
+                }
+                OpCode::Call => {
+                    let argument_count = self.read_byte() as usize;
+                    self.call_value(self.peek(argument_count), argument_count)?;
+                }
+                OpCode::Invoke => {
+                    let name = self.read_string();
+                    let argument_count = self.read_byte() as usize;
+                    let site = self.read_short() as usize;
+                    self.invoke(name, argument_count, site)?;
+                }
+                OpCode::SuperInvoke => {
+                    let name = self.read_string();
+                    let argument_count = self.read_byte() as usize;
+                    let Some(superclass) = self.pop().as_object() else {
+                        return Err(VmError::SuperclassMustBeClass);
+                    };
+                    self.invoke_from_class(superclass, name, argument_count)?;
+                }
+                OpCode::Closure => {
+                    let Some(function) = self.read_constant().as_object() else {
+                        return Err(VmError::InvalidBytecode("closure of a non-function".into()));
+                    };
+
+                    // On the stack before capturing, so the upvalues captured
+                    // so far stay reachable through it.
+                    let closure = self.runtime.alloc(Object::Closure(ObjClosure {
+                        function,
+                        upvalues: Vec::new(),
+                    }));
+                    self.push(Value::object(closure));
+
+                    for _ in 0..self.runtime.heap.function(function).upvalue_count {
+                        let is_local = self.read_byte() == 1;
+                        let index = self.read_byte() as usize;
+                        let frame = *self.runtime.frame();
+
+                        let upvalue = if is_local {
+                            self.runtime.capture_upvalue(frame.slots + index)
+                        } else {
+                            self.runtime.heap.closure(frame.closure).upvalues[index]
+                        };
+                        if let Object::Closure(closure) = self.runtime.object_mut(closure) {
+                            closure.upvalues.push(upvalue);
+                        }
+                    }
+                }
+                OpCode::CloseUpvalue => {
+                    self.runtime.close_upvalues(self.runtime.slots.len() - 1);
+                    self.pop();
+                }
+                OpCode::Return => {
+                    let result = self.pop();
+                    let frame = self.runtime.frames.pop().expect("frame to return from");
+                    self.runtime.close_upvalues(frame.slots);
+
+                    if self.runtime.frames.is_empty() {
+                        self.runtime.slots.clear();
+                        return Ok(());
+                    }
+
+                    self.runtime.slots.truncate(frame.slots);
+                    self.push(result);
+                }
+                OpCode::Class => {
+                    let name = self.read_string();
+                    // A new class may reuse the handle of a freed one.
+                    self.class_epoch += 1;
+                    let class = self.runtime.alloc(Object::Class(ObjClass {
+                        name,
+                        methods: HashMap::new(),
+                    }));
+                    self.push(Value::object(class));
+                }
+                OpCode::Inherit => {
+                    let superclass = self.peek(1).as_object().map(|r| self.runtime.heap.get(r));
+                    let Some(Object::Class(superclass)) = superclass else {
+                        return Err(VmError::SuperclassMustBeClass);
+                    };
+                    let methods = superclass.methods.clone();
+
+                    // Copy down the methods, so lookups never walk the class chain.
+                    let subclass = self.peek_class(0)?;
+                    self.runtime.class_mut(subclass).methods.extend(methods);
+                    self.class_epoch += 1;
+                    self.pop();
+                }
+                OpCode::Method => {
+                    let name = self.read_string();
+                    let method = self.peek(0).as_object();
+                    let Some(method) =
+                        method.filter(|&r| matches!(self.runtime.heap.get(r), Object::Closure(_)))
+                    else {
+                        return Err(VmError::InvalidBytecode("method is not a closure".into()));
+                    };
+                    let class = self.peek_class(1)?;
+                    self.runtime.class_mut(class).methods.insert(name, method);
+                    self.class_epoch += 1;
+                    self.pop();
+                }
+            }
+        }
+    }
+
+    fn call_value(&mut self, callee: Value, argument_count: usize) -> Result<(), VmError> {
+        let Some(reference) = callee.as_object() else {
+            return Err(VmError::NotCallable);
+        };
+
+        match self.runtime.heap.get(reference) {
+            Object::Closure(_) => self.call(reference, argument_count),
+            Object::Native(native) => {
+                let (name, arity, function) = (native.name, native.arity, native.function);
+                if argument_count != arity {
+                    return Err(VmError::ArityMismatch(arity, argument_count));
+                }
+
+                let arguments_start = self.runtime.slots.len() - argument_count;
+                let arguments = self.runtime.slots[arguments_start..].to_vec();
+                let result = function(&mut self.runtime.heap, &arguments).map_err(|message| {
+                    VmError::NativeError(self.runtime.heap.string(name).to_string(), message)
+                })?;
+
+                self.runtime.slots.truncate(arguments_start - 1);
+                self.push(result);
+                Ok(())
+            }
+            Object::Class(class) => {
+                let initializer = class.methods.get(&self.runtime.init_string).copied();
+                let instance = self.runtime.alloc(Object::Instance(ObjInstance {
+                    class: reference,
+                    shape: Shapes::ROOT,
+                    fields: Vec::new(),
+                }));
+
+                let slot = self.runtime.slots.len() - argument_count - 1;
+                self.runtime.slots[slot] = Value::object(instance);
+
+                match initializer {
+                    Some(initializer) => self.call(initializer, argument_count),
+                    None if argument_count != 0 => Err(VmError::ArityMismatch(0, argument_count)),
+                    None => Ok(()),
+                }
+            }
+            Object::BoundMethod(bound) => {
+                let (receiver, method) = (bound.receiver, bound.method);
+                let slot = self.runtime.slots.len() - argument_count - 1;
+                self.runtime.slots[slot] = receiver;
+                self.call(method, argument_count)
+            }
+            _ => Err(VmError::NotCallable),
+        }
+    }
+
+    /// Push a frame for a closure whose arguments are on the stack
+    fn call(&mut self, closure: ObjRef, argument_count: usize) -> Result<(), VmError> {
+        let arity = self
+            .runtime
+            .heap
+            .function(self.runtime.heap.closure(closure).function)
+            .arity;
+        if argument_count != arity {
+            return Err(VmError::ArityMismatch(arity, argument_count));
+        }
+
+        if self.runtime.frames.len() >= FRAMES_MAX {
+            return Err(VmError::StackOverflow);
+        }
+
+        self.runtime.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            slots: self.runtime.slots.len() - argument_count - 1,
+        });
+        Ok(())
+    }
+
+    /// Replace the instance on top of the stack with the value of one of
+    /// its fields, or one of its methods bound to it
+    fn get_property(&mut self, name: ObjRef, site: usize) -> Result<(), VmError> {
+        let instance = self
+            .runtime
+            .as_instance(self.peek(0))
+            .ok_or(VmError::OnlyInstancesHaveProperties)?;
+
+        let (property, hit) = self.lookup(instance, name, site);
+        self.cache_stats.get_property.record(hit);
+        match property.ok_or_else(|| self.runtime.undefined_property(name))? {
+            Property::Field(value) => {
+                self.pop();
+                self.push(value);
+                Ok(())
+            }
+            Property::Method(method) => self.bind(method),
+        }
+    }
+
+    /// Store the value on top of the stack in a field of the instance below it
+    fn set_property(&mut self, name: ObjRef, site: usize) -> Result<(), VmError> {
+        let Some(reference) = self.peek(1).as_object() else {
+            return Err(VmError::OnlyInstancesHaveFields);
+        };
+        let Object::Instance(instance) = self.runtime.heap.get(reference) else {
+            return Err(VmError::OnlyInstancesHaveFields);
+        };
+        let shape = instance.shape;
+
+        let cache = match self.cache(site) {
+            cache @ InlineCache::Field { shape: cached, .. } if cached == shape => Some(cache),
+            cache @ InlineCache::Transition { from, .. } if from == shape => Some(cache),
+            _ => None,
+        };
+        self.cache_stats.set_property.record(cache.is_some());
+        let cache = cache.unwrap_or_else(|| {
+            let cache = match self.runtime.shapes.slot(shape, name) {
+                Some(slot) => InlineCache::Field { shape, slot },
+                None => InlineCache::Transition {
+                    from: shape,
+                    to: self.runtime.shapes.add_field(shape, name),
+                },
+            };
+            self.set_cache(site, cache);
+            cache
+        });
+
+        let value = self.peek(0);
+        let Object::Instance(instance) = self.runtime.object_mut(reference) else {
+            unreachable!("checked instance");
+        };
+        match cache {
+            InlineCache::Field { slot, .. } => instance.fields[slot] = value,
+            InlineCache::Transition { to, .. } => {
+                instance.fields.push(value);
+                instance.shape = to;
+            }
+            _ => unreachable!("cache of a field"),
+        }
+
+        self.pop();
+        self.pop();
+        self.push(value);
+        Ok(())
+    }
+
+    /// Call a method directly, without creating a bound method
+    fn invoke(&mut self, name: ObjRef, argument_count: usize, site: usize) -> Result<(), VmError> {
+        let instance = self
+            .runtime
+            .as_instance(self.peek(argument_count))
+            .ok_or(VmError::OnlyInstancesHaveProperties)?;
+
+        let (property, hit) = self.lookup(instance, name, site);
+        self.cache_stats.invoke.record(hit);
+        match property.ok_or_else(|| self.runtime.undefined_property(name))? {
+            // A field holding a function shadows the method.
+            Property::Field(field) => {
+                let slot = self.runtime.slots.len() - argument_count - 1;
+                self.runtime.slots[slot] = field;
+                self.call_value(field, argument_count)
+            }
+            Property::Method(method) => self.call(method, argument_count),
+        }
+    }
+
+    /// Find a field or method of an instance through the inline cache of a
+    /// call site, updating the cache on a miss. Also tells whether it was a hit.
+    fn lookup(&mut self, instance: ObjRef, name: ObjRef, site: usize) -> (Option<Property>, bool) {
+        let Object::Instance(instance) = self.runtime.heap.get(instance) else {
+            unreachable!("checked instance");
+        };
+        let (class, shape) = (instance.class, instance.shape);
+
+        match self.cache(site) {
+            InlineCache::Field {
+                shape: cached,
+                slot,
+            } if cached == shape => {
+                return (Some(Property::Field(instance.fields[slot])), true);
+            }
+            InlineCache::Method {
+                shape: cached,
+                class: cached_class,
+                epoch,
+                method,
+            } if cached == shape && cached_class == class && epoch == self.class_epoch => {
+                return (Some(Property::Method(method)), true);
+            }
+            _ => {}
+        }
+
+        if let Some(slot) = self.runtime.shapes.slot(shape, name) {
+            let value = instance.fields[slot];
+            self.set_cache(site, InlineCache::Field { shape, slot });
+            return (Some(Property::Field(value)), false);
+        }
+
+        let method = match self.runtime.heap.get(class) {
+            Object::Class(class) => class.methods.get(&name).copied(),
+            _ => None,
+        };
+        if let Some(method) = method {
+            let epoch = self.class_epoch;
+            self.set_cache(
+                site,
+                InlineCache::Method {
+                    shape,
+                    class,
+                    epoch,
+                    method,
+                },
+            );
+        }
+        (method.map(Property::Method), false)
+    }
+
+    fn invoke_from_class(
+        &mut self,
+        class: ObjRef,
+        name: ObjRef,
+        argument_count: usize,
+    ) -> Result<(), VmError> {
+        match self.runtime.find_method(class, name)? {
+            Some(method) => self.call(method, argument_count),
+            None => Err(self.runtime.undefined_property(name)),
+        }
+    }
+
+    /// Replace the instance on top of the stack with its method bound to it
+    fn bind_method(&mut self, class: ObjRef, name: ObjRef) -> Result<(), VmError> {
+        match self.runtime.find_method(class, name)? {
+            Some(method) => self.bind(method),
+            None => Err(self.runtime.undefined_property(name)),
+        }
+    }
+
+    /// Replace the receiver on top of the stack with a method bound to it
+    fn bind(&mut self, method: ObjRef) -> Result<(), VmError> {
+        let receiver = self.peek(0);
+        let bound = self
+            .runtime
+            .alloc(Object::BoundMethod(ObjBoundMethod { receiver, method }));
+        self.pop();
+        self.push(Value::object(bound));
+        Ok(())
+    }
+
+    fn add(&mut self) -> Result<(), VmError> {
+        let (a, b) = (self.peek(1), self.peek(0));
+        if let (Some(a), Some(b)) = (a.as_number(), b.as_number()) {
+            self.pop();
+            self.pop();
+            self.push(Value::number(a + b));
+            return Ok(());
+        }
+
+        match (a.as_object(), b.as_object()) {
+            (Some(a), Some(b)) => {
+                let (Object::String(a), Object::String(b)) =
+                    (self.runtime.heap.get(a), self.runtime.heap.get(b))
+                else {
+                    return Err(VmError::OperandsMustBeNumbersOrStrings("+"));
+                };
+
+                let concatenated = format!("{}{}", a, b);
+                let result = self.runtime.intern(&concatenated);
+                self.pop();
+                self.pop();
+                self.push(Value::object(result));
+            }
+            _ => return Err(VmError::OperandsMustBeNumbersOrStrings("+")),
+        }
+
+        Ok(())
+    }
+
+    fn arithmetic(
+        &mut self,
+        operator: &'static str,
+        op: fn(f64, f64) -> f64,
+    ) -> Result<(), VmError> {
+        let (a, b) = self.number_operands(operator)?;
+        self.push(Value::number(op(a, b)));
+        Ok(())
+    }
+
+    fn comparison(
+        &mut self,
+        operator: &'static str,
+        op: fn(f64, f64) -> bool,
+    ) -> Result<(), VmError> {
+        let (a, b) = self.number_operands(operator)?;
+        self.push(Value::boolean(op(a, b)));
+        Ok(())
+    }
+
+    /// Pop two numbers, leaving the stack untouched when either is not one
+    fn number_operands(&mut self, operator: &'static str) -> Result<(f64, f64), VmError> {
+        let (Some(a), Some(b)) = (self.peek(1).as_number(), self.peek(0).as_number()) else {
+            return Err(VmError::OperandsMustBeNumbers(operator));
+        };
+
+        self.pop();
+        self.pop();
+        Ok((a, b))
+    }
+
+    fn trace_instruction(&self) {
+        let mut stack = String::from("          ");
+        for value in &self.runtime.slots {
+            stack.push_str(&format!("[ {} ]", value.display(&self.runtime.heap)));
+        }
+
+        let frame = self.runtime.frame();
+        let mut instruction = String::new();
+        disassemble_instruction(
+            self.runtime.chunk(frame.closure),
+            frame.ip,
+            &self.runtime.heap,
+            &mut instruction,
+        );
+        eprint!("{}\n{}", stack, instruction);
+    }
+
+    /// The class at `distance` from the top of the stack, only compiled code puts it there
+    fn peek_class(&self, distance: usize) -> Result<ObjRef, VmError> {
+        self.peek(distance)
+            .as_object()
+            .filter(|&reference| matches!(self.runtime.heap.get(reference), Object::Class(_)))
+            .ok_or_else(|| VmError::InvalidBytecode("expected a class on the stack".into()))
+    }
+
+    /// The inline cache of a call site in the running function
+    fn cache(&self, site: usize) -> InlineCache {
+        let function = self
+            .runtime
+            .heap
+            .closure(self.runtime.frame().closure)
+            .function;
+        self.runtime.heap.function(function).caches[site]
+    }
+
+    /// Cached lookups never need tracing, they are checked before being used
+    fn set_cache(&mut self, site: usize, cache: InlineCache) {
+        let function = self
+            .runtime
+            .heap
+            .closure(self.runtime.frame().closure)
+            .function;
+        if let Object::Function(function) = self.runtime.heap.get_mut(function) {
+            function.caches[site] = cache;
+        }
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let frame = self.runtime.frames.last_mut().expect("call frame");
+        let function = self.runtime.heap.closure(frame.closure).function;
+        let byte = self.runtime.heap.function(function).chunk.code[frame.ip];
+        frame.ip += 1;
+        byte
+    }
+
+    fn read_short(&mut self) -> u16 {
+        let high = self.read_byte();
+        let low = self.read_byte();
+        u16::from_be_bytes([high, low])
+    }
+
+    fn read_constant(&mut self) -> Value {
+        let index = self.read_byte() as usize;
+        self.runtime.chunk(self.runtime.frame().closure).constants[index]
+    }
+
+    fn read_string(&mut self) -> ObjRef {
+        let value = self.read_constant();
+        value
+            .as_object()
+            .unwrap_or_else(|| panic!("expected a name constant, found {:?}", value))
+    }
+
+    fn push(&mut self, value: Value) {
+        self.runtime.slots.push(value);
+    }
+
+    fn pop(&mut self) -> Value {
+        self.runtime.slots.pop().expect("value on the stack")
+    }
+
+    fn peek(&self, distance: usize) -> Value {
+        self.runtime.slots[self.runtime.slots.len() - 1 - distance]
+    }
+}
+
+/// Seconds since the Unix epoch
+pub(crate) fn clock(_heap: &mut Heap, _arguments: &[Value]) -> Result<Value, String> {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+
+    Ok(Value::number(elapsed.as_secs_f64()))
+}
@@ -0,0 +1,355 @@
+//! Ahead-of-time backend: translates the register machine's instructions
+//! into a C program, one C function per Lox function, linked against the
+//! small runtime in `c_backend/runtime.c`
+
+use std::collections::{HashMap, HashSet};
+use std::fmt::Write;
+
+use crate::bytecode::object::{Heap, ObjRef, Object};
+use crate::bytecode::value::Value;
+use crate::lox::LoxError;
+use crate::optimizer::OptLevel;
+use crate::register;
+use crate::register::instruction::{Op, INSTRUCTION_SIZE};
+
+/// Values, objects, garbage collection and the operations generated code calls
+const RUNTIME: &str = include_str!("c_backend/runtime.c");
+
+/// Compile a program into a self-contained C source file, with the
+/// optimisations of `opt_level`
+pub fn emit_c(source: &str, opt_level: OptLevel) -> Result<String, LoxError> {
+    let mut heap = Heap::new();
+    let script = register::compile_with(source, &mut heap, opt_level)?;
+
+    Ok(Emitter::new(&heap).emit(script))
+}
+
+/// Writes the C translation of a compiled script
+#[derive(Debug)]
+pub struct Emitter<'h> {
+    heap: &'h Heap,
+    /// Every function of the program, the script first
+    functions: Vec<ObjRef>,
+    /// Index of each string constant in the runtime's constant array
+    strings: HashMap<ObjRef, usize>,
+    out: String,
+}
+
+pub trait EmitterTrait<'h> {
+    fn new(heap: &'h Heap) -> Self;
+    /// The whole C program: the runtime, the compiled functions and `main`
+    fn emit(self, script: ObjRef) -> String;
+
+    /// Number the script and every function nested in it, and the strings they use
+    fn collect(&mut self, function: ObjRef);
+    fn function(&mut self, index: usize);
+    /// The C statements of the instruction at `offset`, returning the offset of the next one
+    fn instruction(&mut self, function: ObjRef, offset: usize) -> usize;
+    /// A constant as a C expression
+    fn constant(&self, function: ObjRef, index: u8) -> String;
+}
+
+impl<'h> EmitterTrait<'h> for Emitter<'h> {
+    fn new(heap: &'h Heap) -> Emitter<'h> {
+        Emitter {
+            heap,
+            functions: Vec::new(),
+            strings: HashMap::new(),
+            out: String::new(),
+        }
+    }
+
+    fn emit(mut self, script: ObjRef) -> String {
+        self.collect(script);
+
+        self.out.push_str(RUNTIME);
+        self.out.push_str("\n/* Compiled program */\n\n");
+
+        let mut strings: Vec<(ObjRef, usize)> =
+            self.strings.iter().map(|(&r, &i)| (r, i)).collect();
+        strings.sort_by_key(|&(_, index)| index);
+        let literals: Vec<String> = strings
+            .iter()
+            .map(|&(reference, _)| c_string(self.heap.string(reference)))
+            .collect();
+        let lengths: Vec<String> = strings
+            .iter()
+            .map(|&(reference, _)| self.heap.string(reference).len().to_string())
+            .collect();
+        // C has no empty arrays, so an unused entry pads them.
+        let count = strings.len();
+        let _ = writeln!(self.out, "static Value S[{}];", count.max(1));
+        let _ = writeln!(
+            self.out,
+            "static const char *const S_CHARS[] = {{{}}};",
+            if count == 0 {
+                "\"\"".to_string()
+            } else {
+                literals.join(", ")
+            }
+        );
+        let _ = writeln!(
+            self.out,
+            "static const int S_LENGTHS[] = {{{}}};\n",
+            if count == 0 {
+                "0".to_string()
+            } else {
+                lengths.join(", ")
+            }
+        );
+
+        for index in 0..self.functions.len() {
+            let _ = writeln!(
+                self.out,
+                "static Value fn_{}(ObjClosure *closure, Value *R);",
+                index
+            );
+        }
+        self.out.push('\n');
+        for (index, &reference) in self.functions.iter().enumerate() {
+            let function = self.heap.function(reference);
+            let name = match function.name {
+                Some(name) => c_string(self.heap.string(name)),
+                None => "NULL".to_string(),
+            };
+            let _ = writeln!(
+                self.out,
+                "static const Function function_{} = {{{}, {}, {}, {}, fn_{}}};",
+                index, name, function.arity, function.registers, function.upvalue_count, index
+            );
+        }
+
+        for index in 0..self.functions.len() {
+            self.function(index);
+        }
+
+        let _ = write!(
+            self.out,
+            "\nint main(void) {{\n    return lox_run(&function_0, S, S_CHARS, S_LENGTHS, {});\n}}\n",
+            count
+        );
+        self.out
+    }
+
+    fn collect(&mut self, function: ObjRef) {
+        self.functions.push(function);
+
+        for constant in &self.heap.function(function).chunk.constants {
+            let Some(reference) = constant.as_object() else {
+                continue;
+            };
+            match self.heap.get(reference) {
+                Object::Function(_) => self.collect(reference),
+                Object::String(_) => {
+                    let next = self.strings.len();
+                    self.strings.entry(reference).or_insert(next);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    fn function(&mut self, index: usize) {
+        let reference = self.functions[index];
+        let heap = self.heap;
+        let code = &heap.function(reference).chunk.code;
+
+        // Only jump targets get a label, so the C compiler sees no unused ones.
+        let mut targets = HashSet::new();
+        let mut offset = 0;
+        while offset + INSTRUCTION_SIZE <= code.len() {
+            let [op, _, b, c] = [0, 1, 2, 3].map(|i| code[offset + i]);
+            let next = offset / INSTRUCTION_SIZE + 1;
+            let distance = u16::from_be_bytes([b, c]) as usize;
+            offset += INSTRUCTION_SIZE;
+            match Op::try_from(op) {
+                Ok(Op::Jump | Op::JumpIfFalse | Op::JumpIfTrue) => {
+                    targets.insert(next + distance);
+                }
+                Ok(Op::Loop) => {
+                    targets.insert(next.saturating_sub(distance));
+                }
+                Ok(Op::Closure) => {
+                    let upvalues = match self.heap.get(self.constant_ref(reference, b)) {
+                        Object::Function(function) => function.upvalue_count,
+                        _ => 0,
+                    };
+                    offset += upvalues * INSTRUCTION_SIZE;
+                }
+                _ => {}
+            }
+        }
+
+        let _ = write!(
+            self.out,
+            "\nstatic Value fn_{}(ObjClosure *closure, Value *R) {{\n    (void)closure;\n",
+            index
+        );
+        let mut offset = 0;
+        while offset + INSTRUCTION_SIZE <= code.len() {
+            if targets.contains(&(offset / INSTRUCTION_SIZE)) {
+                let _ = writeln!(self.out, "L{}:;", offset / INSTRUCTION_SIZE);
+            }
+            offset = self.instruction(reference, offset);
+        }
+        self.out.push_str("}\n");
+    }
+
+    fn instruction(&mut self, function: ObjRef, offset: usize) -> usize {
+        let heap = self.heap;
+        let chunk = &heap.function(function).chunk;
+        let [op, a, b, c] = [0, 1, 2, 3].map(|i| chunk.code[offset + i]);
+        let line = chunk.line(offset);
+        let next = offset / INSTRUCTION_SIZE + 1;
+        let distance = u16::from_be_bytes([b, c]) as usize;
+        let k = |index: u8| self.constant(function, index);
+
+        let Ok(op) = Op::try_from(op) else {
+            panic!("unknown opcode {} in compiled code", op);
+        };
+        let statement = match op {
+            Op::Move => format!("R[{}] = R[{}];", a, b),
+            Op::LoadConstant => format!("R[{}] = {};", a, k(b)),
+            Op::LoadNull => format!("R[{}] = NULL_VAL;", a),
+            Op::LoadTrue => format!("R[{}] = BOOL_VAL(true);", a),
+            Op::LoadFalse => format!("R[{}] = BOOL_VAL(false);", a),
+            Op::GetGlobal => format!("R[{}] = lox_get_global({}, {});", a, k(b), line),
+            Op::DefineGlobal => format!("lox_define_global({}, R[{}]);", k(b), a),
+            Op::SetGlobal => format!("lox_set_global({}, R[{}], {});", k(b), a, line),
+            Op::GetUpvalue => format!("R[{}] = *closure->upvalues[{}]->location;", a, b),
+            Op::SetUpvalue => format!("*closure->upvalues[{}]->location = R[{}];", b, a),
+            Op::GetProperty => {
+                format!("R[{}] = lox_get_property(R[{}], {}, {});", a, b, k(c), line)
+            }
+            Op::SetProperty => format!("lox_set_property(R[{}], {}, R[{}], {});", a, k(b), c, line),
+            Op::GetSuper => format!(
+                "R[{}] = lox_get_super(R[{}], R[{}], {}, {});",
+                a,
+                a,
+                b,
+                k(c),
+                line
+            ),
+            Op::Equal => format!("R[{}] = lox_equal(R[{}], R[{}]);", a, b, c),
+            Op::Greater | Op::Less | Op::Add | Op::Subtract | Op::Multiply | Op::Divide => {
+                let helper = match op {
+                    Op::Greater => "lox_greater",
+                    Op::Less => "lox_less",
+                    Op::Add => "lox_add",
+                    Op::Subtract => "lox_subtract",
+                    Op::Multiply => "lox_multiply",
+                    _ => "lox_divide",
+                };
+                format!("R[{}] = {}(R[{}], R[{}], {});", a, helper, b, c, line)
+            }
+            Op::Not => format!("R[{}] = lox_not(R[{}]);", a, b),
+            Op::Negate => format!("R[{}] = lox_negate(R[{}], {});", a, b, line),
+            Op::Print => format!("lox_print(R[{}]);", a),
+            Op::Jump => format!("goto L{};", next + distance),
+            Op::JumpIfFalse => format!("if (lox_falsey(R[{}])) goto L{};", a, next + distance),
+            Op::JumpIfTrue => format!("if (!lox_falsey(R[{}])) goto L{};", a, next + distance),
+            Op::Loop => format!("goto L{};", next.saturating_sub(distance)),
+            Op::Call => format!("lox_call(R + {}, {}, {});", a, b, line),
+            Op::Invoke => format!("lox_invoke(R + {}, {}, {}, {});", a, b, k(c), line),
+            Op::SuperInvoke => {
+                format!("lox_super_invoke(R + {}, {}, {}, {});", a, b, k(c), line)
+            }
+            Op::Closure => {
+                let nested = self.constant_ref(function, b);
+                let index = self.function_index(nested);
+                let upvalues = self.heap.function(nested).upvalue_count;
+                let captures: Vec<String> = (0..upvalues)
+                    .flat_map(|i| {
+                        let word = offset + (i + 1) * INSTRUCTION_SIZE;
+                        [chunk.code[word], chunk.code[word + 1]].map(|byte| byte.to_string())
+                    })
+                    .collect();
+                let captures = if captures.is_empty() {
+                    "NULL".to_string()
+                } else {
+                    format!("(const uint8_t[]){{{}}}", captures.join(", "))
+                };
+                let _ = writeln!(
+                    self.out,
+                    "    lox_closure(&function_{}, closure, R, R + {}, {});",
+                    index, a, captures
+                );
+                return offset + (upvalues + 1) * INSTRUCTION_SIZE;
+            }
+            Op::Close => format!("lox_close_upvalues(R + {});", a),
+            Op::Return => format!("lox_close_upvalues(R);\n    return R[{}];", a),
+            Op::Class => format!("R[{}] = lox_class({});", a, k(b)),
+            Op::Inherit => format!("lox_inherit(R[{}], R[{}], {});", a, b, line),
+            Op::Method => format!("lox_method(R[{}], R[{}], {});", a, b, k(c)),
+        };
+
+        let _ = writeln!(self.out, "    {}", statement);
+        offset + INSTRUCTION_SIZE
+    }
+
+    fn constant(&self, function: ObjRef, index: u8) -> String {
+        let value = self.heap.function(function).chunk.constants[index as usize];
+        if let Some(reference) = value.as_object() {
+            return match self.strings.get(&reference) {
+                Some(string) => format!("S[{}]", string),
+                None => panic!("constant {} is not a string", index),
+            };
+        }
+        c_value(value)
+    }
+}
+
+impl Emitter<'_> {
+    fn constant_ref(&self, function: ObjRef, index: u8) -> ObjRef {
+        let value = self.heap.function(function).chunk.constants[index as usize];
+        value
+            .as_object()
+            .unwrap_or_else(|| panic!("expected an object constant, found {:?}", value))
+    }
+
+    fn function_index(&self, reference: ObjRef) -> usize {
+        self.functions
+            .iter()
+            .position(|&function| function == reference)
+            .expect("nested function collected")
+    }
+}
+
+/// A value without objects as a C expression
+fn c_value(value: Value) -> String {
+    if let Some(number) = value.as_number() {
+        let literal = match number {
+            n if n.is_nan() => "NAN".to_string(),
+            n if n.is_infinite() && n > 0.0 => "INFINITY".to_string(),
+            n if n.is_infinite() => "-INFINITY".to_string(),
+            // Shortest exponent notation reads back as the same number.
+            n => format!("{:e}", n),
+        };
+        return format!("NUMBER_VAL({})", literal);
+    }
+
+    match value.as_boolean() {
+        Some(boolean) => format!("BOOL_VAL({})", boolean),
+        None => "NULL_VAL".to_string(),
+    }
+}
+
+/// A C string literal, escaping everything but printable ASCII
+fn c_string(string: &str) -> String {
+    let mut literal = String::from("\"");
+    for byte in string.bytes() {
+        match byte {
+            b'"' | b'\\' | b'?' => {
+                literal.push('\\');
+                literal.push(byte as char);
+            }
+            b' '..=b'~' => literal.push(byte as char),
+            // Octal escapes take at most three digits, unlike hex ones.
+            _ => {
+                let _ = write!(literal, "\\{:03o}", byte);
+            }
+        }
+    }
+    literal.push('"');
+    literal
+}
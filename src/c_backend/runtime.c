@@ -0,0 +1,917 @@
+/* Runtime of Lox programs compiled to C by `rlox build --emit c`: values,
+ * objects, a mark-and-sweep garbage collector and the operations the
+ * generated code calls. Every function works on a window of registers in
+ * one shared register stack, which the collector scans for roots. */
+
+#include <math.h>
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+#include <time.h>
+
+#define FRAMES_MAX 255
+#define REGISTERS_MAX 256
+#define STACK_MAX (FRAMES_MAX * REGISTERS_MAX)
+#define INITIAL_NEXT_GC (1024 * 1024)
+
+typedef enum { VAL_NULL = 0, VAL_BOOL, VAL_NUMBER, VAL_OBJ } ValueType;
+
+typedef struct Obj Obj;
+
+typedef struct {
+    ValueType type;
+    union {
+        bool boolean;
+        double number;
+        Obj *obj;
+    } as;
+} Value;
+
+#define NULL_VAL ((Value){VAL_NULL, {.number = 0}})
+#define BOOL_VAL(value) ((Value){VAL_BOOL, {.boolean = (value)}})
+#define NUMBER_VAL(value) ((Value){VAL_NUMBER, {.number = (value)}})
+#define OBJ_VAL(object) ((Value){VAL_OBJ, {.obj = (Obj *)(object)}})
+
+#define IS_NULL(value) ((value).type == VAL_NULL)
+#define IS_BOOL(value) ((value).type == VAL_BOOL)
+#define IS_NUMBER(value) ((value).type == VAL_NUMBER)
+#define IS_OBJ(value) ((value).type == VAL_OBJ)
+#define IS_OBJ_TYPE(value, object_type) (IS_OBJ(value) && (value).as.obj->type == (object_type))
+
+typedef enum {
+    OBJ_STRING,
+    OBJ_CLOSURE,
+    OBJ_UPVALUE,
+    OBJ_NATIVE,
+    OBJ_CLASS,
+    OBJ_INSTANCE,
+    OBJ_BOUND_METHOD,
+} ObjType;
+
+struct Obj {
+    ObjType type;
+    bool marked;
+    struct Obj *next;
+};
+
+typedef struct {
+    Obj obj;
+    int length;
+    uint32_t hash;
+    char chars[];
+} ObjString;
+
+typedef struct {
+    ObjString *key;
+    Value value;
+} Entry;
+
+/* Open addressing hash table, entries are never removed */
+typedef struct {
+    int count;
+    int capacity;
+    Entry *entries;
+} Table;
+
+typedef struct ObjClosure ObjClosure;
+typedef Value (*FunctionCode)(ObjClosure *closure, Value *R);
+
+/* A compiled function, emitted as static data */
+typedef struct {
+    /* NULL for the top-level script */
+    const char *name;
+    int arity;
+    /* Size of the function's register window */
+    int registers;
+    int upvalue_count;
+    FunctionCode code;
+} Function;
+
+typedef struct ObjUpvalue {
+    Obj obj;
+    /* Points into the register stack while open, then at `closed` */
+    Value *location;
+    Value closed;
+    struct ObjUpvalue *next;
+} ObjUpvalue;
+
+struct ObjClosure {
+    Obj obj;
+    const Function *function;
+    ObjUpvalue **upvalues;
+};
+
+typedef Value (*NativeFn)(int argument_count, Value *arguments);
+
+typedef struct {
+    Obj obj;
+    const char *name;
+    int arity;
+    NativeFn function;
+} ObjNative;
+
+typedef struct {
+    Obj obj;
+    ObjString *name;
+    /* Methods by name, including those copied from the superclass */
+    Table methods;
+} ObjClass;
+
+typedef struct {
+    Obj obj;
+    ObjClass *class_;
+    Table fields;
+} ObjInstance;
+
+typedef struct {
+    Obj obj;
+    Value receiver;
+    ObjClosure *method;
+} ObjBoundMethod;
+
+/* A call in progress, with the line of the call it is making */
+typedef struct {
+    ObjClosure *closure;
+    int line;
+} CallFrame;
+
+static Value stack[STACK_MAX];
+/* End of the highest register window in use */
+static Value *stack_top = stack;
+static CallFrame frames[FRAMES_MAX];
+static int frame_count;
+static Table globals;
+static ObjUpvalue *open_upvalues;
+static ObjString *init_string;
+/* String constants of the program, set up by the generated `main` */
+static Value *constants;
+static int constant_count;
+
+static Obj *objects;
+static Obj **gray_stack;
+static int gray_count;
+static int gray_capacity;
+static size_t bytes_allocated;
+static size_t next_gc = INITIAL_NEXT_GC;
+static bool gc_enabled;
+static bool gc_stress;
+
+static void print_value(FILE *out, Value value);
+
+/* Report a runtime error with a stack trace, like the interpreter */
+static void runtime_error(int line, const char *format, ...) {
+    fflush(stdout);
+    fputs("runtime error: ", stderr);
+    va_list args;
+    va_start(args, format);
+    vfprintf(stderr, format, args);
+    va_end(args);
+    fprintf(stderr, " at line: %d\n", line);
+
+    if (frame_count > 0) {
+        frames[frame_count - 1].line = line;
+    }
+    for (int i = frame_count - 1; i >= 0; i--) {
+        const char *name = frames[i].closure->function->name;
+        if (name == NULL) {
+            fprintf(stderr, "[line %d] in script\n", frames[i].line);
+        } else {
+            fprintf(stderr, "[line %d] in %s()\n", frames[i].line, name);
+        }
+    }
+    exit(70);
+}
+
+static void *reallocate(void *pointer, size_t size) {
+    void *result = realloc(pointer, size);
+    if (result == NULL && size > 0) {
+        fputs("out of memory\n", stderr);
+        exit(71);
+    }
+    return result;
+}
+
+/* Memory management */
+
+static void collect_garbage(void);
+
+static Obj *allocate_object(size_t size, ObjType type) {
+    if (gc_enabled && (gc_stress || bytes_allocated > next_gc)) {
+        collect_garbage();
+    }
+
+    bytes_allocated += size;
+    Obj *object = reallocate(NULL, size);
+    object->type = type;
+    object->marked = false;
+    object->next = objects;
+    objects = object;
+    return object;
+}
+
+#define ALLOCATE_OBJ(type, object_type) ((type *)allocate_object(sizeof(type), object_type))
+
+static void mark_object(Obj *object) {
+    if (object == NULL || object->marked) {
+        return;
+    }
+
+    object->marked = true;
+    if (gray_count == gray_capacity) {
+        gray_capacity = gray_capacity < 8 ? 8 : gray_capacity * 2;
+        gray_stack = reallocate(gray_stack, sizeof(Obj *) * gray_capacity);
+    }
+    gray_stack[gray_count++] = object;
+}
+
+static void mark_value(Value value) {
+    if (IS_OBJ(value)) {
+        mark_object(value.as.obj);
+    }
+}
+
+static void mark_table(Table *table) {
+    for (int i = 0; i < table->capacity; i++) {
+        Entry *entry = &table->entries[i];
+        mark_object((Obj *)entry->key);
+        mark_value(entry->value);
+    }
+}
+
+static void blacken_object(Obj *object) {
+    switch (object->type) {
+    case OBJ_STRING:
+    case OBJ_NATIVE:
+        break;
+    case OBJ_CLOSURE: {
+        ObjClosure *closure = (ObjClosure *)object;
+        for (int i = 0; i < closure->function->upvalue_count; i++) {
+            mark_object((Obj *)closure->upvalues[i]);
+        }
+        break;
+    }
+    case OBJ_UPVALUE:
+        mark_value(((ObjUpvalue *)object)->closed);
+        break;
+    case OBJ_CLASS: {
+        ObjClass *class_ = (ObjClass *)object;
+        mark_object((Obj *)class_->name);
+        mark_table(&class_->methods);
+        break;
+    }
+    case OBJ_INSTANCE: {
+        ObjInstance *instance = (ObjInstance *)object;
+        mark_object((Obj *)instance->class_);
+        mark_table(&instance->fields);
+        break;
+    }
+    case OBJ_BOUND_METHOD: {
+        ObjBoundMethod *bound = (ObjBoundMethod *)object;
+        mark_value(bound->receiver);
+        mark_object((Obj *)bound->method);
+        break;
+    }
+    }
+}
+
+static void free_object(Obj *object) {
+    switch (object->type) {
+    case OBJ_STRING:
+        bytes_allocated -= sizeof(ObjString) + ((ObjString *)object)->length + 1;
+        break;
+    case OBJ_CLOSURE:
+        free(((ObjClosure *)object)->upvalues);
+        bytes_allocated -= sizeof(ObjClosure);
+        break;
+    case OBJ_UPVALUE:
+        bytes_allocated -= sizeof(ObjUpvalue);
+        break;
+    case OBJ_NATIVE:
+        bytes_allocated -= sizeof(ObjNative);
+        break;
+    case OBJ_CLASS:
+        free(((ObjClass *)object)->methods.entries);
+        bytes_allocated -= sizeof(ObjClass);
+        break;
+    case OBJ_INSTANCE:
+        free(((ObjInstance *)object)->fields.entries);
+        bytes_allocated -= sizeof(ObjInstance);
+        break;
+    case OBJ_BOUND_METHOD:
+        bytes_allocated -= sizeof(ObjBoundMethod);
+        break;
+    }
+    free(object);
+}
+
+/* Mark the registers, calls, globals and constants, then free the rest */
+static void collect_garbage(void) {
+    for (Value *slot = stack; slot < stack_top; slot++) {
+        mark_value(*slot);
+    }
+    for (int i = 0; i < frame_count; i++) {
+        mark_object((Obj *)frames[i].closure);
+    }
+    for (ObjUpvalue *upvalue = open_upvalues; upvalue != NULL; upvalue = upvalue->next) {
+        mark_object((Obj *)upvalue);
+    }
+    mark_table(&globals);
+    for (int i = 0; i < constant_count; i++) {
+        mark_value(constants[i]);
+    }
+    mark_object((Obj *)init_string);
+
+    while (gray_count > 0) {
+        blacken_object(gray_stack[--gray_count]);
+    }
+
+    Obj **link = &objects;
+    while (*link != NULL) {
+        Obj *object = *link;
+        if (object->marked) {
+            object->marked = false;
+            link = &object->next;
+        } else {
+            *link = object->next;
+            free_object(object);
+        }
+    }
+
+    next_gc = bytes_allocated * 2 < INITIAL_NEXT_GC ? INITIAL_NEXT_GC : bytes_allocated * 2;
+}
+
+/* Strings and tables */
+
+static uint32_t hash_string(const char *chars, int length) {
+    uint32_t hash = 2166136261u;
+    for (int i = 0; i < length; i++) {
+        hash ^= (uint8_t)chars[i];
+        hash *= 16777619;
+    }
+    return hash;
+}
+
+static ObjString *new_string(const char *chars, int length) {
+    size_t size = sizeof(ObjString) + length + 1;
+    ObjString *string = (ObjString *)allocate_object(size, OBJ_STRING);
+    bytes_allocated += length + 1;
+    string->length = length;
+    memcpy(string->chars, chars, length);
+    string->chars[length] = '\0';
+    string->hash = hash_string(chars, length);
+    return string;
+}
+
+static bool strings_equal(ObjString *a, ObjString *b) {
+    return a == b ||
+           (a->hash == b->hash && a->length == b->length && memcmp(a->chars, b->chars, a->length) == 0);
+}
+
+static Entry *find_entry(Entry *entries, int capacity, ObjString *key) {
+    uint32_t index = key->hash & (capacity - 1);
+    for (;;) {
+        Entry *entry = &entries[index];
+        if (entry->key == NULL || strings_equal(entry->key, key)) {
+            return entry;
+        }
+        index = (index + 1) & (capacity - 1);
+    }
+}
+
+static bool table_get(Table *table, ObjString *key, Value *value) {
+    if (table->count == 0) {
+        return false;
+    }
+
+    Entry *entry = find_entry(table->entries, table->capacity, key);
+    if (entry->key == NULL) {
+        return false;
+    }
+    *value = entry->value;
+    return true;
+}
+
+/* Set a key, returning whether it is new */
+static bool table_set(Table *table, ObjString *key, Value value) {
+    if ((table->count + 1) * 4 > table->capacity * 3) {
+        int capacity = table->capacity < 8 ? 8 : table->capacity * 2;
+        Entry *entries = reallocate(NULL, sizeof(Entry) * capacity);
+        for (int i = 0; i < capacity; i++) {
+            entries[i] = (Entry){NULL, NULL_VAL};
+        }
+        for (int i = 0; i < table->capacity; i++) {
+            Entry *entry = &table->entries[i];
+            if (entry->key != NULL) {
+                *find_entry(entries, capacity, entry->key) = *entry;
+            }
+        }
+        free(table->entries);
+        table->entries = entries;
+        table->capacity = capacity;
+    }
+
+    Entry *entry = find_entry(table->entries, table->capacity, key);
+    bool is_new = entry->key == NULL;
+    if (is_new) {
+        table->count++;
+    }
+    entry->key = key;
+    entry->value = value;
+    return is_new;
+}
+
+static void table_add_all(Table *from, Table *to) {
+    for (int i = 0; i < from->capacity; i++) {
+        Entry *entry = &from->entries[i];
+        if (entry->key != NULL) {
+            table_set(to, entry->key, entry->value);
+        }
+    }
+}
+
+/* Printing */
+
+/* Numbers print like Rust's `f64` display: the shortest digits that read
+ * back as the same number, never in exponent notation */
+static void print_number(FILE *out, double number) {
+    if (isnan(number)) {
+        fputs("NaN", out);
+        return;
+    }
+    if (isinf(number)) {
+        fputs(number > 0 ? "inf" : "-inf", out);
+        return;
+    }
+
+    char buffer[64];
+    for (int precision = 0; precision < 17; precision++) {
+        snprintf(buffer, sizeof buffer, "%.*e", precision, number);
+        if (strtod(buffer, NULL) == number) {
+            break;
+        }
+    }
+
+    char *cursor = buffer;
+    if (*cursor == '-') {
+        fputc('-', out);
+        cursor++;
+    }
+    char digits[32];
+    int count = 0;
+    for (; *cursor != 'e'; cursor++) {
+        if (*cursor != '.') {
+            digits[count++] = *cursor;
+        }
+    }
+    while (count > 1 && digits[count - 1] == '0') {
+        count--;
+    }
+    int exponent = atoi(cursor + 1);
+
+    if (exponent < 0) {
+        fputs("0.", out);
+        for (int i = -1; i > exponent; i--) {
+            fputc('0', out);
+        }
+        fwrite(digits, 1, count, out);
+        return;
+    }
+
+    for (int i = 0; i <= exponent || i < count; i++) {
+        if (i == exponent + 1) {
+            fputc('.', out);
+        }
+        fputc(i < count ? digits[i] : '0', out);
+    }
+}
+
+static void print_value(FILE *out, Value value) {
+    switch (value.type) {
+    case VAL_NULL:
+        fputs("null", out);
+        return;
+    case VAL_BOOL:
+        fputs(value.as.boolean ? "true" : "false", out);
+        return;
+    case VAL_NUMBER:
+        print_number(out, value.as.number);
+        return;
+    case VAL_OBJ:
+        break;
+    }
+
+    switch (value.as.obj->type) {
+    case OBJ_STRING:
+        fwrite(((ObjString *)value.as.obj)->chars, 1, ((ObjString *)value.as.obj)->length, out);
+        break;
+    case OBJ_CLOSURE:
+        fprintf(out, "<fn %s>", ((ObjClosure *)value.as.obj)->function->name);
+        break;
+    case OBJ_UPVALUE:
+        fputs("upvalue", out);
+        break;
+    case OBJ_NATIVE:
+        fprintf(out, "<native fn %s>", ((ObjNative *)value.as.obj)->name);
+        break;
+    case OBJ_CLASS:
+        fputs(((ObjClass *)value.as.obj)->name->chars, out);
+        break;
+    case OBJ_INSTANCE:
+        fprintf(out, "%s instance", ((ObjInstance *)value.as.obj)->class_->name->chars);
+        break;
+    case OBJ_BOUND_METHOD:
+        fprintf(out, "<fn %s>", ((ObjBoundMethod *)value.as.obj)->method->function->name);
+        break;
+    }
+}
+
+static inline void lox_print(Value value) {
+    print_value(stdout, value);
+    fputc('\n', stdout);
+}
+
+/* Operators */
+
+static inline bool lox_falsey(Value value) {
+    return IS_NULL(value) || (IS_BOOL(value) && !value.as.boolean);
+}
+
+static bool values_equal(Value a, Value b) {
+    if (a.type != b.type) {
+        return false;
+    }
+
+    switch (a.type) {
+    case VAL_NULL:
+        return true;
+    case VAL_BOOL:
+        return a.as.boolean == b.as.boolean;
+    case VAL_NUMBER:
+        return a.as.number == b.as.number;
+    case VAL_OBJ:
+        if (IS_OBJ_TYPE(a, OBJ_STRING) && IS_OBJ_TYPE(b, OBJ_STRING)) {
+            return strings_equal((ObjString *)a.as.obj, (ObjString *)b.as.obj);
+        }
+        return a.as.obj == b.as.obj;
+    }
+    return false;
+}
+
+static inline Value lox_equal(Value a, Value b) {
+    return BOOL_VAL(values_equal(a, b));
+}
+
+static inline Value lox_not(Value value) {
+    return BOOL_VAL(lox_falsey(value));
+}
+
+static void check_numbers(Value a, Value b, const char *operator, int line) {
+    if (!IS_NUMBER(a) || !IS_NUMBER(b)) {
+        runtime_error(line, "operands of '%s' must be numbers", operator);
+    }
+}
+
+static inline Value lox_greater(Value a, Value b, int line) {
+    check_numbers(a, b, ">", line);
+    return BOOL_VAL(a.as.number > b.as.number);
+}
+
+static inline Value lox_less(Value a, Value b, int line) {
+    check_numbers(a, b, "<", line);
+    return BOOL_VAL(a.as.number < b.as.number);
+}
+
+static inline Value lox_subtract(Value a, Value b, int line) {
+    check_numbers(a, b, "-", line);
+    return NUMBER_VAL(a.as.number - b.as.number);
+}
+
+static inline Value lox_multiply(Value a, Value b, int line) {
+    check_numbers(a, b, "*", line);
+    return NUMBER_VAL(a.as.number * b.as.number);
+}
+
+static inline Value lox_divide(Value a, Value b, int line) {
+    check_numbers(a, b, "/", line);
+    return NUMBER_VAL(a.as.number / b.as.number);
+}
+
+static inline Value lox_negate(Value value, int line) {
+    if (!IS_NUMBER(value)) {
+        runtime_error(line, "operand of '-' must be a number");
+    }
+    return NUMBER_VAL(-value.as.number);
+}
+
+/* Both operands must stay in registers, concatenating allocates */
+static inline Value lox_add(Value a, Value b, int line) {
+    if (IS_NUMBER(a) && IS_NUMBER(b)) {
+        return NUMBER_VAL(a.as.number + b.as.number);
+    }
+    if (!IS_OBJ_TYPE(a, OBJ_STRING) || !IS_OBJ_TYPE(b, OBJ_STRING)) {
+        runtime_error(line, "operands of '+' must be two numbers or two strings");
+    }
+
+    ObjString *left = (ObjString *)a.as.obj;
+    ObjString *right = (ObjString *)b.as.obj;
+    int length = left->length + right->length;
+    char *chars = reallocate(NULL, length + 1);
+    memcpy(chars, left->chars, left->length);
+    memcpy(chars + left->length, right->chars, right->length);
+    ObjString *result = new_string(chars, length);
+    free(chars);
+    return OBJ_VAL(result);
+}
+
+/* Variables */
+
+static inline Value lox_get_global(Value name, int line) {
+    Value value;
+    if (!table_get(&globals, (ObjString *)name.as.obj, &value)) {
+        runtime_error(line, "undefined variable '%s'", ((ObjString *)name.as.obj)->chars);
+    }
+    return value;
+}
+
+static inline void lox_define_global(Value name, Value value) {
+    table_set(&globals, (ObjString *)name.as.obj, value);
+}
+
+static inline void lox_set_global(Value name, Value value, int line) {
+    Value old;
+    if (!table_get(&globals, (ObjString *)name.as.obj, &old)) {
+        runtime_error(line, "undefined variable '%s'", ((ObjString *)name.as.obj)->chars);
+    }
+    table_set(&globals, (ObjString *)name.as.obj, value);
+}
+
+/* The upvalue for a register, shared by every closure capturing it */
+static ObjUpvalue *capture_upvalue(Value *local) {
+    ObjUpvalue *previous = NULL;
+    ObjUpvalue *upvalue = open_upvalues;
+    while (upvalue != NULL && upvalue->location > local) {
+        previous = upvalue;
+        upvalue = upvalue->next;
+    }
+    if (upvalue != NULL && upvalue->location == local) {
+        return upvalue;
+    }
+
+    ObjUpvalue *created = ALLOCATE_OBJ(ObjUpvalue, OBJ_UPVALUE);
+    created->location = local;
+    created->closed = NULL_VAL;
+    created->next = upvalue;
+    if (previous == NULL) {
+        open_upvalues = created;
+    } else {
+        previous->next = created;
+    }
+    return created;
+}
+
+/* Move every variable at or above `last` out of the registers into its upvalue */
+static inline void lox_close_upvalues(Value *last) {
+    while (open_upvalues != NULL && open_upvalues->location >= last) {
+        ObjUpvalue *upvalue = open_upvalues;
+        upvalue->closed = *upvalue->location;
+        upvalue->location = &upvalue->closed;
+        open_upvalues = upvalue->next;
+    }
+}
+
+/* Store a new closure of `function` in `slot`, capturing the registers or
+ * upvalues listed as `is_local, index` pairs in `captures` */
+static inline void lox_closure(const Function *function, ObjClosure *enclosing, Value *R, Value *slot,
+                        const uint8_t *captures) {
+    ObjClosure *closure = ALLOCATE_OBJ(ObjClosure, OBJ_CLOSURE);
+    closure->function = function;
+    closure->upvalues = reallocate(NULL, sizeof(ObjUpvalue *) * (function->upvalue_count + 1));
+    for (int i = 0; i < function->upvalue_count; i++) {
+        closure->upvalues[i] = NULL;
+    }
+    /* In a register before capturing, so the upvalues captured so far stay reachable. */
+    *slot = OBJ_VAL(closure);
+
+    for (int i = 0; i < function->upvalue_count; i++) {
+        uint8_t index = captures[i * 2 + 1];
+        closure->upvalues[i] = captures[i * 2] ? capture_upvalue(R + index) : enclosing->upvalues[index];
+    }
+}
+
+/* Calls */
+
+static void call_closure(ObjClosure *closure, Value *slot, int argument_count, int line) {
+    const Function *function = closure->function;
+    if (argument_count != function->arity) {
+        runtime_error(line, "expected %d arguments but got %d", function->arity, argument_count);
+    }
+    if (frame_count >= FRAMES_MAX || slot + function->registers > stack + STACK_MAX) {
+        runtime_error(line, "stack overflow");
+    }
+
+    Value *caller_top = stack_top;
+    Value *top = slot + function->registers;
+    for (; stack_top < top; stack_top++) {
+        *stack_top = NULL_VAL;
+    }
+    if (frame_count > 0) {
+        frames[frame_count - 1].line = line;
+    }
+    frames[frame_count++] = (CallFrame){closure, line};
+
+    Value result = function->code(closure, slot);
+
+    frame_count--;
+    stack_top = caller_top > stack_top ? stack_top : caller_top;
+    *slot = result;
+}
+
+/* Call the value in `slot` with the arguments after it, the result replaces the callee */
+static inline void lox_call(Value *slot, int argument_count, int line) {
+    Value callee = *slot;
+    if (!IS_OBJ(callee)) {
+        runtime_error(line, "can only call functions and classes");
+    }
+
+    switch (callee.as.obj->type) {
+    case OBJ_CLOSURE:
+        call_closure((ObjClosure *)callee.as.obj, slot, argument_count, line);
+        return;
+    case OBJ_NATIVE: {
+        ObjNative *native = (ObjNative *)callee.as.obj;
+        if (argument_count != native->arity) {
+            runtime_error(line, "expected %d arguments but got %d", native->arity, argument_count);
+        }
+        *slot = native->function(argument_count, slot + 1);
+        return;
+    }
+    case OBJ_CLASS: {
+        ObjClass *class_ = (ObjClass *)callee.as.obj;
+        ObjInstance *instance = ALLOCATE_OBJ(ObjInstance, OBJ_INSTANCE);
+        instance->class_ = class_;
+        instance->fields = (Table){0, 0, NULL};
+        *slot = OBJ_VAL(instance);
+
+        Value initializer;
+        if (table_get(&class_->methods, init_string, &initializer)) {
+            call_closure((ObjClosure *)initializer.as.obj, slot, argument_count, line);
+        } else if (argument_count != 0) {
+            runtime_error(line, "expected 0 arguments but got %d", argument_count);
+        }
+        return;
+    }
+    case OBJ_BOUND_METHOD: {
+        ObjBoundMethod *bound = (ObjBoundMethod *)callee.as.obj;
+        *slot = bound->receiver;
+        call_closure(bound->method, slot, argument_count, line);
+        return;
+    }
+    default:
+        runtime_error(line, "can only call functions and classes");
+    }
+}
+
+/* Classes and instances */
+
+static inline Value lox_class(Value name) {
+    ObjClass *class_ = ALLOCATE_OBJ(ObjClass, OBJ_CLASS);
+    class_->name = (ObjString *)name.as.obj;
+    class_->methods = (Table){0, 0, NULL};
+    return OBJ_VAL(class_);
+}
+
+/* Copy down the methods, so lookups never walk the class chain */
+static inline void lox_inherit(Value subclass, Value superclass, int line) {
+    if (!IS_OBJ_TYPE(superclass, OBJ_CLASS)) {
+        runtime_error(line, "superclass must be a class");
+    }
+    table_add_all(&((ObjClass *)superclass.as.obj)->methods, &((ObjClass *)subclass.as.obj)->methods);
+}
+
+static inline void lox_method(Value class_, Value method, Value name) {
+    table_set(&((ObjClass *)class_.as.obj)->methods, (ObjString *)name.as.obj, method);
+}
+
+/* The receiver must stay in a register, binding allocates */
+static Value bind_method(ObjClass *class_, Value receiver, Value name, int line) {
+    Value method;
+    if (!table_get(&class_->methods, (ObjString *)name.as.obj, &method)) {
+        runtime_error(line, "undefined property '%s'", ((ObjString *)name.as.obj)->chars);
+    }
+
+    ObjBoundMethod *bound = ALLOCATE_OBJ(ObjBoundMethod, OBJ_BOUND_METHOD);
+    bound->receiver = receiver;
+    bound->method = (ObjClosure *)method.as.obj;
+    return OBJ_VAL(bound);
+}
+
+static inline Value lox_get_property(Value receiver, Value name, int line) {
+    if (!IS_OBJ_TYPE(receiver, OBJ_INSTANCE)) {
+        runtime_error(line, "only instances have properties");
+    }
+
+    ObjInstance *instance = (ObjInstance *)receiver.as.obj;
+    Value value;
+    if (table_get(&instance->fields, (ObjString *)name.as.obj, &value)) {
+        return value;
+    }
+    return bind_method(instance->class_, receiver, name, line);
+}
+
+static inline void lox_set_property(Value receiver, Value name, Value value, int line) {
+    if (!IS_OBJ_TYPE(receiver, OBJ_INSTANCE)) {
+        runtime_error(line, "only instances have fields");
+    }
+    table_set(&((ObjInstance *)receiver.as.obj)->fields, (ObjString *)name.as.obj, value);
+}
+
+static inline Value lox_get_super(Value receiver, Value superclass, Value name, int line) {
+    if (!IS_OBJ_TYPE(superclass, OBJ_CLASS)) {
+        runtime_error(line, "superclass must be a class");
+    }
+    return bind_method((ObjClass *)superclass.as.obj, receiver, name, line);
+}
+
+static void invoke_from_class(ObjClass *class_, Value *slot, int argument_count, Value name, int line) {
+    Value method;
+    if (!table_get(&class_->methods, (ObjString *)name.as.obj, &method)) {
+        runtime_error(line, "undefined property '%s'", ((ObjString *)name.as.obj)->chars);
+    }
+    call_closure((ObjClosure *)method.as.obj, slot, argument_count, line);
+}
+
+/* Call a method directly, without creating a bound method */
+static inline void lox_invoke(Value *slot, int argument_count, Value name, int line) {
+    if (!IS_OBJ_TYPE(*slot, OBJ_INSTANCE)) {
+        runtime_error(line, "only instances have properties");
+    }
+
+    ObjInstance *instance = (ObjInstance *)slot->as.obj;
+    Value field;
+    /* A field holding a function shadows the method. */
+    if (table_get(&instance->fields, (ObjString *)name.as.obj, &field)) {
+        *slot = field;
+        lox_call(slot, argument_count, line);
+        return;
+    }
+    invoke_from_class(instance->class_, slot, argument_count, name, line);
+}
+
+/* Like `lox_invoke`, with the superclass in the register after the arguments */
+static inline void lox_super_invoke(Value *slot, int argument_count, Value name, int line) {
+    Value superclass = slot[argument_count + 1];
+    if (!IS_OBJ_TYPE(superclass, OBJ_CLASS)) {
+        runtime_error(line, "superclass must be a class");
+    }
+    invoke_from_class((ObjClass *)superclass.as.obj, slot, argument_count, name, line);
+}
+
+/* Natives */
+
+static Value clock_native(int argument_count, Value *arguments) {
+    (void)argument_count;
+    (void)arguments;
+    struct timespec now;
+    timespec_get(&now, TIME_UTC);
+    return NUMBER_VAL((double)now.tv_sec + (double)now.tv_nsec / 1e9);
+}
+
+static void define_native(const char *name, int arity, NativeFn function) {
+    ObjNative *native = ALLOCATE_OBJ(ObjNative, OBJ_NATIVE);
+    native->name = name;
+    native->arity = arity;
+    native->function = function;
+    table_set(&globals, new_string(name, (int)strlen(name)), OBJ_VAL(native));
+}
+
+/* Entry point */
+
+/* Create the string constants and run the script. The collector stays off
+ * until the constants exist, so nothing needs rooting before. */
+static int lox_run(const Function *script, Value *string_constants, const char *const *chars,
+                   const int *lengths, int count) {
+    const char *stress = getenv("LOX_GC_STRESS");
+    gc_stress = stress != NULL && strcmp(stress, "0") != 0;
+
+    init_string = new_string("init", 4);
+    define_native("clock", 0, clock_native);
+    for (int i = 0; i < count; i++) {
+        string_constants[i] = OBJ_VAL(new_string(chars[i], lengths[i]));
+    }
+    constants = string_constants;
+    constant_count = count;
+    gc_enabled = true;
+
+    ObjClosure *closure = ALLOCATE_OBJ(ObjClosure, OBJ_CLOSURE);
+    closure->function = script;
+    closure->upvalues = NULL;
+    stack[0] = OBJ_VAL(closure);
+    stack_top = stack + 1;
+    call_closure(closure, stack, 0, 0);
+
+    fflush(stdout);
+    return 0;
+}
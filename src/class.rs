@@ -0,0 +1,100 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::function::LoxFunction;
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::token::{Token, TokenTrait};
+use crate::value::{LoxCallable, Value};
+
+/// A class declared in Lox code, calling it creates an instance
+#[derive(Debug)]
+pub struct LoxClass {
+    pub name: String,
+    pub superclass: Option<Rc<LoxClass>>,
+    methods: HashMap<String, Rc<LoxFunction>>,
+}
+
+impl LoxClass {
+    pub fn new(
+        name: String,
+        superclass: Option<Rc<LoxClass>>,
+        methods: HashMap<String, Rc<LoxFunction>>,
+    ) -> LoxClass {
+        LoxClass {
+            name,
+            superclass,
+            methods,
+        }
+    }
+
+    /// Look up a method on this class, then through its superclasses
+    pub fn find_method(&self, name: &str) -> Option<Rc<LoxFunction>> {
+        match self.methods.get(name) {
+            Some(method) => Some(Rc::clone(method)),
+            None => self
+                .superclass
+                .as_ref()
+                .and_then(|superclass| superclass.find_method(name)),
+        }
+    }
+
+    /// Number of arguments taken by `init`, or none without an initializer
+    pub fn arity(&self) -> usize {
+        self.find_method("init").map_or(0, |init| init.arity())
+    }
+
+    /// Create an instance, running `init` on it when the class has one
+    pub fn instantiate(
+        self: &Rc<Self>,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, RuntimeError> {
+        let instance = Rc::new(LoxInstance::new(Rc::clone(self)));
+
+        if let Some(initializer) = self.find_method("init") {
+            initializer
+                .bind(Rc::clone(&instance))
+                .call(interpreter, arguments)?;
+        }
+
+        Ok(Value::Instance(instance))
+    }
+}
+
+/// An object created from a class, fields are added on assignment
+#[derive(Debug)]
+pub struct LoxInstance {
+    pub class: Rc<LoxClass>,
+    pub fields: RefCell<HashMap<String, Value>>,
+}
+
+impl LoxInstance {
+    pub fn new(class: Rc<LoxClass>) -> LoxInstance {
+        LoxInstance {
+            class,
+            fields: RefCell::new(HashMap::new()),
+        }
+    }
+
+    /// Look up a field, then a method bound to this instance, fields shadow methods
+    pub fn get(self: &Rc<Self>, name: &Token) -> Result<Value, RuntimeError> {
+        if let Some(value) = self.fields.borrow().get(name.lexeme()) {
+            return Ok(value.clone());
+        }
+
+        match self.class.find_method(name.lexeme()) {
+            Some(method) => Ok(Value::Callable(Rc::new(method.bind(Rc::clone(self))))),
+            None => Err(RuntimeError::UndefinedProperty(
+                name.lexeme().to_string(),
+                name.span(),
+            )),
+        }
+    }
+
+    pub fn set(&self, name: &Token, value: Value) {
+        self.fields
+            .borrow_mut()
+            .insert(name.lexeme().to_string(), value);
+    }
+}
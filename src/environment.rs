@@ -0,0 +1,114 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::interpreter::RuntimeError;
+use crate::token::{Token, TokenTrait};
+use crate::value::Value;
+
+/// A scope of variables, looking up names through its enclosing scopes
+#[derive(Debug, Default)]
+pub struct Environment {
+    values: HashMap<String, Value>,
+    enclosing: Option<Rc<RefCell<Environment>>>,
+}
+
+impl Environment {
+    pub fn new() -> Environment {
+        Environment::default()
+    }
+
+    /// Create a scope nested inside `enclosing`
+    pub fn with_enclosing(enclosing: Rc<RefCell<Environment>>) -> Environment {
+        Environment {
+            values: HashMap::new(),
+            enclosing: Some(enclosing),
+        }
+    }
+
+    /// Define or redefine a variable in this scope
+    pub fn define(&mut self, name: &str, value: Value) {
+        self.values.insert(name.to_string(), value);
+    }
+
+    /// Look a name up in this scope only, ignoring enclosing scopes
+    pub fn get_local(&self, name: &str) -> Option<Value> {
+        self.values.get(name).cloned()
+    }
+
+    pub fn get(&self, name: &Token) -> Result<Value, RuntimeError> {
+        if let Some(value) = self.values.get(name.lexeme()) {
+            return Ok(value.clone());
+        }
+
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow().get(name),
+            None => Err(RuntimeError::UndefinedVariable(
+                name.lexeme().to_string(),
+                name.span(),
+            )),
+        }
+    }
+
+    /// Assign to an existing variable, the innermost scope defining it wins
+    pub fn assign(&mut self, name: &Token, value: Value) -> Result<(), RuntimeError> {
+        if let Some(slot) = self.values.get_mut(name.lexeme()) {
+            *slot = value;
+            return Ok(());
+        }
+
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow_mut().assign(name, value),
+            None => Err(RuntimeError::UndefinedVariable(
+                name.lexeme().to_string(),
+                name.span(),
+            )),
+        }
+    }
+
+    /// Read a variable from the scope `depth` levels up, as computed by the resolver
+    pub fn get_at(&self, depth: usize, name: &Token) -> Result<Value, RuntimeError> {
+        if depth == 0 {
+            return self.values.get(name.lexeme()).cloned().ok_or_else(|| {
+                RuntimeError::UndefinedVariable(name.lexeme().to_string(), name.span())
+            });
+        }
+
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow().get_at(depth - 1, name),
+            None => Err(RuntimeError::UndefinedVariable(
+                name.lexeme().to_string(),
+                name.span(),
+            )),
+        }
+    }
+
+    /// Assign to a variable in the scope `depth` levels up, as computed by the resolver
+    pub fn assign_at(
+        &mut self,
+        depth: usize,
+        name: &Token,
+        value: Value,
+    ) -> Result<(), RuntimeError> {
+        if depth == 0 {
+            return match self.values.get_mut(name.lexeme()) {
+                Some(slot) => {
+                    *slot = value;
+                    Ok(())
+                }
+                None => Err(RuntimeError::UndefinedVariable(
+                    name.lexeme().to_string(),
+                    name.span(),
+                )),
+            };
+        }
+
+        match &self.enclosing {
+            Some(enclosing) => enclosing.borrow_mut().assign_at(depth - 1, name, value),
+            None => Err(RuntimeError::UndefinedVariable(
+                name.lexeme().to_string(),
+                name.span(),
+            )),
+        }
+    }
+}
@@ -0,0 +1,157 @@
+use std::cell::Cell;
+
+use crate::token::{self, Span, Token, TokenTrait};
+
+/// Expression nodes produced by the parser
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    Assign(Assign),
+    Binary(Binary),
+    Call(Call),
+    Get(Get),
+    Grouping(Grouping),
+    Literal(Literal),
+    Logical(Logical),
+    Set(Set),
+    Super(Super),
+    This(This),
+    Unary(Unary),
+    Variable(Variable),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assign {
+    pub name: Token,
+    pub value: Box<Expr>,
+    /// Set by the resolver, see `Variable::depth`
+    pub depth: Cell<Option<usize>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Binary {
+    pub left: Box<Expr>,
+    pub operator: Token,
+    pub right: Box<Expr>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Call {
+    pub callee: Box<Expr>,
+    /// The closing parenthesis, used to report errors at the call site
+    pub paren: Token,
+    pub arguments: Vec<Expr>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Get {
+    pub object: Box<Expr>,
+    pub name: Token,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grouping {
+    pub expression: Box<Expr>,
+    /// Span of the opening parenthesis
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Literal {
+    pub value: token::Literal,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Logical {
+    pub left: Box<Expr>,
+    pub operator: Token,
+    pub right: Box<Expr>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Set {
+    pub object: Box<Expr>,
+    pub name: Token,
+    pub value: Box<Expr>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Super {
+    pub keyword: Token,
+    pub method: Token,
+    /// Set by the resolver, see `Variable::depth`
+    pub depth: Cell<Option<usize>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct This {
+    pub keyword: Token,
+    /// Set by the resolver, see `Variable::depth`
+    pub depth: Cell<Option<usize>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Unary {
+    pub operator: Token,
+    pub right: Box<Expr>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Variable {
+    pub name: Token,
+    /// Number of scopes between the use and the definition, set by the resolver,
+    /// `None` for globals
+    pub depth: Cell<Option<usize>>,
+}
+
+pub trait ExprVisitor<T> {
+    fn visit_assign_expr(&mut self, expr: &Assign) -> T;
+    fn visit_binary_expr(&mut self, expr: &Binary) -> T;
+    fn visit_call_expr(&mut self, expr: &Call) -> T;
+    fn visit_get_expr(&mut self, expr: &Get) -> T;
+    fn visit_grouping_expr(&mut self, expr: &Grouping) -> T;
+    fn visit_literal_expr(&mut self, expr: &Literal) -> T;
+    fn visit_logical_expr(&mut self, expr: &Logical) -> T;
+    fn visit_set_expr(&mut self, expr: &Set) -> T;
+    fn visit_super_expr(&mut self, expr: &Super) -> T;
+    fn visit_this_expr(&mut self, expr: &This) -> T;
+    fn visit_unary_expr(&mut self, expr: &Unary) -> T;
+    fn visit_variable_expr(&mut self, expr: &Variable) -> T;
+}
+
+impl Expr {
+    pub fn accept<T, V: ExprVisitor<T> + ?Sized>(&self, visitor: &mut V) -> T {
+        match self {
+            Expr::Assign(expr) => visitor.visit_assign_expr(expr),
+            Expr::Binary(expr) => visitor.visit_binary_expr(expr),
+            Expr::Call(expr) => visitor.visit_call_expr(expr),
+            Expr::Get(expr) => visitor.visit_get_expr(expr),
+            Expr::Grouping(expr) => visitor.visit_grouping_expr(expr),
+            Expr::Literal(expr) => visitor.visit_literal_expr(expr),
+            Expr::Logical(expr) => visitor.visit_logical_expr(expr),
+            Expr::Set(expr) => visitor.visit_set_expr(expr),
+            Expr::Super(expr) => visitor.visit_super_expr(expr),
+            Expr::This(expr) => visitor.visit_this_expr(expr),
+            Expr::Unary(expr) => visitor.visit_unary_expr(expr),
+            Expr::Variable(expr) => visitor.visit_variable_expr(expr),
+        }
+    }
+
+    /// Span of the token that best identifies the expression in diagnostics
+    pub fn span(&self) -> Span {
+        match self {
+            Expr::Assign(expr) => expr.name.span(),
+            Expr::Binary(expr) => expr.operator.span(),
+            Expr::Call(expr) => expr.paren.span(),
+            Expr::Get(expr) => expr.name.span(),
+            Expr::Grouping(expr) => expr.span,
+            Expr::Literal(expr) => expr.span,
+            Expr::Logical(expr) => expr.operator.span(),
+            Expr::Set(expr) => expr.name.span(),
+            Expr::Super(expr) => expr.keyword.span(),
+            Expr::This(expr) => expr.keyword.span(),
+            Expr::Unary(expr) => expr.operator.span(),
+            Expr::Variable(expr) => expr.name.span(),
+        }
+    }
+}
@@ -0,0 +1,408 @@
+//! C API for hosting the interpreter in C programs, enabled by the `ffi`
+//! feature and built into a shared and a static library by `capi`.
+//! `build.rs` generates the matching header, committed as `include/lox.h`.
+
+use std::any::Any;
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use crate::lox::{self, LoxError};
+use crate::native::{Args, Arity};
+use crate::value::Value;
+
+/// An interpreter owned by C code, created by `lox_new` and released by `lox_free`
+pub struct Lox {
+    lox: lox::Lox,
+    /// Message of the last call's error
+    error: Option<CString>,
+    /// Strings handed out by the last call, alive until the next one
+    strings: Vec<CString>,
+}
+
+/// Result of an API call
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoxStatus {
+    Ok,
+    /// Scanner, parser or resolver errors, the script did not run
+    CompileError,
+    /// The script failed while running, or a global is undefined
+    RuntimeError,
+    /// A null pointer, invalid UTF-8, an unknown `LoxType` or a value that
+    /// cannot be passed in
+    InvalidArgument,
+    /// A bug in the interpreter stopped the call, the message says where
+    InternalError,
+}
+
+/// Kinds of `LoxValue`, stored in its `kind` field
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoxType {
+    Null,
+    Bool,
+    Number,
+    String,
+    /// A function, class or instance, only readable as the text it prints as
+    Object,
+}
+
+/// A value crossing the API. `kind` is a `LoxType`, kept as a plain integer
+/// so values outside the enum coming from C can be rejected. `string` is set
+/// for strings and objects and points into memory owned by the interpreter
+/// or the caller.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct LoxValue {
+    pub kind: u32,
+    pub boolean: bool,
+    pub number: f64,
+    pub string: *const c_char,
+}
+
+/// A native function, never null. On failure it returns false and may
+/// store a string with the error message in `result`.
+pub type LoxNativeFn = Option<
+    extern "C" fn(
+        user_data: *mut c_void,
+        argument_count: c_int,
+        arguments: *const LoxValue,
+        result: *mut LoxValue,
+    ) -> bool,
+>;
+
+impl LoxType {
+    const ALL: [LoxType; 5] = [
+        LoxType::Null,
+        LoxType::Bool,
+        LoxType::Number,
+        LoxType::String,
+        LoxType::Object,
+    ];
+}
+
+impl TryFrom<u32> for LoxType {
+    type Error = LoxError;
+
+    fn try_from(kind: u32) -> Result<Self, Self::Error> {
+        LoxType::ALL
+            .into_iter()
+            .find(|candidate| *candidate as u32 == kind)
+            .ok_or(LoxError::TypeMismatch("LoxType", "unknown kind"))
+    }
+}
+
+impl LoxValue {
+    const NULL: LoxValue = LoxValue {
+        kind: LoxType::Null as u32,
+        boolean: false,
+        number: 0.0,
+        string: ptr::null(),
+    };
+}
+
+impl Lox {
+    /// Record the outcome of a call, returning its status
+    fn finish(&mut self, result: Result<(), LoxError>) -> LoxStatus {
+        let status = match &result {
+            Ok(()) => LoxStatus::Ok,
+            Err(
+                LoxError::ScannerErrors(_)
+                | LoxError::ParserErrors(_)
+                | LoxError::ResolverErrors(_)
+                | LoxError::CompileErrors(_),
+            ) => LoxStatus::CompileError,
+            Err(LoxError::TypeMismatch(..) | LoxError::InvalidArgument(..)) => {
+                LoxStatus::InvalidArgument
+            }
+            Err(_) => LoxStatus::RuntimeError,
+        };
+        self.error = result.err().map(|error| c_string(error.to_string()));
+        status
+    }
+}
+
+/// Convert a value for C, keeping its string alive in `strings`
+fn export(value: &Value, strings: &mut Vec<CString>) -> LoxValue {
+    let text = |strings: &mut Vec<CString>, text: String| {
+        let string = c_string(text);
+        let pointer = string.as_ptr();
+        strings.push(string);
+        pointer
+    };
+
+    match value {
+        Value::Null => LoxValue::NULL,
+        Value::Boolean(boolean) => LoxValue {
+            kind: LoxType::Bool as u32,
+            boolean: *boolean,
+            ..LoxValue::NULL
+        },
+        Value::Number(number) => LoxValue {
+            kind: LoxType::Number as u32,
+            number: *number,
+            ..LoxValue::NULL
+        },
+        Value::String(string) => LoxValue {
+            kind: LoxType::String as u32,
+            string: text(strings, string.to_string()),
+            ..LoxValue::NULL
+        },
+        value => LoxValue {
+            kind: LoxType::Object as u32,
+            string: text(strings, value.to_string()),
+            ..LoxValue::NULL
+        },
+    }
+}
+
+/// Convert a value from C, copying its string
+///
+/// # Safety
+///
+/// `value.string` must be null or point to a NUL-terminated string.
+unsafe fn import(value: &LoxValue) -> Result<Value, LoxError> {
+    match LoxType::try_from(value.kind)? {
+        LoxType::Null => Ok(Value::Null),
+        LoxType::Bool => Ok(Value::Boolean(value.boolean)),
+        LoxType::Number => Ok(Value::Number(value.number)),
+        LoxType::String => Ok(Value::String(str_arg(value.string)?.into())),
+        LoxType::Object => Err(LoxError::TypeMismatch(
+            "null, bool, number or string",
+            "object",
+        )),
+    }
+}
+
+/// A string argument, null pointers and invalid UTF-8 are rejected
+///
+/// # Safety
+///
+/// `string` must be null or point to a NUL-terminated string.
+unsafe fn str_arg<'a>(string: *const c_char) -> Result<&'a str, LoxError> {
+    if string.is_null() {
+        return Err(LoxError::TypeMismatch("string", "null pointer"));
+    }
+    CStr::from_ptr(string)
+        .to_str()
+        .map_err(|_| LoxError::TypeMismatch("UTF-8 string", "invalid UTF-8"))
+}
+
+/// A C string, cut at the first NUL since C cannot see past it
+fn c_string(mut text: String) -> CString {
+    if let Some(end) = text.find('\0') {
+        text.truncate(end);
+    }
+    CString::new(text).expect("NUL removed")
+}
+
+/// Run an API call on an interpreter, released strings of the previous
+/// call first. A null `lox` is an invalid argument and a panic is reported
+/// as an internal error rather than unwinding into C.
+///
+/// # Safety
+///
+/// `lox` must be null or come from `lox_new`.
+unsafe fn api_call(lox: *mut Lox, call: impl FnOnce(&mut Lox) -> LoxStatus) -> LoxStatus {
+    let Some(lox) = lox.as_mut() else {
+        return LoxStatus::InvalidArgument;
+    };
+    lox.strings.clear();
+
+    match panic::catch_unwind(AssertUnwindSafe(|| call(lox))) {
+        Ok(status) => status,
+        Err(payload) => {
+            let message = format!("internal error: {}", panic_message(payload.as_ref()));
+            lox.error = Some(c_string(message));
+            LoxStatus::InternalError
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    match payload.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => payload
+            .downcast_ref::<String>()
+            .map_or("panic", String::as_str),
+    }
+}
+
+/// Create an interpreter with `clock` defined, null if that failed
+#[no_mangle]
+pub extern "C" fn lox_new() -> *mut Lox {
+    panic::catch_unwind(|| {
+        Box::into_raw(Box::new(Lox {
+            lox: lox::Lox::new(),
+            error: None,
+            strings: Vec::new(),
+        }))
+    })
+    .unwrap_or(ptr::null_mut())
+}
+
+/// Release an interpreter, strings it returned become invalid
+///
+/// # Safety
+///
+/// `lox` must be null or come from `lox_new` and not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn lox_free(lox: *mut Lox) {
+    if !lox.is_null() {
+        // A panic while dropping leaks what is left, there is nobody to tell.
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(lox))));
+    }
+}
+
+/// Run a script. When `result` is not null it receives the value of the
+/// last statement if that is an expression, `null` otherwise. Strings in
+/// it stay valid until the next call on the same interpreter.
+///
+/// # Safety
+///
+/// `lox` must come from `lox_new`, `source` must be a NUL-terminated string
+/// and `result` null or writable.
+#[no_mangle]
+pub unsafe extern "C" fn lox_eval(
+    lox: *mut Lox,
+    source: *const c_char,
+    result: *mut LoxValue,
+) -> LoxStatus {
+    api_call(lox, |lox| {
+        let value = str_arg(source).and_then(|source| lox.lox.eval(source));
+        let value = match value {
+            Ok(value) => value,
+            Err(error) => return lox.finish(Err(error)),
+        };
+        if !result.is_null() {
+            *result = export(&value, &mut lox.strings);
+        }
+        lox.finish(Ok(()))
+    })
+}
+
+/// Define or redefine a global variable, strings are copied
+///
+/// # Safety
+///
+/// `lox` must come from `lox_new`, `name` and a string value must be
+/// NUL-terminated strings.
+#[no_mangle]
+pub unsafe extern "C" fn lox_set_global(
+    lox: *mut Lox,
+    name: *const c_char,
+    value: LoxValue,
+) -> LoxStatus {
+    api_call(lox, |lox| {
+        let result = str_arg(name).and_then(|name| {
+            let value = import(&value)?;
+            lox.lox.set_global(name, value);
+            Ok(())
+        });
+        lox.finish(result)
+    })
+}
+
+/// Read a global variable into `result`, its string is valid until the
+/// next call on the same interpreter
+///
+/// # Safety
+///
+/// `lox` must come from `lox_new`, `name` must be a NUL-terminated string
+/// and `result` writable.
+#[no_mangle]
+pub unsafe extern "C" fn lox_get_global(
+    lox: *mut Lox,
+    name: *const c_char,
+    result: *mut LoxValue,
+) -> LoxStatus {
+    api_call(lox, |lox| {
+        if result.is_null() {
+            return lox.finish(Err(LoxError::TypeMismatch("value pointer", "null pointer")));
+        }
+
+        let value = str_arg(name).and_then(|name| lox.lox.get_global::<Value>(name));
+        match value {
+            Ok(value) => {
+                *result = export(&value, &mut lox.strings);
+                lox.finish(Ok(()))
+            }
+            Err(error) => lox.finish(Err(error)),
+        }
+    })
+}
+
+/// Define a global function implemented in C, taking `arity` arguments or
+/// any number when it is negative. Strings in the arguments are only valid
+/// during the call, `user_data` is passed back on every call.
+///
+/// # Safety
+///
+/// `lox` must come from `lox_new` and `name` must be a NUL-terminated
+/// string. `function` must write valid values to `result`, strings in it
+/// are copied right after it returns.
+#[no_mangle]
+pub unsafe extern "C" fn lox_register_native(
+    lox: *mut Lox,
+    name: *const c_char,
+    arity: c_int,
+    function: LoxNativeFn,
+    user_data: *mut c_void,
+) -> LoxStatus {
+    api_call(lox, |lox| {
+        let Some(function) = function else {
+            return lox.finish(Err(LoxError::TypeMismatch("function", "null pointer")));
+        };
+        let name = match str_arg(name) {
+            Ok(name) => name,
+            Err(error) => return lox.finish(Err(error)),
+        };
+        let arity = match usize::try_from(arity) {
+            Ok(arity) => Arity::Fixed(arity),
+            Err(_) => Arity::Variadic(0),
+        };
+
+        lox.lox.register_fn(name, arity, move |args: &Args| {
+            let mut strings = Vec::new();
+            let arguments: Vec<LoxValue> = args
+                .values()
+                .iter()
+                .map(|value| export(value, &mut strings))
+                .collect();
+
+            let mut result = LoxValue::NULL;
+            let ok = function(
+                user_data,
+                arguments.len() as c_int,
+                arguments.as_ptr(),
+                &mut result,
+            );
+            if ok {
+                return import(&result);
+            }
+            match import(&result) {
+                Ok(Value::String(message)) => Err(LoxError::Native(message.to_string())),
+                _ => Err(LoxError::Native("native function failed".to_string())),
+            }
+        });
+        lox.finish(Ok(()))
+    })
+}
+
+/// The message of the last call's error, or null when it succeeded. Valid
+/// until the next call on the same interpreter.
+///
+/// # Safety
+///
+/// `lox` must be null or come from `lox_new`.
+#[no_mangle]
+pub unsafe extern "C" fn lox_error(lox: *const Lox) -> *const c_char {
+    panic::catch_unwind(AssertUnwindSafe(|| {
+        match lox.as_ref().and_then(|lox| lox.error.as_ref()) {
+            Some(error) => error.as_ptr(),
+            None => ptr::null(),
+        }
+    }))
+    .unwrap_or(ptr::null())
+}
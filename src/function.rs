@@ -0,0 +1,84 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::class::LoxInstance;
+use crate::environment::Environment;
+use crate::interpreter::{Interpreter, InterpreterTrait, RuntimeError, Unwind};
+use crate::stmt;
+use crate::token::TokenTrait;
+use crate::value::{LoxCallable, Value};
+
+/// A function declared in Lox code together with the scope it was declared in
+#[derive(Debug)]
+pub struct LoxFunction {
+    declaration: Rc<stmt::Function>,
+    /// Captured by reference, so later assignments are visible to the function
+    closure: Rc<RefCell<Environment>>,
+    /// An `init` method, which always returns `this`
+    is_initializer: bool,
+}
+
+impl LoxFunction {
+    pub fn new(
+        declaration: Rc<stmt::Function>,
+        closure: Rc<RefCell<Environment>>,
+        is_initializer: bool,
+    ) -> LoxFunction {
+        LoxFunction {
+            declaration,
+            closure,
+            is_initializer,
+        }
+    }
+
+    /// Create a copy of this method with `this` defined as `instance`
+    pub fn bind(&self, instance: Rc<LoxInstance>) -> LoxFunction {
+        let mut environment = Environment::with_enclosing(Rc::clone(&self.closure));
+        environment.define("this", Value::Instance(instance));
+
+        LoxFunction::new(
+            Rc::clone(&self.declaration),
+            Rc::new(RefCell::new(environment)),
+            self.is_initializer,
+        )
+    }
+}
+
+impl LoxCallable for LoxFunction {
+    fn name(&self) -> &str {
+        self.declaration.name.lexeme()
+    }
+
+    fn arity(&self) -> usize {
+        self.declaration.params.len()
+    }
+
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, RuntimeError> {
+        let mut environment = Environment::with_enclosing(Rc::clone(&self.closure));
+        for (param, argument) in self.declaration.params.iter().zip(arguments) {
+            environment.define(param.lexeme(), argument);
+        }
+
+        let environment = Rc::new(RefCell::new(environment));
+        let value = match interpreter.execute_block(&self.declaration.body, environment) {
+            Ok(()) => Value::Null,
+            Err(Unwind::Return(value)) => value,
+            Err(Unwind::Error(e)) => return Err(e),
+        };
+
+        // The resolver rejects `return value;` in `init`, so only `this` is ever returned.
+        if self.is_initializer {
+            return Ok(self
+                .closure
+                .borrow()
+                .get_local("this")
+                .unwrap_or(Value::Null));
+        }
+
+        Ok(value)
+    }
+}
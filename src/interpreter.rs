@@ -0,0 +1,546 @@
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::rc::Rc;
+
+use crate::class::LoxClass;
+use crate::environment::Environment;
+use crate::expr::{self, Expr, ExprVisitor};
+use crate::function::LoxFunction;
+use crate::native::{self, Arity};
+use crate::stmt::{self, Stmt, StmtVisitor};
+use crate::token::{Literal, Span, Token, TokenTrait, TokenType};
+use crate::value::Value;
+
+/// Maximum nesting of calls before reporting a stack overflow, kept low enough
+/// to fit the native stack of the recursive evaluator in debug builds
+pub const MAX_CALL_DEPTH: usize = 255;
+/// Maximum nesting of expressions being evaluated, counted across calls, so
+/// a call at the bottom of a long chain can't overflow the native stack
+/// before `MAX_CALL_DEPTH` is reached
+pub const MAX_EVAL_DEPTH: usize = 1500;
+
+#[derive(Debug)]
+pub struct Interpreter {
+    /// The outermost scope, holding variables the resolver left unresolved
+    globals: Rc<RefCell<Environment>>,
+    /// The innermost scope of the code being executed
+    environment: Rc<RefCell<Environment>>,
+    call_depth: usize,
+    eval_depth: usize,
+}
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum RuntimeError {
+    #[error("operand of '{0}' must be a number at {1}")]
+    OperandMustBeNumber(String, Span),
+    #[error("operands of '{0}' must be numbers at {1}")]
+    OperandsMustBeNumbers(String, Span),
+    #[error("operands of '{0}' must be two numbers or two strings at {1}")]
+    OperandsMustBeNumbersOrStrings(String, Span),
+    #[error("undefined variable '{0}' at {1}")]
+    UndefinedVariable(String, Span),
+    #[error("can only call functions and classes at {0}")]
+    NotCallable(Span),
+    #[error("expected {0} arguments but got {1} at {2}")]
+    ArityMismatch(usize, usize, Span),
+    #[error("undefined property '{0}' at {1}")]
+    UndefinedProperty(String, Span),
+    #[error("only instances have properties at {0}")]
+    OnlyInstancesHaveProperties(Span),
+    #[error("only instances have fields at {0}")]
+    OnlyInstancesHaveFields(Span),
+    #[error("superclass must be a class at {0}")]
+    SuperclassMustBeClass(Span),
+    #[error("expected at least {0} arguments but got {1} at {2}")]
+    TooFewArguments(usize, usize, Span),
+    #[error("{0}: {1} at {2}")]
+    NativeError(String, String, Span),
+    #[error("stack overflow at {0}")]
+    StackOverflow(Span),
+    #[error("{0} is not supported at {1}")]
+    Unsupported(String, Span),
+}
+
+impl RuntimeError {
+    pub fn span(&self) -> Span {
+        match self {
+            RuntimeError::OperandMustBeNumber(_, span)
+            | RuntimeError::OperandsMustBeNumbers(_, span)
+            | RuntimeError::OperandsMustBeNumbersOrStrings(_, span)
+            | RuntimeError::UndefinedVariable(_, span)
+            | RuntimeError::NotCallable(span)
+            | RuntimeError::ArityMismatch(_, _, span)
+            | RuntimeError::UndefinedProperty(_, span)
+            | RuntimeError::OnlyInstancesHaveProperties(span)
+            | RuntimeError::OnlyInstancesHaveFields(span)
+            | RuntimeError::SuperclassMustBeClass(span)
+            | RuntimeError::TooFewArguments(_, _, span)
+            | RuntimeError::NativeError(_, _, span)
+            | RuntimeError::StackOverflow(span)
+            | RuntimeError::Unsupported(_, span) => *span,
+        }
+    }
+}
+
+/// Reasons for statements to stop executing before reaching their end
+#[derive(Debug)]
+pub enum Unwind {
+    /// A `return` statement, carrying the value back to the call
+    Return(Value),
+    Error(RuntimeError),
+}
+
+impl From<RuntimeError> for Unwind {
+    fn from(error: RuntimeError) -> Self {
+        Unwind::Error(error)
+    }
+}
+
+pub trait InterpreterTrait {
+    fn new() -> Self;
+    /// The outermost scope, shared by every piece of code run by this interpreter
+    fn globals(&self) -> Rc<RefCell<Environment>>;
+    /// Execute statements in order, stopping at the first runtime error
+    fn interpret(&mut self, statements: &[Stmt]) -> Result<(), RuntimeError>;
+    fn execute(&mut self, stmt: &Stmt) -> Result<(), Unwind>;
+    /// Call a function or class, reporting errors at `span`
+    fn call(
+        &mut self,
+        callee: Value,
+        arguments: Vec<Value>,
+        span: Span,
+    ) -> Result<Value, RuntimeError>;
+    fn evaluate(&mut self, expr: &Expr) -> Result<Value, RuntimeError>;
+    /// Execute statements in the given scope, restoring the current one afterwards
+    fn execute_block(
+        &mut self,
+        statements: &[Stmt],
+        environment: Rc<RefCell<Environment>>,
+    ) -> Result<(), Unwind>;
+}
+
+impl InterpreterTrait for Interpreter {
+    fn new() -> Interpreter {
+        let mut environment = Environment::new();
+        for builtin in native::builtins() {
+            let name = builtin.name.clone();
+            environment.define(&name, Value::Native(Rc::new(builtin)));
+        }
+        let globals = Rc::new(RefCell::new(environment));
+
+        Interpreter {
+            environment: Rc::clone(&globals),
+            globals,
+            call_depth: 0,
+            eval_depth: 0,
+        }
+    }
+
+    fn globals(&self) -> Rc<RefCell<Environment>> {
+        Rc::clone(&self.globals)
+    }
+
+    fn interpret(&mut self, statements: &[Stmt]) -> Result<(), RuntimeError> {
+        for statement in statements {
+            match self.execute(statement) {
+                Ok(()) => {}
+                // A top level return ends the program.
+                Err(Unwind::Return(_)) => return Ok(()),
+                Err(Unwind::Error(e)) => return Err(e),
+            }
+        }
+
+        Ok(())
+    }
+
+    fn execute(&mut self, stmt: &Stmt) -> Result<(), Unwind> {
+        stmt.accept(self)
+    }
+
+    fn call(
+        &mut self,
+        callee: Value,
+        arguments: Vec<Value>,
+        span: Span,
+    ) -> Result<Value, RuntimeError> {
+        let arity = match &callee {
+            Value::Callable(callable) => Arity::Fixed(callable.arity()),
+            Value::Class(class) => Arity::Fixed(class.arity()),
+            Value::Native(native) => native.arity,
+            _ => return Err(RuntimeError::NotCallable(span)),
+        };
+
+        match arity {
+            Arity::Fixed(arity) if arguments.len() != arity => {
+                return Err(RuntimeError::ArityMismatch(arity, arguments.len(), span));
+            }
+            Arity::Variadic(min) if arguments.len() < min => {
+                return Err(RuntimeError::TooFewArguments(min, arguments.len(), span));
+            }
+            _ => {}
+        }
+
+        if self.call_depth >= MAX_CALL_DEPTH {
+            return Err(RuntimeError::StackOverflow(span));
+        }
+
+        self.call_depth += 1;
+        let result = match &callee {
+            Value::Class(class) => class.instantiate(self, arguments),
+            Value::Callable(callable) => callable.call(self, arguments),
+            Value::Native(native) => native
+                .call(arguments)
+                .map_err(|e| RuntimeError::NativeError(native.name.clone(), e.to_string(), span)),
+            _ => unreachable!("callee checked above"),
+        };
+        self.call_depth -= 1;
+
+        result
+    }
+
+    fn evaluate(&mut self, expr: &Expr) -> Result<Value, RuntimeError> {
+        if self.eval_depth >= MAX_EVAL_DEPTH {
+            return Err(RuntimeError::StackOverflow(expr.span()));
+        }
+
+        self.eval_depth += 1;
+        let result = expr.accept(self);
+        self.eval_depth -= 1;
+        result
+    }
+
+    fn execute_block(
+        &mut self,
+        statements: &[Stmt],
+        environment: Rc<RefCell<Environment>>,
+    ) -> Result<(), Unwind> {
+        let previous = std::mem::replace(&mut self.environment, environment);
+
+        let result = statements.iter().try_for_each(|s| self.execute(s));
+
+        self.environment = previous;
+        result
+    }
+}
+
+fn number_operand(operator: &Token, operand: &Value) -> Result<f64, RuntimeError> {
+    match operand {
+        Value::Number(value) => Ok(*value),
+        _ => Err(RuntimeError::OperandMustBeNumber(
+            operator.lexeme().to_string(),
+            operator.span(),
+        )),
+    }
+}
+
+fn number_operands(
+    operator: &Token,
+    left: &Value,
+    right: &Value,
+) -> Result<(f64, f64), RuntimeError> {
+    match (left, right) {
+        (Value::Number(left), Value::Number(right)) => Ok((*left, *right)),
+        _ => Err(RuntimeError::OperandsMustBeNumbers(
+            operator.lexeme().to_string(),
+            operator.span(),
+        )),
+    }
+}
+
+fn unsupported(kind: &str, span: Span) -> RuntimeError {
+    RuntimeError::Unsupported(kind.to_string(), span)
+}
+
+impl ExprVisitor<Result<Value, RuntimeError>> for Interpreter {
+    fn visit_assign_expr(&mut self, expr: &expr::Assign) -> Result<Value, RuntimeError> {
+        let value = self.evaluate(&expr.value)?;
+        match expr.depth.get() {
+            Some(depth) => {
+                self.environment
+                    .borrow_mut()
+                    .assign_at(depth, &expr.name, value.clone())?
+            }
+            None => self
+                .globals
+                .borrow_mut()
+                .assign(&expr.name, value.clone())?,
+        }
+
+        Ok(value)
+    }
+
+    fn visit_binary_expr(&mut self, expr: &expr::Binary) -> Result<Value, RuntimeError> {
+        let left = self.evaluate(&expr.left)?;
+        let right = self.evaluate(&expr.right)?;
+        let operator = &expr.operator;
+
+        let value = match operator.token_type() {
+            TokenType::Plus => match (&left, &right) {
+                (Value::Number(left), Value::Number(right)) => Value::Number(left + right),
+                (Value::String(left), Value::String(right)) => {
+                    Value::String(format!("{left}{right}").into())
+                }
+                _ => {
+                    return Err(RuntimeError::OperandsMustBeNumbersOrStrings(
+                        operator.lexeme().to_string(),
+                        operator.span(),
+                    ))
+                }
+            },
+            TokenType::Minus => {
+                let (left, right) = number_operands(operator, &left, &right)?;
+                Value::Number(left - right)
+            }
+            TokenType::Star => {
+                let (left, right) = number_operands(operator, &left, &right)?;
+                Value::Number(left * right)
+            }
+            TokenType::Slash => {
+                let (left, right) = number_operands(operator, &left, &right)?;
+                Value::Number(left / right)
+            }
+            TokenType::Greater => {
+                let (left, right) = number_operands(operator, &left, &right)?;
+                Value::Boolean(left > right)
+            }
+            TokenType::GreaterEqual => {
+                let (left, right) = number_operands(operator, &left, &right)?;
+                // Like the bytecode engines, `>=` is `!(a < b)`, true when either side is NaN.
+                Value::Boolean(left.partial_cmp(&right) != Some(Ordering::Less))
+            }
+            TokenType::Less => {
+                let (left, right) = number_operands(operator, &left, &right)?;
+                Value::Boolean(left < right)
+            }
+            TokenType::LessEqual => {
+                let (left, right) = number_operands(operator, &left, &right)?;
+                Value::Boolean(left.partial_cmp(&right) != Some(Ordering::Greater))
+            }
+            TokenType::EqualEqual => Value::Boolean(left == right),
+            TokenType::BangEqual => Value::Boolean(left != right),
+            _ => return Err(unsupported("binary operator", operator.span())),
+        };
+
+        Ok(value)
+    }
+
+    fn visit_call_expr(&mut self, expr: &expr::Call) -> Result<Value, RuntimeError> {
+        let callee = self.evaluate(&expr.callee)?;
+
+        let mut arguments = Vec::with_capacity(expr.arguments.len());
+        for argument in &expr.arguments {
+            arguments.push(self.evaluate(argument)?);
+        }
+
+        self.call(callee, arguments, expr.paren.span())
+    }
+
+    fn visit_get_expr(&mut self, expr: &expr::Get) -> Result<Value, RuntimeError> {
+        match self.evaluate(&expr.object)? {
+            Value::Instance(instance) => instance.get(&expr.name),
+            _ => Err(RuntimeError::OnlyInstancesHaveProperties(expr.name.span())),
+        }
+    }
+
+    fn visit_grouping_expr(&mut self, expr: &expr::Grouping) -> Result<Value, RuntimeError> {
+        self.evaluate(&expr.expression)
+    }
+
+    fn visit_literal_expr(&mut self, expr: &expr::Literal) -> Result<Value, RuntimeError> {
+        let value = match &expr.value {
+            Literal::String(value) => Value::String(value.as_str().into()),
+            Literal::Number(value) => Value::Number(*value),
+            Literal::Boolean(value) => Value::Boolean(*value),
+            Literal::Null => Value::Null,
+        };
+
+        Ok(value)
+    }
+
+    fn visit_logical_expr(&mut self, expr: &expr::Logical) -> Result<Value, RuntimeError> {
+        let left = self.evaluate(&expr.left)?;
+
+        // Short circuit and return the operand itself, not a boolean.
+        if expr.operator.token_type() == TokenType::Or {
+            if left.is_truthy() {
+                return Ok(left);
+            }
+        } else if !left.is_truthy() {
+            return Ok(left);
+        }
+
+        self.evaluate(&expr.right)
+    }
+
+    fn visit_set_expr(&mut self, expr: &expr::Set) -> Result<Value, RuntimeError> {
+        let Value::Instance(instance) = self.evaluate(&expr.object)? else {
+            return Err(RuntimeError::OnlyInstancesHaveFields(expr.name.span()));
+        };
+
+        let value = self.evaluate(&expr.value)?;
+        instance.set(&expr.name, value.clone());
+
+        Ok(value)
+    }
+
+    fn visit_super_expr(&mut self, expr: &expr::Super) -> Result<Value, RuntimeError> {
+        let Some(depth) = expr.depth.get() else {
+            return self.globals.borrow().get(&expr.keyword);
+        };
+
+        let superclass = self.environment.borrow().get_at(depth, &expr.keyword)?;
+        // `this` is always bound in the scope just inside the one holding `super`.
+        let this = Token::new(
+            TokenType::This,
+            "this".to_string(),
+            Literal::Null,
+            expr.keyword.span(),
+        );
+        let object = self.environment.borrow().get_at(depth - 1, &this)?;
+
+        let (Value::Class(superclass), Value::Instance(instance)) = (superclass, object) else {
+            return Err(unsupported("'super'", expr.keyword.span()));
+        };
+
+        match superclass.find_method(expr.method.lexeme()) {
+            Some(method) => Ok(Value::Callable(Rc::new(method.bind(instance)))),
+            None => Err(RuntimeError::UndefinedProperty(
+                expr.method.lexeme().to_string(),
+                expr.method.span(),
+            )),
+        }
+    }
+
+    fn visit_this_expr(&mut self, expr: &expr::This) -> Result<Value, RuntimeError> {
+        match expr.depth.get() {
+            Some(depth) => self.environment.borrow().get_at(depth, &expr.keyword),
+            None => self.globals.borrow().get(&expr.keyword),
+        }
+    }
+
+    fn visit_unary_expr(&mut self, expr: &expr::Unary) -> Result<Value, RuntimeError> {
+        let right = self.evaluate(&expr.right)?;
+
+        match expr.operator.token_type() {
+            TokenType::Minus => Ok(Value::Number(-number_operand(&expr.operator, &right)?)),
+            TokenType::Bang => Ok(Value::Boolean(!right.is_truthy())),
+            _ => Err(unsupported("unary operator", expr.operator.span())),
+        }
+    }
+
+    fn visit_variable_expr(&mut self, expr: &expr::Variable) -> Result<Value, RuntimeError> {
+        match expr.depth.get() {
+            Some(depth) => self.environment.borrow().get_at(depth, &expr.name),
+            None => self.globals.borrow().get(&expr.name),
+        }
+    }
+}
+
+impl StmtVisitor<Result<(), Unwind>> for Interpreter {
+    fn visit_block_stmt(&mut self, stmt: &stmt::Block) -> Result<(), Unwind> {
+        let environment = Environment::with_enclosing(Rc::clone(&self.environment));
+        self.execute_block(&stmt.statements, Rc::new(RefCell::new(environment)))
+    }
+
+    fn visit_class_stmt(&mut self, stmt: &stmt::Class) -> Result<(), Unwind> {
+        let superclass = match &stmt.superclass {
+            Some(superclass) => match self.visit_variable_expr(superclass)? {
+                Value::Class(class) => Some(class),
+                _ => return Err(RuntimeError::SuperclassMustBeClass(superclass.name.span()).into()),
+            },
+            None => None,
+        };
+
+        // Methods of a subclass close over a scope binding `super`.
+        let enclosing = Rc::clone(&self.environment);
+        if let Some(superclass) = &superclass {
+            let mut environment = Environment::with_enclosing(Rc::clone(&enclosing));
+            environment.define("super", Value::Class(Rc::clone(superclass)));
+            self.environment = Rc::new(RefCell::new(environment));
+        }
+
+        let methods = stmt
+            .methods
+            .iter()
+            .map(|method| {
+                let is_initializer = method.name.lexeme() == "init";
+                let function = LoxFunction::new(
+                    Rc::clone(method),
+                    Rc::clone(&self.environment),
+                    is_initializer,
+                );
+                (method.name.lexeme().to_string(), Rc::new(function))
+            })
+            .collect();
+
+        self.environment = enclosing;
+
+        let class = LoxClass::new(stmt.name.lexeme().to_string(), superclass, methods);
+        self.environment
+            .borrow_mut()
+            .define(stmt.name.lexeme(), Value::Class(Rc::new(class)));
+
+        Ok(())
+    }
+
+    fn visit_expression_stmt(&mut self, stmt: &stmt::Expression) -> Result<(), Unwind> {
+        self.evaluate(&stmt.expression)?;
+
+        Ok(())
+    }
+
+    fn visit_function_stmt(&mut self, stmt: &Rc<stmt::Function>) -> Result<(), Unwind> {
+        let function = LoxFunction::new(Rc::clone(stmt), Rc::clone(&self.environment), false);
+        self.environment
+            .borrow_mut()
+            .define(stmt.name.lexeme(), Value::Callable(Rc::new(function)));
+
+        Ok(())
+    }
+
+    fn visit_if_stmt(&mut self, stmt: &stmt::If) -> Result<(), Unwind> {
+        if self.evaluate(&stmt.condition)?.is_truthy() {
+            self.execute(&stmt.then_branch)?;
+        } else if let Some(else_branch) = &stmt.else_branch {
+            self.execute(else_branch)?;
+        }
+
+        Ok(())
+    }
+
+    fn visit_print_stmt(&mut self, stmt: &stmt::Print) -> Result<(), Unwind> {
+        let value = self.evaluate(&stmt.expression)?;
+        println!("{}", value);
+
+        Ok(())
+    }
+
+    fn visit_return_stmt(&mut self, stmt: &stmt::Return) -> Result<(), Unwind> {
+        let value = match &stmt.value {
+            Some(value) => self.evaluate(value)?,
+            None => Value::Null,
+        };
+
+        Err(Unwind::Return(value))
+    }
+
+    fn visit_var_stmt(&mut self, stmt: &stmt::Var) -> Result<(), Unwind> {
+        let value = match &stmt.initializer {
+            Some(initializer) => self.evaluate(initializer)?,
+            None => Value::Null,
+        };
+        self.environment
+            .borrow_mut()
+            .define(stmt.name.lexeme(), value);
+
+        Ok(())
+    }
+
+    fn visit_while_stmt(&mut self, stmt: &stmt::While) -> Result<(), Unwind> {
+        while self.evaluate(&stmt.condition)?.is_truthy() {
+            self.execute(&stmt.body)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,267 @@
+use std::fmt::Display;
+
+/// Minimal JSON document model, objects keep their keys in insertion order
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum JsonError {
+    #[error("unexpected character at offset: {0}, character: {1}")]
+    UnexpectedCharacter(usize, char),
+    #[error("unexpected end of input")]
+    UnexpectedEnd,
+    #[error("invalid number at offset: {0}")]
+    InvalidNumber(usize),
+    #[error("invalid escape sequence at offset: {0}")]
+    InvalidEscape(usize),
+}
+
+impl Json {
+    pub fn parse(source: &str) -> Result<Json, JsonError> {
+        let mut reader = JsonReader {
+            chars: source.chars().collect(),
+            current: 0,
+        };
+
+        let value = reader.value()?;
+        reader.skip_whitespace();
+        if let Some(c) = reader.peek() {
+            return Err(JsonError::UnexpectedCharacter(reader.current, c));
+        }
+
+        Ok(value)
+    }
+
+    /// Look up a key of an object, `None` for missing keys and non objects
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+}
+
+impl Display for Json {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(value) => write!(f, "{}", value),
+            // Rust prints the shortest representation that parses back to the same f64.
+            Json::Number(value) if value.is_finite() => write!(f, "{}", value),
+            Json::Number(_) => write!(f, "null"),
+            Json::String(value) => write_string(f, value),
+            Json::Array(values) => {
+                write!(f, "[")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", value)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(entries) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut std::fmt::Formatter<'_>, value: &str) -> std::fmt::Result {
+    write!(f, "\"")?;
+    for c in value.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+struct JsonReader {
+    chars: Vec<char>,
+    current: usize,
+}
+
+impl JsonReader {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.current).copied()
+    }
+
+    fn advance(&mut self) -> Result<char, JsonError> {
+        let c = self.peek().ok_or(JsonError::UnexpectedEnd)?;
+        self.current += 1;
+        Ok(c)
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), JsonError> {
+        let c = self.advance()?;
+        if c != expected {
+            return Err(JsonError::UnexpectedCharacter(self.current - 1, c));
+        }
+
+        Ok(())
+    }
+
+    fn skip_whitespace(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\n' | '\r' | '\t')) {
+            self.current += 1;
+        }
+    }
+
+    fn keyword(&mut self, keyword: &str, value: Json) -> Result<Json, JsonError> {
+        for expected in keyword.chars() {
+            self.expect(expected)?;
+        }
+
+        Ok(value)
+    }
+
+    fn value(&mut self) -> Result<Json, JsonError> {
+        self.skip_whitespace();
+
+        match self.peek().ok_or(JsonError::UnexpectedEnd)? {
+            'n' => self.keyword("null", Json::Null),
+            't' => self.keyword("true", Json::Bool(true)),
+            'f' => self.keyword("false", Json::Bool(false)),
+            '"' => Ok(Json::String(self.string()?)),
+            '[' => self.array(),
+            '{' => self.object(),
+            c if c == '-' || c.is_ascii_digit() => self.number(),
+            c => Err(JsonError::UnexpectedCharacter(self.current, c)),
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, JsonError> {
+        let start = self.current;
+        while matches!(self.peek(), Some(c) if c.is_ascii_digit() || "+-.eE".contains(c)) {
+            self.current += 1;
+        }
+
+        let text: String = self.chars[start..self.current].iter().collect();
+        text.parse()
+            .map(Json::Number)
+            .map_err(|_| JsonError::InvalidNumber(start))
+    }
+
+    fn hex_escape(&mut self) -> Result<u32, JsonError> {
+        let start = self.current;
+        let mut code = 0;
+        for _ in 0..4 {
+            let digit = self
+                .advance()?
+                .to_digit(16)
+                .ok_or(JsonError::InvalidEscape(start))?;
+            code = code * 16 + digit;
+        }
+
+        Ok(code)
+    }
+
+    fn string(&mut self) -> Result<String, JsonError> {
+        self.expect('"')?;
+        let mut value = String::new();
+
+        loop {
+            match self.advance()? {
+                '"' => return Ok(value),
+                '\\' => {
+                    let start = self.current - 1;
+                    let c = match self.advance()? {
+                        '"' => '"',
+                        '\\' => '\\',
+                        '/' => '/',
+                        'b' => '\u{8}',
+                        'f' => '\u{c}',
+                        'n' => '\n',
+                        'r' => '\r',
+                        't' => '\t',
+                        'u' => {
+                            let mut code = self.hex_escape()?;
+                            // Characters outside the BMP are written as surrogate pairs.
+                            if (0xD800..0xDC00).contains(&code) {
+                                self.expect('\\')?;
+                                self.expect('u')?;
+                                let low = self.hex_escape()?;
+                                if !(0xDC00..0xE000).contains(&low) {
+                                    return Err(JsonError::InvalidEscape(start));
+                                }
+                                code = 0x10000 + ((code - 0xD800) << 10) + (low - 0xDC00);
+                            }
+                            char::from_u32(code).ok_or(JsonError::InvalidEscape(start))?
+                        }
+                        _ => return Err(JsonError::InvalidEscape(start)),
+                    };
+                    value.push(c);
+                }
+                c => value.push(c),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, JsonError> {
+        self.expect('[')?;
+        let mut values = Vec::new();
+
+        self.skip_whitespace();
+        if self.peek() == Some(']') {
+            self.current += 1;
+            return Ok(Json::Array(values));
+        }
+
+        loop {
+            values.push(self.value()?);
+            self.skip_whitespace();
+            match self.advance()? {
+                ',' => continue,
+                ']' => return Ok(Json::Array(values)),
+                c => return Err(JsonError::UnexpectedCharacter(self.current - 1, c)),
+            }
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, JsonError> {
+        self.expect('{')?;
+        let mut entries = Vec::new();
+
+        self.skip_whitespace();
+        if self.peek() == Some('}') {
+            self.current += 1;
+            return Ok(Json::Object(entries));
+        }
+
+        loop {
+            self.skip_whitespace();
+            let key = self.string()?;
+            self.skip_whitespace();
+            self.expect(':')?;
+            entries.push((key, self.value()?));
+            self.skip_whitespace();
+            match self.advance()? {
+                ',' => continue,
+                '}' => return Ok(Json::Object(entries)),
+                c => return Err(JsonError::UnexpectedCharacter(self.current - 1, c)),
+            }
+        }
+    }
+}
@@ -1,2 +1,23 @@
+pub mod ast_dot;
+pub mod ast_json;
+pub mod ast_printer;
+pub mod bytecode;
+pub mod c_backend;
+pub mod class;
+pub mod environment;
+pub mod expr;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod function;
+pub mod interpreter;
+pub mod json;
+pub mod lox;
+pub mod native;
+pub mod optimizer;
+pub mod parser;
+pub mod register;
+pub mod resolver;
 pub mod scanner;
+pub mod stmt;
 pub mod token;
+pub mod value;
@@ -0,0 +1,310 @@
+use std::rc::Rc;
+
+use crate::bytecode::compiler::CompileError;
+use crate::bytecode::serializer::LoadError;
+use crate::bytecode::verifier::VerifyError;
+use crate::bytecode::vm::VmRuntimeError;
+use crate::interpreter::{Interpreter, InterpreterTrait, RuntimeError};
+use crate::native::{Args, Arity, NativeFunction};
+use crate::optimizer::{OptLevel, Optimizer, OptimizerTrait};
+use crate::parser::{Parser, ParserError, ParserTrait};
+use crate::resolver::{Resolver, ResolverError, ResolverTrait};
+use crate::scanner::{Scanner, ScannerError, ScannerTrait};
+use crate::stmt::Stmt;
+use crate::token::{Span, TokenTrait, TokenType};
+use crate::value::Value;
+
+/// A Lox interpreter for hosting scripts in Rust programs, globals persist
+/// between calls
+#[derive(Debug)]
+pub struct Lox {
+    interpreter: Interpreter,
+    opt_level: OptLevel,
+}
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum LoxError {
+    #[error("failed to read file: {0}")]
+    FailedToReadFile(String),
+    #[error("{}", join("scanner error", .0))]
+    ScannerErrors(Vec<ScannerError>),
+    #[error("{}", join("parser error", .0))]
+    ParserErrors(Vec<ParserError>),
+    #[error("{}", join("compile error", .0))]
+    CompileErrors(Vec<CompileError>),
+    #[error("{}", join("resolver error", .0))]
+    ResolverErrors(Vec<ResolverError>),
+    #[error("runtime error: {0}")]
+    RuntimeError(#[from] RuntimeError),
+    #[error("runtime error: {0}")]
+    VmError(#[from] VmRuntimeError),
+    #[error("load error: {0}")]
+    LoadError(#[from] LoadError),
+    #[error("verify error: {0}")]
+    VerifyError(#[from] VerifyError),
+    #[error("undefined global '{0}'")]
+    UndefinedGlobal(String),
+    #[error("expected {0}, found {1}")]
+    TypeMismatch(&'static str, &'static str),
+    #[error("argument {0} expected {1}, found {2}")]
+    InvalidArgument(usize, &'static str, &'static str),
+    /// Raised by native functions for failures of their own
+    #[error("{0}")]
+    Native(String),
+}
+
+fn join<E: std::fmt::Display>(prefix: &str, errors: &[E]) -> String {
+    errors
+        .iter()
+        .map(|e| format!("{}: {}", prefix, e))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Conversion of a Rust value into a Lox value
+pub trait IntoLox {
+    fn into_lox(self) -> Value;
+}
+
+/// Conversion of a Lox value into a Rust value, failing on the wrong type
+pub trait FromLox: Sized {
+    fn from_lox(value: Value) -> Result<Self, LoxError>;
+}
+
+/// Scan and parse a program, collecting every error of the first failing stage
+pub fn parse(source: &str) -> Result<Vec<Stmt>, LoxError> {
+    parse_with(source, false)
+}
+
+/// Parse a line typed at a prompt, where the `;` after a last expression
+/// may be left out
+pub fn parse_line(source: &str) -> Result<Vec<Stmt>, LoxError> {
+    parse_with(source, true)
+}
+
+fn parse_with(source: &str, trailing_expression: bool) -> Result<Vec<Stmt>, LoxError> {
+    let tokens = Scanner::new(source.to_string())
+        .scan_tokens()
+        .map_err(LoxError::ScannerErrors)?;
+
+    let mut parser = Parser::new(tokens);
+    parser.set_trailing_expression(trailing_expression);
+    parser.parse().map_err(LoxError::ParserErrors)
+}
+
+/// Whether a program stops inside a string or with more `(` or `{` opened
+/// than closed, so a prompt should read more lines before running it
+pub fn is_unfinished(source: &str) -> bool {
+    match Scanner::new(source.to_string()).scan_tokens() {
+        Ok(tokens) => {
+            let depth: i32 = tokens
+                .iter()
+                .map(|token| match token.token_type() {
+                    TokenType::LeftParen | TokenType::LeftBrace => 1,
+                    TokenType::RightParen | TokenType::RightBrace => -1,
+                    _ => 0,
+                })
+                .sum();
+            depth > 0
+        }
+        Err(errors) => errors
+            .iter()
+            .all(|error| matches!(error, ScannerError::UnterminatedString(..))),
+    }
+}
+
+impl Default for Lox {
+    fn default() -> Self {
+        Lox::new()
+    }
+}
+
+impl Lox {
+    pub fn new() -> Lox {
+        Lox {
+            interpreter: Interpreter::new(),
+            opt_level: OptLevel::None,
+        }
+    }
+
+    /// Optimise scripts before running them, the output stays the same
+    pub fn set_opt_level(&mut self, opt_level: OptLevel) {
+        self.opt_level = opt_level;
+    }
+
+    /// Run a program, returning the value of its last statement when that is
+    /// an expression, `null` otherwise
+    pub fn eval(&mut self, source: &str) -> Result<Value, LoxError> {
+        self.eval_statements(parse(source)?)
+    }
+
+    /// Run a program that is already parsed, like `eval`
+    pub fn eval_statements(&mut self, mut statements: Vec<Stmt>) -> Result<Value, LoxError> {
+        Resolver::new()
+            .resolve(&statements)
+            .map_err(LoxError::ResolverErrors)?;
+        if self.opt_level >= OptLevel::Fold {
+            statements = Optimizer::new().optimize(statements);
+        }
+
+        match statements.split_last() {
+            Some((Stmt::Expression(last), rest)) => {
+                self.interpreter.interpret(rest)?;
+                Ok(self.interpreter.evaluate(&last.expression)?)
+            }
+            _ => {
+                self.interpreter.interpret(&statements)?;
+                Ok(Value::Null)
+            }
+        }
+    }
+
+    pub fn run_file(&mut self, path: &str) -> Result<Value, LoxError> {
+        let source =
+            std::fs::read_to_string(path).map_err(|e| LoxError::FailedToReadFile(e.to_string()))?;
+
+        self.eval(&source)
+    }
+
+    /// Define or redefine a global variable
+    pub fn set_global(&mut self, name: &str, value: impl IntoLox) {
+        self.interpreter
+            .globals()
+            .borrow_mut()
+            .define(name, value.into_lox());
+    }
+
+    pub fn get_global<T: FromLox>(&self, name: &str) -> Result<T, LoxError> {
+        let value = self
+            .interpreter
+            .globals()
+            .borrow()
+            .get_local(name)
+            .ok_or_else(|| LoxError::UndefinedGlobal(name.to_string()))?;
+
+        T::from_lox(value)
+    }
+
+    /// Define a global function implemented in Rust, errors it returns are
+    /// reported as runtime errors at the call site
+    pub fn register_fn<F, R>(&mut self, name: &str, arity: Arity, function: F)
+    where
+        F: Fn(&Args) -> Result<R, LoxError> + 'static,
+        R: IntoLox,
+    {
+        let native = NativeFunction::new(name, arity, function);
+        self.set_global(name, Value::Native(Rc::new(native)));
+    }
+
+    /// Call a global function or class, arguments can be built with `IntoLox::into_lox`
+    pub fn call_function<T: FromLox>(
+        &mut self,
+        name: &str,
+        arguments: Vec<Value>,
+    ) -> Result<T, LoxError> {
+        let callee = self.get_global::<Value>(name)?;
+        let value = self.interpreter.call(callee, arguments, Span::default())?;
+
+        T::from_lox(value)
+    }
+}
+
+impl IntoLox for Value {
+    fn into_lox(self) -> Value {
+        self
+    }
+}
+
+impl IntoLox for () {
+    fn into_lox(self) -> Value {
+        Value::Null
+    }
+}
+
+impl IntoLox for bool {
+    fn into_lox(self) -> Value {
+        Value::Boolean(self)
+    }
+}
+
+impl IntoLox for f64 {
+    fn into_lox(self) -> Value {
+        Value::Number(self)
+    }
+}
+
+impl IntoLox for i32 {
+    fn into_lox(self) -> Value {
+        Value::Number(self.into())
+    }
+}
+
+impl IntoLox for &str {
+    fn into_lox(self) -> Value {
+        Value::String(Rc::from(self))
+    }
+}
+
+impl IntoLox for String {
+    fn into_lox(self) -> Value {
+        Value::String(Rc::from(self))
+    }
+}
+
+impl<T: IntoLox> IntoLox for Option<T> {
+    fn into_lox(self) -> Value {
+        self.map_or(Value::Null, IntoLox::into_lox)
+    }
+}
+
+impl FromLox for Value {
+    fn from_lox(value: Value) -> Result<Self, LoxError> {
+        Ok(value)
+    }
+}
+
+impl FromLox for () {
+    fn from_lox(value: Value) -> Result<Self, LoxError> {
+        match value {
+            Value::Null => Ok(()),
+            value => Err(LoxError::TypeMismatch("null", value.type_name())),
+        }
+    }
+}
+
+impl FromLox for bool {
+    fn from_lox(value: Value) -> Result<Self, LoxError> {
+        match value {
+            Value::Boolean(value) => Ok(value),
+            value => Err(LoxError::TypeMismatch("boolean", value.type_name())),
+        }
+    }
+}
+
+impl FromLox for f64 {
+    fn from_lox(value: Value) -> Result<Self, LoxError> {
+        match value {
+            Value::Number(value) => Ok(value),
+            value => Err(LoxError::TypeMismatch("number", value.type_name())),
+        }
+    }
+}
+
+impl FromLox for String {
+    fn from_lox(value: Value) -> Result<Self, LoxError> {
+        match value {
+            Value::String(value) => Ok(value.to_string()),
+            value => Err(LoxError::TypeMismatch("string", value.type_name())),
+        }
+    }
+}
+
+/// `null` converts to `None`, anything else must convert to `T`
+impl<T: FromLox> FromLox for Option<T> {
+    fn from_lox(value: Value) -> Result<Self, LoxError> {
+        match value {
+            Value::Null => Ok(None),
+            value => T::from_lox(value).map(Some),
+        }
+    }
+}
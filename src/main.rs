@@ -1,72 +1,449 @@
-use rs_crafting_interpreters::scanner::{Scanner, ScannerError, ScannerTrait};
+use rs_crafting_interpreters::ast_printer::{print_ast, AstFormat};
+use rs_crafting_interpreters::bytecode::object::{Heap, ObjRef};
+use rs_crafting_interpreters::bytecode::{self, disassembler, serializer, vm::Vm};
+use rs_crafting_interpreters::c_backend;
+use rs_crafting_interpreters::lox::{self, Lox, LoxError};
+use rs_crafting_interpreters::optimizer::{OptLevel, Optimizer, OptimizerTrait};
+use rs_crafting_interpreters::register::{self, instruction, vm::RegisterVm};
+use rs_crafting_interpreters::stmt::Stmt;
 use std::env;
+use std::io::{BufRead, IsTerminal, Write};
+
+const USAGE: &str = "Usage: rlox [script]
+       rlox ast [--format lisp|rpn|tree|json|dot] [--opt-level 0|1] <file>
+       rlox run [--engine tree|vm|register] [--opt-level 0|1] [--trace]
+                [--gc mark-sweep|generational] [--gc-stress] [--gc-stats]
+                [--profile]
+                <file|file.loxc>
+       rlox compile [--opt-level 0|1] <file> [-o <file.loxc>]
+       rlox build [--emit c] [--opt-level 0|1] <file> [-o <file.c>]
+       rlox disasm [--engine vm|register] [--opt-level 0|1] <file|file.loxc>";
+
+/// Backend executing a script
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Engine {
+    Tree,
+    Vm,
+    Register,
+}
+
+impl std::str::FromStr for Engine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tree" => Ok(Engine::Tree),
+            "vm" => Ok(Engine::Vm),
+            "register" => Ok(Engine::Register),
+            _ => Err(s.to_string()),
+        }
+    }
+}
 
 #[derive(Debug, thiserror::Error)]
 enum RLoxError {
     #[error("failed to read file: {0}")]
     FailedToReadFile(String),
 
-    #[error("scanner error: {0}")]
-    ScannerError(#[from] ScannerError),
+    #[error("unknown ast format: {0}")]
+    UnknownAstFormat(String),
+
+    #[error("unknown engine: {0}")]
+    UnknownEngine(String),
+
+    #[error("unknown garbage collector: {0}")]
+    UnknownGc(String),
+
+    #[error("unknown optimisation level: {0}")]
+    UnknownOptLevel(String),
+
+    #[error("unknown emit target: {0}")]
+    UnknownEmitTarget(String),
+
+    #[error("compiled bytecode can only run on the vm engine")]
+    CompiledNeedsVm,
+
+    #[error("failed to write file: {0}")]
+    FailedToWriteFile(String),
+
+    #[error("failed to read input: {0}")]
+    FailedToReadInput(String),
+
+    #[error(transparent)]
+    LoxError(#[from] LoxError),
 }
 
 fn main() {
     let args: Vec<String> = env::args().collect();
 
-    match args.len() {
-        i if i > 2 => {
-            println!("Usage: rlox [script]");
-            std::process::exit(64);
+    match args.get(1).map(String::as_str) {
+        Some("ast") => {
+            run_ast(&args[2..]).unwrap_or_else(report_errors);
+        }
+        Some("run") => {
+            run_command(&args[2..]).unwrap_or_else(report_errors);
+        }
+        Some("compile") => {
+            run_compile(&args[2..]).unwrap_or_else(report_errors);
         }
-        2 => {
-            let file_path = &args[1];
+        Some("build") => {
+            run_build(&args[2..]).unwrap_or_else(report_errors);
+        }
+        Some("disasm") => {
+            run_disasm(&args[2..]).unwrap_or_else(report_errors);
+        }
+        Some(_) if args.len() > 2 => usage(),
+        Some(file_path) => {
             run_file(file_path).unwrap_or_else(report_errors);
         }
-        _ => {
+        None => {
             run_prompt().unwrap_or_else(report_errors);
         }
     }
 }
 
+fn usage() -> ! {
+    println!("{}", USAGE);
+    std::process::exit(64);
+}
+
 fn report_errors(errors: Vec<RLoxError>) {
+    let exit_code = match errors.first() {
+        Some(RLoxError::LoxError(LoxError::RuntimeError(_) | LoxError::VmError(_))) => 70,
+        _ => 65,
+    };
+
     for error in errors {
         eprintln!("{}", error);
     }
-    std::process::exit(65);
+    std::process::exit(exit_code);
+}
+
+/// Command line arguments split into options, flags and positional arguments
+#[derive(Debug, Default)]
+struct Options {
+    values: Vec<(String, String)>,
+    flags: Vec<String>,
+    positional: Vec<String>,
+}
+
+impl Options {
+    /// The last value given for an option
+    fn value(&self, name: &str) -> Option<&str> {
+        self.values
+            .iter()
+            .rev()
+            .find(|(option, _)| option == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|flag| flag == name)
+    }
+
+    /// The `--opt-level` option, no optimisation when missing
+    fn opt_level(&self) -> Result<OptLevel, Vec<RLoxError>> {
+        match self.value("opt-level") {
+            Some(value) => value
+                .parse()
+                .map_err(|level| vec![RLoxError::UnknownOptLevel(level)]),
+            None => Ok(OptLevel::None),
+        }
+    }
+}
+
+/// Split `--name value`, `--name=value` and single letter `-n value` options
+/// and `--flag` switches from positional arguments
+fn parse_options(args: &[String], names: &[&str], flags: &[&str]) -> Options {
+    let mut options = Options::default();
+    let mut iter = args.iter();
+
+    while let Some(arg) = iter.next() {
+        let option = arg
+            .strip_prefix("--")
+            .or_else(|| arg.strip_prefix('-').filter(|name| name.len() == 1));
+        let Some(option) = option else {
+            options.positional.push(arg.clone());
+            continue;
+        };
+
+        if flags.contains(&option) {
+            options.flags.push(option.to_string());
+            continue;
+        }
+
+        let (name, value) = match option.split_once('=') {
+            Some((name, value)) => (name, Some(value.to_string())),
+            None => (option, None),
+        };
+        if !names.contains(&name) {
+            usage();
+        }
+
+        let value = value
+            .or_else(|| iter.next().cloned())
+            .unwrap_or_else(|| usage());
+        options.values.push((name.to_string(), value));
+    }
+
+    options
+}
+
+fn read_file(file_path: &str) -> Result<String, Vec<RLoxError>> {
+    std::fs::read_to_string(file_path).map_err(|e| vec![RLoxError::FailedToReadFile(e.to_string())])
+}
+
+fn read_bytes(file_path: &str) -> Result<Vec<u8>, Vec<RLoxError>> {
+    std::fs::read(file_path).map_err(|e| vec![RLoxError::FailedToReadFile(e.to_string())])
+}
+
+/// Compile a source file, or load it when it is precompiled bytecode
+fn load_script(
+    file_path: &str,
+    heap: &mut Heap,
+    opt_level: OptLevel,
+) -> Result<ObjRef, Vec<RLoxError>> {
+    let bytes = read_bytes(file_path)?;
+    let script = if serializer::is_compiled(&bytes) {
+        bytecode::load(&bytes, heap)
+    } else {
+        let source = String::from_utf8(bytes)
+            .map_err(|e| vec![RLoxError::FailedToReadFile(e.to_string())])?;
+        bytecode::compile_with(&source, heap, opt_level)
+    };
+
+    script.map_err(|e| vec![e.into()])
 }
 
 fn run_file(file_path: &str) -> Result<(), Vec<RLoxError>> {
-    let file = std::fs::read_to_string(file_path)
-        .map_err(|e| vec![RLoxError::FailedToReadFile(e.to_string())])?;
+    let file = read_file(file_path)?;
+
+    run(file, OptLevel::None)?;
+
+    Ok(())
+}
+
+fn run_ast(args: &[String]) -> Result<(), Vec<RLoxError>> {
+    let options = parse_options(args, &["format", "opt-level"], &[]);
+    let [file_path] = options.positional.as_slice() else {
+        usage();
+    };
+
+    let format = match options.value("format") {
+        Some(value) => value
+            .parse()
+            .map_err(|name| vec![RLoxError::UnknownAstFormat(name)])?,
+        None => AstFormat::Lisp,
+    };
 
-    run(file)?;
+    let mut statements = lox::parse(&read_file(file_path)?).map_err(|e| vec![e.into()])?;
+    if options.opt_level()? >= OptLevel::Fold {
+        statements = Optimizer::new().optimize(statements);
+    }
+    println!("{}", print_ast(&statements, format));
 
     Ok(())
 }
 
-// Run command line prompt
+fn run_command(args: &[String]) -> Result<(), Vec<RLoxError>> {
+    let options = parse_options(
+        args,
+        &["engine", "gc", "opt-level"],
+        &["trace", "gc-stress", "gc-stats", "profile"],
+    );
+    let [file_path] = options.positional.as_slice() else {
+        usage();
+    };
+
+    let engine = match options.value("engine") {
+        Some(value) => Some(
+            value
+                .parse()
+                .map_err(|name| vec![RLoxError::UnknownEngine(name)])?,
+        ),
+        None => None,
+    };
+
+    // Precompiled files only run on the vm, so it is their default engine.
+    let compiled = serializer::is_compiled(&read_bytes(file_path)?);
+    let engine = match engine {
+        Some(Engine::Tree | Engine::Register) if compiled => {
+            return Err(vec![RLoxError::CompiledNeedsVm])
+        }
+        Some(engine) => engine,
+        None if compiled => Engine::Vm,
+        None => Engine::Tree,
+    };
+
+    match engine {
+        Engine::Tree => run(read_file(file_path)?, options.opt_level()?),
+        Engine::Vm => {
+            let mut vm = Vm::new();
+            vm.set_trace(options.flag("trace"));
+            vm.set_gc_stress(options.flag("gc-stress"));
+            if let Some(value) = options.value("gc") {
+                let mode = value
+                    .parse()
+                    .map_err(|name| vec![RLoxError::UnknownGc(name)])?;
+                vm.set_gc_mode(mode);
+            }
+
+            let script = load_script(file_path, vm.heap_mut(), options.opt_level()?)?;
+            let result = vm.run(script).map_err(|e| vec![LoxError::from(e).into()]);
+            if options.flag("gc-stats") {
+                eprintln!("{}", vm.gc_stats());
+            }
+            if options.flag("profile") {
+                eprintln!("{}", vm.cache_stats());
+            }
+            result
+        }
+        Engine::Register => {
+            let mut vm = RegisterVm::new();
+            vm.set_trace(options.flag("trace"));
+            vm.set_gc_stress(options.flag("gc-stress"));
+            if let Some(value) = options.value("gc") {
+                let mode = value
+                    .parse()
+                    .map_err(|name| vec![RLoxError::UnknownGc(name)])?;
+                vm.set_gc_mode(mode);
+            }
+
+            let script =
+                register::compile_with(&read_file(file_path)?, vm.heap_mut(), options.opt_level()?)
+                    .map_err(|e| vec![e.into()])?;
+            let result = vm.run(script).map_err(|e| vec![LoxError::from(e).into()]);
+            if options.flag("gc-stats") {
+                eprintln!("{}", vm.gc_stats());
+            }
+            result
+        }
+    }
+}
+
+fn run_compile(args: &[String]) -> Result<(), Vec<RLoxError>> {
+    let options = parse_options(args, &["o", "opt-level"], &[]);
+    let [file_path] = options.positional.as_slice() else {
+        usage();
+    };
+    let output = match options.value("o") {
+        Some(output) => output.into(),
+        None => std::path::Path::new(file_path).with_extension("loxc"),
+    };
+
+    let mut heap = Heap::new();
+    let source = read_file(file_path)?;
+    let script = bytecode::compile_with(&source, &mut heap, options.opt_level()?)
+        .map_err(|e| vec![e.into()])?;
+    std::fs::write(output, serializer::serialize(script, &heap))
+        .map_err(|e| vec![RLoxError::FailedToWriteFile(e.to_string())])?;
+
+    Ok(())
+}
+
+fn run_build(args: &[String]) -> Result<(), Vec<RLoxError>> {
+    let options = parse_options(args, &["emit", "o", "opt-level"], &[]);
+    let [file_path] = options.positional.as_slice() else {
+        usage();
+    };
+    match options.value("emit") {
+        Some("c") | None => {}
+        Some(target) => return Err(vec![RLoxError::UnknownEmitTarget(target.to_string())]),
+    }
+    let output = match options.value("o") {
+        Some(output) => output.into(),
+        None => std::path::Path::new(file_path).with_extension("c"),
+    };
+
+    let program = c_backend::emit_c(&read_file(file_path)?, options.opt_level()?)
+        .map_err(|e| vec![e.into()])?;
+    std::fs::write(output, program)
+        .map_err(|e| vec![RLoxError::FailedToWriteFile(e.to_string())])?;
+
+    Ok(())
+}
+
+fn run_disasm(args: &[String]) -> Result<(), Vec<RLoxError>> {
+    let options = parse_options(args, &["engine", "opt-level"], &[]);
+    let [file_path] = options.positional.as_slice() else {
+        usage();
+    };
+
+    let mut heap = Heap::new();
+    match options.value("engine") {
+        Some("register") => {
+            let script =
+                register::compile_with(&read_file(file_path)?, &mut heap, options.opt_level()?)
+                    .map_err(|e| vec![e.into()])?;
+            print!("{}", instruction::disassemble(script, &heap));
+        }
+        Some("vm") | None => {
+            let script = load_script(file_path, &mut heap, options.opt_level()?)?;
+            print!("{}", disassembler::disassemble(script, &heap));
+        }
+        Some(name) => return Err(vec![RLoxError::UnknownEngine(name.to_string())]),
+    }
+
+    Ok(())
+}
+
+/// Read, evaluate and print lines until end of input. Globals persist
+/// between lines, errors are reported without ending the session, and a
+/// line with unclosed brackets or strings continues on the next one.
 fn run_prompt() -> Result<(), Vec<RLoxError>> {
+    let mut lox = Lox::new();
+    let mut stdin = std::io::stdin().lock();
+    let mut source = String::new();
+
     loop {
-        print!("> ");
-        let mut input = String::new();
-        std::io::stdin().read_line(&mut input).unwrap();
+        print!("{}", if source.is_empty() { "> " } else { "... " });
+        std::io::stdout()
+            .flush()
+            .map_err(|e| vec![RLoxError::FailedToReadInput(e.to_string())])?;
 
-        run(input)?;
+        let mut line = String::new();
+        let read = stdin
+            .read_line(&mut line)
+            .map_err(|e| vec![RLoxError::FailedToReadInput(e.to_string())])?;
+        if read == 0 {
+            // Run what is left so its errors are reported, then leave on a fresh line.
+            if !source.trim().is_empty() {
+                eval_line(&mut lox, &source);
+            }
+            if std::io::stdin().is_terminal() {
+                println!();
+            }
+            return Ok(());
+        }
+
+        source.push_str(&line);
+        if lox::is_unfinished(&source) {
+            continue;
+        }
+        eval_line(&mut lox, &source);
+        source.clear();
     }
 }
 
-fn run(source: String) -> Result<(), Vec<RLoxError>> {
-    println!("Run source: {:?}", source);
-    let mut scanner = Scanner::new(source);
-    let tokens = scanner.scan_tokens().map_err(|scan_errors| {
-        let rlox_error: Vec<RLoxError> = scan_errors
-            .into_iter()
-            .map(RLoxError::ScannerError)
-            .collect();
-        rlox_error
-    })?;
+/// Run a line of the prompt, echoing the value when it is an expression.
+/// The semicolon after a last expression may be left out.
+fn eval_line(lox: &mut Lox, source: &str) {
+    let result = lox::parse_line(source).and_then(|statements| {
+        let echo = matches!(statements.as_slice(), [.., Stmt::Expression(_)]);
+        lox.eval_statements(statements).map(|value| (value, echo))
+    });
+
+    match result {
+        Ok((value, true)) => println!("{}", value),
+        Ok(_) => {}
+        Err(error) => eprintln!("{}", error),
+    }
+}
 
-    println!("Tokens: {:?}", tokens);
+fn run(source: String, opt_level: OptLevel) -> Result<(), Vec<RLoxError>> {
+    let mut lox = Lox::new();
+    lox.set_opt_level(opt_level);
+    lox.eval(&source).map_err(|e| vec![e.into()])?;
 
     Ok(())
 }
@@ -0,0 +1,86 @@
+use std::fmt::Debug;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::lox::{FromLox, IntoLox, LoxError};
+use crate::value::Value;
+
+/// Number of arguments a native function accepts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Arity {
+    Fixed(usize),
+    /// At least this many arguments
+    Variadic(usize),
+}
+
+type NativeFn = dyn Fn(&Args) -> Result<Value, LoxError>;
+
+/// A Rust closure callable from Lox code
+pub struct NativeFunction {
+    pub name: String,
+    pub arity: Arity,
+    function: Box<NativeFn>,
+}
+
+/// Arguments passed to a native function, converted on access
+#[derive(Debug)]
+pub struct Args(Vec<Value>);
+
+impl NativeFunction {
+    pub fn new<F, R>(name: &str, arity: Arity, function: F) -> NativeFunction
+    where
+        F: Fn(&Args) -> Result<R, LoxError> + 'static,
+        R: IntoLox,
+    {
+        NativeFunction {
+            name: name.to_string(),
+            arity,
+            function: Box::new(move |args| function(args).map(IntoLox::into_lox)),
+        }
+    }
+
+    pub fn call(&self, arguments: Vec<Value>) -> Result<Value, LoxError> {
+        (self.function)(&Args(arguments))
+    }
+}
+
+impl Debug for NativeFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+impl Args {
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Convert the argument at `index`, reporting its position on a type mismatch
+    pub fn get<T: FromLox>(&self, index: usize) -> Result<T, LoxError> {
+        let value = self.0.get(index).cloned().unwrap_or(Value::Null);
+
+        T::from_lox(value).map_err(|e| match e {
+            LoxError::TypeMismatch(expected, found) => {
+                LoxError::InvalidArgument(index, expected, found)
+            }
+            e => e,
+        })
+    }
+
+    pub fn values(&self) -> &[Value] {
+        &self.0
+    }
+}
+
+/// Functions defined in every interpreter
+pub fn builtins() -> Vec<NativeFunction> {
+    vec![NativeFunction::new("clock", Arity::Fixed(0), |_| {
+        let elapsed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        Ok(elapsed.as_secs_f64())
+    })]
+}
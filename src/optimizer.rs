@@ -0,0 +1,369 @@
+//! Optimisations on the syntax tree. They run after the resolver, so code
+//! they remove still reports its errors, and never change what a program
+//! prints or the runtime errors it raises.
+
+use std::cmp::Ordering;
+use std::rc::Rc;
+use std::str::FromStr;
+
+use crate::expr::{self, Expr};
+use crate::stmt::{self, Stmt};
+use crate::token::{Literal, Span, Token, TokenTrait, TokenType};
+
+/// How much a program is optimised before it runs, `--opt-level`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OptLevel {
+    /// Run the program as written
+    #[default]
+    None,
+    /// Fold constant expressions, remove double negations and branches that
+    /// can never run
+    Fold,
+}
+
+impl FromStr for OptLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "0" => Ok(OptLevel::None),
+            "1" => Ok(OptLevel::Fold),
+            _ => Err(s.to_string()),
+        }
+    }
+}
+
+/// Rewrites a resolved program into a cheaper one with the same behavior
+#[derive(Debug, Default)]
+pub struct Optimizer;
+
+pub trait OptimizerTrait {
+    fn new() -> Self;
+    fn optimize(&mut self, statements: Vec<Stmt>) -> Vec<Stmt>;
+    fn optimize_stmt(&mut self, stmt: Stmt) -> Stmt;
+    fn optimize_function(&mut self, function: Rc<stmt::Function>) -> Rc<stmt::Function>;
+    fn optimize_expr(&mut self, expr: Expr) -> Expr;
+    /// Optimise an expression whose value is only tested for truthiness
+    fn optimize_condition(&mut self, expr: Expr) -> Expr;
+}
+
+impl OptimizerTrait for Optimizer {
+    fn new() -> Optimizer {
+        Optimizer
+    }
+
+    fn optimize(&mut self, statements: Vec<Stmt>) -> Vec<Stmt> {
+        statements
+            .into_iter()
+            .map(|statement| self.optimize_stmt(statement))
+            .collect()
+    }
+
+    fn optimize_stmt(&mut self, stmt: Stmt) -> Stmt {
+        match stmt {
+            Stmt::Block(block) => Stmt::Block(stmt::Block {
+                statements: self.optimize(block.statements),
+                span: block.span,
+            }),
+            Stmt::Class(class) => Stmt::Class(stmt::Class {
+                methods: class
+                    .methods
+                    .into_iter()
+                    .map(|method| self.optimize_function(method))
+                    .collect(),
+                ..class
+            }),
+            Stmt::Expression(statement) => Stmt::Expression(stmt::Expression {
+                expression: self.optimize_expr(statement.expression),
+            }),
+            Stmt::Function(function) => Stmt::Function(self.optimize_function(function)),
+            Stmt::If(statement) => {
+                let condition = self.optimize_condition(statement.condition);
+                let Expr::Literal(literal) = condition else {
+                    return Stmt::If(stmt::If {
+                        condition,
+                        then_branch: Box::new(self.optimize_stmt(*statement.then_branch)),
+                        else_branch: statement
+                            .else_branch
+                            .map(|branch| Box::new(self.optimize_stmt(*branch))),
+                        span: statement.span,
+                    });
+                };
+
+                let branch = match is_truthy(&literal.value) {
+                    true => Some(statement.then_branch),
+                    false => statement.else_branch,
+                };
+                match branch {
+                    Some(branch) => self.optimize_stmt(*branch),
+                    // Not removed, `Lox::eval` looks at the last statement.
+                    None => empty_block(statement.span),
+                }
+            }
+            Stmt::Print(statement) => Stmt::Print(stmt::Print {
+                expression: self.optimize_expr(statement.expression),
+                span: statement.span,
+            }),
+            Stmt::Return(statement) => Stmt::Return(stmt::Return {
+                value: statement.value.map(|value| self.optimize_expr(value)),
+                keyword: statement.keyword,
+            }),
+            Stmt::Var(statement) => Stmt::Var(stmt::Var {
+                initializer: statement
+                    .initializer
+                    .map(|initializer| self.optimize_expr(initializer)),
+                name: statement.name,
+            }),
+            Stmt::While(statement) => {
+                let condition = self.optimize_condition(statement.condition);
+                if let Expr::Literal(literal) = &condition {
+                    if !is_truthy(&literal.value) {
+                        return empty_block(statement.span);
+                    }
+                }
+
+                Stmt::While(stmt::While {
+                    condition,
+                    body: Box::new(self.optimize_stmt(*statement.body)),
+                    span: statement.span,
+                })
+            }
+        }
+    }
+
+    fn optimize_function(&mut self, function: Rc<stmt::Function>) -> Rc<stmt::Function> {
+        let function = Rc::try_unwrap(function).unwrap_or_else(|shared| (*shared).clone());
+
+        Rc::new(stmt::Function {
+            body: self.optimize(function.body),
+            ..function
+        })
+    }
+
+    fn optimize_expr(&mut self, expr: Expr) -> Expr {
+        // Every arm lives in its own function, so the frame of this one,
+        // repeated for each level of the tree, stays small.
+        match expr {
+            Expr::Assign(assign) => self.assign(assign),
+            Expr::Binary(binary) => self.binary(binary),
+            Expr::Call(call) => self.call(call),
+            Expr::Get(get) => self.get(get),
+            Expr::Grouping(grouping) => self.grouping(grouping),
+            Expr::Logical(logical) => {
+                let left = self.optimize_expr(*logical.left);
+                let right = self.optimize_expr(*logical.right);
+                self.logical(logical.operator, left, right)
+            }
+            Expr::Set(set) => self.set(set),
+            Expr::Unary(unary) => self.unary(unary),
+            expr @ (Expr::Literal(_) | Expr::Super(_) | Expr::This(_) | Expr::Variable(_)) => expr,
+        }
+    }
+
+    fn optimize_condition(&mut self, expr: Expr) -> Expr {
+        match self.optimize_expr(expr) {
+            // Only the truthiness of the value is used, so `!!x` tests the same as `x`.
+            Expr::Unary(unary) if is_not(&unary) => match *unary.right {
+                Expr::Unary(inner) if is_not(&inner) => self.optimize_condition(*inner.right),
+                right => unary_expr(unary.operator, right),
+            },
+            // The operand a logical expression returns is tested in its place.
+            Expr::Logical(logical) => {
+                let left = self.optimize_condition(*logical.left);
+                let right = self.optimize_condition(*logical.right);
+                self.logical(logical.operator, left, right)
+            }
+            Expr::Grouping(grouping) => Expr::Grouping(expr::Grouping {
+                expression: Box::new(self.optimize_condition(*grouping.expression)),
+                span: grouping.span,
+            }),
+            expr => expr,
+        }
+    }
+}
+
+impl Optimizer {
+    fn assign(&mut self, assign: expr::Assign) -> Expr {
+        Expr::Assign(expr::Assign {
+            value: Box::new(self.optimize_expr(*assign.value)),
+            ..assign
+        })
+    }
+
+    fn binary(&mut self, binary: expr::Binary) -> Expr {
+        let left = self.optimize_expr(*binary.left);
+        let right = self.optimize_expr(*binary.right);
+
+        if let (Expr::Literal(a), Expr::Literal(b)) = (&left, &right) {
+            if let Some(value) = fold_binary(binary.operator.token_type(), &a.value, &b.value) {
+                return literal_expr(value, binary.operator.span());
+            }
+        }
+
+        Expr::Binary(expr::Binary {
+            left: Box::new(left),
+            operator: binary.operator,
+            right: Box::new(right),
+        })
+    }
+
+    fn call(&mut self, call: expr::Call) -> Expr {
+        Expr::Call(expr::Call {
+            callee: Box::new(self.optimize_expr(*call.callee)),
+            arguments: call
+                .arguments
+                .into_iter()
+                .map(|argument| self.optimize_expr(argument))
+                .collect(),
+            paren: call.paren,
+        })
+    }
+
+    fn get(&mut self, get: expr::Get) -> Expr {
+        Expr::Get(expr::Get {
+            object: Box::new(self.optimize_expr(*get.object)),
+            name: get.name,
+        })
+    }
+
+    fn grouping(&mut self, grouping: expr::Grouping) -> Expr {
+        match self.optimize_expr(*grouping.expression) {
+            Expr::Literal(literal) => Expr::Literal(literal),
+            expression => Expr::Grouping(expr::Grouping {
+                expression: Box::new(expression),
+                span: grouping.span,
+            }),
+        }
+    }
+
+    fn set(&mut self, set: expr::Set) -> Expr {
+        Expr::Set(expr::Set {
+            object: Box::new(self.optimize_expr(*set.object)),
+            value: Box::new(self.optimize_expr(*set.value)),
+            name: set.name,
+        })
+    }
+
+    fn unary(&mut self, unary: expr::Unary) -> Expr {
+        let right = self.optimize_expr(*unary.right);
+        let is_bang = unary.operator.token_type() == TokenType::Bang;
+
+        match right {
+            Expr::Literal(literal) => match fold_unary(unary.operator.token_type(), &literal.value)
+            {
+                Some(value) => literal_expr(value, unary.operator.span()),
+                None => unary_expr(unary.operator, Expr::Literal(literal)),
+            },
+            // `!!x` is `x` when `x` is already a boolean.
+            Expr::Unary(inner) if is_bang && is_not(&inner) && is_boolean(&inner.right) => {
+                *inner.right
+            }
+            right => unary_expr(unary.operator, right),
+        }
+    }
+
+    /// A logical expression with a constant left operand is one of its operands
+    fn logical(&mut self, operator: Token, left: Expr, right: Expr) -> Expr {
+        if let Expr::Literal(literal) = &left {
+            let returns_left = match operator.token_type() {
+                TokenType::Or => is_truthy(&literal.value),
+                _ => !is_truthy(&literal.value),
+            };
+
+            return if returns_left { left } else { right };
+        }
+
+        Expr::Logical(expr::Logical {
+            left: Box::new(left),
+            operator,
+            right: Box::new(right),
+        })
+    }
+}
+
+fn literal_expr(value: Literal, span: Span) -> Expr {
+    Expr::Literal(expr::Literal { value, span })
+}
+
+fn unary_expr(operator: Token, right: Expr) -> Expr {
+    Expr::Unary(expr::Unary {
+        operator,
+        right: Box::new(right),
+    })
+}
+
+fn empty_block(span: Span) -> Stmt {
+    Stmt::Block(stmt::Block {
+        statements: Vec::new(),
+        span,
+    })
+}
+
+fn is_not(unary: &expr::Unary) -> bool {
+    unary.operator.token_type() == TokenType::Bang
+}
+
+/// Whether an expression always evaluates to `true` or `false`
+fn is_boolean(expr: &Expr) -> bool {
+    match expr {
+        Expr::Literal(literal) => matches!(literal.value, Literal::Boolean(_)),
+        Expr::Unary(unary) => is_not(unary),
+        Expr::Binary(binary) => matches!(
+            binary.operator.token_type(),
+            TokenType::EqualEqual
+                | TokenType::BangEqual
+                | TokenType::Greater
+                | TokenType::GreaterEqual
+                | TokenType::Less
+                | TokenType::LessEqual
+        ),
+        Expr::Grouping(grouping) => is_boolean(&grouping.expression),
+        _ => false,
+    }
+}
+
+fn is_truthy(value: &Literal) -> bool {
+    !matches!(value, Literal::Null | Literal::Boolean(false))
+}
+
+/// The value of a unary operator on a literal, `None` when it raises an error
+fn fold_unary(operator: TokenType, value: &Literal) -> Option<Literal> {
+    match (operator, value) {
+        (TokenType::Minus, Literal::Number(number)) => Some(Literal::Number(-number)),
+        (TokenType::Bang, value) => Some(Literal::Boolean(!is_truthy(value))),
+        _ => None,
+    }
+}
+
+/// The value of a binary operator on literals, `None` when it raises an error
+fn fold_binary(operator: TokenType, left: &Literal, right: &Literal) -> Option<Literal> {
+    if let (Literal::Number(a), Literal::Number(b)) = (left, right) {
+        let (a, b) = (*a, *b);
+        let value = match operator {
+            TokenType::Plus => Literal::Number(a + b),
+            TokenType::Minus => Literal::Number(a - b),
+            TokenType::Star => Literal::Number(a * b),
+            TokenType::Slash => Literal::Number(a / b),
+            TokenType::Greater => Literal::Boolean(a > b),
+            // `>=` and `<=` are the negation of `<` and `>`, as the engines run them.
+            TokenType::GreaterEqual => Literal::Boolean(a.partial_cmp(&b) != Some(Ordering::Less)),
+            TokenType::Less => Literal::Boolean(a < b),
+            TokenType::LessEqual => Literal::Boolean(a.partial_cmp(&b) != Some(Ordering::Greater)),
+            TokenType::EqualEqual => Literal::Boolean(a == b),
+            TokenType::BangEqual => Literal::Boolean(a != b),
+            _ => return None,
+        };
+        return Some(value);
+    }
+
+    match operator {
+        TokenType::Plus => match (left, right) {
+            (Literal::String(a), Literal::String(b)) => Some(Literal::String(format!("{a}{b}"))),
+            _ => None,
+        },
+        // Literals of different types are never equal, like values.
+        TokenType::EqualEqual => Some(Literal::Boolean(left == right)),
+        TokenType::BangEqual => Some(Literal::Boolean(left != right)),
+        _ => None,
+    }
+}
@@ -0,0 +1,780 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+use crate::expr::{self, Expr};
+use crate::stmt::{self, Stmt};
+use crate::token::{Literal, Span, Token, TokenTrait, TokenType};
+
+/// Maximum number of arguments or parameters of a function
+pub const MAX_ARITY: usize = 255;
+/// Deepest nesting of expressions or statements, deeper programs are
+/// reported instead of overflowing the stack of the parser. With
+/// `MAX_OPERATORS` it also bounds how deep later passes recurse into a tree.
+pub const MAX_NESTING: usize = 64;
+/// Most binary and logical operators in one expression. The parser reads a
+/// chain like `a + b + c` in a loop, but later passes recurse into each
+/// operator, so longer expressions are reported like deep nesting.
+pub const MAX_OPERATORS: usize = 256;
+
+#[derive(Debug)]
+pub struct Parser {
+    tokens: Vec<Token>,
+    current: usize,
+    errors: Vec<ParserError>,
+    /// Expressions and statements being parsed inside each other
+    depth: usize,
+    /// Binary and logical operators in the expressions of the statement
+    /// being parsed, which can't contain statements of their own
+    operators: usize,
+    /// Whether the last statement may be an expression without its `;`
+    trailing_expression: bool,
+}
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum ParserError {
+    #[error("expected {0}, found {1} at {2}")]
+    UnexpectedToken(String, String, Span),
+    #[error("invalid assignment target at {0}")]
+    InvalidAssignmentTarget(Span),
+    #[error("can't have more than 255 arguments at {0}")]
+    TooManyArguments(Span),
+    #[error("can't have more than 255 parameters at {0}")]
+    TooManyParameters(Span),
+    #[error("{0} nested too deeply at {1}")]
+    NestedTooDeeply(&'static str, Span),
+    #[error("too many operators in one expression at {0}")]
+    TooManyOperators(Span),
+}
+
+impl ParserError {
+    pub fn span(&self) -> Span {
+        match self {
+            ParserError::UnexpectedToken(_, _, span)
+            | ParserError::InvalidAssignmentTarget(span)
+            | ParserError::TooManyArguments(span)
+            | ParserError::TooManyParameters(span)
+            | ParserError::NestedTooDeeply(_, span)
+            | ParserError::TooManyOperators(span) => *span,
+        }
+    }
+}
+
+pub trait ParserTrait {
+    fn new(tokens: Vec<Token>) -> Self;
+    /// Accept a last top-level expression without its `;`, as typed at a prompt
+    fn set_trailing_expression(&mut self, allowed: bool);
+    /// Parse every declaration, recovering from errors so all of them are reported
+    fn parse(&mut self) -> Result<Vec<Stmt>, Vec<ParserError>>;
+    /// Parse a declaration, on error record it and skip to the next statement
+    fn declaration(&mut self) -> Option<Stmt>;
+    fn class_declaration(&mut self) -> Result<Stmt, ParserError>;
+    /// Parse a function or method, `kind` is used in error messages
+    fn function(&mut self, kind: &str) -> Result<Rc<stmt::Function>, ParserError>;
+    fn var_declaration(&mut self) -> Result<Stmt, ParserError>;
+    /// Parse a statement one level deeper, see `nested`
+    fn statement(&mut self) -> Result<Stmt, ParserError>;
+    /// Dispatch on the keyword starting a statement
+    fn statement_kind(&mut self) -> Result<Stmt, ParserError>;
+    /// Parse a for loop and desugar it into a while loop
+    fn for_statement(&mut self) -> Result<Stmt, ParserError>;
+    fn if_statement(&mut self) -> Result<Stmt, ParserError>;
+    fn print_statement(&mut self) -> Result<Stmt, ParserError>;
+    fn return_statement(&mut self) -> Result<Stmt, ParserError>;
+    fn while_statement(&mut self) -> Result<Stmt, ParserError>;
+    /// Parse the statements of a block, the opening brace is already consumed
+    fn block(&mut self) -> Result<Vec<Stmt>, ParserError>;
+    fn expression_statement(&mut self) -> Result<Stmt, ParserError>;
+    fn expression(&mut self) -> Result<Expr, ParserError>;
+    fn assignment(&mut self) -> Result<Expr, ParserError>;
+    fn or(&mut self) -> Result<Expr, ParserError>;
+    fn and(&mut self) -> Result<Expr, ParserError>;
+    fn equality(&mut self) -> Result<Expr, ParserError>;
+    fn comparison(&mut self) -> Result<Expr, ParserError>;
+    fn term(&mut self) -> Result<Expr, ParserError>;
+    fn factor(&mut self) -> Result<Expr, ParserError>;
+    fn unary(&mut self) -> Result<Expr, ParserError>;
+    fn call(&mut self) -> Result<Expr, ParserError>;
+    fn finish_call(&mut self, callee: Expr) -> Result<Expr, ParserError>;
+    fn primary(&mut self) -> Result<Expr, ParserError>;
+    /// Consume the current token if it has one of the given types
+    fn token_match(&mut self, types: &[TokenType]) -> bool;
+    /// Consume a token of the given type or fail with "expected {expected}"
+    fn consume(&mut self, token_type: TokenType, expected: &str) -> Result<Token, ParserError>;
+    fn check(&self, token_type: TokenType) -> bool;
+    fn advance(&mut self) -> Token;
+    fn is_at_end(&self) -> bool;
+    fn peek(&self) -> &Token;
+    fn previous(&self) -> &Token;
+    /// Build an "expected X, found Y" error at the current token
+    fn error(&self, expected: &str) -> ParserError;
+    /// The binary or logical operator just matched, reporting the first one
+    /// past `MAX_OPERATORS` in a statement
+    fn operator(&mut self) -> Token;
+    /// Run `parse` one level deeper, failing past `MAX_NESTING`; `what` names
+    /// the construct in the error
+    fn nested<T>(
+        &mut self,
+        what: &'static str,
+        parse: impl FnOnce(&mut Self) -> Result<T, ParserError>,
+    ) -> Result<T, ParserError>;
+    /// Discard tokens until the start of the next statement
+    fn synchronize(&mut self);
+}
+
+impl ParserTrait for Parser {
+    fn new(tokens: Vec<Token>) -> Parser {
+        Parser {
+            tokens,
+            current: 0,
+            errors: Vec::new(),
+            depth: 0,
+            operators: 0,
+            trailing_expression: false,
+        }
+    }
+
+    fn set_trailing_expression(&mut self, allowed: bool) {
+        self.trailing_expression = allowed;
+    }
+
+    fn parse(&mut self) -> Result<Vec<Stmt>, Vec<ParserError>> {
+        let mut statements: Vec<Stmt> = Vec::new();
+
+        while !self.is_at_end() {
+            if let Some(statement) = self.declaration() {
+                statements.push(statement);
+            }
+        }
+
+        if !self.errors.is_empty() {
+            return Err(std::mem::take(&mut self.errors));
+        }
+
+        Ok(statements)
+    }
+
+    fn declaration(&mut self) -> Option<Stmt> {
+        self.operators = 0;
+        let result = if self.token_match(&[TokenType::Class]) {
+            self.class_declaration()
+        } else if self.token_match(&[TokenType::Fun]) {
+            self.function("function").map(Stmt::Function)
+        } else if self.token_match(&[TokenType::Var]) {
+            self.var_declaration()
+        } else {
+            self.statement()
+        };
+
+        match result {
+            Ok(statement) => Some(statement),
+            Err(e) => {
+                self.errors.push(e);
+                self.synchronize();
+                None
+            }
+        }
+    }
+
+    fn class_declaration(&mut self) -> Result<Stmt, ParserError> {
+        let name = self.consume(TokenType::Identifier, "class name")?;
+
+        let superclass = if self.token_match(&[TokenType::Less]) {
+            let name = self.consume(TokenType::Identifier, "superclass name")?;
+            Some(expr::Variable {
+                name,
+                depth: Cell::default(),
+            })
+        } else {
+            None
+        };
+
+        self.consume(TokenType::LeftBrace, "'{' before class body")?;
+
+        let mut methods = Vec::new();
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            methods.push(self.function("method")?);
+        }
+
+        self.consume(TokenType::RightBrace, "'}' after class body")?;
+
+        Ok(Stmt::Class(stmt::Class {
+            name,
+            superclass,
+            methods,
+        }))
+    }
+
+    fn function(&mut self, kind: &str) -> Result<Rc<stmt::Function>, ParserError> {
+        let name = self.consume(TokenType::Identifier, &format!("{kind} name"))?;
+        self.consume(TokenType::LeftParen, &format!("'(' after {kind} name"))?;
+
+        let mut params = Vec::new();
+        if !self.check(TokenType::RightParen) {
+            loop {
+                if params.len() >= MAX_ARITY {
+                    self.errors
+                        .push(ParserError::TooManyParameters(self.peek().span()));
+                }
+                params.push(self.consume(TokenType::Identifier, "parameter name")?);
+
+                if !self.token_match(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightParen, "')' after parameters")?;
+
+        self.consume(TokenType::LeftBrace, &format!("'{{' before {kind} body"))?;
+        let body = self.nested("statement", |parser| parser.block())?;
+
+        Ok(Rc::new(stmt::Function { name, params, body }))
+    }
+
+    fn var_declaration(&mut self) -> Result<Stmt, ParserError> {
+        let name = self.consume(TokenType::Identifier, "variable name")?;
+
+        let initializer = if self.token_match(&[TokenType::Equal]) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+
+        self.consume(TokenType::Semicolon, "';' after variable declaration")?;
+
+        Ok(Stmt::Var(stmt::Var { name, initializer }))
+    }
+
+    fn statement(&mut self) -> Result<Stmt, ParserError> {
+        self.nested("statement", |parser| parser.statement_kind())
+    }
+
+    fn statement_kind(&mut self) -> Result<Stmt, ParserError> {
+        self.operators = 0;
+        if self.token_match(&[TokenType::For]) {
+            return self.for_statement();
+        }
+        if self.token_match(&[TokenType::If]) {
+            return self.if_statement();
+        }
+        if self.token_match(&[TokenType::Print]) {
+            return self.print_statement();
+        }
+        if self.token_match(&[TokenType::Return]) {
+            return self.return_statement();
+        }
+        if self.token_match(&[TokenType::While]) {
+            return self.while_statement();
+        }
+        if self.token_match(&[TokenType::LeftBrace]) {
+            let span = self.previous().span();
+            return Ok(Stmt::Block(stmt::Block {
+                statements: self.block()?,
+                span,
+            }));
+        }
+
+        self.expression_statement()
+    }
+
+    fn for_statement(&mut self) -> Result<Stmt, ParserError> {
+        let span = self.previous().span();
+        self.consume(TokenType::LeftParen, "'(' after 'for'")?;
+
+        let initializer = if self.token_match(&[TokenType::Semicolon]) {
+            None
+        } else if self.token_match(&[TokenType::Var]) {
+            Some(self.var_declaration()?)
+        } else {
+            Some(self.expression_statement()?)
+        };
+
+        let condition = if !self.check(TokenType::Semicolon) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        self.consume(TokenType::Semicolon, "';' after loop condition")?;
+
+        let increment = if !self.check(TokenType::RightParen) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        self.consume(TokenType::RightParen, "')' after for clauses")?;
+
+        let mut body = self.statement()?;
+
+        if let Some(increment) = increment {
+            body = Stmt::Block(stmt::Block {
+                statements: vec![
+                    body,
+                    Stmt::Expression(stmt::Expression {
+                        expression: increment,
+                    }),
+                ],
+                span,
+            });
+        }
+
+        let condition = condition.unwrap_or(Expr::Literal(expr::Literal {
+            value: Literal::Boolean(true),
+            span,
+        }));
+        body = Stmt::While(stmt::While {
+            condition,
+            body: Box::new(body),
+            span,
+        });
+
+        if let Some(initializer) = initializer {
+            body = Stmt::Block(stmt::Block {
+                statements: vec![initializer, body],
+                span,
+            });
+        }
+
+        Ok(body)
+    }
+
+    fn if_statement(&mut self) -> Result<Stmt, ParserError> {
+        let span = self.previous().span();
+        self.consume(TokenType::LeftParen, "'(' after 'if'")?;
+        let condition = self.expression()?;
+        self.consume(TokenType::RightParen, "')' after if condition")?;
+
+        let then_branch = Box::new(self.statement()?);
+        let else_branch = if self.token_match(&[TokenType::Else]) {
+            Some(Box::new(self.statement()?))
+        } else {
+            None
+        };
+
+        Ok(Stmt::If(stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+            span,
+        }))
+    }
+
+    fn print_statement(&mut self) -> Result<Stmt, ParserError> {
+        let span = self.previous().span();
+        let expression = self.expression()?;
+        self.consume(TokenType::Semicolon, "';' after value")?;
+
+        Ok(Stmt::Print(stmt::Print { expression, span }))
+    }
+
+    fn return_statement(&mut self) -> Result<Stmt, ParserError> {
+        let keyword = self.previous().clone();
+        let value = if !self.check(TokenType::Semicolon) {
+            Some(self.expression()?)
+        } else {
+            None
+        };
+        self.consume(TokenType::Semicolon, "';' after return value")?;
+
+        Ok(Stmt::Return(stmt::Return { keyword, value }))
+    }
+
+    fn while_statement(&mut self) -> Result<Stmt, ParserError> {
+        let span = self.previous().span();
+        self.consume(TokenType::LeftParen, "'(' after 'while'")?;
+        let condition = self.expression()?;
+        self.consume(TokenType::RightParen, "')' after condition")?;
+        let body = Box::new(self.statement()?);
+
+        Ok(Stmt::While(stmt::While {
+            condition,
+            body,
+            span,
+        }))
+    }
+
+    fn block(&mut self) -> Result<Vec<Stmt>, ParserError> {
+        let mut statements = Vec::new();
+
+        while !self.check(TokenType::RightBrace) && !self.is_at_end() {
+            if let Some(statement) = self.declaration() {
+                statements.push(statement);
+            }
+        }
+
+        self.consume(TokenType::RightBrace, "'}' after block")?;
+
+        Ok(statements)
+    }
+
+    fn expression_statement(&mut self) -> Result<Stmt, ParserError> {
+        let expression = self.expression()?;
+        // A depth of one is a top-level statement, not the body of an `if` or loop.
+        let trailing = self.trailing_expression && self.depth == 1 && self.is_at_end();
+        if !trailing {
+            self.consume(TokenType::Semicolon, "';' after expression")?;
+        }
+
+        Ok(Stmt::Expression(stmt::Expression { expression }))
+    }
+
+    fn expression(&mut self) -> Result<Expr, ParserError> {
+        self.nested("expression", |parser| parser.assignment())
+    }
+
+    fn assignment(&mut self) -> Result<Expr, ParserError> {
+        let expr = self.or()?;
+
+        if self.token_match(&[TokenType::Equal]) {
+            let equals = self.previous().clone();
+            let value = Box::new(self.nested("expression", |parser| parser.assignment())?);
+
+            match expr {
+                Expr::Variable(variable) => {
+                    return Ok(Expr::Assign(expr::Assign {
+                        name: variable.name,
+                        value,
+                        depth: Cell::default(),
+                    }));
+                }
+                Expr::Get(get) => {
+                    return Ok(Expr::Set(expr::Set {
+                        object: get.object,
+                        name: get.name,
+                        value,
+                    }));
+                }
+                expr => {
+                    // Report without unwinding, the parser is not confused.
+                    self.errors
+                        .push(ParserError::InvalidAssignmentTarget(equals.span()));
+                    return Ok(expr);
+                }
+            }
+        }
+
+        Ok(expr)
+    }
+
+    fn or(&mut self) -> Result<Expr, ParserError> {
+        let mut expr = self.and()?;
+
+        while self.token_match(&[TokenType::Or]) {
+            let operator = self.operator();
+            let right = self.and()?;
+            expr = Expr::Logical(expr::Logical {
+                left: Box::new(expr),
+                operator,
+                right: Box::new(right),
+            });
+        }
+
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, ParserError> {
+        let mut expr = self.equality()?;
+
+        while self.token_match(&[TokenType::And]) {
+            let operator = self.operator();
+            let right = self.equality()?;
+            expr = Expr::Logical(expr::Logical {
+                left: Box::new(expr),
+                operator,
+                right: Box::new(right),
+            });
+        }
+
+        Ok(expr)
+    }
+
+    fn equality(&mut self) -> Result<Expr, ParserError> {
+        let mut expr = self.comparison()?;
+
+        while self.token_match(&[TokenType::BangEqual, TokenType::EqualEqual]) {
+            let operator = self.operator();
+            let right = self.comparison()?;
+            expr = Expr::Binary(expr::Binary {
+                left: Box::new(expr),
+                operator,
+                right: Box::new(right),
+            });
+        }
+
+        Ok(expr)
+    }
+
+    fn comparison(&mut self) -> Result<Expr, ParserError> {
+        let mut expr = self.term()?;
+
+        while self.token_match(&[
+            TokenType::Greater,
+            TokenType::GreaterEqual,
+            TokenType::Less,
+            TokenType::LessEqual,
+        ]) {
+            let operator = self.operator();
+            let right = self.term()?;
+            expr = Expr::Binary(expr::Binary {
+                left: Box::new(expr),
+                operator,
+                right: Box::new(right),
+            });
+        }
+
+        Ok(expr)
+    }
+
+    fn term(&mut self) -> Result<Expr, ParserError> {
+        let mut expr = self.factor()?;
+
+        while self.token_match(&[TokenType::Minus, TokenType::Plus]) {
+            let operator = self.operator();
+            let right = self.factor()?;
+            expr = Expr::Binary(expr::Binary {
+                left: Box::new(expr),
+                operator,
+                right: Box::new(right),
+            });
+        }
+
+        Ok(expr)
+    }
+
+    fn factor(&mut self) -> Result<Expr, ParserError> {
+        let mut expr = self.unary()?;
+
+        while self.token_match(&[TokenType::Slash, TokenType::Star]) {
+            let operator = self.operator();
+            let right = self.unary()?;
+            expr = Expr::Binary(expr::Binary {
+                left: Box::new(expr),
+                operator,
+                right: Box::new(right),
+            });
+        }
+
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, ParserError> {
+        if self.token_match(&[TokenType::Bang, TokenType::Minus]) {
+            let operator = self.previous().clone();
+            let right = self.nested("expression", |parser| parser.unary())?;
+            return Ok(Expr::Unary(expr::Unary {
+                operator,
+                right: Box::new(right),
+            }));
+        }
+
+        self.call()
+    }
+
+    fn call(&mut self) -> Result<Expr, ParserError> {
+        let mut expr = self.primary()?;
+
+        loop {
+            if self.token_match(&[TokenType::LeftParen]) {
+                expr = self.finish_call(expr)?;
+            } else if self.token_match(&[TokenType::Dot]) {
+                let name = self.consume(TokenType::Identifier, "property name after '.'")?;
+                expr = Expr::Get(expr::Get {
+                    object: Box::new(expr),
+                    name,
+                });
+            } else {
+                break;
+            }
+        }
+
+        Ok(expr)
+    }
+
+    fn finish_call(&mut self, callee: Expr) -> Result<Expr, ParserError> {
+        let mut arguments = Vec::new();
+
+        if !self.check(TokenType::RightParen) {
+            loop {
+                if arguments.len() >= MAX_ARITY {
+                    self.errors
+                        .push(ParserError::TooManyArguments(self.peek().span()));
+                }
+                arguments.push(self.expression()?);
+
+                if !self.token_match(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+
+        let paren = self.consume(TokenType::RightParen, "')' after arguments")?;
+
+        Ok(Expr::Call(expr::Call {
+            callee: Box::new(callee),
+            paren,
+            arguments,
+        }))
+    }
+
+    fn primary(&mut self) -> Result<Expr, ParserError> {
+        let token = self.peek().clone();
+
+        match token.token_type() {
+            TokenType::False => {
+                self.advance();
+                Ok(Expr::Literal(expr::Literal {
+                    value: Literal::Boolean(false),
+                    span: token.span(),
+                }))
+            }
+            TokenType::True => {
+                self.advance();
+                Ok(Expr::Literal(expr::Literal {
+                    value: Literal::Boolean(true),
+                    span: token.span(),
+                }))
+            }
+            TokenType::Null | TokenType::Number | TokenType::String => {
+                self.advance();
+                Ok(Expr::Literal(expr::Literal {
+                    value: token.literal().clone(),
+                    span: token.span(),
+                }))
+            }
+            TokenType::Super => {
+                let keyword = self.advance();
+                self.consume(TokenType::Dot, "'.' after 'super'")?;
+                let method = self.consume(TokenType::Identifier, "superclass method name")?;
+                Ok(Expr::Super(expr::Super {
+                    keyword,
+                    method,
+                    depth: Cell::default(),
+                }))
+            }
+            TokenType::This => Ok(Expr::This(expr::This {
+                keyword: self.advance(),
+                depth: Cell::default(),
+            })),
+            TokenType::Identifier => Ok(Expr::Variable(expr::Variable {
+                name: self.advance(),
+                depth: Cell::default(),
+            })),
+            TokenType::LeftParen => {
+                self.advance();
+                let expression = self.expression()?;
+                self.consume(TokenType::RightParen, "')' after expression")?;
+                Ok(Expr::Grouping(expr::Grouping {
+                    expression: Box::new(expression),
+                    span: token.span(),
+                }))
+            }
+            _ => Err(self.error("expression")),
+        }
+    }
+
+    fn token_match(&mut self, types: &[TokenType]) -> bool {
+        for token_type in types {
+            if self.check(*token_type) {
+                self.advance();
+                return true;
+            }
+        }
+
+        false
+    }
+
+    fn consume(&mut self, token_type: TokenType, expected: &str) -> Result<Token, ParserError> {
+        if self.check(token_type) {
+            return Ok(self.advance());
+        }
+
+        Err(self.error(expected))
+    }
+
+    fn check(&self, token_type: TokenType) -> bool {
+        if self.is_at_end() {
+            return false;
+        }
+
+        self.peek().token_type() == token_type
+    }
+
+    fn advance(&mut self) -> Token {
+        if !self.is_at_end() {
+            self.current += 1;
+        }
+
+        self.previous().clone()
+    }
+
+    fn is_at_end(&self) -> bool {
+        self.peek().token_type() == TokenType::Eof
+    }
+
+    fn peek(&self) -> &Token {
+        &self.tokens[self.current]
+    }
+
+    fn previous(&self) -> &Token {
+        &self.tokens[self.current.saturating_sub(1)]
+    }
+
+    fn error(&self, expected: &str) -> ParserError {
+        let token = self.peek();
+        let found = match token.token_type() {
+            TokenType::Eof => "end of file".to_string(),
+            _ => format!("'{}'", token.lexeme()),
+        };
+
+        ParserError::UnexpectedToken(expected.to_string(), found, token.span())
+    }
+
+    fn operator(&mut self) -> Token {
+        let operator = self.previous().clone();
+        self.operators += 1;
+        if self.operators == MAX_OPERATORS + 1 {
+            // Report without unwinding, the parser is not confused.
+            self.errors
+                .push(ParserError::TooManyOperators(operator.span()));
+        }
+
+        operator
+    }
+
+    fn nested<T>(
+        &mut self,
+        what: &'static str,
+        parse: impl FnOnce(&mut Self) -> Result<T, ParserError>,
+    ) -> Result<T, ParserError> {
+        if self.depth >= MAX_NESTING {
+            return Err(ParserError::NestedTooDeeply(what, self.peek().span()));
+        }
+
+        self.depth += 1;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    fn synchronize(&mut self) {
+        self.advance();
+
+        while !self.is_at_end() {
+            if self.previous().token_type() == TokenType::Semicolon {
+                return;
+            }
+
+            match self.peek().token_type() {
+                TokenType::Class
+                | TokenType::Fun
+                | TokenType::Var
+                | TokenType::For
+                | TokenType::If
+                | TokenType::While
+                | TokenType::Print
+                | TokenType::Return => return,
+                _ => {}
+            }
+
+            self.advance();
+        }
+    }
+}
@@ -0,0 +1,46 @@
+//! Register backend: a compiler from the resolved syntax tree into
+//! three-address instructions, and a machine running them with a window of
+//! registers per call instead of an operand stack
+
+pub mod compiler;
+pub mod instruction;
+pub mod vm;
+
+use crate::bytecode::object::{Heap, ObjRef};
+use crate::lox::{self, LoxError};
+use crate::optimizer::{OptLevel, Optimizer, OptimizerTrait};
+use crate::register::compiler::{Compiler, CompilerTrait};
+use crate::register::vm::RegisterVm;
+use crate::resolver::{Resolver, ResolverTrait};
+
+/// Parse, resolve and compile a program into its top-level script function
+pub fn compile(source: &str, heap: &mut Heap) -> Result<ObjRef, LoxError> {
+    compile_with(source, heap, OptLevel::None)
+}
+
+/// Compile a program with the optimisations of `opt_level`
+pub fn compile_with(
+    source: &str,
+    heap: &mut Heap,
+    opt_level: OptLevel,
+) -> Result<ObjRef, LoxError> {
+    let mut statements = lox::parse(source)?;
+    Resolver::new()
+        .resolve(&statements)
+        .map_err(LoxError::ResolverErrors)?;
+    if opt_level >= OptLevel::Fold {
+        statements = Optimizer::new().optimize(statements);
+    }
+
+    Compiler::new(heap)
+        .compile(&statements)
+        .map_err(LoxError::CompileErrors)
+}
+
+/// Compile and run a program on the register machine
+pub fn interpret(source: &str, vm: &mut RegisterVm) -> Result<(), LoxError> {
+    let script = compile(source, vm.heap_mut())?;
+    vm.run(script)?;
+
+    Ok(())
+}
@@ -0,0 +1,840 @@
+use crate::bytecode::chunk::Chunk;
+use crate::bytecode::compiler::{CompileError, FunctionKind};
+use crate::bytecode::object::{Heap, ObjFunction, ObjRef, Object};
+use crate::bytecode::value::Value;
+use crate::expr::{self, Expr};
+use crate::register::instruction::{Op, INSTRUCTION_SIZE};
+use crate::stmt::{self, Stmt};
+use crate::token::{self, Span, Token, TokenTrait, TokenType};
+
+/// Registers are addressed by a one byte operand
+const MAX_REGISTERS: usize = 256;
+/// Constants are addressed by a one byte operand
+const MAX_CONSTANTS: usize = 256;
+/// Upvalues are addressed by a one byte operand
+const MAX_UPVALUES: usize = 256;
+
+/// Compiler from a resolved syntax tree into register machine code. Locals
+/// live in fixed registers of the frame's window, temporaries are allocated
+/// above them like a stack and freed once the expression using them is done.
+#[derive(Debug)]
+pub struct Compiler<'h> {
+    heap: &'h mut Heap,
+    /// Functions being compiled, the innermost last
+    functions: Vec<FunctionState>,
+    errors: Vec<CompileError>,
+    /// Location of the node being compiled, for lines and errors
+    span: Span,
+}
+
+#[derive(Debug)]
+struct FunctionState {
+    function: ObjFunction,
+    kind: FunctionKind,
+    locals: Vec<Local>,
+    /// Variables captured from enclosing functions, in the closure's order
+    upvalues: Vec<Upvalue>,
+    scope_depth: usize,
+    /// First free register
+    next: usize,
+    /// Whether `TooManyRegisters` was reported, once is enough
+    out_of_registers: bool,
+}
+
+#[derive(Debug)]
+struct Local {
+    name: String,
+    register: u8,
+    depth: usize,
+    /// Whether a closure captures the variable, so leaving its scope must
+    /// close the upvalue
+    is_captured: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Upvalue {
+    /// Register in the enclosing function when `is_local`, otherwise the
+    /// index of one of its upvalues
+    index: u8,
+    is_local: bool,
+}
+
+/// Where a variable is stored
+#[derive(Debug, Clone, Copy)]
+enum Variable {
+    Local(u8),
+    Upvalue(u8),
+    /// Constant index of the name
+    Global(u8),
+}
+
+pub trait CompilerTrait<'h> {
+    fn new(heap: &'h mut Heap) -> Self;
+    /// Compile a resolved program into the top-level script function,
+    /// reporting every error found
+    fn compile(self, statements: &[Stmt]) -> Result<ObjRef, Vec<CompileError>>;
+
+    fn statement(&mut self, statement: &Stmt);
+    fn class(&mut self, class: &stmt::Class);
+    /// Compile a function and store its closure in `target`
+    fn function(&mut self, function: &stmt::Function, kind: FunctionKind, target: u8);
+    fn if_statement(&mut self, statement: &stmt::If);
+    fn return_statement(&mut self, statement: &stmt::Return);
+    fn while_statement(&mut self, statement: &stmt::While);
+
+    /// Compile an expression into `target`
+    fn expression_to(&mut self, expression: &Expr, target: u8);
+    /// Compile an expression into whichever register is cheapest, a local's
+    /// own register or a new temporary
+    fn expression_any(&mut self, expression: &Expr) -> u8;
+    /// Compile an assignment, storing the value in `into` unless it goes to
+    /// a local, and return the register holding the value
+    fn assign(&mut self, assign: &expr::Assign, into: Option<u8>) -> u8;
+    fn binary(&mut self, binary: &expr::Binary, target: u8);
+    fn call(&mut self, call: &expr::Call, target: u8);
+    /// Compile the arguments of a call into the next free registers,
+    /// returning how many there are
+    fn arguments(&mut self, arguments: &[Expr]) -> u8;
+    fn set(&mut self, set: &expr::Set, target: u8);
+    /// Load a variable into `target`
+    fn named_to(&mut self, name: &str, target: u8);
+
+    fn resolve_local(&self, function: usize, name: &str) -> Option<usize>;
+    fn resolve_upvalue(&mut self, function: usize, name: &str) -> Option<u8>;
+    fn add_upvalue(&mut self, function: usize, index: u8, is_local: bool) -> u8;
+
+    fn add_local(&mut self, name: &str, register: u8);
+    /// Store a declared variable's value, held in `register`, in a global
+    /// at the top level or keep the register as a local
+    fn define(&mut self, name: &Token, register: u8);
+    fn begin_scope(&mut self);
+    fn end_scope(&mut self);
+
+    /// Reserve the next free register
+    fn temporary(&mut self) -> u8;
+    /// Free the registers allocated since `mark`
+    fn free(&mut self, mark: usize);
+    fn string_constant(&mut self, name: &str) -> u8;
+    fn make_constant(&mut self, value: Value) -> u8;
+
+    fn emit(&mut self, op: Op, a: u8, b: u8, c: u8);
+    /// Copy a register unless both are the same
+    fn emit_move(&mut self, target: u8, source: u8);
+    fn emit_jump(&mut self, op: Op, a: u8) -> usize;
+    fn patch_jump(&mut self, jump: usize);
+    fn emit_loop(&mut self, loop_start: usize);
+    fn emit_return(&mut self);
+}
+
+impl<'h> CompilerTrait<'h> for Compiler<'h> {
+    fn new(heap: &'h mut Heap) -> Compiler<'h> {
+        Compiler {
+            heap,
+            functions: vec![FunctionState::new(FunctionKind::Script, None)],
+            errors: Vec::new(),
+            span: Span::default(),
+        }
+    }
+
+    fn compile(mut self, statements: &[Stmt]) -> Result<ObjRef, Vec<CompileError>> {
+        for statement in statements {
+            self.statement(statement);
+        }
+        self.emit_return();
+
+        if !self.errors.is_empty() {
+            return Err(self.errors);
+        }
+
+        let state = self.functions.pop().expect("script being compiled");
+        Ok(self.heap.alloc(Object::Function(state.finish())))
+    }
+
+    fn statement(&mut self, statement: &Stmt) {
+        self.span = statement.span();
+        let mark = self.state().next;
+
+        match statement {
+            Stmt::Block(block) => {
+                self.begin_scope();
+                for statement in &block.statements {
+                    self.statement(statement);
+                }
+                self.end_scope();
+            }
+            Stmt::Class(class) => self.class(class),
+            Stmt::Expression(statement) => {
+                self.expression_any(&statement.expression);
+                self.free(mark);
+            }
+            Stmt::Function(function) => {
+                let target = self.temporary();
+                // A local is declared before its body, so the function can
+                // call itself.
+                let global = self.state().scope_depth == 0;
+                if !global {
+                    self.add_local(function.name.lexeme(), target);
+                }
+                self.function(function, FunctionKind::Function, target);
+                if global {
+                    self.define(&function.name, target);
+                }
+            }
+            Stmt::If(statement) => self.if_statement(statement),
+            Stmt::Print(statement) => {
+                let value = self.expression_any(&statement.expression);
+                self.span = statement.span;
+                self.emit(Op::Print, value, 0, 0);
+                self.free(mark);
+            }
+            Stmt::Return(statement) => self.return_statement(statement),
+            Stmt::Var(statement) => {
+                let target = self.temporary();
+                match &statement.initializer {
+                    Some(initializer) => self.expression_to(initializer, target),
+                    None => self.emit(Op::LoadNull, target, 0, 0),
+                }
+                self.define(&statement.name, target);
+            }
+            Stmt::While(statement) => self.while_statement(statement),
+        }
+    }
+
+    fn class(&mut self, class: &stmt::Class) {
+        let mark = self.state().next;
+        let name = self.string_constant(class.name.lexeme());
+        let register = self.temporary();
+        let global = self.state().scope_depth == 0;
+
+        self.span = class.name.span();
+        if !global {
+            self.add_local(class.name.lexeme(), register);
+        }
+        self.emit(Op::Class, register, name, 0);
+        if global {
+            self.emit(Op::DefineGlobal, register, name, 0);
+        }
+
+        if let Some(superclass) = &class.superclass {
+            // Methods capture the superclass as the local `super`.
+            self.begin_scope();
+            let super_register = self.temporary();
+            self.named_to(superclass.name.lexeme(), super_register);
+            self.add_local("super", super_register);
+            self.span = superclass.name.span();
+            self.emit(Op::Inherit, register, super_register, 0);
+        }
+
+        for method in &class.methods {
+            let kind = match method.name.lexeme() {
+                "init" => FunctionKind::Initializer,
+                _ => FunctionKind::Method,
+            };
+            let method_mark = self.state().next;
+            let closure = self.temporary();
+            self.function(method, kind, closure);
+            let name = self.string_constant(method.name.lexeme());
+            self.emit(Op::Method, register, closure, name);
+            self.free(method_mark);
+        }
+
+        if class.superclass.is_some() {
+            self.end_scope();
+        }
+        if global {
+            self.free(mark);
+        }
+    }
+
+    fn function(&mut self, function: &stmt::Function, kind: FunctionKind, target: u8) {
+        let name = self.heap.intern(function.name.lexeme());
+        self.functions.push(FunctionState::new(kind, Some(name)));
+        self.begin_scope();
+
+        for param in &function.params {
+            let register = self.temporary();
+            self.add_local(param.lexeme(), register);
+        }
+        self.state().function.arity = function.params.len();
+
+        for statement in &function.body {
+            self.statement(statement);
+        }
+        self.emit_return();
+
+        let state = self.functions.pop().expect("function being compiled");
+        let upvalues = state.upvalues.clone();
+        let function_object = self.heap.alloc(Object::Function(state.finish()));
+        let constant = self.make_constant(Value::object(function_object));
+
+        self.span = function.name.span();
+        self.emit(Op::Closure, target, constant, 0);
+        for upvalue in upvalues {
+            self.emit_word([upvalue.is_local as u8, upvalue.index, 0, 0]);
+        }
+    }
+
+    fn if_statement(&mut self, statement: &stmt::If) {
+        let mark = self.state().next;
+        let condition = self.expression_any(&statement.condition);
+        self.free(mark);
+
+        self.span = statement.span;
+        let then_jump = self.emit_jump(Op::JumpIfFalse, condition);
+        self.statement(&statement.then_branch);
+
+        match &statement.else_branch {
+            Some(else_branch) => {
+                let else_jump = self.emit_jump(Op::Jump, 0);
+                self.patch_jump(then_jump);
+                self.statement(else_branch);
+                self.patch_jump(else_jump);
+            }
+            None => self.patch_jump(then_jump),
+        }
+    }
+
+    fn return_statement(&mut self, statement: &stmt::Return) {
+        let mark = self.state().next;
+
+        match &statement.value {
+            // The resolver rejects values returned from initializers.
+            _ if self.state().kind == FunctionKind::Initializer => {
+                self.emit(Op::Return, 0, 0, 0);
+            }
+            Some(value) => {
+                let value = self.expression_any(value);
+                self.span = statement.keyword.span();
+                self.emit(Op::Return, value, 0, 0);
+            }
+            None => self.emit_return(),
+        }
+
+        self.free(mark);
+    }
+
+    fn while_statement(&mut self, statement: &stmt::While) {
+        let loop_start = self.chunk().code.len();
+        let mark = self.state().next;
+        let condition = self.expression_any(&statement.condition);
+        self.free(mark);
+
+        self.span = statement.span;
+        let exit_jump = self.emit_jump(Op::JumpIfFalse, condition);
+        self.statement(&statement.body);
+        self.emit_loop(loop_start);
+        self.patch_jump(exit_jump);
+    }
+
+    fn expression_to(&mut self, expression: &Expr, target: u8) {
+        self.span = expression.span();
+        let mark = self.state().next;
+
+        match expression {
+            Expr::Assign(assign) => {
+                let value = self.assign(assign, Some(target));
+                self.emit_move(target, value);
+            }
+            Expr::Binary(binary) => self.binary(binary, target),
+            Expr::Call(call) => self.call(call, target),
+            Expr::Get(get) => {
+                let object = self.expression_any(&get.object);
+                let name = self.string_constant(get.name.lexeme());
+                self.span = get.name.span();
+                self.emit(Op::GetProperty, target, object, name);
+            }
+            Expr::Grouping(grouping) => self.expression_to(&grouping.expression, target),
+            Expr::Literal(literal) => match &literal.value {
+                token::Literal::Number(number) => {
+                    let constant = self.make_constant(Value::number(*number));
+                    self.emit(Op::LoadConstant, target, constant, 0);
+                }
+                token::Literal::String(string) => {
+                    let constant = self.string_constant(string);
+                    self.emit(Op::LoadConstant, target, constant, 0);
+                }
+                token::Literal::Boolean(true) => self.emit(Op::LoadTrue, target, 0, 0),
+                token::Literal::Boolean(false) => self.emit(Op::LoadFalse, target, 0, 0),
+                token::Literal::Null => self.emit(Op::LoadNull, target, 0, 0),
+            },
+            Expr::Logical(logical) => {
+                self.expression_to(&logical.left, target);
+                let op = match logical.operator.token_type() {
+                    TokenType::And => Op::JumpIfFalse,
+                    _ => Op::JumpIfTrue,
+                };
+                self.span = logical.operator.span();
+                let end_jump = self.emit_jump(op, target);
+                self.expression_to(&logical.right, target);
+                self.patch_jump(end_jump);
+            }
+            Expr::Set(set) => self.set(set, target),
+            Expr::Super(super_) => {
+                self.named_to("this", target);
+                let superclass = self.temporary();
+                self.named_to("super", superclass);
+                let name = self.string_constant(super_.method.lexeme());
+                self.span = super_.method.span();
+                self.emit(Op::GetSuper, target, superclass, name);
+            }
+            Expr::This(_) => self.named_to("this", target),
+            Expr::Unary(unary) => {
+                let operand = self.expression_any(&unary.right);
+                let op = match unary.operator.token_type() {
+                    TokenType::Minus => Op::Negate,
+                    _ => Op::Not,
+                };
+                self.span = unary.operator.span();
+                self.emit(op, target, operand, 0);
+            }
+            Expr::Variable(variable) => {
+                self.span = variable.name.span();
+                self.named_to(variable.name.lexeme(), target);
+            }
+        }
+
+        self.free(mark);
+    }
+
+    fn expression_any(&mut self, expression: &Expr) -> u8 {
+        match expression {
+            Expr::Assign(assign) => return self.assign(assign, None),
+            Expr::Grouping(grouping) => return self.expression_any(&grouping.expression),
+            Expr::Variable(expr::Variable { name, .. })
+            | Expr::This(expr::This { keyword: name, .. }) => {
+                if let Variable::Local(register) = self.resolve(name.lexeme()) {
+                    return register;
+                }
+            }
+            _ => {}
+        }
+
+        let target = self.temporary();
+        self.expression_to(expression, target);
+        target
+    }
+
+    fn assign(&mut self, assign: &expr::Assign, into: Option<u8>) -> u8 {
+        let variable = self.resolve(assign.name.lexeme());
+
+        let register = match variable {
+            Variable::Local(register) if writes_last(&assign.value) => {
+                self.expression_to(&assign.value, register);
+                return register;
+            }
+            // Operands read after the first write would see the new value.
+            Variable::Local(register) => {
+                let mark = self.state().next;
+                let value = self.temporary();
+                self.expression_to(&assign.value, value);
+                self.emit_move(register, value);
+                self.free(mark);
+                return register;
+            }
+            Variable::Upvalue(_) | Variable::Global(_) => {
+                let register = into.unwrap_or_else(|| self.temporary());
+                self.expression_to(&assign.value, register);
+                register
+            }
+        };
+
+        self.span = assign.name.span();
+        match variable {
+            Variable::Upvalue(index) => self.emit(Op::SetUpvalue, register, index, 0),
+            Variable::Global(name) => self.emit(Op::SetGlobal, register, name, 0),
+            Variable::Local(_) => unreachable!("stored above"),
+        }
+        register
+    }
+
+    fn binary(&mut self, binary: &expr::Binary, target: u8) {
+        // A fresh temporary target holds the left operand too, so a chain
+        // like `a + b + c` reuses it at every level instead of growing.
+        // Variables are still read from their own register when local.
+        let in_place = matches!(
+            binary.left.as_ref(),
+            Expr::Assign(_) | Expr::This(_) | Expr::Variable(_)
+        );
+        let mut left = if self.is_fresh(target) && !in_place {
+            self.expression_to(&binary.left, target);
+            target
+        } else {
+            self.expression_any(&binary.left)
+        };
+        // A local read as the left operand must be copied when the right
+        // one may assign it.
+        if self.is_local(left) && !is_pure(&binary.right) {
+            let copy = self.temporary();
+            self.emit(Op::Move, copy, left, 0);
+            left = copy;
+        }
+        let right = self.expression_any(&binary.right);
+
+        let (op, negate) = match binary.operator.token_type() {
+            TokenType::BangEqual => (Op::Equal, true),
+            TokenType::EqualEqual => (Op::Equal, false),
+            TokenType::Greater => (Op::Greater, false),
+            TokenType::GreaterEqual => (Op::Less, true),
+            TokenType::Less => (Op::Less, false),
+            TokenType::LessEqual => (Op::Greater, true),
+            TokenType::Plus => (Op::Add, false),
+            TokenType::Minus => (Op::Subtract, false),
+            TokenType::Star => (Op::Multiply, false),
+            TokenType::Slash => (Op::Divide, false),
+            token_type => unreachable!("binary operator {:?}", token_type),
+        };
+        self.span = binary.operator.span();
+        self.emit(op, target, left, right);
+        if negate {
+            self.emit(Op::Not, target, target, 0);
+        }
+    }
+
+    fn call(&mut self, call: &expr::Call, target: u8) {
+        // The callee and its arguments need consecutive registers, the
+        // target itself works when nothing is allocated above it.
+        let base = if self.is_fresh(target) {
+            target
+        } else {
+            self.temporary()
+        };
+
+        match call.callee.as_ref() {
+            Expr::Get(get) => {
+                self.expression_to(&get.object, base);
+                let count = self.arguments(&call.arguments);
+                let name = self.string_constant(get.name.lexeme());
+                self.span = call.paren.span();
+                self.emit(Op::Invoke, base, count, name);
+            }
+            Expr::Super(super_) => {
+                self.named_to("this", base);
+                let count = self.arguments(&call.arguments);
+                let superclass = self.temporary();
+                self.named_to("super", superclass);
+                let name = self.string_constant(super_.method.lexeme());
+                self.span = call.paren.span();
+                self.emit(Op::SuperInvoke, base, count, name);
+            }
+            callee => {
+                self.expression_to(callee, base);
+                let count = self.arguments(&call.arguments);
+                self.span = call.paren.span();
+                self.emit(Op::Call, base, count, 0);
+            }
+        }
+
+        self.emit_move(target, base);
+    }
+
+    fn arguments(&mut self, arguments: &[Expr]) -> u8 {
+        for argument in arguments {
+            let register = self.temporary();
+            self.expression_to(argument, register);
+        }
+
+        arguments.len() as u8
+    }
+
+    fn set(&mut self, set: &expr::Set, target: u8) {
+        let mut object = self.expression_any(&set.object);
+        if self.is_local(object) && !is_pure(&set.value) {
+            let copy = self.temporary();
+            self.emit(Op::Move, copy, object, 0);
+            object = copy;
+        }
+        let value = self.expression_any(&set.value);
+
+        let name = self.string_constant(set.name.lexeme());
+        self.span = set.name.span();
+        self.emit(Op::SetProperty, object, name, value);
+        self.emit_move(target, value);
+    }
+
+    fn named_to(&mut self, name: &str, target: u8) {
+        match self.resolve(name) {
+            Variable::Local(register) => self.emit_move(target, register),
+            Variable::Upvalue(index) => self.emit(Op::GetUpvalue, target, index, 0),
+            Variable::Global(name) => self.emit(Op::GetGlobal, target, name, 0),
+        }
+    }
+
+    fn resolve_local(&self, function: usize, name: &str) -> Option<usize> {
+        self.functions[function]
+            .locals
+            .iter()
+            .rposition(|local| local.name == name)
+    }
+
+    fn resolve_upvalue(&mut self, function: usize, name: &str) -> Option<u8> {
+        let enclosing = function.checked_sub(1)?;
+
+        if let Some(local) = self.resolve_local(enclosing, name) {
+            let local = &mut self.functions[enclosing].locals[local];
+            local.is_captured = true;
+            let register = local.register;
+            return Some(self.add_upvalue(function, register, true));
+        }
+
+        let upvalue = self.resolve_upvalue(enclosing, name)?;
+        Some(self.add_upvalue(function, upvalue, false))
+    }
+
+    fn add_upvalue(&mut self, function: usize, index: u8, is_local: bool) -> u8 {
+        let upvalue = Upvalue { index, is_local };
+        let upvalues = &mut self.functions[function].upvalues;
+        if let Some(existing) = upvalues.iter().position(|u| *u == upvalue) {
+            return existing as u8;
+        }
+
+        if upvalues.len() >= MAX_UPVALUES {
+            self.errors.push(CompileError::TooManyUpvalues(self.span));
+            return 0;
+        }
+
+        upvalues.push(upvalue);
+        (upvalues.len() - 1) as u8
+    }
+
+    fn add_local(&mut self, name: &str, register: u8) {
+        let state = self.state();
+        let depth = state.scope_depth;
+        state.locals.push(Local {
+            name: name.to_string(),
+            register,
+            depth,
+            is_captured: false,
+        });
+    }
+
+    fn define(&mut self, name: &Token, register: u8) {
+        if self.state().scope_depth > 0 {
+            self.add_local(name.lexeme(), register);
+            return;
+        }
+
+        let global = self.string_constant(name.lexeme());
+        self.span = name.span();
+        self.emit(Op::DefineGlobal, register, global, 0);
+        self.free(register as usize);
+    }
+
+    fn begin_scope(&mut self) {
+        self.state().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self) {
+        let state = self.state();
+        state.scope_depth -= 1;
+
+        let mut lowest = None;
+        let mut lowest_captured = None;
+        while let Some(local) = state.locals.last() {
+            if local.depth <= state.scope_depth {
+                break;
+            }
+
+            lowest = Some(local.register);
+            if local.is_captured {
+                lowest_captured = Some(local.register);
+            }
+            state.locals.pop();
+        }
+
+        if let Some(register) = lowest {
+            state.next = register as usize;
+        }
+        if let Some(register) = lowest_captured {
+            self.emit(Op::Close, register, 0, 0);
+        }
+    }
+
+    fn temporary(&mut self) -> u8 {
+        let state = self.state();
+        if state.next >= MAX_REGISTERS {
+            if !state.out_of_registers {
+                state.out_of_registers = true;
+                self.errors.push(CompileError::TooManyRegisters(self.span));
+            }
+            return (MAX_REGISTERS - 1) as u8;
+        }
+
+        let register = state.next;
+        state.next += 1;
+        state.function.registers = state.function.registers.max(state.next);
+        register as u8
+    }
+
+    fn free(&mut self, mark: usize) {
+        let state = self.state();
+        state.next = state.next.min(mark);
+    }
+
+    fn string_constant(&mut self, name: &str) -> u8 {
+        let string = self.heap.intern(name);
+        self.make_constant(Value::object(string))
+    }
+
+    fn make_constant(&mut self, value: Value) -> u8 {
+        let index = self.chunk().add_constant(value);
+        if index >= MAX_CONSTANTS {
+            self.errors.push(CompileError::TooManyConstants(self.span));
+            return 0;
+        }
+
+        index as u8
+    }
+
+    fn emit(&mut self, op: Op, a: u8, b: u8, c: u8) {
+        self.emit_word([op as u8, a, b, c]);
+    }
+
+    fn emit_move(&mut self, target: u8, source: u8) {
+        if target != source {
+            self.emit(Op::Move, target, source, 0);
+        }
+    }
+
+    fn emit_jump(&mut self, op: Op, a: u8) -> usize {
+        self.emit(op, a, 0xff, 0xff);
+        self.chunk().code.len() - INSTRUCTION_SIZE
+    }
+
+    fn patch_jump(&mut self, jump: usize) {
+        // Counted from the instruction after the jump.
+        let distance = (self.chunk().code.len() - jump) / INSTRUCTION_SIZE - 1;
+        if distance > u16::MAX as usize {
+            self.errors.push(CompileError::JumpTooLarge(self.span));
+        }
+
+        let [high, low] = (distance as u16).to_be_bytes();
+        let code = &mut self.chunk().code;
+        code[jump + 2] = high;
+        code[jump + 3] = low;
+    }
+
+    fn emit_loop(&mut self, loop_start: usize) {
+        let distance = (self.chunk().code.len() - loop_start) / INSTRUCTION_SIZE + 1;
+        if distance > u16::MAX as usize {
+            self.errors.push(CompileError::LoopTooLarge(self.span));
+        }
+
+        let [high, low] = (distance as u16).to_be_bytes();
+        self.emit(Op::Loop, 0, high, low);
+    }
+
+    fn emit_return(&mut self) {
+        if self.state().kind == FunctionKind::Initializer {
+            self.emit(Op::Return, 0, 0, 0);
+            return;
+        }
+
+        let mark = self.state().next;
+        let register = self.temporary();
+        self.emit(Op::LoadNull, register, 0, 0);
+        self.emit(Op::Return, register, 0, 0);
+        self.free(mark);
+    }
+}
+
+impl Compiler<'_> {
+    /// Where a variable is stored, capturing it when it belongs to an
+    /// enclosing function
+    fn resolve(&mut self, name: &str) -> Variable {
+        let current = self.functions.len() - 1;
+        if let Some(local) = self.resolve_local(current, name) {
+            return Variable::Local(self.functions[current].locals[local].register);
+        }
+        if let Some(index) = self.resolve_upvalue(current, name) {
+            return Variable::Upvalue(index);
+        }
+
+        Variable::Global(self.string_constant(name))
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.state().function.chunk
+    }
+
+    /// The innermost function being compiled
+    fn state(&mut self) -> &mut FunctionState {
+        self.functions.last_mut().expect("function being compiled")
+    }
+
+    /// Write one instruction word at the line of the current node
+    fn emit_word(&mut self, word: [u8; INSTRUCTION_SIZE]) {
+        let line = self.span.line;
+        for byte in word {
+            self.chunk().write(byte, line);
+        }
+    }
+
+    /// Whether a register is the last temporary allocated, free to be
+    /// written before the expression it is reserved for is done
+    fn is_fresh(&mut self, register: u8) -> bool {
+        register as usize + 1 == self.state().next && !self.is_local(register)
+    }
+
+    /// Whether a register holds a local variable rather than a temporary
+    fn is_local(&self, register: u8) -> bool {
+        let state = self.functions.last().expect("function being compiled");
+        state.locals.iter().any(|local| local.register == register)
+    }
+}
+
+impl FunctionState {
+    fn new(kind: FunctionKind, name: Option<ObjRef>) -> FunctionState {
+        // Register zero holds the called function, or `this` inside methods.
+        let register_zero = match kind {
+            FunctionKind::Method | FunctionKind::Initializer => "this",
+            FunctionKind::Script | FunctionKind::Function => "",
+        };
+
+        FunctionState {
+            function: ObjFunction {
+                name,
+                registers: 1,
+                ..ObjFunction::default()
+            },
+            kind,
+            locals: vec![Local {
+                name: register_zero.to_string(),
+                register: 0,
+                depth: 0,
+                is_captured: false,
+            }],
+            upvalues: Vec::new(),
+            scope_depth: 0,
+            next: 1,
+            out_of_registers: false,
+        }
+    }
+
+    fn finish(mut self) -> ObjFunction {
+        self.function.upvalue_count = self.upvalues.len();
+        self.function
+    }
+}
+
+/// Whether an expression writes its target only with its last instruction,
+/// so it can read the variable it is assigned to
+fn writes_last(expression: &Expr) -> bool {
+    match expression {
+        Expr::Logical(_) => false,
+        Expr::Grouping(grouping) => writes_last(&grouping.expression),
+        _ => true,
+    }
+}
+
+/// Whether evaluating an expression can't assign a variable
+fn is_pure(expression: &Expr) -> bool {
+    match expression {
+        Expr::Assign(_) | Expr::Call(_) | Expr::Set(_) => false,
+        Expr::Binary(binary) => is_pure(&binary.left) && is_pure(&binary.right),
+        Expr::Logical(logical) => is_pure(&logical.left) && is_pure(&logical.right),
+        Expr::Get(get) => is_pure(&get.object),
+        Expr::Grouping(grouping) => is_pure(&grouping.expression),
+        Expr::Unary(unary) => is_pure(&unary.right),
+        Expr::Literal(_) | Expr::Super(_) | Expr::This(_) | Expr::Variable(_) => true,
+    }
+}
@@ -0,0 +1,301 @@
+use std::fmt::{Display, Write};
+
+use crate::bytecode::chunk::Chunk;
+use crate::bytecode::object::{Heap, ObjRef, Object};
+
+/// Size of every instruction: the opcode and the `a`, `b` and `c` operands
+pub const INSTRUCTION_SIZE: usize = 4;
+
+/// Instructions of the register machine. `R[x]` is register `x` of the
+/// frame's window, `K[x]` a constant and `U[x]` an upvalue. Jumps take `b`
+/// and `c` together as a 16-bit count of instructions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Op {
+    /// `R[a] = R[b]`
+    Move,
+    /// `R[a] = K[b]`
+    LoadConstant,
+    LoadNull,
+    LoadTrue,
+    LoadFalse,
+    /// `R[a] = globals[K[b]]`
+    GetGlobal,
+    /// `globals[K[b]] = R[a]`
+    DefineGlobal,
+    /// `globals[K[b]] = R[a]`, the global must exist
+    SetGlobal,
+    /// `R[a] = U[b]`
+    GetUpvalue,
+    /// `U[b] = R[a]`
+    SetUpvalue,
+    /// `R[a] = R[b].K[c]`
+    GetProperty,
+    /// `R[a].K[b] = R[c]`
+    SetProperty,
+    /// `R[a]` = method `K[c]` of the class `R[b]` bound to `R[a]`
+    GetSuper,
+    /// `R[a] = R[b] == R[c]`, and likewise for the other binary operators
+    Equal,
+    Greater,
+    Less,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    /// `R[a] = !R[b]`
+    Not,
+    /// `R[a] = -R[b]`
+    Negate,
+    /// Print `R[a]`
+    Print,
+    /// Skip forward over `bc` instructions
+    Jump,
+    /// Skip forward over `bc` instructions when `R[a]` is falsey
+    JumpIfFalse,
+    /// Skip forward over `bc` instructions when `R[a]` is truthy
+    JumpIfTrue,
+    /// Jump back `bc` instructions from the next one
+    Loop,
+    /// Call `R[a]` with the `b` arguments after it, the result replaces `R[a]`
+    Call,
+    /// Call method `K[c]` of `R[a]` with the `b` arguments after it
+    Invoke,
+    /// Like `Invoke`, with the superclass in the register after the arguments
+    SuperInvoke,
+    /// `R[a]` = closure of the function `K[b]`, followed by an `is_local,
+    /// index` instruction word per upvalue
+    Closure,
+    /// Close the upvalues of `R[a]` and the registers above it
+    Close,
+    /// Return `R[a]` to the caller
+    Return,
+    /// `R[a]` = new class named `K[b]`
+    Class,
+    /// Copy the methods of the class `R[b]` into the class `R[a]`
+    Inherit,
+    /// Add the closure `R[b]` to the class `R[a]` as method `K[c]`
+    Method,
+}
+
+impl Op {
+    const ALL: [Op; 36] = [
+        Op::Move,
+        Op::LoadConstant,
+        Op::LoadNull,
+        Op::LoadTrue,
+        Op::LoadFalse,
+        Op::GetGlobal,
+        Op::DefineGlobal,
+        Op::SetGlobal,
+        Op::GetUpvalue,
+        Op::SetUpvalue,
+        Op::GetProperty,
+        Op::SetProperty,
+        Op::GetSuper,
+        Op::Equal,
+        Op::Greater,
+        Op::Less,
+        Op::Add,
+        Op::Subtract,
+        Op::Multiply,
+        Op::Divide,
+        Op::Not,
+        Op::Negate,
+        Op::Print,
+        Op::Jump,
+        Op::JumpIfFalse,
+        Op::JumpIfTrue,
+        Op::Loop,
+        Op::Call,
+        Op::Invoke,
+        Op::SuperInvoke,
+        Op::Closure,
+        Op::Close,
+        Op::Return,
+        Op::Class,
+        Op::Inherit,
+        Op::Method,
+    ];
+}
+
+/// Decode an opcode, returning the byte back when it is not one
+impl TryFrom<u8> for Op {
+    type Error = u8;
+
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        Op::ALL.get(byte as usize).copied().ok_or(byte)
+    }
+}
+
+/// The name used by the disassembler, e.g. `MOVE`
+impl Display for Op {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Op::Move => "MOVE",
+            Op::LoadConstant => "LOADK",
+            Op::LoadNull => "LOADNULL",
+            Op::LoadTrue => "LOADTRUE",
+            Op::LoadFalse => "LOADFALSE",
+            Op::GetGlobal => "GETGLOBAL",
+            Op::DefineGlobal => "DEFGLOBAL",
+            Op::SetGlobal => "SETGLOBAL",
+            Op::GetUpvalue => "GETUPVAL",
+            Op::SetUpvalue => "SETUPVAL",
+            Op::GetProperty => "GETPROP",
+            Op::SetProperty => "SETPROP",
+            Op::GetSuper => "GETSUPER",
+            Op::Equal => "EQ",
+            Op::Greater => "GT",
+            Op::Less => "LT",
+            Op::Add => "ADD",
+            Op::Subtract => "SUB",
+            Op::Multiply => "MUL",
+            Op::Divide => "DIV",
+            Op::Not => "NOT",
+            Op::Negate => "NEG",
+            Op::Print => "PRINT",
+            Op::Jump => "JMP",
+            Op::JumpIfFalse => "JMPF",
+            Op::JumpIfTrue => "JMPT",
+            Op::Loop => "LOOP",
+            Op::Call => "CALL",
+            Op::Invoke => "INVOKE",
+            Op::SuperInvoke => "SUPERINVOKE",
+            Op::Closure => "CLOSURE",
+            Op::Close => "CLOSE",
+            Op::Return => "RETURN",
+            Op::Class => "CLASS",
+            Op::Inherit => "INHERIT",
+            Op::Method => "METHOD",
+        };
+
+        f.pad(name)
+    }
+}
+
+/// Disassemble a compiled script followed by every function nested in it
+pub fn disassemble(function: ObjRef, heap: &Heap) -> String {
+    let mut out = String::new();
+    disassemble_function(function, heap, &mut out);
+    out
+}
+
+fn disassemble_function(reference: ObjRef, heap: &Heap, out: &mut String) {
+    let function = heap.function(reference);
+    let name = match function.name {
+        Some(name) => heap.string(name),
+        None => "<script>",
+    };
+    let _ = writeln!(out, "== {} ({} registers) ==", name, function.registers);
+
+    let chunk = &function.chunk;
+    let mut offset = 0;
+    while offset + INSTRUCTION_SIZE <= chunk.code.len() {
+        offset = disassemble_instruction(chunk, offset, heap, out);
+    }
+
+    for constant in &chunk.constants {
+        if let Some(nested) = constant.as_object() {
+            if let Object::Function(_) = heap.get(nested) {
+                out.push('\n');
+                disassemble_function(nested, heap, out);
+            }
+        }
+    }
+}
+
+/// Write the instruction at `offset` as one line and return the offset of the next one
+pub fn disassemble_instruction(
+    chunk: &Chunk,
+    offset: usize,
+    heap: &Heap,
+    out: &mut String,
+) -> usize {
+    let index = offset / INSTRUCTION_SIZE;
+    let _ = write!(out, "{:04} ", index);
+    if offset > 0 && chunk.line(offset) == chunk.line(offset - 1) {
+        out.push_str("   | ");
+    } else {
+        let _ = write!(out, "{:4} ", chunk.line(offset));
+    }
+
+    let [op, a, b, c] = [0, 1, 2, 3].map(|i| chunk.code[offset + i]);
+    let Ok(op) = Op::try_from(op) else {
+        let _ = writeln!(out, "Unknown opcode {}", op);
+        return offset + INSTRUCTION_SIZE;
+    };
+    let constant = |index: u8| match chunk.constants.get(index as usize) {
+        Some(value) => format!("'{}'", value.display(heap)),
+        None => "<invalid constant>".to_string(),
+    };
+    let jump = u16::from_be_bytes([b, c]) as usize;
+
+    let _ = match op {
+        Op::LoadNull | Op::LoadTrue | Op::LoadFalse | Op::Print | Op::Close | Op::Return => {
+            writeln!(out, "{:<12} {}", op, a)
+        }
+        Op::Move | Op::GetUpvalue | Op::SetUpvalue | Op::Not | Op::Negate | Op::Inherit => {
+            writeln!(out, "{:<12} {} {}", op, a, b)
+        }
+        Op::LoadConstant | Op::GetGlobal | Op::DefineGlobal | Op::SetGlobal | Op::Class => {
+            writeln!(out, "{:<12} {} {}", op, a, constant(b))
+        }
+        Op::GetProperty | Op::GetSuper | Op::Method => {
+            writeln!(out, "{:<12} {} {} {}", op, a, b, constant(c))
+        }
+        Op::SetProperty => writeln!(out, "{:<12} {} {} {}", op, a, constant(b), c),
+        Op::Equal | Op::Greater | Op::Less | Op::Add | Op::Subtract | Op::Multiply | Op::Divide => {
+            writeln!(out, "{:<12} {} {} {}", op, a, b, c)
+        }
+        Op::Jump => writeln!(out, "{:<12} -> {}", op, index + 1 + jump),
+        Op::JumpIfFalse | Op::JumpIfTrue => {
+            writeln!(out, "{:<12} {} -> {}", op, a, index + 1 + jump)
+        }
+        Op::Loop => writeln!(out, "{:<12} -> {}", op, (index + 1).saturating_sub(jump)),
+        Op::Call => writeln!(out, "{:<12} {} ({} args)", op, a, b),
+        Op::Invoke | Op::SuperInvoke => {
+            writeln!(out, "{:<12} {} ({} args) {}", op, a, b, constant(c))
+        }
+        Op::Closure => return closure_instruction(chunk, offset, heap, out),
+    };
+
+    offset + INSTRUCTION_SIZE
+}
+
+/// The function constant followed by one line per captured variable
+fn closure_instruction(chunk: &Chunk, offset: usize, heap: &Heap, out: &mut String) -> usize {
+    let (a, b) = (chunk.code[offset + 1], chunk.code[offset + 2]);
+    let function = chunk.constants.get(b as usize).copied();
+    let _ = write!(out, "{:<12} {} ", Op::Closure, a);
+
+    let Some(function) = function else {
+        out.push_str("<invalid constant>\n");
+        return offset + INSTRUCTION_SIZE;
+    };
+    let _ = writeln!(out, "{}", function.display(heap));
+
+    let upvalue_count = match function.as_object().map(|reference| heap.get(reference)) {
+        Some(Object::Function(function)) => function.upvalue_count,
+        _ => 0,
+    };
+
+    let mut offset = offset + INSTRUCTION_SIZE;
+    for _ in 0..upvalue_count {
+        let (Some(&is_local), Some(&index)) = (chunk.code.get(offset), chunk.code.get(offset + 1))
+        else {
+            break;
+        };
+        let kind = if is_local == 1 { "local" } else { "upvalue" };
+        let _ = writeln!(
+            out,
+            "{:04}    |   {} {}",
+            offset / INSTRUCTION_SIZE,
+            kind,
+            index
+        );
+        offset += INSTRUCTION_SIZE;
+    }
+
+    offset
+}
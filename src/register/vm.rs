@@ -0,0 +1,597 @@
+use std::collections::HashMap;
+
+use crate::bytecode::gc::{GcMode, GcStats};
+use crate::bytecode::object::{
+    Heap, NativeFn, ObjBoundMethod, ObjClass, ObjClosure, ObjInstance, ObjRef, ObjUpvalue, Object,
+};
+use crate::bytecode::runtime::{Frame, Runtime};
+use crate::bytecode::shape::Shapes;
+use crate::bytecode::value::Value;
+use crate::bytecode::vm::{clock, VmError, VmRuntimeError, FRAMES_MAX};
+use crate::register::instruction::{disassemble_instruction, Op, INSTRUCTION_SIZE};
+
+/// Register-based virtual machine. Every call gets a window of registers,
+/// starting at the register holding the callee in the caller's window, so
+/// arguments are passed without copying.
+#[derive(Debug)]
+pub struct RegisterVm {
+    /// The heap, the register windows, the call frames and the globals
+    runtime: Runtime<CallFrame>,
+    /// Print the registers and every instruction before executing it
+    trace: bool,
+}
+
+/// A function call in progress
+#[derive(Debug, Clone, Copy)]
+struct CallFrame {
+    closure: ObjRef,
+    /// Offset of the next instruction in the closure's chunk
+    ip: usize,
+    /// Index of the frame's register zero, the called function or `this`
+    base: usize,
+    /// End of the highest window of this frame and its callers
+    top: usize,
+}
+
+impl Frame for CallFrame {
+    fn closure(&self) -> ObjRef {
+        self.closure
+    }
+
+    fn ip(&self) -> usize {
+        self.ip
+    }
+}
+
+/// A field value or a method found on an instance
+#[derive(Debug, Clone, Copy)]
+enum Property {
+    Field(Value),
+    Method(ObjRef),
+}
+
+impl Default for RegisterVm {
+    fn default() -> Self {
+        RegisterVm::new()
+    }
+}
+
+impl RegisterVm {
+    pub fn new() -> RegisterVm {
+        let mut vm = RegisterVm {
+            runtime: Runtime::new(),
+            trace: false,
+        };
+        vm.define_native("clock", 0, clock);
+        vm
+    }
+
+    /// Objects of the running program, compiled scripts go there
+    pub fn heap(&self) -> &Heap {
+        &self.runtime.heap
+    }
+
+    pub fn heap_mut(&mut self) -> &mut Heap {
+        &mut self.runtime.heap
+    }
+
+    pub fn set_trace(&mut self, trace: bool) {
+        self.trace = trace;
+    }
+
+    /// Collect garbage before every allocation
+    pub fn set_gc_stress(&mut self, stress: bool) {
+        self.runtime.heap.set_stress(stress);
+    }
+
+    pub fn set_gc_mode(&mut self, mode: GcMode) {
+        self.runtime.heap.set_mode(mode);
+    }
+
+    /// Collections and their pause times so far
+    pub fn gc_stats(&self) -> &GcStats {
+        &self.runtime.gc_stats
+    }
+
+    /// Define a global function implemented in Rust
+    pub fn define_native(&mut self, name: &str, arity: usize, function: NativeFn) {
+        self.runtime.define_native(name, arity, function);
+    }
+
+    pub fn global(&mut self, name: &str) -> Option<Value> {
+        self.runtime.global(name)
+    }
+
+    /// Run a compiled script, globals are kept for the next one
+    pub fn run(&mut self, script: ObjRef) -> Result<(), VmRuntimeError> {
+        // Keep the function reachable while its closure is allocated.
+        self.runtime.slots.push(Value::object(script));
+        let script = self.runtime.alloc(Object::Closure(ObjClosure {
+            function: script,
+            upvalues: Vec::new(),
+        }));
+        self.runtime.slots[0] = Value::object(script);
+        let result = self
+            .call(script, 0, 0)
+            .and_then(|()| self.execute())
+            .map_err(|error| self.runtime.runtime_error(error));
+
+        if result.is_err() {
+            self.runtime.reset();
+        }
+        result
+    }
+
+    fn execute(&mut self) -> Result<(), VmError> {
+        loop {
+            if self.trace {
+                self.trace_instruction();
+            }
+
+            let [op, a, b, c] = self.read_instruction();
+            let op = Op::try_from(op)
+                .map_err(|byte| VmError::InvalidBytecode(format!("unknown opcode {}", byte)))?;
+            let base = self.runtime.frame().base;
+            let (ra, rb, rc) = (base + a as usize, base + b as usize, base + c as usize);
+
+            match op {
+                Op::Move => self.runtime.slots[ra] = self.runtime.slots[rb],
+                Op::LoadConstant => self.runtime.slots[ra] = self.constant(b),
+                Op::LoadNull => self.runtime.slots[ra] = Value::NULL,
+                Op::LoadTrue => self.runtime.slots[ra] = Value::boolean(true),
+                Op::LoadFalse => self.runtime.slots[ra] = Value::boolean(false),
+                Op::GetGlobal => {
+                    let name = self.string_constant(b);
+                    match self.runtime.globals.get(&name) {
+                        Some(value) => self.runtime.slots[ra] = *value,
+                        None => return Err(self.runtime.undefined_variable(name)),
+                    }
+                }
+                Op::DefineGlobal => {
+                    let name = self.string_constant(b);
+                    self.runtime.globals.insert(name, self.runtime.slots[ra]);
+                }
+                Op::SetGlobal => {
+                    let name = self.string_constant(b);
+                    if !self.runtime.globals.contains_key(&name) {
+                        return Err(self.runtime.undefined_variable(name));
+                    }
+                    self.runtime.globals.insert(name, self.runtime.slots[ra]);
+                }
+                Op::GetUpvalue => {
+                    let upvalue = self
+                        .runtime
+                        .heap
+                        .closure(self.runtime.frame().closure)
+                        .upvalues[b as usize];
+                    self.runtime.slots[ra] = match self.runtime.upvalue(upvalue) {
+                        ObjUpvalue::Open(register) => self.runtime.slots[register],
+                        ObjUpvalue::Closed(value) => value,
+                    };
+                }
+                Op::SetUpvalue => {
+                    let upvalue = self
+                        .runtime
+                        .heap
+                        .closure(self.runtime.frame().closure)
+                        .upvalues[b as usize];
+                    let value = self.runtime.slots[ra];
+                    match self.runtime.upvalue(upvalue) {
+                        ObjUpvalue::Open(register) => self.runtime.slots[register] = value,
+                        ObjUpvalue::Closed(_) => {
+                            *self.runtime.object_mut(upvalue) =
+                                Object::Upvalue(ObjUpvalue::Closed(value))
+                        }
+                    }
+                }
+                Op::GetProperty => {
+                    let receiver = self.runtime.slots[rb];
+                    let instance = self
+                        .runtime
+                        .as_instance(receiver)
+                        .ok_or(VmError::OnlyInstancesHaveProperties)?;
+                    let name = self.string_constant(c);
+                    self.runtime.slots[ra] = match self.property(instance, name) {
+                        Some(Property::Field(value)) => value,
+                        Some(Property::Method(method)) => self.bind(receiver, method),
+                        None => return Err(self.runtime.undefined_property(name)),
+                    };
+                }
+                Op::SetProperty => {
+                    let instance = self
+                        .runtime
+                        .as_instance(self.runtime.slots[ra])
+                        .ok_or(VmError::OnlyInstancesHaveFields)?;
+                    let name = self.string_constant(b);
+                    self.set_field(instance, name, self.runtime.slots[rc]);
+                }
+                Op::GetSuper => {
+                    let name = self.string_constant(c);
+                    let Some(superclass) = self.runtime.slots[rb].as_object() else {
+                        return Err(VmError::SuperclassMustBeClass);
+                    };
+                    let method = self
+                        .runtime
+                        .find_method(superclass, name)?
+                        .ok_or_else(|| self.runtime.undefined_property(name))?;
+                    self.runtime.slots[ra] = self.bind(self.runtime.slots[ra], method);
+                }
+                Op::Equal => {
+                    self.runtime.slots[ra] =
+                        Value::boolean(self.runtime.slots[rb] == self.runtime.slots[rc])
+                }
+                Op::Greater => {
+                    let (x, y) = self.number_operands(rb, rc, ">")?;
+                    self.runtime.slots[ra] = Value::boolean(x > y);
+                }
+                Op::Less => {
+                    let (x, y) = self.number_operands(rb, rc, "<")?;
+                    self.runtime.slots[ra] = Value::boolean(x < y);
+                }
+                Op::Add => self.runtime.slots[ra] = self.add(rb, rc)?,
+                Op::Subtract => {
+                    let (x, y) = self.number_operands(rb, rc, "-")?;
+                    self.runtime.slots[ra] = Value::number(x - y);
+                }
+                Op::Multiply => {
+                    let (x, y) = self.number_operands(rb, rc, "*")?;
+                    self.runtime.slots[ra] = Value::number(x * y);
+                }
+                Op::Divide => {
+                    let (x, y) = self.number_operands(rb, rc, "/")?;
+                    self.runtime.slots[ra] = Value::number(x / y);
+                }
+                Op::Not => {
+                    self.runtime.slots[ra] = Value::boolean(self.runtime.slots[rb].is_falsey())
+                }
+                Op::Negate => {
+                    let Some(value) = self.runtime.slots[rb].as_number() else {
+                        return Err(VmError::OperandMustBeNumber("-"));
+                    };
+                    self.runtime.slots[ra] = Value::number(-value);
+                }
+                Op::Print => println!("{}", self.runtime.slots[ra].display(&self.runtime.heap)),
+                Op::Jump => self.jump(b, c),
+                Op::JumpIfFalse => {
+                    if self.runtime.slots[ra].is_falsey() {
+                        self.jump(b, c);
+                    }
+                }
+                Op::JumpIfTrue => {
+                    if !self.runtime.slots[ra].is_falsey() {
+                        self.jump(b, c);
+                    }
+                }
+                Op::Loop => {
+                    let distance = u16::from_be_bytes([b, c]) as usize;
+                    self.runtime.frame_mut().ip -= distance * INSTRUCTION_SIZE;
+                }
+                Op::Call => self.call_value(ra, b as usize)?,
+                Op::Invoke => {
+                    let name = self.string_constant(c);
+                    self.invoke(ra, name, b as usize)?;
+                }
+                Op::SuperInvoke => {
+                    let name = self.string_constant(c);
+                    let Some(superclass) = self.runtime.slots[ra + b as usize + 1].as_object()
+                    else {
+                        return Err(VmError::SuperclassMustBeClass);
+                    };
+                    let method = self
+                        .runtime
+                        .find_method(superclass, name)?
+                        .ok_or_else(|| self.runtime.undefined_property(name))?;
+                    self.call(method, ra, b as usize)?;
+                }
+                Op::Closure => self.closure(ra, b)?,
+                Op::Close => self.runtime.close_upvalues(ra),
+                Op::Return => {
+                    let result = self.runtime.slots[ra];
+                    let frame = self.runtime.frames.pop().expect("frame to return from");
+                    self.runtime.close_upvalues(frame.base);
+
+                    let Some(caller) = self.runtime.frames.last() else {
+                        self.runtime.slots.clear();
+                        return Ok(());
+                    };
+
+                    self.runtime.slots.truncate(caller.top);
+                    self.runtime.slots[frame.base] = result;
+                }
+                Op::Class => {
+                    let name = self.string_constant(b);
+                    let class = self.runtime.alloc(Object::Class(ObjClass {
+                        name,
+                        methods: HashMap::new(),
+                    }));
+                    self.runtime.slots[ra] = Value::object(class);
+                }
+                Op::Inherit => {
+                    let superclass = self.runtime.slots[rb]
+                        .as_object()
+                        .map(|r| self.runtime.heap.get(r));
+                    let Some(Object::Class(superclass)) = superclass else {
+                        return Err(VmError::SuperclassMustBeClass);
+                    };
+                    let methods = superclass.methods.clone();
+
+                    // Copy down the methods, so lookups never walk the class chain.
+                    let subclass = self.class_at(ra)?;
+                    self.runtime.class_mut(subclass).methods.extend(methods);
+                }
+                Op::Method => {
+                    let name = self.string_constant(c);
+                    let method = self.runtime.slots[rb].as_object();
+                    let Some(method) =
+                        method.filter(|&r| matches!(self.runtime.heap.get(r), Object::Closure(_)))
+                    else {
+                        return Err(VmError::InvalidBytecode("method is not a closure".into()));
+                    };
+                    let class = self.class_at(ra)?;
+                    self.runtime.class_mut(class).methods.insert(name, method);
+                }
+            }
+        }
+    }
+
+    /// Call the value in register `slot` with the arguments after it, the
+    /// result replaces the callee
+    fn call_value(&mut self, slot: usize, argument_count: usize) -> Result<(), VmError> {
+        let Some(reference) = self.runtime.slots[slot].as_object() else {
+            return Err(VmError::NotCallable);
+        };
+
+        match self.runtime.heap.get(reference) {
+            Object::Closure(_) => self.call(reference, slot, argument_count),
+            Object::Native(native) => {
+                let (name, arity, function) = (native.name, native.arity, native.function);
+                if argument_count != arity {
+                    return Err(VmError::ArityMismatch(arity, argument_count));
+                }
+
+                let arguments = self.runtime.slots[slot + 1..slot + 1 + argument_count].to_vec();
+                let result = function(&mut self.runtime.heap, &arguments).map_err(|message| {
+                    VmError::NativeError(self.runtime.heap.string(name).to_string(), message)
+                })?;
+                self.runtime.slots[slot] = result;
+                Ok(())
+            }
+            Object::Class(class) => {
+                let initializer = class.methods.get(&self.runtime.init_string).copied();
+                let instance = self.runtime.alloc(Object::Instance(ObjInstance {
+                    class: reference,
+                    shape: Shapes::ROOT,
+                    fields: Vec::new(),
+                }));
+                self.runtime.slots[slot] = Value::object(instance);
+
+                match initializer {
+                    Some(initializer) => self.call(initializer, slot, argument_count),
+                    None if argument_count != 0 => Err(VmError::ArityMismatch(0, argument_count)),
+                    None => Ok(()),
+                }
+            }
+            Object::BoundMethod(bound) => {
+                let (receiver, method) = (bound.receiver, bound.method);
+                self.runtime.slots[slot] = receiver;
+                self.call(method, slot, argument_count)
+            }
+            _ => Err(VmError::NotCallable),
+        }
+    }
+
+    /// Push a frame whose window starts at register `slot`
+    fn call(&mut self, closure: ObjRef, slot: usize, argument_count: usize) -> Result<(), VmError> {
+        let function = self
+            .runtime
+            .heap
+            .function(self.runtime.heap.closure(closure).function);
+        let (arity, size) = (function.arity, function.registers);
+        if argument_count != arity {
+            return Err(VmError::ArityMismatch(arity, argument_count));
+        }
+
+        if self.runtime.frames.len() >= FRAMES_MAX {
+            return Err(VmError::StackOverflow);
+        }
+
+        let top = self
+            .runtime
+            .frames
+            .last()
+            .map_or(0, |frame| frame.top)
+            .max(slot + size);
+        if self.runtime.slots.len() < top {
+            self.runtime.slots.resize(top, Value::NULL);
+        }
+        self.runtime.frames.push(CallFrame {
+            closure,
+            ip: 0,
+            base: slot,
+            top,
+        });
+        Ok(())
+    }
+
+    /// Call a method directly, without creating a bound method
+    fn invoke(&mut self, slot: usize, name: ObjRef, argument_count: usize) -> Result<(), VmError> {
+        let instance = self
+            .runtime
+            .as_instance(self.runtime.slots[slot])
+            .ok_or(VmError::OnlyInstancesHaveProperties)?;
+
+        match self.property(instance, name) {
+            // A field holding a function shadows the method.
+            Some(Property::Field(field)) => {
+                self.runtime.slots[slot] = field;
+                self.call_value(slot, argument_count)
+            }
+            Some(Property::Method(method)) => self.call(method, slot, argument_count),
+            None => Err(self.runtime.undefined_property(name)),
+        }
+    }
+
+    /// Create the closure of the function constant `constant` in register
+    /// `slot`, capturing the variables listed after the instruction
+    fn closure(&mut self, slot: usize, constant: u8) -> Result<(), VmError> {
+        let Some(function) = self.constant(constant).as_object() else {
+            return Err(VmError::InvalidBytecode("closure of a non-function".into()));
+        };
+
+        // In a register before capturing, so the upvalues captured so far
+        // stay reachable through it.
+        let closure = self.runtime.alloc(Object::Closure(ObjClosure {
+            function,
+            upvalues: Vec::new(),
+        }));
+        self.runtime.slots[slot] = Value::object(closure);
+
+        for _ in 0..self.runtime.heap.function(function).upvalue_count {
+            let [is_local, index, _, _] = self.read_instruction();
+            let frame = *self.runtime.frame();
+
+            let upvalue = if is_local == 1 {
+                self.runtime.capture_upvalue(frame.base + index as usize)
+            } else {
+                self.runtime.heap.closure(frame.closure).upvalues[index as usize]
+            };
+            if let Object::Closure(closure) = self.runtime.object_mut(closure) {
+                closure.upvalues.push(upvalue);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// A field of an instance or else a method of its class
+    fn property(&self, instance: ObjRef, name: ObjRef) -> Option<Property> {
+        let Object::Instance(instance) = self.runtime.heap.get(instance) else {
+            unreachable!("checked instance");
+        };
+
+        if let Some(slot) = self.runtime.shapes.slot(instance.shape, name) {
+            return Some(Property::Field(instance.fields[slot]));
+        }
+
+        match self.runtime.heap.get(instance.class) {
+            Object::Class(class) => class.methods.get(&name).copied().map(Property::Method),
+            _ => None,
+        }
+    }
+
+    fn set_field(&mut self, instance: ObjRef, name: ObjRef, value: Value) {
+        let Object::Instance(ObjInstance { shape, .. }) = self.runtime.heap.get(instance) else {
+            unreachable!("checked instance");
+        };
+        let shape = *shape;
+        let slot = self.runtime.shapes.slot(shape, name);
+        let next = match slot {
+            Some(_) => shape,
+            None => self.runtime.shapes.add_field(shape, name),
+        };
+
+        let Object::Instance(instance) = self.runtime.object_mut(instance) else {
+            unreachable!("checked instance");
+        };
+        match slot {
+            Some(slot) => instance.fields[slot] = value,
+            None => {
+                instance.fields.push(value);
+                instance.shape = next;
+            }
+        }
+    }
+
+    /// A method bound to its receiver
+    fn bind(&mut self, receiver: Value, method: ObjRef) -> Value {
+        let bound = self
+            .runtime
+            .alloc(Object::BoundMethod(ObjBoundMethod { receiver, method }));
+        Value::object(bound)
+    }
+
+    fn add(&mut self, left: usize, right: usize) -> Result<Value, VmError> {
+        let (a, b) = (self.runtime.slots[left], self.runtime.slots[right]);
+        if let (Some(a), Some(b)) = (a.as_number(), b.as_number()) {
+            return Ok(Value::number(a + b));
+        }
+
+        let (Some(a), Some(b)) = (a.as_object(), b.as_object()) else {
+            return Err(VmError::OperandsMustBeNumbersOrStrings("+"));
+        };
+        let (Object::String(a), Object::String(b)) =
+            (self.runtime.heap.get(a), self.runtime.heap.get(b))
+        else {
+            return Err(VmError::OperandsMustBeNumbersOrStrings("+"));
+        };
+
+        let concatenated = format!("{}{}", a, b);
+        Ok(Value::object(self.runtime.intern(&concatenated)))
+    }
+
+    fn number_operands(
+        &self,
+        left: usize,
+        right: usize,
+        operator: &'static str,
+    ) -> Result<(f64, f64), VmError> {
+        match (
+            self.runtime.slots[left].as_number(),
+            self.runtime.slots[right].as_number(),
+        ) {
+            (Some(a), Some(b)) => Ok((a, b)),
+            _ => Err(VmError::OperandsMustBeNumbers(operator)),
+        }
+    }
+
+    fn trace_instruction(&self) {
+        let frame = self.runtime.frame();
+        let mut registers = String::from("          ");
+        for value in &self.runtime.slots[frame.base..frame.top] {
+            registers.push_str(&format!("[ {} ]", value.display(&self.runtime.heap)));
+        }
+
+        let mut instruction = String::new();
+        disassemble_instruction(
+            self.runtime.chunk(frame.closure),
+            frame.ip,
+            &self.runtime.heap,
+            &mut instruction,
+        );
+        eprint!("{}\n{}", registers, instruction);
+    }
+
+    /// The class in a register, only compiled code puts it there
+    fn class_at(&self, register: usize) -> Result<ObjRef, VmError> {
+        self.runtime.slots[register]
+            .as_object()
+            .filter(|&reference| matches!(self.runtime.heap.get(reference), Object::Class(_)))
+            .ok_or_else(|| VmError::InvalidBytecode("expected a class in a register".into()))
+    }
+
+    fn read_instruction(&mut self) -> [u8; INSTRUCTION_SIZE] {
+        let frame = self.runtime.frames.last_mut().expect("call frame");
+        let function = self.runtime.heap.closure(frame.closure).function;
+        let code = &self.runtime.heap.function(function).chunk.code;
+        let word = [0, 1, 2, 3].map(|i| code[frame.ip + i]);
+        frame.ip += INSTRUCTION_SIZE;
+        word
+    }
+
+    /// Skip forward over the `bc` instructions of a jump
+    fn jump(&mut self, b: u8, c: u8) {
+        let distance = u16::from_be_bytes([b, c]) as usize;
+        self.runtime.frame_mut().ip += distance * INSTRUCTION_SIZE;
+    }
+
+    fn constant(&self, index: u8) -> Value {
+        self.runtime.chunk(self.runtime.frame().closure).constants[index as usize]
+    }
+
+    fn string_constant(&self, index: u8) -> ObjRef {
+        let value = self.constant(index);
+        value
+            .as_object()
+            .unwrap_or_else(|| panic!("expected a name constant, found {:?}", value))
+    }
+}
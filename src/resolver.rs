@@ -0,0 +1,343 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::expr::{self, ExprVisitor};
+use crate::stmt::{self, Stmt, StmtVisitor};
+use crate::token::{Span, Token, TokenTrait};
+
+/// Static pass binding every local variable use to the scope that declares it
+#[derive(Debug, Default)]
+pub struct Resolver {
+    /// Local scopes, the value is whether the variable's initializer has finished
+    scopes: Vec<HashMap<String, bool>>,
+    current_function: FunctionType,
+    current_class: ClassType,
+    errors: Vec<ResolverError>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FunctionType {
+    #[default]
+    None,
+    Function,
+    Method,
+    /// An `init` method, which can't return a value
+    Initializer,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ClassType {
+    #[default]
+    None,
+    Class,
+    /// A class with a superclass, where `super` can be used
+    Subclass,
+}
+
+#[derive(Debug, Clone, thiserror::Error)]
+pub enum ResolverError {
+    #[error("can't read local variable '{0}' in its own initializer at {1}")]
+    ReadInOwnInitializer(String, Span),
+    #[error("variable '{0}' is already declared in this scope at {1}")]
+    AlreadyDeclared(String, Span),
+    #[error("can't return from top-level code at {0}")]
+    TopLevelReturn(Span),
+    #[error("can't return a value from an initializer at {0}")]
+    ReturnFromInitializer(Span),
+    #[error("can't use 'this' outside of a class at {0}")]
+    ThisOutsideClass(Span),
+    #[error("can't use 'super' outside of a class at {0}")]
+    SuperOutsideClass(Span),
+    #[error("can't use 'super' in a class with no superclass at {0}")]
+    SuperWithoutSuperclass(Span),
+    #[error("a class can't inherit from itself at {0}")]
+    InheritFromSelf(Span),
+}
+
+impl ResolverError {
+    pub fn span(&self) -> Span {
+        match self {
+            ResolverError::ReadInOwnInitializer(_, span)
+            | ResolverError::AlreadyDeclared(_, span)
+            | ResolverError::TopLevelReturn(span)
+            | ResolverError::ReturnFromInitializer(span)
+            | ResolverError::ThisOutsideClass(span)
+            | ResolverError::SuperOutsideClass(span)
+            | ResolverError::SuperWithoutSuperclass(span)
+            | ResolverError::InheritFromSelf(span) => *span,
+        }
+    }
+}
+
+pub trait ResolverTrait {
+    fn new() -> Self;
+    /// Resolve a program, reporting every static error found
+    fn resolve(&mut self, statements: &[Stmt]) -> Result<(), Vec<ResolverError>>;
+    fn resolve_statements(&mut self, statements: &[Stmt]);
+    fn resolve_function(&mut self, function: &stmt::Function, function_type: FunctionType);
+    /// Record how many scopes away `name` is declared, globals are left unresolved
+    fn resolve_local(&mut self, name: &Token, depth: &Cell<Option<usize>>);
+    fn begin_scope(&mut self);
+    fn end_scope(&mut self);
+    /// Add a variable to the innermost scope, not yet usable
+    fn declare(&mut self, name: &Token);
+    /// Mark a declared variable as initialized
+    fn define(&mut self, name: &Token);
+}
+
+impl ResolverTrait for Resolver {
+    fn new() -> Resolver {
+        Resolver::default()
+    }
+
+    fn resolve(&mut self, statements: &[Stmt]) -> Result<(), Vec<ResolverError>> {
+        self.resolve_statements(statements);
+
+        if !self.errors.is_empty() {
+            return Err(std::mem::take(&mut self.errors));
+        }
+
+        Ok(())
+    }
+
+    fn resolve_statements(&mut self, statements: &[Stmt]) {
+        for statement in statements {
+            statement.accept(self);
+        }
+    }
+
+    fn resolve_function(&mut self, function: &stmt::Function, function_type: FunctionType) {
+        let enclosing_function = std::mem::replace(&mut self.current_function, function_type);
+
+        self.begin_scope();
+        for param in &function.params {
+            self.declare(param);
+            self.define(param);
+        }
+        self.resolve_statements(&function.body);
+        self.end_scope();
+
+        self.current_function = enclosing_function;
+    }
+
+    fn resolve_local(&mut self, name: &Token, depth: &Cell<Option<usize>>) {
+        let found = self
+            .scopes
+            .iter()
+            .rev()
+            .position(|scope| scope.contains_key(name.lexeme()));
+
+        depth.set(found);
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn declare(&mut self, name: &Token) {
+        let Some(scope) = self.scopes.last_mut() else {
+            return;
+        };
+
+        if scope.contains_key(name.lexeme()) {
+            self.errors.push(ResolverError::AlreadyDeclared(
+                name.lexeme().to_string(),
+                name.span(),
+            ));
+        }
+        scope.insert(name.lexeme().to_string(), false);
+    }
+
+    fn define(&mut self, name: &Token) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.lexeme().to_string(), true);
+        }
+    }
+}
+
+impl ExprVisitor<()> for Resolver {
+    fn visit_assign_expr(&mut self, expr: &expr::Assign) {
+        expr.value.accept(self);
+        self.resolve_local(&expr.name, &expr.depth);
+    }
+
+    fn visit_binary_expr(&mut self, expr: &expr::Binary) {
+        expr.left.accept(self);
+        expr.right.accept(self);
+    }
+
+    fn visit_call_expr(&mut self, expr: &expr::Call) {
+        expr.callee.accept(self);
+        for argument in &expr.arguments {
+            argument.accept(self);
+        }
+    }
+
+    fn visit_get_expr(&mut self, expr: &expr::Get) {
+        expr.object.accept(self);
+    }
+
+    fn visit_grouping_expr(&mut self, expr: &expr::Grouping) {
+        expr.expression.accept(self);
+    }
+
+    fn visit_literal_expr(&mut self, _expr: &expr::Literal) {}
+
+    fn visit_logical_expr(&mut self, expr: &expr::Logical) {
+        expr.left.accept(self);
+        expr.right.accept(self);
+    }
+
+    fn visit_set_expr(&mut self, expr: &expr::Set) {
+        expr.value.accept(self);
+        expr.object.accept(self);
+    }
+
+    fn visit_super_expr(&mut self, expr: &expr::Super) {
+        match self.current_class {
+            ClassType::None => self
+                .errors
+                .push(ResolverError::SuperOutsideClass(expr.keyword.span())),
+            ClassType::Class => self
+                .errors
+                .push(ResolverError::SuperWithoutSuperclass(expr.keyword.span())),
+            ClassType::Subclass => {}
+        }
+
+        self.resolve_local(&expr.keyword, &expr.depth);
+    }
+
+    fn visit_this_expr(&mut self, expr: &expr::This) {
+        if self.current_class == ClassType::None {
+            self.errors
+                .push(ResolverError::ThisOutsideClass(expr.keyword.span()));
+            return;
+        }
+
+        self.resolve_local(&expr.keyword, &expr.depth);
+    }
+
+    fn visit_unary_expr(&mut self, expr: &expr::Unary) {
+        expr.right.accept(self);
+    }
+
+    fn visit_variable_expr(&mut self, expr: &expr::Variable) {
+        let name = expr.name.lexeme();
+        if self.scopes.last().and_then(|scope| scope.get(name)) == Some(&false) {
+            self.errors.push(ResolverError::ReadInOwnInitializer(
+                name.to_string(),
+                expr.name.span(),
+            ));
+        }
+
+        self.resolve_local(&expr.name, &expr.depth);
+    }
+}
+
+impl StmtVisitor<()> for Resolver {
+    fn visit_block_stmt(&mut self, stmt: &stmt::Block) {
+        self.begin_scope();
+        self.resolve_statements(&stmt.statements);
+        self.end_scope();
+    }
+
+    fn visit_class_stmt(&mut self, stmt: &stmt::Class) {
+        let enclosing_class = std::mem::replace(&mut self.current_class, ClassType::Class);
+
+        self.declare(&stmt.name);
+        self.define(&stmt.name);
+
+        if let Some(superclass) = &stmt.superclass {
+            if superclass.name.lexeme() == stmt.name.lexeme() {
+                self.errors
+                    .push(ResolverError::InheritFromSelf(superclass.name.span()));
+            }
+
+            self.current_class = ClassType::Subclass;
+            self.visit_variable_expr(superclass);
+
+            self.begin_scope();
+            if let Some(scope) = self.scopes.last_mut() {
+                scope.insert("super".to_string(), true);
+            }
+        }
+
+        self.begin_scope();
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert("this".to_string(), true);
+        }
+
+        for method in &stmt.methods {
+            let function_type = if method.name.lexeme() == "init" {
+                FunctionType::Initializer
+            } else {
+                FunctionType::Method
+            };
+            self.resolve_function(method, function_type);
+        }
+
+        self.end_scope();
+        if stmt.superclass.is_some() {
+            self.end_scope();
+        }
+
+        self.current_class = enclosing_class;
+    }
+
+    fn visit_expression_stmt(&mut self, stmt: &stmt::Expression) {
+        stmt.expression.accept(self);
+    }
+
+    fn visit_function_stmt(&mut self, stmt: &Rc<stmt::Function>) {
+        // Define eagerly so the function can refer to itself recursively.
+        self.declare(&stmt.name);
+        self.define(&stmt.name);
+
+        self.resolve_function(stmt, FunctionType::Function);
+    }
+
+    fn visit_if_stmt(&mut self, stmt: &stmt::If) {
+        stmt.condition.accept(self);
+        stmt.then_branch.accept(self);
+        if let Some(else_branch) = &stmt.else_branch {
+            else_branch.accept(self);
+        }
+    }
+
+    fn visit_print_stmt(&mut self, stmt: &stmt::Print) {
+        stmt.expression.accept(self);
+    }
+
+    fn visit_return_stmt(&mut self, stmt: &stmt::Return) {
+        if self.current_function == FunctionType::None {
+            self.errors
+                .push(ResolverError::TopLevelReturn(stmt.keyword.span()));
+        }
+
+        if let Some(value) = &stmt.value {
+            if self.current_function == FunctionType::Initializer {
+                self.errors
+                    .push(ResolverError::ReturnFromInitializer(stmt.keyword.span()));
+            }
+            value.accept(self);
+        }
+    }
+
+    fn visit_var_stmt(&mut self, stmt: &stmt::Var) {
+        self.declare(&stmt.name);
+        if let Some(initializer) = &stmt.initializer {
+            initializer.accept(self);
+        }
+        self.define(&stmt.name);
+    }
+
+    fn visit_while_stmt(&mut self, stmt: &stmt::While) {
+        stmt.condition.accept(self);
+        stmt.body.accept(self);
+    }
+}
@@ -1,4 +1,4 @@
-use crate::token::{Literal, Token, TokenTrait, TokenType};
+use crate::token::{Literal, Span, Token, TokenTrait, TokenType};
 
 #[derive(Debug)]
 pub struct Scanner {
@@ -7,6 +7,11 @@ pub struct Scanner {
     start: usize,
     current: usize,
     line: u32,
+    /// Offset of the first character of the current line
+    line_start: usize,
+    /// Line and column where the current token starts
+    start_line: u32,
+    start_column: u32,
 }
 
 #[derive(Debug, Clone, thiserror::Error)]
@@ -54,6 +59,9 @@ impl ScannerTrait for Scanner {
             start: 0,
             current: 0,
             line: 1,
+            line_start: 0,
+            start_line: 1,
+            start_column: 1,
         }
     }
 
@@ -62,6 +70,8 @@ impl ScannerTrait for Scanner {
 
         while !self.is_at_end() {
             self.start = self.current;
+            self.start_line = self.line;
+            self.start_column = (self.current - self.line_start) as u32 + 1;
             match self.scan_token() {
                 Ok(_) => {}
                 Err(e) => {
@@ -71,7 +81,13 @@ impl ScannerTrait for Scanner {
             }
         }
 
-        let end_token = Token::new(TokenType::Eof, "".to_string(), Literal::Null, self.line);
+        let end_span = Span {
+            line: self.line,
+            column: (self.current - self.line_start) as u32 + 1,
+            start: self.current,
+            end: self.current,
+        };
+        let end_token = Token::new(TokenType::Eof, "".to_string(), Literal::Null, end_span);
         self.tokens.push(end_token);
 
         if !errors.is_empty() {
@@ -150,6 +166,7 @@ impl ScannerTrait for Scanner {
             }
             '\n' => {
                 self.line += 1;
+                self.line_start = self.current;
             }
             '"' => self.add_string()?,
             _ => {
@@ -172,7 +189,13 @@ impl ScannerTrait for Scanner {
 
     fn add_token(&mut self, token_type: TokenType, literal: Literal) {
         let text = self.source[self.start..self.current].to_string();
-        let token = Token::new(token_type, text, literal, self.line);
+        let span = Span {
+            line: self.start_line,
+            column: self.start_column,
+            start: self.start,
+            end: self.current,
+        };
+        let token = Token::new(token_type, text, literal, span);
         self.tokens.push(token);
     }
 
@@ -186,6 +209,7 @@ impl ScannerTrait for Scanner {
         while self.peek() != '"' && !self.is_at_end() {
             if self.peek() == '\n' {
                 self.line += 1;
+                self.line_start = self.current + 1;
             }
             self.advance();
         }
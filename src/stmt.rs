@@ -0,0 +1,124 @@
+use std::rc::Rc;
+
+use crate::expr::{self, Expr};
+use crate::token::{Span, Token, TokenTrait};
+
+/// Statement nodes produced by the parser
+#[derive(Debug, Clone, PartialEq)]
+pub enum Stmt {
+    Block(Block),
+    Class(Class),
+    Expression(Expression),
+    Function(Rc<Function>),
+    If(If),
+    Print(Print),
+    Return(Return),
+    Var(Var),
+    While(While),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Block {
+    pub statements: Vec<Stmt>,
+    /// Span of the opening brace
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Class {
+    pub name: Token,
+    pub superclass: Option<expr::Variable>,
+    pub methods: Vec<Rc<Function>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expression {
+    pub expression: Expr,
+}
+
+/// Function declarations are shared with the runtime objects created from them
+#[derive(Debug, Clone, PartialEq)]
+pub struct Function {
+    pub name: Token,
+    pub params: Vec<Token>,
+    pub body: Vec<Stmt>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct If {
+    pub condition: Expr,
+    pub then_branch: Box<Stmt>,
+    pub else_branch: Option<Box<Stmt>>,
+    /// Span of the `if` keyword
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Print {
+    pub expression: Expr,
+    /// Span of the `print` keyword
+    pub span: Span,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Return {
+    pub keyword: Token,
+    pub value: Option<Expr>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Var {
+    pub name: Token,
+    pub initializer: Option<Expr>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct While {
+    pub condition: Expr,
+    pub body: Box<Stmt>,
+    /// Span of the `while` or `for` keyword
+    pub span: Span,
+}
+
+pub trait StmtVisitor<T> {
+    fn visit_block_stmt(&mut self, stmt: &Block) -> T;
+    fn visit_class_stmt(&mut self, stmt: &Class) -> T;
+    fn visit_expression_stmt(&mut self, stmt: &Expression) -> T;
+    fn visit_function_stmt(&mut self, stmt: &Rc<Function>) -> T;
+    fn visit_if_stmt(&mut self, stmt: &If) -> T;
+    fn visit_print_stmt(&mut self, stmt: &Print) -> T;
+    fn visit_return_stmt(&mut self, stmt: &Return) -> T;
+    fn visit_var_stmt(&mut self, stmt: &Var) -> T;
+    fn visit_while_stmt(&mut self, stmt: &While) -> T;
+}
+
+impl Stmt {
+    pub fn accept<T, V: StmtVisitor<T> + ?Sized>(&self, visitor: &mut V) -> T {
+        match self {
+            Stmt::Block(stmt) => visitor.visit_block_stmt(stmt),
+            Stmt::Class(stmt) => visitor.visit_class_stmt(stmt),
+            Stmt::Expression(stmt) => visitor.visit_expression_stmt(stmt),
+            Stmt::Function(stmt) => visitor.visit_function_stmt(stmt),
+            Stmt::If(stmt) => visitor.visit_if_stmt(stmt),
+            Stmt::Print(stmt) => visitor.visit_print_stmt(stmt),
+            Stmt::Return(stmt) => visitor.visit_return_stmt(stmt),
+            Stmt::Var(stmt) => visitor.visit_var_stmt(stmt),
+            Stmt::While(stmt) => visitor.visit_while_stmt(stmt),
+        }
+    }
+
+    /// Span of the token that best identifies the statement in diagnostics
+    pub fn span(&self) -> Span {
+        match self {
+            Stmt::Block(stmt) => stmt.span,
+            Stmt::Class(stmt) => stmt.name.span(),
+            Stmt::Expression(stmt) => stmt.expression.span(),
+            Stmt::Function(stmt) => stmt.name.span(),
+            Stmt::If(stmt) => stmt.span,
+            Stmt::Print(stmt) => stmt.span,
+            Stmt::Return(stmt) => stmt.keyword.span(),
+            Stmt::Var(stmt) => stmt.name.span(),
+            Stmt::While(stmt) => stmt.span,
+        }
+    }
+}
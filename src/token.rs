@@ -1,6 +1,7 @@
 use std::fmt::Display;
+use std::str::FromStr;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum TokenType {
     // Single-character tokens.
     LeftParen,
@@ -51,37 +52,136 @@ pub enum TokenType {
     Eof,
 }
 
-#[derive(Debug, Clone)]
+/// Parse a token type from its variant name, e.g. `LeftParen`
+impl FromStr for TokenType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "LeftParen" => Ok(TokenType::LeftParen),
+            "RightParen" => Ok(TokenType::RightParen),
+            "LeftBrace" => Ok(TokenType::LeftBrace),
+            "RightBrace" => Ok(TokenType::RightBrace),
+            "Comma" => Ok(TokenType::Comma),
+            "Dot" => Ok(TokenType::Dot),
+            "Minus" => Ok(TokenType::Minus),
+            "Plus" => Ok(TokenType::Plus),
+            "Semicolon" => Ok(TokenType::Semicolon),
+            "Slash" => Ok(TokenType::Slash),
+            "Star" => Ok(TokenType::Star),
+            "Bang" => Ok(TokenType::Bang),
+            "BangEqual" => Ok(TokenType::BangEqual),
+            "Equal" => Ok(TokenType::Equal),
+            "EqualEqual" => Ok(TokenType::EqualEqual),
+            "Greater" => Ok(TokenType::Greater),
+            "GreaterEqual" => Ok(TokenType::GreaterEqual),
+            "Less" => Ok(TokenType::Less),
+            "LessEqual" => Ok(TokenType::LessEqual),
+            "Identifier" => Ok(TokenType::Identifier),
+            "String" => Ok(TokenType::String),
+            "Number" => Ok(TokenType::Number),
+            "And" => Ok(TokenType::And),
+            "Class" => Ok(TokenType::Class),
+            "Else" => Ok(TokenType::Else),
+            "False" => Ok(TokenType::False),
+            "Fun" => Ok(TokenType::Fun),
+            "For" => Ok(TokenType::For),
+            "If" => Ok(TokenType::If),
+            "Null" => Ok(TokenType::Null),
+            "Or" => Ok(TokenType::Or),
+            "Print" => Ok(TokenType::Print),
+            "Return" => Ok(TokenType::Return),
+            "Super" => Ok(TokenType::Super),
+            "This" => Ok(TokenType::This),
+            "True" => Ok(TokenType::True),
+            "Var" => Ok(TokenType::Var),
+            "While" => Ok(TokenType::While),
+            "Eof" => Ok(TokenType::Eof),
+            _ => Err(s.to_string()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Literal {
     String(String),
     Number(f64),
     Boolean(bool),
-    Class,
-    Function,
     Null,
 }
 
-#[derive(Debug, Clone)]
+/// Location of a token in the source, `start` and `end` are character offsets
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub line: u32,
+    pub column: u32,
+    pub start: usize,
+    pub end: usize,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct Token {
     token_type: TokenType,
     lexeme: String,
     literal: Literal,
-    line: u32,
+    span: Span,
 }
 
 pub trait TokenTrait {
-    fn new(token_type: TokenType, lexeme: String, literal: Literal, line: u32) -> Self;
+    fn new(token_type: TokenType, lexeme: String, literal: Literal, span: Span) -> Self;
+    fn token_type(&self) -> TokenType;
+    fn lexeme(&self) -> &str;
+    fn literal(&self) -> &Literal;
+    fn line(&self) -> u32;
+    fn span(&self) -> Span;
 }
 
 impl TokenTrait for Token {
-    fn new(token_type: TokenType, lexeme: String, literal: Literal, line: u32) -> Token {
+    fn new(token_type: TokenType, lexeme: String, literal: Literal, span: Span) -> Token {
         Token {
             token_type,
             lexeme,
             literal,
-            line,
+            span,
         }
     }
+
+    fn token_type(&self) -> TokenType {
+        self.token_type
+    }
+
+    fn lexeme(&self) -> &str {
+        &self.lexeme
+    }
+
+    fn literal(&self) -> &Literal {
+        &self.literal
+    }
+
+    fn line(&self) -> u32 {
+        self.span.line
+    }
+
+    fn span(&self) -> Span {
+        self.span
+    }
+}
+
+impl Display for Literal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Literal::String(value) => write!(f, "{}", value),
+            Literal::Number(value) => write!(f, "{}", value),
+            Literal::Boolean(value) => write!(f, "{}", value),
+            Literal::Null => write!(f, "null"),
+        }
+    }
+}
+
+impl Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "line: {}, column: {}", self.line, self.column)
+    }
 }
 
 impl Display for Token {
@@ -0,0 +1,93 @@
+use std::fmt::{Debug, Display};
+use std::rc::Rc;
+
+use crate::class::{LoxClass, LoxInstance};
+use crate::interpreter::{Interpreter, RuntimeError};
+use crate::native::NativeFunction;
+
+/// Runtime values of the tree-walking interpreter
+#[derive(Clone)]
+pub enum Value {
+    Null,
+    Boolean(bool),
+    Number(f64),
+    String(Rc<str>),
+    Callable(Rc<dyn LoxCallable>),
+    Native(Rc<NativeFunction>),
+    Class(Rc<LoxClass>),
+    Instance(Rc<LoxInstance>),
+}
+
+/// Anything that can be called with `(...)` from Lox code
+pub trait LoxCallable {
+    fn name(&self) -> &str;
+    fn arity(&self) -> usize;
+    fn call(
+        &self,
+        interpreter: &mut Interpreter,
+        arguments: Vec<Value>,
+    ) -> Result<Value, RuntimeError>;
+}
+
+impl Value {
+    /// Only `null` and `false` are falsey
+    pub fn is_truthy(&self) -> bool {
+        !matches!(self, Value::Null | Value::Boolean(false))
+    }
+
+    /// Name of the value's type, used in error messages
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Null => "null",
+            Value::Boolean(_) => "boolean",
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Callable(_) | Value::Native(_) => "callable",
+            Value::Class(_) => "class",
+            Value::Instance(_) => "instance",
+        }
+    }
+}
+
+/// Values of different types are never equal, objects compare by identity
+impl PartialEq for Value {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (Value::Null, Value::Null) => true,
+            (Value::Boolean(a), Value::Boolean(b)) => a == b,
+            (Value::Number(a), Value::Number(b)) => a == b,
+            (Value::String(a), Value::String(b)) => a == b,
+            (Value::Callable(a), Value::Callable(b)) => {
+                std::ptr::addr_eq(Rc::as_ptr(a), Rc::as_ptr(b))
+            }
+            (Value::Native(a), Value::Native(b)) => Rc::ptr_eq(a, b),
+            (Value::Class(a), Value::Class(b)) => Rc::ptr_eq(a, b),
+            (Value::Instance(a), Value::Instance(b)) => Rc::ptr_eq(a, b),
+            _ => false,
+        }
+    }
+}
+
+impl Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Null => write!(f, "null"),
+            Value::Boolean(value) => write!(f, "{}", value),
+            Value::Number(value) => write!(f, "{}", value),
+            Value::String(value) => write!(f, "{}", value),
+            Value::Callable(callable) => write!(f, "<fn {}>", callable.name()),
+            Value::Native(native) => write!(f, "<native fn {}>", native.name),
+            Value::Class(class) => write!(f, "{}", class.name),
+            Value::Instance(instance) => write!(f, "{} instance", instance.class.name),
+        }
+    }
+}
+
+impl Debug for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::String(value) => write!(f, "{:?}", value),
+            value => write!(f, "{}", value),
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use rs_crafting_interpreters::ast_dot::print_dot;
+use rs_crafting_interpreters::parser::{Parser, ParserTrait};
+use rs_crafting_interpreters::scanner::{Scanner, ScannerTrait};
+
+fn dot(source: &str) -> String {
+    let tokens = Scanner::new(source.to_string()).scan_tokens().unwrap();
+    let statements = Parser::new(tokens).parse().unwrap();
+    print_dot(&statements)
+}
+
+#[test]
+fn nodes_and_edges_of_a_small_program() {
+    let expected = r#"digraph ast {
+  node [shape=box, fontname="monospace"];
+  n0 [label="Program"];
+  n1 [label="Var\ngreeting"];
+  n2 [label="Literal\n\"hi\""];
+  n1 -> n2 [label="initializer"];
+  n0 -> n1 [label="statements[0]"];
+  n3 [label="Print"];
+  n4 [label="Unary\n-"];
+  n5 [label="-", shape=ellipse];
+  n4 -> n5 [label="operator"];
+  n6 [label="Grouping"];
+  n7 [label="Binary\n+"];
+  n8 [label="Literal\n1"];
+  n7 -> n8 [label="left"];
+  n9 [label="+", shape=ellipse];
+  n7 -> n9 [label="operator"];
+  n10 [label="Variable\ngreeting"];
+  n7 -> n10 [label="right"];
+  n6 -> n7 [label="expression"];
+  n4 -> n6 [label="right"];
+  n3 -> n4 [label="expression"];
+  n0 -> n3 [label="statements[1]"];
+}"#;
+
+    assert_eq!(
+        dot("var greeting = \"hi\";\nprint -(1 + greeting);"),
+        expected
+    );
+}
+
+#[test]
+fn backslashes_in_string_literals_are_escaped() {
+    let output = dot(r#"print "a\b";"#);
+
+    // The literal is quoted as `"a\\b"`, then every `\` and `"` is escaped for DOT.
+    assert!(
+        output.contains(r#"n2 [label="Literal\n\"a\\\\b\""];"#),
+        "{output}"
+    );
+}
@@ -0,0 +1,115 @@
+use rs_crafting_interpreters::ast_json::{
+    statements_from_json, statements_from_str, statements_to_json, AstJsonError,
+};
+use rs_crafting_interpreters::json::Json;
+use rs_crafting_interpreters::optimizer::{Optimizer, OptimizerTrait};
+use rs_crafting_interpreters::parser::{Parser, ParserTrait};
+use rs_crafting_interpreters::scanner::{Scanner, ScannerTrait};
+use rs_crafting_interpreters::stmt::Stmt;
+
+fn parse(source: &str) -> Vec<Stmt> {
+    let tokens = Scanner::new(source.to_string()).scan_tokens().unwrap();
+    Parser::new(tokens).parse().unwrap()
+}
+
+fn assert_round_trip(source: &str) {
+    let statements = parse(source);
+    let text = statements_to_json(&statements).to_string();
+
+    assert_eq!(statements_from_str(&text).unwrap(), statements);
+}
+
+#[test]
+fn round_trips_every_expression_kind() {
+    assert_round_trip(
+        r#"
+        a = -1 + 2 * (3 - 4) / 5;
+        print !true == false and null != "s" or a >= 1 and a <= 2;
+        f(1, g(2), h)(3);
+        object.field.other = this.value;
+        print super.method;
+        "#,
+    );
+}
+
+#[test]
+fn round_trips_every_statement_kind() {
+    assert_round_trip(
+        r#"
+        var a;
+        var b = 1;
+        { var c = 2; print c; }
+        if (a) print 1; else { print 2; }
+        if (b) print 3;
+        while (a < 10) a = a + 1;
+        for (var i = 0; i < 10; i = i + 1) print i;
+        for (;;) {}
+        fun add(x, y) { return x + y; }
+        fun nothing() { return; }
+        class A { init() { this.x = 1; } method() {} }
+        class B < A { method() { super.method(); } }
+        "#,
+    );
+}
+
+#[test]
+fn round_trips_numbers_and_string_escapes() {
+    assert_round_trip(
+        "print 0.1 + 123456789.987654321 + 0;\nprint \"back\\slash tab\t newline\n\";",
+    );
+
+    let text = "quote \" control \u{1} unicode é 🦀";
+    let json = Json::String(text.to_string()).to_string();
+    assert_eq!(Json::parse(&json).unwrap(), Json::String(text.to_string()));
+    assert_eq!(
+        Json::parse(r#""\ud83e\udd80 \u00e9""#).unwrap(),
+        Json::String("🦀 é".to_string())
+    );
+}
+
+#[test]
+fn round_trips_folded_non_finite_numbers() {
+    let statements = Optimizer::new().optimize(parse("print 1 / 0; print -1 / 0; print 0 / 0;"));
+    let text = statements_to_json(&statements).to_string();
+
+    assert!(!text.contains("null"));
+    for value in [r#""Infinity""#, r#""-Infinity""#, r#""NaN""#] {
+        assert!(text.contains(&format!(r#""kind":"Number","value":{}"#, value)));
+    }
+    // NaN is never equal to itself, so compare the encodings of the decoded tree.
+    let decoded = statements_from_str(&text).unwrap();
+    assert_eq!(statements_to_json(&decoded).to_string(), text);
+}
+
+#[test]
+fn encodes_tokens_with_spans_and_literals() {
+    let Json::Array(statements) = statements_to_json(&parse("print 12;")) else {
+        panic!("statements must be encoded as an array");
+    };
+    let literal = statements[0].get("expression").unwrap();
+
+    assert_eq!(
+        literal.to_string(),
+        r#"{"kind":"Literal","value":{"kind":"Number","value":12},"span":{"line":1,"column":7,"start":6,"end":8}}"#
+    );
+}
+
+#[test]
+fn reports_malformed_documents() {
+    assert!(matches!(
+        statements_from_str(r#"[{"kind":"Print"}]"#),
+        Err(AstJsonError::MissingField(field)) if field == "expression"
+    ));
+    assert!(matches!(
+        statements_from_str(r#"[{"kind":"Loop"}]"#),
+        Err(AstJsonError::UnknownKind(kind)) if kind == "Loop"
+    ));
+    assert!(matches!(
+        statements_from_str("[{"),
+        Err(AstJsonError::InvalidJson(_))
+    ));
+    assert!(matches!(
+        statements_from_json(&Json::Null),
+        Err(AstJsonError::InvalidField(_, _))
+    ));
+}
@@ -0,0 +1,61 @@
+use rs_crafting_interpreters::ast_printer::{print_ast, AstFormat};
+use rs_crafting_interpreters::parser::{Parser, ParserTrait};
+use rs_crafting_interpreters::scanner::{Scanner, ScannerTrait};
+
+const NESTED: &str = "print -(1 + 2) * (3 - -4) / !true;";
+
+fn print(source: &str, format: AstFormat) -> String {
+    let tokens = Scanner::new(source.to_string()).scan_tokens().unwrap();
+    let statements = Parser::new(tokens).parse().unwrap();
+    print_ast(&statements, format)
+}
+
+#[test]
+fn lisp_keeps_groupings_and_precedence() {
+    assert_eq!(
+        print(NESTED, AstFormat::Lisp),
+        "(print (/ (* (- (group (+ 1 2))) (group (- 3 (- 4)))) (! true)))"
+    );
+}
+
+#[test]
+fn rpn_puts_operators_after_their_operands() {
+    assert_eq!(
+        print(NESTED, AstFormat::Rpn),
+        "1 2 + neg 3 4 neg - * true ! / print"
+    );
+}
+
+#[test]
+fn tree_indents_children_with_their_spans() {
+    let expected = "\
+Print [1:1]
+  expression: Binary '/' [1:27]
+    left: Binary '*' [1:16]
+      left: Unary '-' [1:7]
+        right: Grouping [1:8]
+          expression: Binary '+' [1:11]
+            left: Literal 1 [1:9]
+            right: Literal 2 [1:13]
+      right: Grouping [1:18]
+        expression: Binary '-' [1:21]
+          left: Literal 3 [1:19]
+          right: Unary '-' [1:23]
+            right: Literal 4 [1:24]
+    right: Unary '!' [1:29]
+      right: Literal true [1:30]";
+
+    assert_eq!(print(NESTED, AstFormat::Tree), expected);
+}
+
+#[test]
+fn one_line_per_top_level_statement() {
+    assert_eq!(
+        print("1 - 2 - 3;\n1 - (2 - 3);", AstFormat::Lisp),
+        "(; (- (- 1 2) 3))\n(; (- 1 (group (- 2 3))))"
+    );
+    assert_eq!(
+        print("1 - 2 - 3;\n1 - (2 - 3);", AstFormat::Rpn),
+        "1 2 - 3 -\n1 2 3 - -"
+    );
+}
@@ -0,0 +1,483 @@
+use rs_crafting_interpreters::bytecode::chunk::{Chunk, LineRun, OpCode};
+use rs_crafting_interpreters::bytecode::compiler::CompileError;
+use rs_crafting_interpreters::bytecode::disassembler::disassemble;
+use rs_crafting_interpreters::bytecode::gc::GcMode;
+use rs_crafting_interpreters::bytecode::object::{Heap, ObjFunction, Object};
+use rs_crafting_interpreters::bytecode::serializer::{self, LoadError, FORMAT_VERSION};
+use rs_crafting_interpreters::bytecode::value::Value;
+use rs_crafting_interpreters::bytecode::verifier::{self, VerifyErrorKind};
+use rs_crafting_interpreters::bytecode::vm::Vm;
+use rs_crafting_interpreters::bytecode::{self};
+use rs_crafting_interpreters::lox::LoxError;
+use rs_crafting_interpreters::optimizer::OptLevel;
+
+fn disasm(source: &str) -> String {
+    let mut heap = Heap::new();
+    let script = bytecode::compile(source, &mut heap).unwrap();
+    disassemble(script, &heap)
+}
+
+#[test]
+fn disassembles_in_the_book_format() {
+    assert_eq!(
+        disasm("print 1.2;\n\nprint -(1 + 2) >= 3;"),
+        "\
+== <script> ==
+0000    1 OP_CONSTANT         0 '1.2'
+0002    | OP_PRINT
+0003    3 OP_CONSTANT         1 '1'
+0005    | OP_CONSTANT         2 '2'
+0007    | OP_ADD
+0008    | OP_NEGATE
+0009    | OP_CONSTANT         3 '3'
+0011    | OP_LESS
+0012    | OP_NOT
+0013    | OP_PRINT
+0014    | OP_NULL
+0015    | OP_RETURN
+"
+    );
+}
+
+#[test]
+fn disassembles_nested_functions_and_jumps() {
+    let output = disasm("fun f(a) {\n  while (a) a = false;\n}\nf(true);");
+
+    assert!(output.contains("0000    3 OP_CLOSURE          1 <fn f>\n"));
+    assert!(output.contains("\n\n== f ==\n0000    2 OP_GET_LOCAL        1\n"));
+    assert!(output.contains("0002    | OP_JUMP_IF_FALSE    2 -> 13"));
+    assert!(output.contains("0010    | OP_LOOP            10 -> 0"));
+}
+
+#[test]
+fn disassembles_inline_cache_indices() {
+    let output = disasm("var a;\na.b = a.c;\na.d(1);");
+
+    assert!(output.contains(
+        "0007    | OP_GET_PROPERTY     2 'c' (cache 0)\n0011    | OP_SET_PROPERTY     1 'b' (cache 1)\n0015"
+    ));
+    assert!(output.contains("0020    | OP_INVOKE        (1 args)    3 'd' (cache 2)\n0025"));
+}
+
+#[test]
+fn disassembles_captured_variables() {
+    let output = disasm("{\n  var a = 1;\n  fun f() { return a; }\n}");
+
+    assert!(output.contains(
+        "0002    3 OP_CLOSURE          1 <fn f>\n0004      |                     local 1\n"
+    ));
+    assert!(output.contains("0006    4 OP_POP\n0007    | OP_CLOSE_UPVALUE\n"));
+    assert!(output.contains("== f ==\n0000    3 OP_GET_UPVALUE      0\n"));
+}
+
+#[test]
+fn folds_constants_and_removes_dead_branches() {
+    let mut heap = Heap::new();
+    let script = bytecode::compile_with(
+        "print -(1 + 2) >= 3;\nif (!!false) print \"dead\"; else print \"a\" + \"b\";\nwhile (1 > 2) print 1;\nfor (;false;) print 2;",
+        &mut heap,
+        OptLevel::Fold,
+    )
+    .unwrap();
+
+    assert_eq!(
+        disassemble(script, &heap),
+        "\
+== <script> ==
+0000    1 OP_FALSE
+0001    | OP_PRINT
+0002    2 OP_CONSTANT         0 'ab'
+0004    | OP_PRINT
+0005    4 OP_NULL
+0006    | OP_RETURN
+"
+    );
+}
+
+#[test]
+fn folded_operands_leave_no_constants_behind() {
+    let source: String = (1..=200).map(|i| format!("print -{i};\n")).collect();
+    let mut heap = Heap::new();
+    let script = bytecode::compile_with(&source, &mut heap, OptLevel::Fold).unwrap();
+
+    let Object::Function(function) = heap.get(script) else {
+        panic!("expected a function");
+    };
+    assert_eq!(function.chunk.constants.len(), 200);
+}
+
+#[test]
+fn optimized_code_reports_the_same_errors() {
+    let source = "if (false) { var a = a; }\nprint 1 + ;";
+    let plain = bytecode::compile(source, &mut Heap::new()).unwrap_err();
+    let optimized = bytecode::compile_with(source, &mut Heap::new(), OptLevel::Fold).unwrap_err();
+
+    assert_eq!(optimized.to_string(), plain.to_string());
+}
+
+#[test]
+fn property_accesses_hit_their_inline_caches() {
+    let mut vm = Vm::new();
+    bytecode::interpret(
+        "class Point {\n\
+           init(x) { this.x = x; }\n\
+           get() { return this.x; }\n\
+         }\n\
+         var sum = 0;\n\
+         for (var i = 0; i < 100; i = i + 1) sum = sum + Point(i).get();",
+        &mut vm,
+    )
+    .unwrap();
+    assert_eq!(vm.global("sum"), Some(Value::number(4950.0)));
+
+    let stats = vm.cache_stats();
+    assert_eq!(
+        (stats.get_property.hits, stats.get_property.misses),
+        (99, 1)
+    );
+    assert_eq!(
+        (stats.set_property.hits, stats.set_property.misses),
+        (99, 1)
+    );
+    assert_eq!((stats.invoke.hits, stats.invoke.misses), (99, 1));
+    assert_eq!(stats.shapes, 2);
+}
+
+#[test]
+fn line_table_is_run_length_encoded() {
+    let mut chunk = Chunk::new();
+    let constant = chunk.add_constant(Value::number(1.0));
+    chunk.write_op(OpCode::Constant, 1);
+    chunk.write(constant as u8, 1);
+    chunk.write_op(OpCode::Negate, 1);
+    chunk.write_op(OpCode::Return, 3);
+
+    assert_eq!(
+        chunk.lines(),
+        &[LineRun { line: 1, count: 3 }, LineRun { line: 3, count: 1 }]
+    );
+    assert_eq!(chunk.line(2), 1);
+    assert_eq!(chunk.line(3), 3);
+    assert_eq!(chunk.add_constant(Value::number(1.0)), constant);
+}
+
+#[test]
+fn reports_every_compile_error() {
+    let mut heap = Heap::new();
+    let error = bytecode::compile("var a = ;\n{ var b = b; }\nreturn 1;", &mut heap).unwrap_err();
+
+    let LoxError::CompileErrors(errors) = &error else {
+        panic!("expected compile errors, got {:?}", error);
+    };
+    assert_eq!(errors.len(), 3);
+    assert_eq!(
+        error.to_string(),
+        "\
+compile error: expected expression, found ';' at line: 1, column: 9
+compile error: can't read local variable 'b' in its own initializer at line: 2, column: 11
+compile error: can't return from top-level code at line: 3, column: 1"
+    );
+}
+
+#[test]
+fn deep_nesting_is_a_compile_error() {
+    let unary = format!("print {}1;", "-".repeat(3000));
+    let groupings = format!("print {}1{};", "(1 + ".repeat(3000), ")".repeat(3000));
+    let blocks = format!("{}{}", "{".repeat(3000), "}".repeat(3000));
+
+    for (source, what) in [
+        (unary, "expression"),
+        (groupings, "expression"),
+        (blocks, "statement"),
+    ] {
+        let mut heap = Heap::new();
+        let error = bytecode::compile(&source, &mut heap).unwrap_err();
+
+        let LoxError::CompileErrors(errors) = &error else {
+            panic!("expected compile errors, got {:?}", error);
+        };
+        assert!(
+            errors[0]
+                .to_string()
+                .starts_with(&format!("{what} nested too deeply at line: 1")),
+            "{error}"
+        );
+    }
+}
+
+#[test]
+fn operators_count_across_groupings_and_arguments() {
+    let term = vec!["1"; 100].join(" + ");
+    let mut heap = Heap::new();
+    bytecode::compile(&format!("print {term};"), &mut heap).unwrap();
+
+    let source = format!("print ({term}) - f({term}, {term});");
+    let error = bytecode::compile(&source, &mut heap).unwrap_err();
+    let LoxError::CompileErrors(errors) = &error else {
+        panic!("expected compile errors, got {:?}", error);
+    };
+    assert!(matches!(
+        errors.as_slice(),
+        [CompileError::TooManyOperators(_)]
+    ));
+}
+
+#[test]
+fn runtime_errors_carry_a_stack_trace() {
+    let mut vm = Vm::new();
+    let error = bytecode::interpret(
+        "fun add(a, b) {\n  return a + b;\n}\nfun run() {\n  add(1, \"x\");\n}\nrun();",
+        &mut vm,
+    )
+    .unwrap_err();
+
+    let LoxError::VmError(error) = &error else {
+        panic!("expected a runtime error, got {:?}", error);
+    };
+    assert_eq!(
+        error.to_string(),
+        "\
+operands of '+' must be two numbers or two strings at line: 2
+[line 2] in add()
+[line 5] in run()
+[line 7] in script"
+    );
+}
+
+#[test]
+fn globals_persist_between_scripts() {
+    let mut vm = Vm::new();
+    bytecode::interpret("var a = 1;", &mut vm).unwrap();
+    assert!(bytecode::interpret("a = a + b;", &mut vm).is_err());
+    bytecode::interpret("a = a + 1;", &mut vm).unwrap();
+
+    assert_eq!(vm.global("a"), Some(Value::number(2.0)));
+    assert_eq!(vm.global("b"), None);
+}
+
+#[test]
+fn collects_unreachable_cycles() {
+    let mut vm = Vm::new();
+    vm.set_gc_stress(true);
+    bytecode::interpret(
+        "class Node {}\n\
+         for (var i = 0; i < 100; i = i + 1) {\n\
+           var a = Node();\n\
+           var b = Node();\n\
+           a.next = b;\n\
+           b.next = a;\n\
+         }\n\
+         var kept = Node();\n\
+         kept.name = \"kept\";",
+        &mut vm,
+    )
+    .unwrap();
+
+    // The cycles are gone, only the last pair may still be waiting for a collection.
+    assert!(
+        vm.heap().object_count() < 40,
+        "{} objects",
+        vm.heap().object_count()
+    );
+    bytecode::interpret("print kept.name;", &mut vm).unwrap();
+}
+
+#[test]
+fn generational_collector_keeps_young_objects_stored_in_old_ones() {
+    let mut vm = Vm::new();
+    vm.set_gc_mode(GcMode::Generational);
+    vm.set_gc_stress(true);
+    bytecode::interpret(
+        "class Box {}\n\
+         var old = Box();\n\
+         for (var i = 0; i < 50; i = i + 1) {\n\
+           Box();\n\
+           var young = Box();\n\
+           young.value = \"v\" + \"alue\";\n\
+           old.child = young;\n\
+         }",
+        &mut vm,
+    )
+    .unwrap();
+    bytecode::interpret("print old.child.value;", &mut vm).unwrap();
+
+    let stats = vm.gc_stats();
+    assert!(stats.minor_collections > 0);
+    assert!(stats.bytes_freed > 0);
+    assert!(stats.max_pause <= stats.total_pause);
+}
+
+const PROGRAM: &str = "\
+fun counter(step) {
+  var count = 0;
+  fun next() { count = count + step; return count; }
+  return next;
+}
+class Greeter { greet(name) { return \"hi \" + name; } }
+var next = counter(1.5);
+next();
+print next();
+print Greeter().greet(\"bob\");
+print null == false;";
+
+#[test]
+fn precompiled_scripts_round_trip() {
+    let mut heap = Heap::new();
+    let script = bytecode::compile(PROGRAM, &mut heap).unwrap();
+    let bytes = serializer::serialize(script, &heap);
+    assert!(serializer::is_compiled(&bytes));
+
+    let mut loaded_heap = Heap::new();
+    let loaded = bytecode::load(&bytes, &mut loaded_heap).unwrap();
+    assert_eq!(
+        disassemble(loaded, &loaded_heap),
+        disassemble(script, &heap)
+    );
+
+    let mut vm = Vm::new();
+    let script = bytecode::load(&bytes, vm.heap_mut()).unwrap();
+    vm.run(script).unwrap();
+    assert!(vm.global("next").is_some());
+}
+
+#[test]
+fn rejects_other_versions_and_truncated_files() {
+    let mut heap = Heap::new();
+    let script = bytecode::compile(PROGRAM, &mut heap).unwrap();
+    let mut bytes = serializer::serialize(script, &heap);
+
+    for length in 0..bytes.len() {
+        assert!(
+            bytecode::load(&bytes[..length], &mut Heap::new()).is_err(),
+            "loaded the first {} bytes",
+            length
+        );
+    }
+
+    bytes[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+    assert!(matches!(
+        serializer::deserialize(&bytes, &mut Heap::new()),
+        Err(LoadError::UnsupportedVersion(version)) if version == FORMAT_VERSION + 1
+    ));
+    assert!(matches!(
+        serializer::deserialize(b"print 1;", &mut Heap::new()),
+        Err(LoadError::NotCompiled)
+    ));
+}
+
+/// Verify a script made of the given code, with a single number constant
+fn verify_code(code: &[u8]) -> Result<(), VerifyErrorKind> {
+    let mut heap = Heap::new();
+    let mut chunk = Chunk::new();
+    chunk.add_constant(Value::number(1.0));
+    for byte in code {
+        chunk.write(*byte, 1);
+    }
+    let script = heap.alloc(Object::Function(ObjFunction {
+        chunk,
+        ..ObjFunction::default()
+    }));
+
+    verifier::verify(script, &heap).map_err(|error| error.kind)
+}
+
+#[test]
+fn verifier_rejects_malformed_code() {
+    let constant = OpCode::Constant as u8;
+    let pop = OpCode::Pop as u8;
+    let null = OpCode::Null as u8;
+    let ret = OpCode::Return as u8;
+    let jump = OpCode::Jump as u8;
+
+    assert_eq!(verify_code(&[constant, 0, pop, null, ret]), Ok(()));
+
+    assert_eq!(
+        verify_code(&[constant, 1, ret]),
+        Err(VerifyErrorKind::ConstantOutOfRange(1))
+    );
+    assert_eq!(
+        verify_code(&[pop, pop, null, ret]),
+        Err(VerifyErrorKind::StackUnderflow(1, 0))
+    );
+    assert_eq!(
+        verify_code(&[jump, 0, 1, constant, 0, ret]),
+        Err(VerifyErrorKind::BadJumpTarget(4))
+    );
+    assert_eq!(
+        verify_code(&[jump, 0, 9, null, ret]),
+        Err(VerifyErrorKind::BadJumpTarget(12))
+    );
+    assert_eq!(
+        verify_code(&[OpCode::GetGlobal as u8, 0, ret]),
+        Err(VerifyErrorKind::ConstantType(0, "string"))
+    );
+    assert_eq!(
+        verify_code(&[null, pop]),
+        Err(VerifyErrorKind::MissingReturn)
+    );
+    assert_eq!(
+        verify_code(&[constant]),
+        Err(VerifyErrorKind::TruncatedInstruction)
+    );
+    assert_eq!(
+        verify_code(&[255]),
+        Err(VerifyErrorKind::UnknownOpcode(255))
+    );
+}
+
+#[test]
+fn verifier_rejects_captured_locals_left_open() {
+    let mut heap = Heap::new();
+    let script =
+        bytecode::compile("{\n  var a = 1;\n  fun f() { return a; }\n}", &mut heap).unwrap();
+    assert!(verifier::verify(script, &heap).is_ok());
+
+    let Object::Function(function) = heap.get_mut(script) else {
+        unreachable!();
+    };
+    let close = OpCode::CloseUpvalue as u8;
+    let offset = function
+        .chunk
+        .code
+        .iter()
+        .position(|&byte| byte == close)
+        .unwrap();
+    function.chunk.code[offset] = OpCode::Pop as u8;
+
+    let error = verifier::verify(script, &heap).unwrap_err();
+    assert_eq!(error.kind, VerifyErrorKind::UnclosedLocal(1));
+    assert_eq!(
+        error.to_string(),
+        format!(
+            "captured local 1 is popped without being closed at offset {} in script",
+            offset
+        )
+    );
+}
+
+#[test]
+fn verifier_rejects_unknown_inline_caches() {
+    let mut heap = Heap::new();
+    let script = bytecode::compile("var a;\na.b;", &mut heap).unwrap();
+    assert!(verifier::verify(script, &heap).is_ok());
+
+    let Object::Function(function) = heap.get_mut(script) else {
+        unreachable!();
+    };
+    function.caches.clear();
+
+    let error = verifier::verify(script, &heap).unwrap_err();
+    assert_eq!(error.kind, VerifyErrorKind::CacheOutOfRange(0));
+}
+
+#[cfg(feature = "nan-boxing")]
+#[test]
+fn nan_boxed_values_fit_in_eight_bytes() {
+    assert_eq!(std::mem::size_of::<Value>(), 8);
+
+    assert_eq!(Value::number(-0.5).as_number(), Some(-0.5));
+    assert!(Value::number(f64::NAN).as_number().unwrap().is_nan());
+    assert_ne!(Value::number(f64::NAN), Value::number(f64::NAN));
+    assert_eq!(Value::boolean(false).as_boolean(), Some(false));
+    assert!(Value::NULL.is_null() && Value::NULL.as_number().is_none());
+}
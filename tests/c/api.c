@@ -0,0 +1,124 @@
+/* Exercises the C API through the generated header. Prints what scripts
+ * print, one line per failed check, and "ok" when everything passed. */
+
+#include <stdio.h>
+#include <string.h>
+
+#include "lox.h"
+
+static int failures;
+
+#define CHECK(condition)                                                     \
+    do {                                                                     \
+        if (!(condition)) {                                                  \
+            printf("%s:%d: check failed: %s\n", __FILE__, __LINE__, #condition); \
+            failures++;                                                      \
+        }                                                                    \
+    } while (0)
+
+static LoxValue number(double value) {
+    LoxValue result = {LOX_TYPE_NUMBER, false, value, NULL};
+    return result;
+}
+
+static LoxValue string(const char *value) {
+    LoxValue result = {LOX_TYPE_STRING, false, 0, value};
+    return result;
+}
+
+/* add(a, b), counting its calls in user_data */
+static bool add(void *user_data, int argument_count, const LoxValue *arguments, LoxValue *result) {
+    (*(int *)user_data)++;
+    if (argument_count != 2 || arguments[0].kind != LOX_TYPE_NUMBER ||
+        arguments[1].kind != LOX_TYPE_NUMBER) {
+        *result = string("add takes two numbers");
+        return false;
+    }
+    *result = number(arguments[0].number + arguments[1].number);
+    return true;
+}
+
+/* Any number of arguments, returns how many */
+static bool count(void *user_data, int argument_count, const LoxValue *arguments, LoxValue *result) {
+    (void)user_data;
+    (void)arguments;
+    *result = number(argument_count);
+    return true;
+}
+
+/* The length of a string argument */
+static bool length(void *user_data, int argument_count, const LoxValue *arguments, LoxValue *result) {
+    (void)user_data;
+    (void)argument_count;
+    if (arguments[0].kind != LOX_TYPE_STRING) {
+        *result = string("length of a non-string");
+        return false;
+    }
+    *result = number((double)strlen(arguments[0].string));
+    return true;
+}
+
+int main(void) {
+    Lox *lox = lox_new();
+    LoxValue result;
+
+    CHECK(lox_eval(lox, "1 + 2;", &result) == LOX_STATUS_OK);
+    CHECK(result.kind == LOX_TYPE_NUMBER && result.number == 3);
+    CHECK(lox_error(lox) == NULL);
+
+    CHECK(lox_eval(lox, "print \"printed by lox\";", &result) == LOX_STATUS_OK);
+    CHECK(result.kind == LOX_TYPE_NULL);
+
+    /* Globals persist between scripts and can be set from C. */
+    CHECK(lox_eval(lox, "var greeting = \"hello\";", NULL) == LOX_STATUS_OK);
+    CHECK(lox_set_global(lox, "name", string("world")) == LOX_STATUS_OK);
+    CHECK(lox_eval(lox, "greeting + \" \" + name;", &result) == LOX_STATUS_OK);
+    CHECK(result.kind == LOX_TYPE_STRING && strcmp(result.string, "hello world") == 0);
+
+    LoxValue flag = {LOX_TYPE_BOOL, true, 0, NULL};
+    CHECK(lox_set_global(lox, "flag", flag) == LOX_STATUS_OK);
+    CHECK(lox_get_global(lox, "flag", &result) == LOX_STATUS_OK);
+    CHECK(result.kind == LOX_TYPE_BOOL && result.boolean);
+    CHECK(lox_get_global(lox, "missing", &result) == LOX_STATUS_RUNTIME_ERROR);
+    CHECK(strcmp(lox_error(lox), "undefined global 'missing'") == 0);
+
+    /* Native functions */
+    int calls = 0;
+    CHECK(lox_register_native(lox, "add", 2, add, &calls) == LOX_STATUS_OK);
+    CHECK(lox_register_native(lox, "count", -1, count, NULL) == LOX_STATUS_OK);
+    CHECK(lox_register_native(lox, "length", 1, length, NULL) == LOX_STATUS_OK);
+    CHECK(lox_eval(lox, "add(add(1, 2), 3);", &result) == LOX_STATUS_OK);
+    CHECK(result.kind == LOX_TYPE_NUMBER && result.number == 6 && calls == 2);
+    CHECK(lox_eval(lox, "count() + count(1, \"a\", null);", &result) == LOX_STATUS_OK);
+    CHECK(result.kind == LOX_TYPE_NUMBER && result.number == 3);
+    CHECK(lox_eval(lox, "length(greeting);", &result) == LOX_STATUS_OK);
+    CHECK(result.kind == LOX_TYPE_NUMBER && result.number == 5);
+    CHECK(lox_eval(lox, "print add;", NULL) == LOX_STATUS_OK);
+
+    /* Errors are returned as a status, the message stays until the next call. */
+    CHECK(lox_eval(lox, "add(1, \"x\");", &result) == LOX_STATUS_RUNTIME_ERROR);
+    CHECK(strstr(lox_error(lox), "add takes two numbers") != NULL);
+    CHECK(lox_eval(lox, "add(1);", NULL) == LOX_STATUS_RUNTIME_ERROR);
+    CHECK(strstr(lox_error(lox), "runtime error") != NULL);
+    CHECK(lox_eval(lox, "var;", NULL) == LOX_STATUS_COMPILE_ERROR);
+    CHECK(strstr(lox_error(lox), "parser error") != NULL);
+    CHECK(lox_eval(lox, "1;", NULL) == LOX_STATUS_OK);
+    CHECK(lox_error(lox) == NULL);
+
+    /* Functions, classes and instances come back as the text they print as. */
+    CHECK(lox_eval(lox, "class Point {} Point();", &result) == LOX_STATUS_OK);
+    CHECK(result.kind == LOX_TYPE_OBJECT && strcmp(result.string, "Point instance") == 0);
+    CHECK(lox_set_global(lox, "copy", result) == LOX_STATUS_INVALID_ARGUMENT);
+    CHECK(lox_set_global(lox, NULL, number(1)) == LOX_STATUS_INVALID_ARGUMENT);
+    LoxValue unknown = {99, false, 0, NULL};
+    CHECK(lox_set_global(lox, "unknown", unknown) == LOX_STATUS_INVALID_ARGUMENT);
+    CHECK(strstr(lox_error(lox), "unknown kind") != NULL);
+    CHECK(lox_eval(NULL, "1;", NULL) == LOX_STATUS_INVALID_ARGUMENT);
+
+    lox_free(lox);
+
+    if (failures == 0) {
+        printf("ok\n");
+    }
+    return failures == 0 ? 0 : 1;
+}
@@ -0,0 +1,146 @@
+use rs_crafting_interpreters::lox::{self, IntoLox, Lox, LoxError};
+use rs_crafting_interpreters::native::Arity;
+use rs_crafting_interpreters::optimizer::OptLevel;
+use rs_crafting_interpreters::value::Value;
+
+#[test]
+fn eval_returns_the_last_expression() {
+    let mut lox = Lox::new();
+
+    assert_eq!(lox.eval("var a = 1; a + 2;").unwrap(), Value::Number(3.0));
+    assert_eq!(lox.eval("var b = 1;").unwrap(), Value::Null);
+}
+
+#[test]
+fn eval_statements_runs_a_parsed_program() {
+    let mut lox = Lox::new();
+    let statements = lox::parse_line("var a = 20;\na * 2 + 2").unwrap();
+
+    assert_eq!(
+        lox.eval_statements(statements).unwrap(),
+        Value::Number(42.0)
+    );
+    assert!(lox::parse("a").is_err());
+}
+
+#[test]
+fn globals_persist_between_evals() {
+    let mut lox = Lox::new();
+    lox.eval("var greeting = \"hello\";").unwrap();
+    lox.eval("greeting = greeting + \" world\";").unwrap();
+
+    assert_eq!(lox.get_global::<String>("greeting").unwrap(), "hello world");
+}
+
+#[test]
+fn set_global_is_visible_to_scripts() {
+    let mut lox = Lox::new();
+    lox.set_global("limit", 10);
+    lox.set_global("name", "lox");
+    lox.set_global("missing", None::<f64>);
+
+    assert_eq!(lox.eval("limit * 2;").unwrap(), Value::Number(20.0));
+    assert_eq!(lox.eval("name;").unwrap(), "lox".into_lox());
+    assert_eq!(lox.get_global::<Option<f64>>("missing").unwrap(), None);
+}
+
+#[test]
+fn call_function_converts_arguments_and_result() {
+    let mut lox = Lox::new();
+    lox.eval("fun add(a, b) { return a + b; }").unwrap();
+
+    let sum: f64 = lox
+        .call_function("add", vec![1.5.into_lox(), 2.into_lox()])
+        .unwrap();
+    assert_eq!(sum, 3.5);
+
+    let result = lox.call_function::<f64>("add", vec![1.into_lox()]);
+    assert!(matches!(result, Err(LoxError::RuntimeError(_))));
+}
+
+#[test]
+fn call_function_instantiates_classes() {
+    let mut lox = Lox::new();
+    lox.eval("class Point { init(x) { this.x = x; } }").unwrap();
+
+    let point: Value = lox.call_function("Point", vec![4.into_lox()]).unwrap();
+    lox.set_global("point", point);
+
+    assert_eq!(lox.eval("point.x;").unwrap(), Value::Number(4.0));
+}
+
+#[test]
+fn errors_are_reported() {
+    let mut lox = Lox::new();
+
+    assert!(matches!(
+        lox.get_global::<f64>("nope"),
+        Err(LoxError::UndefinedGlobal(_))
+    ));
+    assert!(matches!(
+        lox.eval("var x = \"s\";").and(lox.get_global::<f64>("x")),
+        Err(LoxError::TypeMismatch("number", "string"))
+    ));
+    assert!(matches!(lox.eval("1 +;"), Err(LoxError::ParserErrors(_))));
+    assert!(matches!(
+        lox.eval("{ var a = a; }"),
+        Err(LoxError::ResolverErrors(_))
+    ));
+    assert!(matches!(
+        lox.eval("-\"s\";"),
+        Err(LoxError::RuntimeError(_))
+    ));
+}
+
+#[test]
+fn native_functions_take_typed_arguments() {
+    let mut lox = Lox::new();
+    lox.register_fn("hypot", Arity::Fixed(2), |args| {
+        let (x, y): (f64, f64) = (args.get(0)?, args.get(1)?);
+        Ok(x.hypot(y))
+    });
+    lox.register_fn("join", Arity::Variadic(1), |args| {
+        let separator: String = args.get(0)?;
+        let parts: Vec<String> = args.values()[1..].iter().map(|v| v.to_string()).collect();
+        Ok(parts.join(&separator))
+    });
+
+    assert_eq!(lox.eval("hypot(3, 4);").unwrap(), Value::Number(5.0));
+    assert_eq!(
+        lox.eval("join(\"-\", 1, true, \"x\");").unwrap(),
+        "1-true-x".into_lox()
+    );
+}
+
+#[test]
+fn native_errors_are_runtime_errors_at_the_call_site() {
+    let mut lox = Lox::new();
+    lox.register_fn("half", Arity::Fixed(1), |args| {
+        let n: f64 = args.get(0)?;
+        if n < 0.0 {
+            return Err(LoxError::Native("negative input".to_string()));
+        }
+        Ok(n / 2.0)
+    });
+    lox.register_fn("first", Arity::Variadic(1), |args| args.get::<Value>(0));
+
+    let error = lox.eval("\n\nhalf(\"two\");").unwrap_err().to_string();
+    assert!(error.contains("half: argument 0 expected number, found string at line: 3"));
+
+    let error = lox.eval("half(-1);").unwrap_err().to_string();
+    assert!(error.contains("half: negative input at line: 1"));
+
+    let error = lox.eval("first();").unwrap_err().to_string();
+    assert!(error.contains("expected at least 1 arguments but got 0"));
+}
+
+#[test]
+fn optimized_eval_returns_the_same_values() {
+    let mut lox = Lox::new();
+    lox.set_opt_level(OptLevel::Fold);
+
+    assert_eq!(lox.eval("1 + 2 * 3;").unwrap(), Value::Number(7.0));
+    assert_eq!(lox.eval("\"a\" + \"b\";").unwrap(), "ab".into_lox());
+    assert_eq!(lox.eval("var x = 0; !!x;").unwrap(), Value::Boolean(true));
+    assert!(lox.eval("1 + \"a\";").is_err());
+}
@@ -0,0 +1,15 @@
+#![cfg(feature = "ffi")]
+
+/// `build.rs` generates the header into `OUT_DIR`, the committed copy is
+/// what C programs include
+#[test]
+fn committed_header_matches_src_ffi() {
+    let generated = include_str!(concat!(env!("OUT_DIR"), "/lox.h"));
+    let committed = include_str!("../include/lox.h");
+
+    assert!(
+        generated == committed,
+        "include/lox.h is out of date, copy it from {}/lox.h",
+        env!("OUT_DIR")
+    );
+}
@@ -0,0 +1,278 @@
+//! Runs every script under `tests/lox` and compares the result with the
+//! expectations written in its comments, like the Crafting Interpreters suite:
+//!
+//! - `// expect: <line>` a line printed to stdout
+//! - `// expect runtime error: <message>` stderr contains message, exit code 70
+//! - `// expect error: <message>` stderr contains message, exit code 65
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+struct Expectation {
+    output: Vec<String>,
+    errors: Vec<String>,
+    exit_code: i32,
+}
+
+fn expectation(source: &str) -> Expectation {
+    let mut expectation = Expectation {
+        output: Vec::new(),
+        errors: Vec::new(),
+        exit_code: 0,
+    };
+
+    for line in source.lines() {
+        if let Some((_, expected)) = line.split_once("// expect: ") {
+            expectation.output.push(expected.to_string());
+        } else if let Some((_, expected)) = line.split_once("// expect runtime error: ") {
+            expectation.errors.push(expected.to_string());
+            expectation.exit_code = 70;
+        } else if let Some((_, expected)) = line.split_once("// expect error: ") {
+            expectation.errors.push(expected.to_string());
+            expectation.exit_code = 65;
+        }
+    }
+
+    expectation
+}
+
+fn scripts(dir: &Path, found: &mut Vec<PathBuf>) {
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            scripts(&path, found);
+        } else if path.extension().is_some_and(|e| e == "lox") {
+            found.push(path);
+        }
+    }
+}
+
+fn rlox(args: &[&str], path: &Path) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_rs-crafting-interpreters"))
+        .args(args)
+        .arg(path)
+        .output()
+        .unwrap()
+}
+
+/// Run a script with the given arguments, returning a description of each mismatch
+fn check(path: &Path, args: &[&str]) -> Vec<String> {
+    compare(path, rlox(args, path))
+}
+
+/// Compile a script to a `.loxc` file and run that instead. Scripts with
+/// compile errors are checked against the output of `rlox compile`.
+fn check_compiled(path: &Path) -> Vec<String> {
+    let name = path.strip_prefix(env!("CARGO_MANIFEST_DIR")).unwrap();
+    let compiled = Path::new(env!("CARGO_TARGET_TMPDIR"))
+        .join(name)
+        .with_extension("loxc");
+    std::fs::create_dir_all(compiled.parent().unwrap()).unwrap();
+
+    let output = rlox(&["compile", "-o", compiled.to_str().unwrap()], path);
+    if !output.status.success() {
+        return compare(path, output);
+    }
+
+    compare(path, rlox(&["run"], &compiled))
+}
+
+/// Translate a script to C, compile it with the system C compiler and run
+/// the binary, once as is and once collecting garbage on every allocation.
+/// Scripts with compile errors are checked against the output of `rlox build`.
+fn check_c(path: &Path, args: &[&str]) -> Vec<String> {
+    let name = path.strip_prefix(env!("CARGO_MANIFEST_DIR")).unwrap();
+    let source = Path::new(env!("CARGO_TARGET_TMPDIR"))
+        .join(name)
+        .with_extension("c");
+    let binary = source.with_extension("");
+    std::fs::create_dir_all(source.parent().unwrap()).unwrap();
+
+    let mut build = vec!["build", "--emit", "c", "-o", source.to_str().unwrap()];
+    build.extend(args);
+    let output = rlox(&build, path);
+    if !output.status.success() {
+        return compare(path, output);
+    }
+
+    let cc = Command::new("cc")
+        .arg("-o")
+        .arg(&binary)
+        .arg(&source)
+        .arg("-lm")
+        .output()
+        .unwrap();
+    if !cc.status.success() {
+        return vec![format!(
+            "{}: cc failed: {}",
+            path.display(),
+            String::from_utf8_lossy(&cc.stderr)
+        )];
+    }
+
+    let mut failures = compare(path, Command::new(&binary).output().unwrap());
+    let stressed = Command::new(&binary)
+        .env("LOX_GC_STRESS", "1")
+        .output()
+        .unwrap();
+    failures.extend(compare(path, stressed));
+    failures
+}
+
+fn compare(path: &Path, output: Output) -> Vec<String> {
+    let source = std::fs::read_to_string(path).unwrap();
+    let expected = expectation(&source);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let mut failures = Vec::new();
+
+    let actual: Vec<&str> = stdout.lines().collect();
+    if actual != expected.output {
+        failures.push(format!(
+            "expected output {:?}, got {:?}",
+            expected.output, actual
+        ));
+    }
+    for error in &expected.errors {
+        if !stderr.contains(error.as_str()) {
+            failures.push(format!("expected error {:?}, got {:?}", error, stderr));
+        }
+    }
+    if output.status.code() != Some(expected.exit_code) {
+        failures.push(format!(
+            "expected exit code {}, got {:?}, stderr {:?}",
+            expected.exit_code,
+            output.status.code(),
+            stderr
+        ));
+    }
+
+    failures
+        .into_iter()
+        .map(|failure| format!("{}: {}", path.display(), failure))
+        .collect()
+}
+
+fn run_suite(args: &[&str]) {
+    run_suite_with(|path| check(path, args));
+}
+
+fn run_suite_with(check: impl Fn(&Path) -> Vec<String>) {
+    let mut paths = Vec::new();
+    scripts(
+        &Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/lox"),
+        &mut paths,
+    );
+    paths.sort();
+
+    let failures: Vec<String> = paths.iter().flat_map(|path| check(path)).collect();
+
+    assert!(
+        failures.is_empty(),
+        "{} failures:\n{}",
+        failures.len(),
+        failures.join("\n")
+    );
+}
+
+#[test]
+fn tree_walk_interpreter() {
+    run_suite(&[]);
+}
+
+#[test]
+fn bytecode_vm() {
+    run_suite(&["run", "--engine=vm"]);
+}
+
+#[test]
+fn bytecode_vm_gc_stress() {
+    run_suite(&["run", "--engine=vm", "--gc-stress"]);
+}
+
+#[test]
+fn bytecode_vm_generational_gc_stress() {
+    run_suite(&["run", "--engine=vm", "--gc=generational", "--gc-stress"]);
+}
+
+#[test]
+fn precompiled_bytecode() {
+    run_suite_with(check_compiled);
+}
+
+#[test]
+fn tree_walk_interpreter_optimized() {
+    run_suite(&["run", "--opt-level=1"]);
+}
+
+#[test]
+fn bytecode_vm_optimized() {
+    run_suite(&["run", "--engine=vm", "--opt-level=1"]);
+}
+
+#[test]
+fn register_vm() {
+    run_suite(&["run", "--engine=register"]);
+}
+
+#[test]
+fn register_vm_gc_stress() {
+    run_suite(&["run", "--engine=register", "--gc-stress"]);
+}
+
+#[test]
+fn register_vm_optimized() {
+    run_suite(&["run", "--engine=register", "--opt-level=1"]);
+}
+
+#[test]
+fn compiled_to_c() {
+    run_suite_with(|path| check_c(path, &[]));
+}
+
+#[test]
+fn compiled_to_c_optimized() {
+    run_suite_with(|path| check_c(path, &["--opt-level=1"]));
+}
+
+/// Only the tree-walking interpreter keeps its own evaluation on the native
+/// stack, the virtual machines run this script to the end
+#[test]
+fn deep_evaluation_across_calls_is_a_runtime_error() {
+    let chain = vec!["1"; 250].join(" + ");
+    let script = Path::new(env!("CARGO_TARGET_TMPDIR")).join("deep_evaluation.lox");
+    std::fs::write(
+        &script,
+        format!("fun f(n) {{\n  if (n == 0) return 0;\n  return f(n - 1) + {chain};\n}}\nprint f(200);\n"),
+    )
+    .unwrap();
+
+    let output = rlox(&["run"], &script);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert_eq!(output.status.code(), Some(70), "{stderr}");
+    assert!(
+        stderr.contains("runtime error: stack overflow at line: 3"),
+        "{stderr}"
+    );
+
+    let output = rlox(&["run", "--engine=vm"], &script);
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "50000\n");
+}
+
+#[test]
+fn trace_prints_the_stack_and_each_instruction() {
+    let script = Path::new(env!("CARGO_TARGET_TMPDIR")).join("trace.lox");
+    std::fs::write(&script, "print 1 + 2;\n").unwrap();
+
+    let output = rlox(&["run", "--engine=vm", "--trace"], &script);
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+
+    assert!(output.status.success(), "{stderr}");
+    assert_eq!(stdout, "3\n");
+    assert!(
+        stderr.contains("          [ <script> ][ 1 ][ 2 ]\n0004    | OP_ADD\n"),
+        "{stderr}"
+    );
+}
@@ -0,0 +1,111 @@
+use rs_crafting_interpreters::parser::{
+    Parser, ParserError, ParserTrait, MAX_NESTING, MAX_OPERATORS,
+};
+use rs_crafting_interpreters::scanner::{Scanner, ScannerTrait};
+
+fn parse_errors(source: &str) -> Vec<ParserError> {
+    let tokens = Scanner::new(source.to_string()).scan_tokens().unwrap();
+    Parser::new(tokens).parse().unwrap_err()
+}
+
+fn messages(errors: &[ParserError]) -> Vec<String> {
+    errors.iter().map(ToString::to_string).collect()
+}
+
+#[test]
+fn reports_every_syntax_error_in_one_parse() {
+    let errors = parse_errors("var = 1;\nprint 2;\nprint (3;\nfun f( {}\nprint 4 +;");
+
+    assert_eq!(
+        messages(&errors),
+        [
+            "expected variable name, found '=' at line: 1, column: 5",
+            "expected ')' after expression, found ';' at line: 3, column: 9",
+            "expected parameter name, found '{' at line: 4, column: 8",
+            "expected expression, found ';' at line: 5, column: 10",
+        ]
+    );
+    assert_eq!(errors[1].span().line, 3);
+    assert_eq!(errors[1].span().column, 9);
+}
+
+#[test]
+fn synchronizes_at_the_next_statement() {
+    // The error inside the block skips to the `print` after it, which still parses.
+    let errors = parse_errors("{ var a = ; print a; }\nclass { }\nvar ok = 1;\n1 = 2;");
+
+    assert_eq!(
+        messages(&errors),
+        [
+            "expected expression, found ';' at line: 1, column: 11",
+            "expected class name, found '{' at line: 2, column: 7",
+            "invalid assignment target at line: 4, column: 3",
+        ]
+    );
+}
+
+#[test]
+fn deeply_nested_expressions_are_reported() {
+    let depth = MAX_NESTING + 100;
+    let source = format!("print {}1{};", "(1 + ".repeat(depth), ")".repeat(depth));
+
+    let errors = parse_errors(&source);
+    assert!(matches!(
+        errors.as_slice(),
+        [ParserError::NestedTooDeeply("expression", _)]
+    ));
+    assert!(errors[0]
+        .to_string()
+        .starts_with("expression nested too deeply at line: 1"));
+}
+
+#[test]
+fn deeply_nested_unary_operators_and_blocks_are_reported() {
+    let unary = format!("print {}1;", "-".repeat(3000));
+    assert!(matches!(
+        parse_errors(&unary).as_slice(),
+        [ParserError::NestedTooDeeply("expression", _)]
+    ));
+
+    let blocks = format!("{}{}", "{".repeat(3000), "}".repeat(3000));
+    assert!(matches!(
+        parse_errors(&blocks).first(),
+        Some(ParserError::NestedTooDeeply("statement", _))
+    ));
+}
+
+#[test]
+fn long_operator_chains_are_reported() {
+    let chain = |operator: &str, count: usize| vec!["x"; count + 1].join(operator);
+    let parses = |source: String| {
+        let tokens = Scanner::new(source).scan_tokens().unwrap();
+        Parser::new(tokens).parse()
+    };
+
+    assert!(parses(format!("print {};", chain(" + ", MAX_OPERATORS))).is_ok());
+    // Statements are counted one at a time.
+    assert!(parses(format!("{0}; {0};", chain(" or ", MAX_OPERATORS))).is_ok());
+
+    let errors = parse_errors(&format!("print {};", chain(" and ", MAX_OPERATORS + 1)));
+    assert_eq!(
+        messages(&errors),
+        ["too many operators in one expression at line: 1, column: 1545"]
+    );
+}
+
+#[test]
+fn only_a_last_top_level_expression_may_leave_out_its_semicolon() {
+    let parse = |source: &str| {
+        let tokens = Scanner::new(source.to_string()).scan_tokens().unwrap();
+        let mut parser = Parser::new(tokens);
+        parser.set_trailing_expression(true);
+        parser.parse()
+    };
+
+    assert_eq!(parse("var a = 1;\na + 2").unwrap().len(), 2);
+    assert!(parse("1 + 2\nprint 3;").is_err());
+    assert!(parse("print 3").is_err());
+    assert!(parse("if (true) 3").is_err());
+    assert!(parse("{ 3").is_err());
+    assert_eq!(parse_errors("1 + 2").len(), 1);
+}
@@ -0,0 +1,132 @@
+use rs_crafting_interpreters::bytecode::object::Heap;
+use rs_crafting_interpreters::bytecode::value::Value;
+use rs_crafting_interpreters::lox::LoxError;
+use rs_crafting_interpreters::parser::MAX_OPERATORS;
+use rs_crafting_interpreters::register::instruction::disassemble;
+use rs_crafting_interpreters::register::vm::RegisterVm;
+use rs_crafting_interpreters::register::{self};
+
+fn disasm(source: &str) -> String {
+    let mut heap = Heap::new();
+    let script = register::compile(source, &mut heap).unwrap();
+    disassemble(script, &heap)
+}
+
+#[test]
+fn disassembles_three_address_code() {
+    assert_eq!(
+        disasm("print 1.2;\n\nprint -(1 + 2) >= 3;"),
+        "\
+== <script> (4 registers) ==
+0000    1 LOADK        1 '1.2'
+0001    | PRINT        1
+0002    3 LOADK        2 '1'
+0003    | LOADK        3 '2'
+0004    | ADD          2 2 3
+0005    | NEG          1 2
+0006    | LOADK        2 '3'
+0007    | LT           1 1 2
+0008    | NOT          1 1
+0009    | PRINT        1
+0010    | LOADNULL     1
+0011    | RETURN       1
+"
+    );
+}
+
+#[test]
+fn locals_are_operands_without_copies() {
+    let output = disasm("fun f(a, b) {\n  var c = a * b;\n  return c + 1;\n}\nprint f(2, 3);");
+
+    assert!(output.contains("0005    | CALL         1 (2 args)\n"));
+    assert!(output.contains(
+        "== f (6 registers) ==\n0000    2 MUL          3 1 2\n0001    3 LOADK        5 '1'\n0002    | ADD          4 3 5\n0003    | RETURN       4\n"
+    ));
+}
+
+#[test]
+fn long_chains_reuse_their_target() {
+    let source = format!("print {};", vec!["1"; MAX_OPERATORS + 1].join(" + "));
+
+    assert!(disasm(&source).starts_with("== <script> (3 registers) =="));
+}
+
+#[test]
+fn running_out_of_registers_is_reported_once() {
+    let locals: String = (0..300).map(|i| format!("var a{} = true; ", i)).collect();
+    let mut heap = Heap::new();
+    let error = register::compile(&format!("{{ {} }}", locals), &mut heap).unwrap_err();
+
+    let LoxError::CompileErrors(errors) = &error else {
+        panic!("expected compile errors, got {:?}", error);
+    };
+    assert_eq!(
+        errors
+            .iter()
+            .filter(|error| error.to_string().contains("too many registers"))
+            .count(),
+        1
+    );
+}
+
+#[test]
+fn disassembles_jumps_and_captured_variables() {
+    let output = disasm("{\n  var a = 1;\n  while (a < 3) a = a + 1;\n  fun f() { return a; }\n}");
+
+    assert!(output.contains("0003    | JMPF         2 -> 7\n"));
+    assert!(output.contains("0006    | LOOP         -> 1\n"));
+    assert!(output.contains("0007    4 CLOSURE      2 <fn f>\n0008    |   local 1\n"));
+    assert!(output.contains("0009    | CLOSE        1\n"));
+}
+
+#[test]
+fn runtime_errors_carry_a_stack_trace() {
+    let mut vm = RegisterVm::new();
+    let error = register::interpret(
+        "fun add(a, b) {\n  return a + b;\n}\nfun run() {\n  add(1, \"x\");\n}\nrun();",
+        &mut vm,
+    )
+    .unwrap_err();
+
+    let LoxError::VmError(error) = &error else {
+        panic!("expected a runtime error, got {:?}", error);
+    };
+    assert_eq!(
+        error.to_string(),
+        "\
+operands of '+' must be two numbers or two strings at line: 2
+[line 2] in add()
+[line 5] in run()
+[line 7] in script"
+    );
+}
+
+#[test]
+fn globals_persist_between_scripts() {
+    let mut vm = RegisterVm::new();
+    register::interpret("var a = 1;", &mut vm).unwrap();
+    assert!(register::interpret("a = a + b;", &mut vm).is_err());
+    register::interpret("a = a + 1;", &mut vm).unwrap();
+
+    assert_eq!(vm.global("a"), Some(Value::number(2.0)));
+    assert_eq!(vm.global("b"), None);
+}
+
+#[test]
+fn operands_assigned_later_in_an_expression_keep_their_value() {
+    let mut vm = RegisterVm::new();
+    register::interpret(
+        "fun f() {\n\
+           var a = 1;\n\
+           var b = a + (a = 5);\n\
+           var c = true;\n\
+           c = false or c;\n\
+           return b * 10 + a;\n\
+         }\n\
+         var result = f();",
+        &mut vm,
+    )
+    .unwrap();
+
+    assert_eq!(vm.global("result"), Some(Value::number(65.0)));
+}
@@ -0,0 +1,73 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+/// Run the prompt with `input` on stdin until it reaches end of input
+fn repl(input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_rs-crafting-interpreters"))
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(input.as_bytes())
+        .unwrap();
+
+    child.wait_with_output().unwrap()
+}
+
+#[test]
+fn definitions_persist_and_expressions_echo() {
+    let output = repl("var a = 20;\nfun twice(x) { return x * 2; }\ntwice(a) + 2\nprint a;\n");
+
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "> > > 42\n> 20\n> "
+    );
+    assert!(output.status.success());
+}
+
+#[test]
+fn errors_are_reported_and_the_session_continues() {
+    let output = repl("print missing;\n1 + ;\nvar a = 1;\na;\n");
+
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "> > > > 1\n> ");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("runtime error: undefined variable 'missing'"));
+    assert!(stderr.contains("parser error: expected expression"));
+    assert!(output.status.success());
+}
+
+#[test]
+fn only_a_last_expression_may_leave_out_its_semicolon() {
+    let output = repl("var a = 1; a + 1\nprint a\nif (true) a\n");
+
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "> 2\n> > > ");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("expected ';' after value"));
+    assert!(stderr.contains("expected ';' after expression"));
+    assert!(output.status.success());
+}
+
+#[test]
+fn unclosed_brackets_continue_on_the_next_line() {
+    let output = repl("fun add(a,\n  b) {\n  return a + b;\n}\nadd(1, 2)\n\"two\nlines\"\n");
+
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "> ... ... ... > 3\n> ... two\nlines\n> "
+    );
+    assert!(output.status.success());
+}
+
+#[test]
+fn end_of_input_runs_an_unfinished_line_and_exits() {
+    let output = repl("print 1;\n{ print 2;\n");
+
+    assert_eq!(String::from_utf8_lossy(&output.stdout), "> 1\n> ... ");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("parser error"));
+    assert!(output.status.success());
+}